@@ -0,0 +1,229 @@
+//! Benchmarks for the functions that dominate the running time of large simulations.
+//!
+//! Run with `cargo bench --bench hot_paths`.
+
+#[macro_use]
+extern crate criterion;
+extern crate ewok;
+#[macro_use]
+extern crate maplit;
+
+use criterion::{BatchSize, Criterion};
+use ewok::block::{Block, BlockId, Vote};
+use ewok::blocks::{Blocks, CurrentBlocks, VoteCounts};
+use ewok::generate::generate_network;
+use ewok::message::Message;
+use ewok::message::MessageContent::*;
+use ewok::name::{Name, Prefix};
+use ewok::network::Network;
+use ewok::node::Node;
+use ewok::params::{NameDistribution, NodeParams, RecipientRule, SimulationParams};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// The network has `2^PREFIX_LEN` sections.
+const PREFIX_LEN: usize = 7;
+const SECTION_SIZE: usize = 10;
+/// Number of votes in a single section's history.
+const CHAIN_LEN: usize = 100;
+/// Number of nodes sending each other messages in the network benchmark.
+const NUM_PEERS: u64 = 50;
+
+/// Blocks for a network of equally sized sections with evenly spread names.
+fn network(blocks: &mut Blocks) -> CurrentBlocks {
+    let sections = (0..1 << PREFIX_LEN)
+        .map(|i| {
+            (Prefix::short(PREFIX_LEN, (i << (8 - PREFIX_LEN)) as u8), SECTION_SIZE)
+        })
+        .collect();
+    generate_network(
+        blocks,
+        &sections,
+        NameDistribution::Spread,
+        &NodeParams::default(),
+    ).1
+}
+
+/// History of a single section, in which nodes alternately join and leave. Every vote is made
+/// by all the members of its `from` block that remain in its `to` block.
+fn chain(blocks: &mut Blocks) -> Vec<(Vote, BTreeSet<Name>)> {
+    let mut names = Prefix::empty().spread_names();
+    let mut block = Block::genesis(names.next().unwrap());
+    for name in names.by_ref().take(SECTION_SIZE - 1) {
+        block = block.add_node(name);
+    }
+
+    let mut from = blocks.insert(block.clone());
+    let mut votes = vec![];
+    let mut joined = None;
+    for _ in 0..CHAIN_LEN {
+        let next = match joined.take() {
+            Some(name) => block.remove_node(name),
+            None => {
+                let name = names.next().unwrap();
+                joined = Some(name);
+                block.add_node(name)
+            }
+        };
+        let to = blocks.insert(next.clone());
+        votes.push((Vote { from, to }, &*block.members & &*next.members));
+        block = next;
+        from = to;
+    }
+    votes
+}
+
+fn vote_counts(votes: &[(Vote, BTreeSet<Name>)]) -> VoteCounts {
+    let mut vote_counts = VoteCounts::default();
+    for &(ref vote, ref voters) in votes {
+        vote_counts
+            .entry(vote.from)
+            .or_insert_with(Default::default)
+            .insert(vote.to, voters.clone());
+    }
+    vote_counts
+}
+
+fn new_valid_blocks(c: &mut Criterion) {
+    let mut blocks = Blocks::new();
+    let votes = chain(&mut blocks);
+    let valid_blocks = btreeset!{ votes[0].0.from };
+    let vote_counts = vote_counts(&votes);
+    let new_votes: BTreeSet<Vote> = votes.into_iter().map(|(vote, _)| vote).collect();
+    let params = NodeParams::default();
+
+    c.bench_function("new_valid_blocks", move |b| {
+        b.iter(|| {
+            blocks.new_valid_blocks(&valid_blocks, &vote_counts, new_votes.clone(), &params)
+        })
+    });
+}
+
+fn compute_current_candidate_blocks(c: &mut Criterion) {
+    // Every section has a few versions of its block, as a node would after some churn.
+    let mut blocks = Blocks::new();
+    let current_blocks = network(&mut blocks);
+    let mut valid_blocks = BTreeSet::new();
+    for id in current_blocks {
+        let mut block = id.into_block(&blocks).clone();
+        let mut names = block.prefix.spread_names().skip(SECTION_SIZE);
+        for _ in 0..4 {
+            valid_blocks.insert(blocks.insert(block.clone()));
+            block = block.add_node(names.next().unwrap());
+        }
+    }
+
+    c.bench_function("compute_current_candidate_blocks", move |b| {
+        b.iter(|| blocks.compute_current_candidate_blocks(valid_blocks.clone()))
+    });
+}
+
+fn construct_proof(c: &mut Criterion) {
+    // A node that has seen a section's whole history is asked to prove its latest block to a
+    // node that only knows the genesis block.
+    let mut blocks = Blocks::new();
+    let votes = chain(&mut blocks);
+    let genesis: BlockId = votes[0].0.from;
+    let latest: BlockId = votes[votes.len() - 1].0.to;
+    let (our_name, requester) = {
+        let mut members = genesis.into_block(&blocks).members.iter().cloned();
+        (members.next().unwrap(), members.next().unwrap())
+    };
+
+    let mut node = Node::new(
+        our_name,
+        &blocks,
+        btreeset!{ genesis },
+        NodeParams::default(),
+        0,
+    );
+    let bundle = Message {
+        sender: requester,
+        recipient: our_name,
+        content: Arc::new(VoteBundle(votes)),
+        trace_id: 0,
+    };
+    node.handle_message(bundle, &blocks, 0);
+    node.update_state(&blocks, 0);
+    assert!(node.valid_blocks.contains(&latest));
+
+    let request = Message {
+        sender: requester,
+        recipient: our_name,
+        content: Arc::new(RequestProof(latest, btreeset!{ genesis })),
+        trace_id: 0,
+    };
+    c.bench_function("construct_proof", move |b| {
+        b.iter(|| node.handle_message(request.clone(), &blocks, 1))
+    });
+}
+
+fn network_receive(c: &mut Criterion) {
+    // Every peer has sent every other peer a message on each step up to the maximum delay.
+    let params = SimulationParams::default();
+    let peers: Vec<Name> = Prefix::empty()
+        .spread_names()
+        .take(NUM_PEERS as usize)
+        .collect();
+    let messages: Vec<Message> = peers
+        .iter()
+        .flat_map(|&sender| {
+            peers.iter().filter(move |&&recipient| recipient != sender).map(
+                move |&recipient| {
+                    Message {
+                        sender,
+                        recipient,
+                        content: Arc::new(Connect),
+                        trace_id: 0,
+                    }
+                },
+            )
+        })
+        .collect();
+
+    c.bench_function("network_receive", move |b| {
+        b.iter_batched(
+            || {
+                let mut network = Network::new(&params);
+                for step in 0..params.max_delay {
+                    network.send(step, messages.clone());
+                }
+                network
+            },
+            |mut network| network.receive(params.max_delay),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn vote_agreed_recipients(c: &mut Criterion) {
+    let mut blocks = Blocks::new();
+    let current_blocks = network(&mut blocks);
+
+    let from = current_blocks.iter().next().unwrap().into_block(&blocks).clone();
+    let our_name = *from.members.iter().next().unwrap();
+    let joining = from.prefix.spread_names().nth(SECTION_SIZE).unwrap();
+    let to = blocks.insert(from.add_node(joining));
+    let content = VoteAgreedMsg((
+        Vote {
+            from: from.get_id(),
+            to,
+        },
+        (*from.members).clone(),
+    ));
+
+    let policy = RecipientRule::default().policy();
+    c.bench_function("vote_agreed_recipients", move |b| {
+        b.iter(|| content.recipients(&blocks, &current_blocks, our_name, policy))
+    });
+}
+
+criterion_group!(
+    benches,
+    new_valid_blocks,
+    compute_current_candidate_blocks,
+    construct_proof,
+    network_receive,
+    vote_agreed_recipients
+);
+criterion_main!(benches);
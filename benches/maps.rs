@@ -0,0 +1,76 @@
+//! Compares the map types used for vote counts on the access pattern nodes produce: many
+//! insertions of votes between blocks, each followed by lookups of the votes from a block.
+//!
+//! Run with `cargo bench --bench maps`.
+
+#[macro_use]
+extern crate criterion;
+extern crate ewok;
+extern crate fxhash;
+
+use criterion::{black_box, Criterion};
+use ewok::block::{Block, BlockId, Vote};
+use ewok::name::Name;
+use fxhash::FxHashMap;
+use std::collections::{BTreeMap, BTreeSet};
+
+const NUM_BLOCKS: u64 = 2000;
+const VOTES_PER_BLOCK: u64 = 4;
+
+/// Votes between pseudo-random blocks, along with the voters for each.
+fn votes() -> Vec<(Vote, BTreeSet<Name>)> {
+    let ids: Vec<BlockId> = (0..NUM_BLOCKS)
+        .map(|i| Block::genesis(Name(i.wrapping_mul(0x9e37_79b9_7f4a_7c15).into())).get_id())
+        .collect();
+    (0..NUM_BLOCKS * VOTES_PER_BLOCK)
+        .map(|i| {
+            let from = ids[(i % NUM_BLOCKS) as usize];
+            let to = ids[(i.wrapping_mul(7919) % NUM_BLOCKS) as usize];
+            let voters = (0..8).map(|j| Name((i * 8 + j).into())).collect();
+            (Vote { from, to }, voters)
+        })
+        .collect()
+}
+
+fn btree_vote_counts(c: &mut Criterion) {
+    let votes = votes();
+    c.bench_function("btree_vote_counts", move |b| {
+        b.iter(|| {
+            let mut counts: BTreeMap<BlockId, BTreeMap<BlockId, BTreeSet<Name>>> =
+                BTreeMap::new();
+            for &(ref vote, ref voters) in &votes {
+                counts
+                    .entry(vote.from)
+                    .or_insert_with(BTreeMap::new)
+                    .entry(vote.to)
+                    .or_insert_with(BTreeSet::new)
+                    .extend(voters.iter().cloned());
+                black_box(counts.get(&vote.to).map(|map| map.len()));
+            }
+            counts
+        })
+    });
+}
+
+fn fx_hash_vote_counts(c: &mut Criterion) {
+    let votes = votes();
+    c.bench_function("fx_hash_vote_counts", move |b| {
+        b.iter(|| {
+            let mut counts: FxHashMap<BlockId, FxHashMap<BlockId, BTreeSet<Name>>> =
+                FxHashMap::default();
+            for &(ref vote, ref voters) in &votes {
+                counts
+                    .entry(vote.from)
+                    .or_insert_with(FxHashMap::default)
+                    .entry(vote.to)
+                    .or_insert_with(BTreeSet::new)
+                    .extend(voters.iter().cloned());
+                black_box(counts.get(&vote.to).map(|map| map.len()));
+            }
+            counts
+        })
+    });
+}
+
+criterion_group!(benches, btree_vote_counts, fx_hash_vote_counts);
+criterion_main!(benches);
@@ -0,0 +1,293 @@
+//! Running external membership implementations under the simulator.
+//!
+//! An implementation of section membership that isn't ewok's own `Node` can be tested against
+//! the same event schedules and consistency checks by implementing `MembershipNode`. Messages
+//! between adapted nodes are opaque bytes, so the implementation is free to use its own wire
+//! format, and the only thing the harness needs to know about its state is the set of sections
+//! each node currently believes in.
+
+use block::Block;
+use consistency::check_sections;
+pub use consistency::Inconsistent;
+use event::Event;
+use event_schedule::EventSchedule;
+use generate::generate_sections;
+use name::{Name, Prefix};
+use params::{NameDistribution, NodeParams};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+
+/// Messages to send, as pairs of recipient and encoded message.
+pub type Outgoing = Vec<(Name, Vec<u8>)>;
+
+/// A node of an external membership implementation.
+pub trait MembershipNode {
+    /// Handle a message from another node.
+    fn handle_message(&mut self, sender: Name, message: &[u8], step: u64) -> Outgoing;
+
+    /// Called on every existing node when a node joins the network.
+    fn node_joined(&mut self, name: Name, step: u64) -> Outgoing;
+
+    /// Called on every remaining node when a node leaves the network.
+    fn node_left(&mut self, name: Name, step: u64) -> Outgoing;
+
+    /// Called once per step after all messages have been handled, e.g. to handle timeouts.
+    fn update(&mut self, _step: u64) -> Outgoing {
+        vec![]
+    }
+
+    /// The sections that this node currently believes make up the network.
+    fn current_sections(&self) -> Vec<Block>;
+}
+
+/// Harness that runs a network of adapted nodes through an event schedule.
+///
+/// Messages sent in one step are delivered at the start of the next, and messages for nodes
+/// that have left are dropped. `new_node` creates nodes from their name and the genesis blocks,
+/// which are empty for nodes joining after the start.
+pub struct AdapterSimulation<N, F> {
+    nodes: BTreeMap<Name, N>,
+    new_node: F,
+    event_schedule: EventSchedule,
+    node_params: NodeParams,
+    /// Messages to deliver next step, as (sender, recipient, message).
+    in_flight: Vec<(Name, Name, Vec<u8>)>,
+    step: u64,
+}
+
+impl<N, F> AdapterSimulation<N, F>
+where
+    N: MembershipNode,
+    F: FnMut(Name, &BTreeSet<Block>) -> N,
+{
+    /// Create a network with sections of the given sizes.
+    pub fn new(
+        sections: &BTreeMap<Prefix, usize>,
+        event_schedule: EventSchedule,
+        node_params: NodeParams,
+        mut new_node: F,
+    ) -> Self {
+        let genesis = generate_sections(sections, NameDistribution::Random);
+        let nodes = genesis
+            .iter()
+            .flat_map(|block| block.members.iter().cloned())
+            .map(|name| (name, new_node(name, &genesis)))
+            .collect();
+        AdapterSimulation {
+            nodes,
+            new_node,
+            event_schedule,
+            node_params,
+            in_flight: vec![],
+            step: 0,
+        }
+    }
+
+    pub fn nodes(&self) -> &BTreeMap<Name, N> {
+        &self.nodes
+    }
+
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    /// Run until every event has happened and no messages have been sent for a timeout's worth
+    /// of steps, or until `max_steps`, then check the nodes' sections for consistency.
+    pub fn run(&mut self, max_steps: u64) -> Result<BTreeMap<Prefix, Block>, Inconsistent> {
+        let quiet_steps = self.node_params.max_timeout() + 1;
+        let mut quiet_for = 0;
+        while self.step < max_steps && quiet_for < quiet_steps {
+            let scheduled = !self.event_schedule.get_events(self.step).is_empty();
+            if self.run_step() || scheduled || self.event_schedule.next_step(self.step).is_some() {
+                quiet_for = 0;
+            } else {
+                quiet_for += 1;
+            }
+            self.step += 1;
+        }
+        if quiet_for < quiet_steps {
+            warn!("adapted network still active after {} steps", max_steps);
+        }
+        self.check_consistency()
+    }
+
+    /// Run the current step, returning true if any messages were sent.
+    fn run_step(&mut self) -> bool {
+        let step = self.step;
+        let mut outgoing = vec![];
+
+        for event in self.event_schedule.get_events(step) {
+            self.apply_event(event, &mut outgoing);
+        }
+
+        for (sender, recipient, message) in mem::take(&mut self.in_flight) {
+            if let Some(node) = self.nodes.get_mut(&recipient) {
+                let sent = node.handle_message(sender, &message, step);
+                outgoing.extend(sent.into_iter().map(|(to, msg)| (recipient, to, msg)));
+            } else {
+                trace!("dropping message from {} for departed node {}", sender, recipient);
+            }
+        }
+
+        for (&name, node) in &mut self.nodes {
+            outgoing.extend(node.update(step).into_iter().map(|(to, msg)| (name, to, msg)));
+        }
+
+        let sent = !outgoing.is_empty();
+        self.in_flight = outgoing;
+        sent
+    }
+
+    fn apply_event(&mut self, event: Event, outgoing: &mut Vec<(Name, Name, Vec<u8>)>) {
+        let step = self.step;
+        match event {
+            // Adapted nodes manage their own timeouts, so the give-up step isn't enforced, and
+            // their state is their own, so restarted nodes always start afresh.
+            Event::AddNode(joining) |
+            Event::AddNodeUntil(joining, _) |
+            Event::AddStaleNode(joining) |
+            Event::RestartNode(joining) => {
+                for (&name, node) in &mut self.nodes {
+                    let sent = node.node_joined(joining, step);
+                    outgoing.extend(sent.into_iter().map(|(to, msg)| (name, to, msg)));
+                }
+                let node = (self.new_node)(joining, &BTreeSet::new());
+                self.nodes.insert(joining, node);
+            }
+            Event::RemoveNode(leaving) | Event::CrashNode(leaving) => {
+                self.remove_node(leaving, outgoing)
+            }
+            // Clients aren't modelled, so data operations and clients don't involve the nodes.
+            Event::Put(_) | Event::Get(_) | Event::AddClient(_) | Event::RemoveClient(_) => (),
+            // Adapted nodes are always fully connected.
+            Event::Partition(_) | Event::HealPartition => {
+                warn!("partitions aren't supported for adapted nodes, ignoring {:?}", event)
+            }
+            // Adapted nodes always form a single network.
+            Event::IntroduceRoots => warn!("adapted nodes have no separate networks to introduce"),
+            Event::RemoveNodeFrom(prefix) => {
+                let leaving = self.nodes.keys().find(|&&name| prefix.matches(name)).cloned();
+                match leaving {
+                    Some(leaving) => self.remove_node(leaving, outgoing),
+                    None => warn!("no node to remove from {:?}", prefix),
+                }
+            }
+        }
+    }
+
+    fn remove_node(&mut self, leaving: Name, outgoing: &mut Vec<(Name, Name, Vec<u8>)>) {
+        if self.nodes.remove(&leaving).is_none() {
+            warn!("can't remove {}, it isn't in the network", leaving);
+            return;
+        }
+        let step = self.step;
+        for (&name, node) in &mut self.nodes {
+            let sent = node.node_left(leaving, step);
+            outgoing.extend(sent.into_iter().map(|(to, msg)| (name, to, msg)));
+        }
+    }
+
+    /// Check that all the nodes have a consistent view of the network.
+    pub fn check_consistency(&self) -> Result<BTreeMap<Prefix, Block>, Inconsistent> {
+        let current_blocks = self.nodes.values().flat_map(|node| node.current_sections());
+        check_sections(
+            current_blocks,
+            |name| self.nodes.contains_key(name),
+            &self.node_params,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use name::NameBits;
+    use std::sync::Arc;
+
+    const NAME_BYTES: usize = mem::size_of::<NameBits>();
+
+    /// Single-section membership where existing members send new nodes the member list.
+    struct Gossip {
+        members: BTreeSet<Name>,
+        forget_departures: bool,
+    }
+
+    impl Gossip {
+        fn new(genesis: &BTreeSet<Block>, forget_departures: bool) -> Self {
+            Gossip {
+                members: genesis.iter().flat_map(|b| b.members.iter().cloned()).collect(),
+                forget_departures,
+            }
+        }
+    }
+
+    impl MembershipNode for Gossip {
+        fn handle_message(&mut self, _: Name, message: &[u8], _: u64) -> Outgoing {
+            for chunk in message.chunks(NAME_BYTES) {
+                let mut buf = [0; NAME_BYTES];
+                buf.copy_from_slice(chunk);
+                self.members.insert(Name(NameBits::from_be_bytes(buf)));
+            }
+            vec![]
+        }
+
+        fn node_joined(&mut self, name: Name, _: u64) -> Outgoing {
+            self.members.insert(name);
+            let list = self.members.iter().flat_map(|m| m.0.to_be_bytes().to_vec()).collect();
+            vec![(name, list)]
+        }
+
+        fn node_left(&mut self, name: Name, _: u64) -> Outgoing {
+            if !self.forget_departures {
+                self.members.remove(&name);
+            }
+            vec![]
+        }
+
+        fn current_sections(&self) -> Vec<Block> {
+            vec![
+                Block {
+                    prefix: Prefix::empty(),
+                    members: Arc::new(self.members.clone()),
+                    version: self.members.len() as u64,
+                },
+            ]
+        }
+    }
+
+    fn schedule() -> EventSchedule {
+        EventSchedule::from_events(vec![
+            (2, Event::AddNode(Name(1))),
+            (2, Event::AddNode(Name(2))),
+            (5, Event::RemoveNodeFrom(Prefix::empty())),
+            (9, Event::AddNode(Name(3))),
+        ])
+    }
+
+    #[test]
+    fn consistent_gossip() {
+        let sections = btreemap!{ Prefix::empty() => 4 };
+        let mut simulation = AdapterSimulation::new(
+            &sections,
+            schedule(),
+            NodeParams::default(),
+            |_, genesis| Gossip::new(genesis, false),
+        );
+        let result = simulation.run(1000).unwrap();
+        assert_eq!(result[&Prefix::empty()].members.len(), 6);
+        assert!(simulation.step() < 1000);
+    }
+
+    #[test]
+    fn inconsistent_gossip() {
+        let sections = btreemap!{ Prefix::empty() => 4 };
+        let mut simulation = AdapterSimulation::new(
+            &sections,
+            schedule(),
+            NodeParams::default(),
+            |_, genesis| Gossip::new(genesis, true),
+        );
+        assert_eq!(simulation.run(1000), Err(Inconsistent));
+    }
+}
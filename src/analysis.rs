@@ -0,0 +1,294 @@
+//! Statistics for comparing batches of runs, e.g. with and without a change to the protocol.
+//!
+//! A batch is summarised by how many of its runs failed. Two batches are compared by the
+//! difference in their failure rates, with a confidence interval for it (Newcombe's method, from
+//! the Wilson score intervals of each rate), and a two-sided two-proportion z-test of whether the
+//! rates differ at all.
+
+use sweep::SweepResult;
+use trace::Record;
+
+use std::f64::consts::SQRT_2;
+use std::fmt;
+
+/// Outcomes of a batch of runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Batch {
+    pub runs: usize,
+    /// Number of runs that finished inconsistent, or panicked.
+    pub failures: usize,
+}
+
+impl Batch {
+    /// Batch made up of the reports in a trace, i.e. its `Record::Finished` records.
+    pub fn from_records<'a, I: IntoIterator<Item = &'a Record>>(records: I) -> Self {
+        let mut batch = Batch::default();
+        for record in records {
+            if let Record::Finished { consistent, .. } = *record {
+                batch.add(consistent);
+            }
+        }
+        batch
+    }
+
+    /// Add the outcome of a single run.
+    pub fn add(&mut self, succeeded: bool) {
+        self.runs += 1;
+        if !succeeded {
+            self.failures += 1;
+        }
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            self.failures as f64 / self.runs as f64
+        }
+    }
+
+    /// Wilson score interval for the failure rate, with `z` standard errors either side.
+    fn wilson_interval(&self, z: f64) -> (f64, f64) {
+        let n = self.runs as f64;
+        let p = self.failure_rate();
+        let denominator = 1.0 + z * z / n;
+        let centre = (p + z * z / (2.0 * n)) / denominator;
+        let half_width = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt() / denominator;
+        (centre - half_width, centre + half_width)
+    }
+}
+
+impl<'a> From<&'a SweepResult> for Batch {
+    fn from(result: &'a SweepResult) -> Self {
+        Batch {
+            runs: result.runs,
+            failures: result.failures,
+        }
+    }
+}
+
+/// Comparison of the failure rate of batch `b` against that of batch `a`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Comparison {
+    /// Failure rate of `b` minus that of `a`.
+    pub difference: f64,
+    /// Confidence interval for the difference.
+    pub interval: (f64, f64),
+    /// Confidence level of the interval, e.g. 0.95.
+    pub confidence: f64,
+    /// Test statistic of the two-proportion z-test.
+    pub z: f64,
+    /// Probability of a difference at least this large if the failure rates were the same.
+    pub p_value: f64,
+}
+
+impl Comparison {
+    /// Whether the difference is significant at the level of the confidence interval.
+    pub fn is_significant(&self) -> bool {
+        self.p_value < 1.0 - self.confidence
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "failure rate {:+.1}% ({:.0}% CI {:+.1}% to {:+.1}%), z = {:.2}, p = {:.4}",
+            100.0 * self.difference,
+            100.0 * self.confidence,
+            100.0 * self.interval.0,
+            100.0 * self.interval.1,
+            self.z,
+            self.p_value
+        )
+    }
+}
+
+/// Compare the failure rates of two batches, with a confidence interval at the given level.
+///
+/// Panics if either batch is empty, or the confidence level isn't strictly between 0 and 1.
+pub fn compare(a: &Batch, b: &Batch, confidence: f64) -> Comparison {
+    assert!(a.runs > 0 && b.runs > 0, "can't compare empty batches");
+    assert!(
+        confidence > 0.0 && confidence < 1.0,
+        "confidence level {} isn't between 0 and 1",
+        confidence
+    );
+    let (p_a, p_b) = (a.failure_rate(), b.failure_rate());
+    let difference = p_b - p_a;
+
+    let z_interval = normal_quantile(0.5 + confidence / 2.0);
+    let (l_a, u_a) = a.wilson_interval(z_interval);
+    let (l_b, u_b) = b.wilson_interval(z_interval);
+    let interval = (
+        difference - ((p_b - l_b).powi(2) + (u_a - p_a).powi(2)).sqrt(),
+        difference + ((u_b - p_b).powi(2) + (p_a - l_a).powi(2)).sqrt(),
+    );
+
+    let pooled = (a.failures + b.failures) as f64 / (a.runs + b.runs) as f64;
+    let std_err = (pooled * (1.0 - pooled) * (1.0 / a.runs as f64 + 1.0 / b.runs as f64)).sqrt();
+    // With no failures (or nothing but failures) in either batch, there's no difference to test.
+    let z = if std_err > 0.0 { difference / std_err } else { 0.0 };
+    let p_value = erfc(z.abs() / SQRT_2);
+
+    Comparison {
+        difference,
+        interval,
+        confidence,
+        z,
+        p_value,
+    }
+}
+
+/// Complementary error function, with a fractional error below 1.2e-7 (Numerical Recipes'
+/// `erfcc`).
+fn erfc(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 10] = [
+        -1.265_512_23,
+        1.000_023_68,
+        0.374_091_96,
+        0.096_784_18,
+        -0.186_288_06,
+        0.278_868_07,
+        -1.135_203_98,
+        1.488_515_87,
+        -0.822_152_23,
+        0.170_872_77,
+    ];
+    let t = 1.0 / (1.0 + 0.5 * x.abs());
+    let poly = COEFFICIENTS.iter().rev().fold(0.0, |acc, &c| acc * t + c);
+    let result = t * (-x * x + poly).exp();
+    if x >= 0.0 { result } else { 2.0 - result }
+}
+
+/// Quantile function of the standard normal distribution, with a relative error below 1.2e-9
+/// (Acklam's algorithm).
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) /
+            ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    let p_low = 0.024_25;
+    if p < p_low {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - p_low {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q /
+            (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} isn't close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn compare_failure_rates() {
+        assert_close(normal_quantile(0.975), 1.959_963_984_540_054);
+        assert_close(normal_quantile(0.005), -2.575_829_303_548_901);
+
+        let a = Batch {
+            runs: 100,
+            failures: 10,
+        };
+        let b = Batch {
+            runs: 100,
+            failures: 25,
+        };
+        let comparison = compare(&a, &b, 0.95);
+        assert_close(comparison.difference, 0.15);
+        assert_close(comparison.interval.0, 0.044_702_141_644);
+        assert_close(comparison.interval.1, 0.253_255_674_711);
+        assert_close(comparison.z, 2.791_452_631_195);
+        assert_close(comparison.p_value, 0.005_247_203_739);
+        assert!(comparison.is_significant());
+
+        // Rare failures: the interval still covers zero, so the change can't be told apart.
+        let none_failed = Batch {
+            runs: 50,
+            failures: 0,
+        };
+        let few_failed = Batch {
+            runs: 50,
+            failures: 3,
+        };
+        let comparison = compare(&none_failed, &few_failed, 0.95);
+        assert_close(comparison.interval.0, -0.021_496_383_127);
+        assert_close(comparison.interval.1, 0.162_170_916_888);
+        assert_close(comparison.p_value, 0.078_640_179_349);
+        assert!(!comparison.is_significant());
+
+        let comparison = compare(&none_failed, &none_failed, 0.95);
+        assert_eq!(comparison.z, 0.0);
+        assert_close(comparison.p_value, 1.0);
+    }
+
+    #[test]
+    fn batch_from_reports() {
+        let finished = |consistent| {
+            Record::Finished {
+                step: 100,
+                consistent,
+                sections: 1,
+                nodes: 8,
+                force_merges: 0,
+            }
+        };
+        let records = vec![
+            finished(true),
+            Record::Queue { step: 100, size: 0 },
+            finished(false),
+            finished(true),
+        ];
+        let batch = Batch::from_records(&records);
+        assert_eq!(
+            batch,
+            Batch {
+                runs: 3,
+                failures: 1,
+            }
+        );
+        assert_close(batch.failure_rate(), 1.0 / 3.0);
+    }
+}
@@ -0,0 +1,205 @@
+//! Recommended usage:
+//!
+//! EWOK_TRACE=trace_file ewok
+//! churn trace_file
+//!
+//! Every agreed vote is classified as an add, drop, split, merge or force-merge, and the changes
+//! are counted per prefix. Pass `--list` to also print each change with the step it was first
+//! agreed at.
+//!
+//! Merges are told apart from force-merges using the minimum section size recorded in the trace,
+//! which can be overridden with `--min-section-size`.
+
+#![cfg_attr(feature="cargo-clippy", allow(doc_markdown))]
+
+extern crate ewok;
+extern crate clap;
+
+use clap::{App, Arg};
+use ewok::block::{Block, BlockId};
+use ewok::name::Prefix;
+use ewok::params::NodeParams;
+use ewok::trace::{Record, TraceReader};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Change {
+    Add,
+    Drop,
+    Split,
+    Merge,
+    ForceMerge,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Change::Add => "add",
+            Change::Drop => "drop",
+            Change::Split => "split",
+            Change::Merge => "merge",
+            Change::ForceMerge => "force-merge",
+        };
+        f.pad(name)
+    }
+}
+
+const ALL_CHANGES: [Change; 5] = [
+    Change::Add,
+    Change::Drop,
+    Change::Split,
+    Change::Merge,
+    Change::ForceMerge,
+];
+
+/// Work out which kind of change a vote from `from` to `to` makes.
+///
+/// A merge is taken to be forced if neither of the merging sections was below the minimum
+/// section size, in which case it can only have been triggered by one of them losing quorum.
+fn classify(from: &Block, to: &Block, min_section_size: usize) -> Option<Change> {
+    if from.prefix == to.prefix {
+        if to.members.len() > from.members.len() {
+            Some(Change::Add)
+        } else {
+            Some(Change::Drop)
+        }
+    } else if to.prefix.popped() == from.prefix {
+        Some(Change::Split)
+    } else if from.prefix.popped() == to.prefix {
+        let sibling_size = to.members.len() - from.members.len();
+        if from.members.len() < min_section_size || sibling_size < min_section_size {
+            Some(Change::Merge)
+        } else {
+            Some(Change::ForceMerge)
+        }
+    } else {
+        None
+    }
+}
+
+/// Counts of each kind of change for a single prefix.
+#[derive(Default)]
+struct PrefixChurn {
+    counts: BTreeMap<Change, usize>,
+    first_step: Option<u64>,
+    last_step: u64,
+}
+
+impl PrefixChurn {
+    fn record(&mut self, change: Change, step: u64) {
+        *self.counts.entry(change).or_insert(0) += 1;
+        self.first_step = Some(self.first_step.map_or(step, |first| first.min(step)));
+        self.last_step = self.last_step.max(step);
+    }
+
+    fn count(&self, change: Change) -> usize {
+        self.counts.get(&change).cloned().unwrap_or(0)
+    }
+}
+
+fn main() {
+    let matches = App::new("ewok_churn")
+        .about("This tool takes a trace file from an Ewok simulation and reports the number of \
+                adds, drops, splits, merges and force-merges agreed for each prefix, along with \
+                the first and last steps at which they happened.")
+        .arg(Arg::with_name("list")
+                 .short("l")
+                 .long("list")
+                 .takes_value(false)
+                 .help("Also list every change in the order it was agreed"))
+        .arg(Arg::with_name("min_section_size")
+                 .long("min-section-size")
+                 .value_name("SIZE")
+                 .help("Minimum section size the run used, for telling merges from \
+                        force-merges (defaults to the one recorded in the trace)"))
+        .arg(Arg::with_name("INPUT")
+                 .help("Sets the trace file to use")
+                 .required(true)
+                 .index(1))
+        .get_matches();
+    let min_section_size_arg = matches.value_of("min_section_size").map(|s| {
+        s.parse().expect("invalid minimum section size")
+    });
+    let mut min_section_size =
+        min_section_size_arg.unwrap_or(NodeParams::default().min_section_size);
+
+    let mut blocks: BTreeMap<BlockId, Block> = BTreeMap::new();
+    // Every node records each agreement it sees, so only count the first.
+    let mut seen = BTreeSet::new();
+    let mut changes = vec![];
+
+    for record in TraceReader::open(matches.value_of("INPUT").unwrap()).unwrap() {
+        match record {
+            Record::Params { node_params, .. } => {
+                if min_section_size_arg.is_none() {
+                    min_section_size = node_params.min_section_size;
+                }
+            }
+            Record::Block { id, block } => {
+                blocks.insert(id, block);
+            }
+            Record::Agreement { step, from, to, .. } => {
+                let (from, to) = (&blocks[&from], &blocks[&to]);
+                let change = match classify(from, to, min_section_size) {
+                    Some(change) => change,
+                    None => continue,
+                };
+                // A split has one vote per child and a merge one vote per parent, but each
+                // is a single change.
+                let key = match change {
+                    Change::Split => (from.get_id(), None),
+                    Change::Merge | Change::ForceMerge => (to.get_id(), None),
+                    _ => (from.get_id(), Some(to.get_id())),
+                };
+                if seen.insert(key) {
+                    let prefix = match change {
+                        Change::Merge | Change::ForceMerge => to.prefix,
+                        _ => from.prefix,
+                    };
+                    changes.push((step, change, prefix, from.clone(), to.clone()));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut churn: BTreeMap<Prefix, PrefixChurn> = BTreeMap::new();
+    for &(step, change, prefix, _, _) in &changes {
+        churn.entry(prefix).or_insert_with(PrefixChurn::default).record(change, step);
+    }
+
+    print!("{:<20}", "Prefix");
+    for change in &ALL_CHANGES {
+        print!("{:>12}", change);
+    }
+    println!("{:>12}{:>12}", "first step", "last step");
+    for (prefix, prefix_churn) in &churn {
+        print!("{:<20}", format!("({})", prefix));
+        for &change in &ALL_CHANGES {
+            print!("{:>12}", prefix_churn.count(change));
+        }
+        println!("{:>12}{:>12}",
+                 prefix_churn.first_step.unwrap_or(0),
+                 prefix_churn.last_step);
+    }
+    print!("{:<20}", "Total");
+    for &change in &ALL_CHANGES {
+        print!("{:>12}",
+               churn.values().map(|c| c.count(change)).sum::<usize>());
+    }
+    println!();
+
+    if matches.is_present("list") {
+        println!();
+        println!("{:>8}  {:<12}{:<20}{:<20}{:>8}", "Step", "Change", "From", "To", "Members");
+        for (step, change, _, from, to) in changes {
+            println!("{:>8}  {:<12}{:<20}{:<20}{:>8}",
+                     step,
+                     change,
+                     format!("({}) v{}", from.prefix, from.version),
+                     format!("({}) v{}", to.prefix, to.version),
+                     to.members.len());
+        }
+    }
+}
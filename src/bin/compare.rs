@@ -0,0 +1,141 @@
+//! Recommended usage:
+//!
+//! EWOK_TRACE=before.json ewok
+//! (change the protocol)
+//! EWOK_TRACE=after.json ewok
+//! compare before.json after.json
+//!
+//! Running both simulations with the same `EWOK_SEED` makes the final membership comparable
+//! node by node, for as long as the change doesn't alter the sequence of random events.
+
+#![cfg_attr(feature="cargo-clippy", allow(doc_markdown))]
+
+extern crate ewok;
+extern crate clap;
+
+mod utils;
+
+use clap::{App, Arg};
+use ewok::message::MessageKind;
+use ewok::name::{Name, Prefix};
+use ewok::trace::TraceReader;
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use utils::summary::RunSummary;
+
+fn main() {
+    let matches = App::new("ewok_compare")
+        .about("This tool takes the trace files from two Ewok simulations and reports the \
+                differences between them in section evolution, message volume and final \
+                membership.")
+        .arg(Arg::with_name("A")
+                 .help("Trace file for the first run")
+                 .required(true)
+                 .index(1))
+        .arg(Arg::with_name("B")
+                 .help("Trace file for the second run")
+                 .required(true)
+                 .index(2))
+        .get_matches();
+
+    println!("Reading traces...");
+    let a = RunSummary::from_trace(TraceReader::open(matches.value_of("A").unwrap()).unwrap());
+    let b = RunSummary::from_trace(TraceReader::open(matches.value_of("B").unwrap()).unwrap());
+
+    print_totals(&a, &b);
+    print_section_evolution(&a, &b);
+    print_membership(&a, &b);
+}
+
+fn row<T: Display>(label: &str, a: T, b: T, diff: i64) {
+    println!("{:<24}{:>12}{:>12}{:>12}", label, a, b, format!("{:+}", diff));
+}
+
+fn count_row(label: &str, a: usize, b: usize) {
+    row(label, a, b, b as i64 - a as i64);
+}
+
+fn print_totals(a: &RunSummary, b: &RunSummary) {
+    println!();
+    println!("{:<24}{:>12}{:>12}{:>12}", "", "A", "B", "B - A");
+    row("Steps", a.steps, b.steps, b.steps as i64 - a.steps as i64);
+    count_row("Final nodes", a.final_nodes, b.final_nodes);
+    count_row("Max nodes", a.max_nodes, b.max_nodes);
+    count_row("Final sections", a.sections.blocks.len(), b.sections.blocks.len());
+    row("Last section change",
+        a.last_section_change,
+        b.last_section_change,
+        b.last_section_change as i64 - a.last_section_change as i64);
+    count_row("Peak queue size", a.peak_queue, b.peak_queue);
+    count_row("Messages sent", a.total_sent, b.total_sent);
+    count_row("Bytes sent", a.total_bytes, b.total_bytes);
+    for &kind in MessageKind::all() {
+        count_row(&format!("  {}", kind), a.sent_of_kind(kind), b.sent_of_kind(kind));
+    }
+}
+
+fn print_section_evolution(a: &RunSummary, b: &RunSummary) {
+    let prefixes: BTreeSet<Prefix> = a.prefix_first_seen
+        .keys()
+        .chain(b.prefix_first_seen.keys())
+        .cloned()
+        .collect();
+    let first_seen = |summary: &RunSummary, prefix| {
+        summary
+            .prefix_first_seen
+            .get(prefix)
+            .map_or("-".to_string(), |step| step.to_string())
+    };
+
+    println!();
+    println!("Step at which each prefix was first agreed:");
+    println!("{:<24}{:>12}{:>12}", "Prefix", "A", "B");
+    for prefix in &prefixes {
+        let (in_a, in_b) = (first_seen(a, prefix), first_seen(b, prefix));
+        if in_a != in_b {
+            println!("{:<24}{:>12}{:>12}", format!("({})", prefix), in_a, in_b);
+        }
+    }
+}
+
+fn print_membership(a: &RunSummary, b: &RunSummary) {
+    let prefixes: BTreeSet<Prefix> = a.sections
+        .blocks
+        .keys()
+        .chain(b.sections.blocks.keys())
+        .cloned()
+        .collect();
+    let members = |summary: &RunSummary, prefix| {
+        summary
+            .sections
+            .blocks
+            .get(prefix)
+            .map_or_else(BTreeSet::new, |block| (*block.members).clone())
+    };
+
+    println!();
+    println!("Final membership:");
+    println!("{:<24}{:>12}{:>12}{:>12}", "Prefix", "A", "B", "Common");
+    for prefix in &prefixes {
+        let (in_a, in_b) = (members(a, prefix), members(b, prefix));
+        println!("{:<24}{:>12}{:>12}{:>12}",
+                 format!("({})", prefix),
+                 in_a.len(),
+                 in_b.len(),
+                 in_a.intersection(&in_b).count());
+    }
+
+    let all_a: BTreeSet<Name> = a.sections
+        .blocks
+        .values()
+        .flat_map(|block| block.members.iter().cloned())
+        .collect();
+    let all_b: BTreeSet<Name> = b.sections
+        .blocks
+        .values()
+        .flat_map(|block| block.members.iter().cloned())
+        .collect();
+    println!();
+    println!("Nodes only in A: {:?}", all_a.difference(&all_b).collect::<Vec<_>>());
+    println!("Nodes only in B: {:?}", all_b.difference(&all_a).collect::<Vec<_>>());
+}
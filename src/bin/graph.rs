@@ -1,71 +1,241 @@
 //! Recommended usage:
 //!
-//! graph ewok_log_file -o output_file
+//! EWOK_TRACE=trace_file ewok
+//! graph trace_file -o output_file
 //! dot -Tsvg -O output_file
 //!
 //! (The resulting images are large, so the SVG format is recommended for
 //! quality-conserving zooming.)
 //! The 'dot' utility can be found in the 'graphviz' package.
+//!
+//! With `--format graphml` the graph is written as GraphML instead, which can be opened in
+//! Gephi or Cytoscape to explore large graphs interactively.
+//!
+//! With `--frames N`, one file is written per N steps instead, showing the sections that were
+//! current at that step. These can be rendered and stitched into an animation, e.g.:
+//!
+//! graph trace_file -o frame.dot --frames 10
+//! for f in frame_*.dot; do dot -Tpng -O $f; done
+//! ffmpeg -pattern_type glob -i 'frame_*.dot.png' sections.mp4
 
 #![cfg_attr(feature="cargo-clippy", allow(doc_markdown))]
 
-extern crate regex;
+extern crate ewok;
 extern crate clap;
-#[macro_use]
-extern crate lazy_static;
 
 mod utils;
 
 use clap::{App, Arg};
+use ewok::block::{Block, BlockId};
+use ewok::name::Prefix;
+use ewok::trace::{Record, TraceReader};
 use std::collections::{BTreeSet, BTreeMap};
 use std::fs::File;
 use std::io::{Write, BufWriter};
-use utils::log_parse::{LogData, LogIterator};
+use std::path::Path;
+use utils::chain::{block_label, SectionMap};
 
 fn main() {
     let matches = App::new("ewok_graph")
-        .about("This tool takes a log output from an Ewok simulation and generates a file \
-               describing a graph of blocks in the DOT language. The resulting file can then be \
-               converted into a graphics file using the 'dot' utility from the 'graphviz' toolset.")
+        .about("This tool takes a trace file from an Ewok simulation (written to the path in \
+               EWOK_TRACE) and generates a file describing a graph of blocks in the DOT \
+               language. The resulting file can then be converted into a graphics file using \
+               the 'dot' utility from the 'graphviz' toolset.")
         .arg(Arg::with_name("output")
                  .short("o")
                  .long("output")
                  .value_name("FILE")
                  .help("The name for the output file."))
+        .arg(Arg::with_name("format")
+                 .long("format")
+                 .value_name("FORMAT")
+                 .possible_values(&["dot", "graphml"])
+                 .help("Format of the output file (default dot)."))
+        .arg(Arg::with_name("frames")
+                 .short("f")
+                 .long("frames")
+                 .value_name("STEPS")
+                 .help("Write one file every STEPS steps showing the current sections, named \
+                        after the output file with the step number appended."))
         .arg(Arg::with_name("INPUT")
-                 .help("Sets the input file to use")
+                 .help("Sets the trace file to use")
                  .required(true)
                  .index(1))
         .get_matches();
     let input = matches.value_of("INPUT").unwrap();
     let output = matches.value_of("output").unwrap_or("output.dot");
+    let frames = matches
+        .value_of("frames")
+        .map(|n| n.parse::<u64>().expect("invalid number of steps per frame"));
+
+    let graphml = matches.value_of("format") == Some("graphml");
+
+    let trace = TraceReader::open(input).unwrap();
+
+    println!("Reading trace...");
+    match frames {
+        Some(_) if graphml => panic!("frames can only be written in the dot format"),
+        Some(0) => panic!("number of steps per frame must be positive"),
+        Some(steps_per_frame) => write_frames(trace, output, steps_per_frame),
+        None => {
+            let (blocks, votes) = read_graph(trace);
+            if graphml {
+                write_graphml(output, blocks, votes);
+            } else {
+                write_graph(output, blocks, votes);
+            }
+        }
+    }
+}
+
+/// Read all of the blocks and agreed votes between them from a trace.
+fn read_graph(trace: TraceReader) -> (BTreeMap<BlockId, Block>, BTreeSet<(BlockId, BlockId)>) {
     let mut blocks = BTreeMap::new();
     let mut votes = BTreeSet::new();
 
-    let file = File::open(input).unwrap();
-    let log_iter = LogIterator::new(file);
-
-    println!("Reading log...");
-    for data in log_iter {
-        if let LogData::VoteAgreement(vote, block_from, block_to) = data {
-            blocks.insert(block_from.get_id(), block_from);
-            blocks.insert(block_to.get_id(), block_to);
-            votes.insert(vote);
+    for record in trace {
+        match record {
+            Record::Block { id, block } => {
+                blocks.insert(id, block);
+            }
+            Record::Agreement { from, to, .. } => {
+                votes.insert((from, to));
+            }
+            _ => (),
         }
     }
+    (blocks, votes)
+}
+
+/// Write the whole graph of agreed blocks to a single file, with a cluster for each prefix.
+fn write_graph(output: &str,
+               blocks: BTreeMap<BlockId, Block>,
+               votes: BTreeSet<(BlockId, BlockId)>) {
+
+    // Group the blocks into one cluster per prefix, ordered by version within each.
+    let mut clusters: BTreeMap<Prefix, Vec<(u64, BlockId, Block)>> = BTreeMap::new();
+    for (id, block) in blocks {
+        clusters
+            .entry(block.prefix)
+            .or_insert_with(Vec::new)
+            .push((block.version, id, block));
+    }
 
     println!("Reading finished. Outputting the dot file...");
     let file = File::create(output).unwrap();
     let mut writer = BufWriter::new(file);
     let _ = write!(writer, "digraph {{\n");
-    for (b, block) in blocks {
+    for (prefix, mut cluster) in clusters {
+        cluster.sort_by_key(|&(version, id, _)| (version, id));
+        let _ = write!(writer, "subgraph \"cluster_{}\" {{\n", prefix);
+        let _ = write!(writer, "label = \"({})\";\n", prefix);
+        for (_, id, block) in cluster {
+            let _ = write!(writer,
+                           "\"{}\" [label = {}; shape=box];\n",
+                           id,
+                           block_label(&block));
+        }
+        let _ = write!(writer, "}}\n");
+    }
+    for (from, to) in votes {
+        let _ = write!(writer, "\"{}\"->\"{}\"\n", from, to);
+    }
+    let _ = write!(writer, "}}\n");
+}
+
+/// Write the graph of agreed blocks in the GraphML format.
+fn write_graphml(output: &str,
+                 blocks: BTreeMap<BlockId, Block>,
+                 votes: BTreeSet<(BlockId, BlockId)>) {
+    println!("Reading finished. Outputting the GraphML file...");
+    let file = File::create(output).unwrap();
+    let mut writer = BufWriter::new(file);
+    let _ = write!(writer,
+                   "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                    <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n\
+                    <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n\
+                    <key id=\"prefix\" for=\"node\" attr.name=\"prefix\" attr.type=\"string\"/>\n\
+                    <key id=\"version\" for=\"node\" attr.name=\"version\" attr.type=\"long\"/>\n\
+                    <key id=\"size\" for=\"node\" attr.name=\"size\" attr.type=\"int\"/>\n\
+                    <key id=\"members\" for=\"node\" attr.name=\"members\" \
+                    attr.type=\"string\"/>\n\
+                    <graph id=\"blocks\" edgedefault=\"directed\">\n");
+    for (id, block) in blocks {
+        let members: Vec<String> = block
+            .members
+            .iter()
+            .map(|name| format!("{:016x}", name.0))
+            .collect();
         let _ = write!(writer,
-                       "{} [label = {}; shape=box];\n",
-                       b,
-                       block.get_label());
+                       "<node id=\"{}\">\
+                        <data key=\"label\">({}) v{}</data>\
+                        <data key=\"prefix\">{}</data>\
+                        <data key=\"version\">{}</data>\
+                        <data key=\"size\">{}</data>\
+                        <data key=\"members\">{}</data>\
+                        </node>\n",
+                       id,
+                       block.prefix,
+                       block.version,
+                       block.prefix,
+                       block.version,
+                       block.members.len(),
+                       members.join(" "));
+    }
+    for (from, to) in votes {
+        let _ = write!(writer, "<edge source=\"{}\" target=\"{}\"/>\n", from, to);
+    }
+    let _ = write!(writer, "</graph>\n</graphml>\n");
+}
+
+/// Write a file for every `steps_per_frame` steps, showing the sections current at that step.
+fn write_frames(trace: TraceReader, output: &str, steps_per_frame: u64) {
+    let mut blocks: BTreeMap<BlockId, Block> = BTreeMap::new();
+    let mut sections = SectionMap::default();
+    let mut num_frames = 0;
+
+    for record in trace {
+        match record {
+            Record::Block { id, block } => {
+                blocks.insert(id, block);
+            }
+            Record::Agreement { from, to, .. } => {
+                sections.insert(&blocks[&from]);
+                sections.insert(&blocks[&to]);
+            }
+            Record::Queue { step, .. } if step % steps_per_frame == 0 => {
+                write_frame(&frame_path(output, step), step, &sections);
+                num_frames += 1;
+            }
+            _ => (),
+        }
     }
-    for vote in votes {
-        let _ = write!(writer, "{}->{}\n", vote.from, vote.to);
+
+    println!("Reading finished. Wrote {} frames.", num_frames);
+}
+
+/// Name of the frame for `step`, e.g. `frame_000120.dot` for an output of `frame.dot`.
+fn frame_path(output: &str, step: u64) -> String {
+    let path = Path::new(output);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+    let file_name = match path.extension().and_then(|s| s.to_str()) {
+        Some(ext) => format!("{}_{:06}.{}", stem, step, ext),
+        None => format!("{}_{:06}", stem, step),
+    };
+    path.with_file_name(file_name).to_string_lossy().into_owned()
+}
+
+fn write_frame(path: &str, step: u64, sections: &SectionMap) {
+    let file = File::create(path).unwrap();
+    let mut writer = BufWriter::new(file);
+    let _ = write!(writer, "digraph {{\n");
+    let _ = write!(writer, "label = \"step {}\";\n", step);
+    let _ = write!(writer, "rankdir = LR;\n");
+    for block in sections.blocks.values() {
+        let _ = write!(writer,
+                       "\"{}\" [label = {}; shape=box];\n",
+                       block.get_id(),
+                       block_label(block));
     }
     let _ = write!(writer, "}}\n");
 }
@@ -1,24 +1,43 @@
 #![cfg_attr(feature="cargo-clippy", allow(doc_markdown))]
 
-extern crate regex;
+extern crate ewok;
 extern crate clap;
-#[macro_use]
-extern crate lazy_static;
-
-mod utils;
+#[cfg(feature = "plotters")]
+extern crate plotters;
 
 use clap::{App, Arg};
-use std::collections::{BTreeSet, BTreeMap};
+use ewok::message::MessageKind;
+use ewok::name::Name;
+use ewok::trace::{Record, TraceReader};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{Write, BufWriter};
 use std::mem;
-use utils::log_parse::{LogData, LogIterator};
 use std::process::Command;
 
 struct StepData {
-    pub msgs_sent: BTreeMap<String, u64>,
-    pub msgs_queue: u64,
-    pub network_size: u64,
+    pub msgs_sent: BTreeMap<Name, usize>,
+    pub msgs_by_kind: BTreeMap<MessageKind, usize>,
+    pub msgs_queue: usize,
+    pub network_size: usize,
+}
+
+impl StepData {
+    fn total_sent(&self) -> usize {
+        self.msgs_sent.values().sum()
+    }
+
+    fn avg_sent(&self) -> f64 {
+        self.total_sent() as f64 / self.network_size as f64
+    }
+
+    fn max_sent(&self) -> usize {
+        self.msgs_sent.values().cloned().max().unwrap_or(0)
+    }
+
+    fn sent_of_kind(&self, kind: MessageKind) -> usize {
+        self.msgs_by_kind.get(&kind).cloned().unwrap_or(0)
+    }
 }
 
 fn gnuplot_command(input: &str,
@@ -27,7 +46,8 @@ fn gnuplot_command(input: &str,
                    queue_size: bool,
                    total_sent: bool,
                    avg_sent: bool,
-                   max_sent: bool)
+                   max_sent: bool,
+                   by_kind: bool)
                    -> String {
     let mut column_counter = 2;
     let mut params = vec![];
@@ -60,6 +80,13 @@ fn gnuplot_command(input: &str,
         params.push(format!("'{}' u 1:{} title 'Max messages sent' lt rgb '#222222'",
                             input,
                             column_counter));
+        column_counter += 1;
+    }
+    if by_kind {
+        for kind in MessageKind::all() {
+            params.push(format!("'{}' u 1:{} title '{} messages'", input, column_counter, kind));
+            column_counter += 1;
+        }
     }
 
     format!("set terminal png size 1920,1080; set output '{}';\
@@ -68,15 +95,114 @@ fn gnuplot_command(input: &str,
             params.join(","))
 }
 
+/// A plotted line: title, colour and the value it takes at each step.
+#[cfg(feature = "plotters")]
+type Series = (String, plotters::style::RGBColor, Box<dyn Fn(&StepData) -> f64>);
+
+/// Render the selected columns to `output` without any external tools.
+#[cfg(feature = "plotters")]
+fn plot_native(output: &str,
+               result: &[StepData],
+               network_size: bool,
+               queue_size: bool,
+               total_sent: bool,
+               avg_sent: bool,
+               max_sent: bool,
+               by_kind: bool) {
+    use plotters::prelude::*;
+
+    let mut series: Vec<Series> = vec![];
+    if network_size {
+        series.push(("Nodes".to_string(), RGBColor(0x80, 0x00, 0xFF), Box::new(|d| d.network_size as f64)));
+    }
+    if queue_size {
+        series.push(("Queue size / 100".to_string(),
+                     RGBColor(0xFF, 0x00, 0x00),
+                     Box::new(|d| d.msgs_queue as f64 / 100.0)));
+    }
+    if total_sent {
+        series.push(("Messages sent / 100".to_string(),
+                     RGBColor(0x00, 0xFF, 0x00),
+                     Box::new(|d| d.total_sent() as f64 / 100.0)));
+    }
+    if avg_sent {
+        series.push(("Avg messages sent".to_string(),
+                     RGBColor(0x00, 0x00, 0x80),
+                     Box::new(StepData::avg_sent)));
+    }
+    if max_sent {
+        series.push(("Max messages sent".to_string(),
+                     RGBColor(0x22, 0x22, 0x22),
+                     Box::new(|d| d.max_sent() as f64)));
+    }
+    if by_kind {
+        for (i, &kind) in MessageKind::all().iter().enumerate() {
+            let (r, g, b) = Palette99::pick(i).to_rgba().rgb();
+            series.push((format!("{} messages", kind),
+                         RGBColor(r, g, b),
+                         Box::new(move |d| d.sent_of_kind(kind) as f64)));
+        }
+    }
+
+    let size = (1920, 1080);
+    let rendered = if output.ends_with(".svg") {
+        draw_chart(SVGBackend::new(output, size).into_drawing_area(), result, series)
+    } else {
+        draw_chart(BitMapBackend::new(output, size).into_drawing_area(), result, series)
+    };
+    rendered.expect("failed to render plot");
+}
+
+#[cfg(feature = "plotters")]
+fn draw_chart<DB>(root: plotters::drawing::DrawingArea<DB, plotters::coord::Shift>,
+                  result: &[StepData],
+                  series: Vec<Series>)
+                  -> Result<(), Box<dyn std::error::Error>>
+    where DB: plotters::prelude::DrawingBackend,
+          DB::ErrorType: 'static
+{
+    use plotters::prelude::*;
+
+    let max_y = series
+        .iter()
+        .flat_map(|&(_, _, ref f)| result.iter().map(move |d| f(d)))
+        .fold(1.0, f64::max);
+
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0..result.len(), 0.0..max_y * 1.05)?;
+    chart.configure_mesh().x_desc("Step").draw()?;
+    for (title, colour, f) in series {
+        chart
+            .draw_series(LineSeries::new(result.iter().map(|d| f(d)).enumerate(), &colour))?
+            .label(title)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &colour));
+    }
+    chart
+        .configure_series_labels()
+        .background_style(&WHITE)
+        .border_style(&BLACK)
+        .draw()?;
+    root.present()?;
+    Ok(())
+}
+
 fn main() {
     let matches = App::new("ewok_graph_msgs")
-        .about("This tool takes a log output from an Ewok simulation and generates a file \
+        .about("This tool takes a trace file from an Ewok simulation and generates a file \
             containing a description of evolution of the chain and the message queue. This \
             file can then be used to create graphs of the number of messages sent, messages \
             in queue and valid blocks for prefix for the latest version over time.\n\n\
             Output file row format:\n\n\
             step_number [network_size] [queue_size] [total_messages_sent] [avg_messages_sent] \
-            [max_messages_sent_per_node]")
+            [max_messages_sent_per_node] [messages_sent_of_each_kind...]\n\n\
+            Messages are broken down by kind in the order: Vote, VoteAgreed, VoteBundle, \
+            RequestProof, NoProof, NodeJoined, Bootstrap, Connect, Disconnect, \
+            ResourceProofChallenge, ResourceProofResponse, ApproveCandidate, Relocate, \
+            NodeRelocated, ClientConnect, BlockUpdate, VoteAggregated.")
         .arg(Arg::with_name("output")
                  .short("o")
                  .long("output")
@@ -107,13 +233,25 @@ fn main() {
                  .long("max-sent")
                  .takes_value(false)
                  .help("Include the maximum number of messages sent per node in the output"))
+        .arg(Arg::with_name("include_by_kind")
+                 .short("k")
+                 .long("by-kind")
+                 .takes_value(false)
+                 .help("Include the number of messages of each kind sent in the output"))
         .arg(Arg::with_name("plot")
                  .short("p")
                  .long("plot")
                  .value_name("PLOT")
-                 .help("Plot the graph using gnuplot to the given file"))
+                 .help("Plot the graph to the given file. Uses native rendering (PNG, or SVG \
+                        if the file name ends in .svg) when built with the `plot` feature, \
+                        otherwise gnuplot."))
+        .arg(Arg::with_name("gnuplot")
+                 .short("g")
+                 .long("gnuplot")
+                 .takes_value(false)
+                 .help("Plot using gnuplot even if native rendering is available"))
         .arg(Arg::with_name("INPUT")
-                 .help("Sets the input file to use")
+                 .help("Sets the trace file to use")
                  .required(true)
                  .index(1))
         .get_matches();
@@ -124,31 +262,34 @@ fn main() {
     let total_sent = matches.is_present("include_total_sent");
     let avg_sent = matches.is_present("include_avg_sent");
     let max_sent = matches.is_present("include_max_sent");
+    let by_kind = matches.is_present("include_by_kind");
     let plot = matches.value_of("plot");
+    let use_gnuplot = matches.is_present("gnuplot") || cfg!(not(feature = "plotters"));
     let mut sent_msgs = BTreeMap::new();
-    let mut node_names = BTreeSet::new();
+    let mut sent_by_kind = BTreeMap::new();
+    let mut num_nodes = 0;
     let mut result = Vec::new();
-    let mut msgs_in_queue = 0;
-
-    let file = File::open(input).unwrap();
-    let log_iter = LogIterator::new(file);
-
-    println!("Reading log...");
-    for data in log_iter {
-        match data {
-            LogData::SentMsgs(name, sent) => {
-                node_names.insert(name.clone());
-                let count = sent_msgs.entry(name).or_insert(0);
-                *count += sent;
+
+    let trace = TraceReader::open(input).unwrap();
+
+    println!("Reading trace...");
+    for record in trace {
+        match record {
+            Record::Step { nodes, .. } => {
+                num_nodes = nodes;
+            }
+            Record::Sent { node, count, .. } => {
+                *sent_msgs.entry(node).or_insert(0) += count;
             }
-            LogData::MsgsInQueue(count) => {
-                msgs_in_queue = count;
+            Record::SentByKind { message_kind, count, .. } => {
+                *sent_by_kind.entry(message_kind).or_insert(0) += count;
             }
-            LogData::Step(s, n) if s > 0 => {
+            Record::Queue { size, .. } => {
                 let data = StepData {
                     msgs_sent: mem::replace(&mut sent_msgs, BTreeMap::new()),
-                    msgs_queue: msgs_in_queue,
-                    network_size: n,
+                    msgs_by_kind: mem::replace(&mut sent_by_kind, BTreeMap::new()),
+                    msgs_queue: size,
+                    network_size: num_nodes,
                 };
                 result.push(data);
             }
@@ -160,7 +301,7 @@ fn main() {
     let file = File::create(&output).unwrap();
     let mut writer = BufWriter::new(file);
 
-    for (i, data) in result.into_iter().enumerate() {
+    for (i, data) in result.iter().enumerate() {
         write!(writer, "{}", i).unwrap();
         if network_size {
             write!(writer, "\t{}", data.network_size).unwrap();
@@ -169,44 +310,48 @@ fn main() {
             write!(writer, "\t{}", data.msgs_queue).unwrap();
         }
         if total_sent {
-            write!(writer,
-                   "\t{}",
-                   data.msgs_sent.iter().map(|(_, n)| *n).sum::<u64>())
-                    .unwrap();
+            write!(writer, "\t{}", data.total_sent()).unwrap();
         }
         if avg_sent {
-            write!(writer,
-                   "\t{}",
-                   data.msgs_sent.iter().map(|(_, n)| *n).sum::<u64>() as f64 /
-                   data.network_size as f64)
-                    .unwrap();
+            write!(writer, "\t{}", data.avg_sent()).unwrap();
         }
         if max_sent {
-            write!(writer,
-                   "\t{}",
-                   data.msgs_sent
-                       .iter()
-                       .map(|(_, n)| *n)
-                       .max()
-                       .unwrap_or(0))
-                    .unwrap();
+            write!(writer, "\t{}", data.max_sent()).unwrap();
+        }
+        if by_kind {
+            for &kind in MessageKind::all() {
+                write!(writer, "\t{}", data.sent_of_kind(kind)).unwrap();
+            }
         }
         write!(writer, "\n").unwrap();
     }
 
     if let Some(plot_output) = plot {
-        let command = gnuplot_command(&output,
-                                      &plot_output,
-                                      network_size,
-                                      queue_size,
-                                      total_sent,
-                                      avg_sent,
-                                      max_sent);
-        let mut child = Command::new("gnuplot")
-            .args(&["-e", &command])
-            .spawn()
-            .expect("failed to execute gnuplot");
-        let _ = child.wait().expect("failed to wait on child");
+        if use_gnuplot {
+            let command = gnuplot_command(&output,
+                                          &plot_output,
+                                          network_size,
+                                          queue_size,
+                                          total_sent,
+                                          avg_sent,
+                                          max_sent,
+                                          by_kind);
+            let mut child = Command::new("gnuplot")
+                .args(&["-e", &command])
+                .spawn()
+                .expect("failed to execute gnuplot");
+            let _ = child.wait().expect("failed to wait on child");
+        } else {
+            #[cfg(feature = "plotters")]
+            plot_native(plot_output,
+                        &result,
+                        network_size,
+                        queue_size,
+                        total_sent,
+                        avg_sent,
+                        max_sent,
+                        by_kind);
+        }
     }
 
     println!("Done!");
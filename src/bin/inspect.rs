@@ -0,0 +1,253 @@
+//! Recommended usage:
+//!
+//! inspect paused.json
+//!
+//! Loads a snapshot, such as one written when a run pauses (see
+//! `Simulation::snapshot_on_pause`), and answers commands about it read from stdin: `summary`,
+//! `sections`, `node NAME`, `queue`, `help` and `quit`. Node names can be given by the first few
+//! hex digits of the name, as they're displayed in the logs.
+
+#![cfg_attr(feature="cargo-clippy", allow(doc_markdown))]
+
+extern crate ewok;
+extern crate clap;
+#[cfg(test)]
+#[macro_use]
+extern crate maplit;
+
+use clap::{App, Arg};
+use ewok::block::BlockId;
+use ewok::blocks::Blocks;
+use ewok::message::MessageKind;
+use ewok::name::{NAME_BITS, Name, Prefix};
+use ewok::snapshot::{NodeSnapshot, Snapshot};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+const HELP: &str = "\
+summary    what the snapshot holds
+sections   the current blocks of the nodes, and how many nodes hold each
+node NAME  the state of the node whose name starts with the given hex digits
+queue      the messages waiting to be delivered, by kind
+quit       stop inspecting";
+
+fn main() {
+    let matches = App::new("ewok_inspect")
+        .about("This tool loads a snapshot of an Ewok simulation, such as one written when a \
+                run pauses, and answers commands about the state it holds.")
+        .arg(Arg::with_name("INPUT")
+                 .help("Sets the snapshot file to use")
+                 .required(true)
+                 .index(1))
+        .get_matches();
+
+    let path = matches.value_of("INPUT").unwrap();
+    let snapshot = Snapshot::load(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    });
+    let blocks = snapshot.blocks();
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    execute(&mut out, &snapshot, &blocks, "summary").unwrap();
+    let stdin = io::stdin();
+    loop {
+        write!(out, "> ").unwrap();
+        out.flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        if !execute(&mut out, &snapshot, &blocks, &line).unwrap() {
+            break;
+        }
+    }
+}
+
+/// Run a single command, returning false if it was to stop inspecting.
+fn execute<W: Write>(
+    out: &mut W,
+    snapshot: &Snapshot,
+    blocks: &Blocks,
+    line: &str,
+) -> io::Result<bool> {
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next()) {
+        (None, _) => (),
+        (Some("summary"), None) => summary(out, snapshot)?,
+        (Some("sections"), None) => sections(out, snapshot, blocks)?,
+        (Some("node"), Some(name)) => node(out, snapshot, blocks, name)?,
+        (Some("queue"), None) => queue(out, snapshot)?,
+        (Some("help"), None) => writeln!(out, "{}", HELP)?,
+        (Some("quit"), None) => return Ok(false),
+        _ => writeln!(out, "unknown command {:?}, try `help`", line.trim())?,
+    }
+    Ok(true)
+}
+
+fn summary<W: Write>(out: &mut W, snapshot: &Snapshot) -> io::Result<()> {
+    writeln!(out, "step {} ({:?})", snapshot.step, snapshot.phase)?;
+    writeln!(out, "{} nodes, {} blocks", snapshot.nodes.len(), snapshot.blocks.len())?;
+    writeln!(out, "{} messages queued", snapshot.messages.len())?;
+    writeln!(out, "{} clients, {} mocks", snapshot.clients.len(), snapshot.mocks.len())?;
+    let cut = snapshot.partitions.cut();
+    if !cut.is_empty() {
+        writeln!(out, "partitioned: {:?}", cut)?;
+    }
+    Ok(())
+}
+
+fn sections<W: Write>(out: &mut W, snapshot: &Snapshot, blocks: &Blocks) -> io::Result<()> {
+    let mut holders: BTreeMap<(Prefix, u64, BlockId), usize> = BTreeMap::new();
+    for node in &snapshot.nodes {
+        for &id in &node.current_blocks {
+            let block = id.into_block(blocks);
+            *holders.entry((block.prefix, block.version, id)).or_insert(0) += 1;
+        }
+    }
+    for (&(prefix, version, id), count) in &holders {
+        let members = id.into_block(blocks).members.len();
+        writeln!(
+            out,
+            "{:?} v{} {}: {} members, current for {} nodes",
+            prefix,
+            version,
+            id,
+            members,
+            count
+        )?;
+    }
+    Ok(())
+}
+
+fn node<W: Write>(
+    out: &mut W,
+    snapshot: &Snapshot,
+    blocks: &Blocks,
+    name: &str,
+) -> io::Result<()> {
+    let digits = name.trim_start_matches("0x").trim_end_matches("..");
+    let matching: Vec<&NodeSnapshot> = snapshot
+        .nodes
+        .iter()
+        .filter(|node| hex(node.name).starts_with(digits))
+        .collect();
+    let node = match matching.len() {
+        0 => return writeln!(out, "no node's name starts with {}", digits),
+        1 => matching[0],
+        _ => {
+            let names: Vec<String> = matching.iter().map(|node| hex(node.name)).collect();
+            return writeln!(out, "{} could be any of: {}", digits, names.join(", "));
+        }
+    };
+
+    writeln!(out, "Node({}), created at step {}", hex(node.name), node.step_created)?;
+    writeln!(out, "speed {:?}, clock skew {}", node.speed, node.clock_skew)?;
+    for &id in &node.current_blocks {
+        let block = id.into_block(blocks);
+        let member = if block.members.contains(&node.name) { "member" } else { "not a member" };
+        writeln!(out, "current: {:?} v{} {} ({})", block.prefix, block.version, id, member)?;
+    }
+    writeln!(
+        out,
+        "{} valid blocks, {} votes, {} recent votes, {} deferred votes",
+        node.valid_blocks.len(),
+        node.votes.len(),
+        node.recent_votes.len(),
+        node.deferred_votes.len()
+    )?;
+    writeln!(
+        out,
+        "{} connections, {} connect requests",
+        node.connections.len(),
+        node.connect_requests.len()
+    )?;
+    for (name, since) in node.peer_states.lost() {
+        writeln!(out, "lost {} at step {}", name, since)?;
+    }
+    for (name, step) in &node.candidates {
+        let approvals = node.candidate_approvals.get(name).map_or(0, |approvals| approvals.len());
+        writeln!(out, "candidate {} added at step {}, {} approvals", name, step, approvals)?;
+    }
+    for &(name, step) in &node.queued_candidates {
+        writeln!(out, "queued candidate {} at step {}", name, step)?;
+    }
+    for (name, parts) in &node.proof_challengers {
+        writeln!(out, "sent {} parts of resource proof to {}", parts, name)?;
+    }
+    if !node.backlog.is_empty() {
+        let kinds = count_kinds(node.backlog.iter().map(|message| message.content.kind()));
+        writeln!(out, "backlog: {}", kinds)?;
+    }
+    Ok(())
+}
+
+fn queue<W: Write>(out: &mut W, snapshot: &Snapshot) -> io::Result<()> {
+    if snapshot.messages.is_empty() {
+        return writeln!(out, "no messages queued");
+    }
+    let oldest = snapshot.messages.iter().map(|queued| queued.step_sent).min().unwrap();
+    writeln!(out, "{} messages, oldest sent at step {}", snapshot.messages.len(), oldest)?;
+    let kinds = count_kinds(snapshot.messages.iter().map(|queued| queued.message.content.kind()));
+    writeln!(out, "{}", kinds)
+}
+
+/// How many of each kind of message there are, e.g. `Vote: 3, Connect: 1`.
+fn count_kinds<I: Iterator<Item = MessageKind>>(kinds: I) -> String {
+    let mut counts: BTreeMap<MessageKind, usize> = BTreeMap::new();
+    for kind in kinds {
+        *counts.entry(kind).or_insert(0) += 1;
+    }
+    counts
+        .iter()
+        .map(|(kind, count)| format!("{:?}: {}", kind, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The name in full, rather than abbreviated as `Display` does.
+fn hex(name: Name) -> String {
+    format!("{1:00$x}", NAME_BITS / 4, name.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ewok::event_schedule::EventSchedule;
+    use ewok::params::{NodeParams, SimulationParams};
+    use ewok::simulation::Simulation;
+    use ewok::testing::p;
+
+    fn output(snapshot: &Snapshot, command: &str) -> String {
+        let mut out = vec![];
+        assert!(execute(&mut out, snapshot, &snapshot.blocks(), command).unwrap());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn commands() {
+        let sections = btreemap!{ p("0") => 8, p("1") => 8 };
+        let simulation = Simulation::new_from(
+            sections,
+            EventSchedule::empty(),
+            SimulationParams::default(),
+            NodeParams::default(),
+        );
+        let snapshot = simulation.snapshot();
+
+        assert!(output(&snapshot, "summary").contains("16 nodes, 2 blocks"));
+        let sections = output(&snapshot, "sections");
+        assert_eq!(sections.lines().count(), 2);
+        assert!(sections.lines().all(|line| line.ends_with("current for 16 nodes")));
+
+        let name = hex(snapshot.nodes[0].name);
+        let node = output(&snapshot, &format!("node {}..", &name[..6]));
+        assert!(node.starts_with(&format!("Node({})", name)));
+        assert!(output(&snapshot, "node").starts_with("unknown command"));
+
+        let mut out = vec![];
+        assert!(!execute(&mut out, &snapshot, &snapshot.blocks(), "quit").unwrap());
+    }
+}
@@ -0,0 +1,465 @@
+//! Recommended usage:
+//!
+//! EWOK_TRACE=trace_file ewok
+//! minimize trace_file -o minimized.rs
+//!
+//! The seed and parameters are taken from the start of the trace. Older traces don't record them,
+//! in which case the seed printed at the start of the failing run must be passed with `--seed`, and
+//! any parameters that run was given passed again with `--set`. The events recorded in the trace
+//! are replayed as a schedule with random churn turned off, then removed in chunks for as long as
+//! the simulation keeps failing. The result is written as a `#[test]` function that can be pasted
+//! into `tests/manual_setup.rs`.
+
+#![cfg_attr(feature="cargo-clippy", allow(doc_markdown))]
+
+extern crate ewok;
+extern crate clap;
+#[macro_use]
+extern crate maplit;
+
+use clap::{App, Arg};
+use ewok::event::Event;
+use ewok::params::{NodeParams, SimulationParams, apply_env_overrides, set_param};
+use ewok::name::{NAME_BITS, Prefix};
+use ewok::random::parse_seed;
+use ewok::shrink::{Reproduction, replay_params};
+use ewok::trace::{Record, TraceReader};
+use std::env;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, Write};
+use std::panic;
+
+fn main() {
+    let matches = App::new("ewok_minimize")
+        .about("This tool takes a trace file from a failing Ewok simulation, finds a smaller \
+                schedule of events that still fails, and writes it out as a test function \
+                that reproduces the failure using `Simulation::new_from`.")
+        .arg(Arg::with_name("seed")
+                 .long("seed")
+                 .value_name("SEED")
+                 .help("Seed printed by the failing run, e.g. \"[1, 2, 3, 4]\" (defaults to \
+                        the one recorded in the trace)."))
+        .arg(Arg::with_name("set")
+                 .short("s")
+                 .long("set")
+                 .value_name("PARAM=VALUE")
+                 .multiple(true)
+                 .number_of_values(1)
+                 .help("Parameter overrides to apply on top of the recorded parameters."))
+        .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("FILE")
+                 .help("Write the test function to this file rather than to stdout."))
+        .arg(Arg::with_name("INPUT")
+                 .help("Sets the trace file to use")
+                 .required(true)
+                 .index(1))
+        .get_matches();
+
+    // Runs made while shrinking mustn't overwrite the trace we're reading from.
+    env::remove_var("EWOK_TRACE");
+
+    let mut recorded = None;
+    let mut events: Vec<(u64, Event)> = vec![];
+    for record in TraceReader::open(matches.value_of("INPUT").unwrap()).unwrap() {
+        match record {
+            Record::Params {
+                seed,
+                params,
+                node_params,
+            } => recorded = Some((Some(seed), *params, *node_params)),
+            Record::Event { step, event } => events.push((step, event)),
+            _ => (),
+        }
+    }
+    assert!(!events.is_empty(), "the trace doesn't contain any events");
+
+    let (mut seed, mut params, mut node_params) = recorded.unwrap_or_else(|| {
+        let mut params = SimulationParams::default();
+        let mut node_params = NodeParams::default();
+        apply_env_overrides(&mut params, &mut node_params).unwrap_or_else(|e| panic!("{}", e));
+        (None, params, node_params)
+    });
+    if let Some(seed_arg) = matches.value_of("seed") {
+        seed = Some(parse_seed(seed_arg).unwrap_or_else(|| {
+            panic!("seed {} isn't in the form '[1, 2, 3, 4]'", seed_arg)
+        }));
+    }
+    let seed = seed.expect("the trace doesn't record a seed, so one must be given with --seed");
+    for setting in matches.values_of("set").into_iter().flat_map(|v| v) {
+        let mut parts = setting.splitn(2, '=');
+        let name = parts.next().unwrap();
+        let value = parts
+            .next()
+            .unwrap_or_else(|| panic!("{:?} isn't in the form name=value", setting));
+        set_param(&mut params, &mut node_params, name, value).unwrap_or_else(
+            |e| panic!("{}", e),
+        );
+    }
+
+    let reproduction = Reproduction {
+        sections: btreemap!{ Prefix::empty() => 1 },
+        params: replay_params(&params, &events),
+        node_params,
+        seed,
+    };
+
+    println!("Shrinking {} events...", events.len());
+
+    // Every failing run panics or logs, so silence panic messages while we search.
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| ()));
+    let minimal = reproduction.shrink(events, |remaining| {
+        println!("{} events remaining", remaining);
+    });
+    panic::set_hook(default_hook);
+
+    let minimal = match minimal {
+        Some(minimal) => minimal,
+        None => {
+            println!("Replaying the recorded events doesn't reproduce the failure.");
+            return;
+        }
+    };
+
+    println!("Minimal schedule has {} events.", minimal.len());
+    match matches.value_of("output") {
+        Some(path) => write_test(&mut File::create(path).unwrap(), &reproduction, &minimal),
+        None => write_test(&mut io::stdout(), &reproduction, &minimal),
+    }.unwrap();
+}
+
+/// Write a test function reproducing the failure with the given events.
+fn write_test<W: Write>(writer: &mut W,
+                        reproduction: &Reproduction,
+                        events: &[(u64, Event)])
+                        -> io::Result<()> {
+    writeln!(writer,
+             "// Needs `use ewok::event::Event::*;`, `use ewok::name::Name;` and \
+              `use ewok::random::reseed;`,")?;
+    writeln!(writer, "// along with the other imports of `tests/manual_setup.rs`.")?;
+    writeln!(writer, "#[test]")?;
+    writeln!(writer, "fn minimized_failure() {{")?;
+    writeln!(writer, "    init_logging();")?;
+    writeln!(writer, "    reseed({:?});", reproduction.seed)?;
+    writeln!(writer)?;
+    // Logs from the test should go to stdout, like those of the other tests.
+    let params = SimulationParams {
+        log_file: None,
+        log_file_max_bytes: None,
+        log_levels: None,
+        ..reproduction.params.clone()
+    };
+    writeln!(writer, "    let params = {};", indent(&simulation_params_expr(&params)))?;
+    writeln!(writer,
+             "    let node_params = {};",
+             indent(&node_params_expr(&reproduction.node_params)))?;
+    writeln!(writer)?;
+    writeln!(writer, "    let sections = btreemap! {{")?;
+    for (prefix, size) in &reproduction.sections {
+        writeln!(writer, "        {} => {},", prefix_expr(prefix), size)?;
+    }
+    writeln!(writer, "    }};")?;
+    writeln!(writer)?;
+    writeln!(writer, "    let schedule = EventSchedule::new(btreemap! {{")?;
+    let mut i = 0;
+    while i < events.len() {
+        let step = events[i].0;
+        let at_step: Vec<String> = events[i..]
+            .iter()
+            .take_while(|&&(s, _)| s == step)
+            .map(|&(_, ref event)| event_expr(event))
+            .collect();
+        i += at_step.len();
+        writeln!(writer, "        {} => vec![{}],", step, at_step.join(", "))?;
+    }
+    writeln!(writer, "    }});")?;
+    writeln!(writer)?;
+    writeln!(writer,
+             "    let mut simulation = Simulation::new_from(sections, schedule, params, \
+              node_params);")?;
+    writeln!(writer, "    simulation.run().unwrap();")?;
+    writeln!(writer, "}}")
+}
+
+/// Indent all but the first line of a multi-line expression to sit inside the test body.
+fn indent(text: &str) -> String {
+    text.replace('\n', "\n    ")
+}
+
+/// A struct literal with the given fields, one per line.
+fn struct_expr(path: &str, fields: &[(&str, String)]) -> String {
+    let mut expr = format!("{} {{\n", path);
+    for &(name, ref value) in fields {
+        expr.push_str(&format!("    {}: {},\n", name, value));
+    }
+    expr.push('}');
+    expr
+}
+
+/// A fully qualified variant of one of the enums in `ewok::params`.
+fn params_enum_expr<T: Debug>(enum_name: &str, value: &T) -> String {
+    format!("ewok::params::{}::{:?}", enum_name, value)
+}
+
+fn option_expr<T, F: Fn(&T) -> String>(value: &Option<T>, expr: F) -> String {
+    match *value {
+        Some(ref value) => format!("Some({})", expr(value)),
+        None => "None".to_string(),
+    }
+}
+
+fn debug_expr<T: Debug>(value: &T) -> String {
+    format!("{:?}", value)
+}
+
+fn simulation_params_expr(params: &SimulationParams) -> String {
+    // Listing every field means a new one can't be left out here.
+    let SimulationParams {
+        ref max_delay,
+        ref delay_distribution,
+        ref delivery_percentile,
+        ref burst_reorder_prob,
+        ref grow_prob_join,
+        ref grow_prob_drop,
+        ref prob_churn,
+        ref steady_state,
+        ref shrink_prob_join,
+        ref shrink_prob_drop,
+        ref churn_correlation,
+        ref churn_memory,
+        ref max_network_size,
+        ref name_distribution,
+        ref grow_prob_disconnect,
+        ref grow_prob_reconnect,
+        ref prob_disconnect,
+        ref prob_reconnect,
+        ref shrink_prob_disconnect,
+        ref shrink_prob_reconnect,
+        ref starting_complete,
+        ref grow_complete,
+        ref stable_steps,
+        ref log_file,
+        ref log_file_max_bytes,
+        ref log_levels,
+        ref malicious_join_prob,
+        ref malicious_behaviour,
+        ref restart_mode,
+        ref chaos,
+        ref chaos_prob,
+        ref chaos_duration,
+        ref chaos_max_partition,
+        ref chaos_max_loss,
+        ref chaos_max_duplicate,
+        ref data_op_prob,
+        ref sybil_fraction,
+        ref sybil_window,
+        ref sybil_target,
+        ref overload_drop_prob,
+        ref overload_window,
+        ref slow_node_ratio,
+        ref fast_node_ratio,
+        ref message_capacity,
+        ref max_clock_skew,
+        ref track_votes,
+    } = *params;
+    let string_expr = |value: &String| format!("{:?}.to_string()", value);
+    struct_expr(
+        "ewok::params::SimulationParams",
+        &[
+            ("max_delay", debug_expr(max_delay)),
+            ("delay_distribution", params_enum_expr("DelayDistribution", delay_distribution)),
+            ("delivery_percentile", debug_expr(delivery_percentile)),
+            ("burst_reorder_prob", debug_expr(burst_reorder_prob)),
+            ("grow_prob_join", debug_expr(grow_prob_join)),
+            ("grow_prob_drop", debug_expr(grow_prob_drop)),
+            ("prob_churn", debug_expr(prob_churn)),
+            ("steady_state", debug_expr(steady_state)),
+            ("shrink_prob_join", debug_expr(shrink_prob_join)),
+            ("shrink_prob_drop", debug_expr(shrink_prob_drop)),
+            ("churn_correlation", debug_expr(churn_correlation)),
+            ("churn_memory", debug_expr(churn_memory)),
+            ("max_network_size", debug_expr(max_network_size)),
+            ("name_distribution", params_enum_expr("NameDistribution", name_distribution)),
+            ("grow_prob_disconnect", debug_expr(grow_prob_disconnect)),
+            ("grow_prob_reconnect", debug_expr(grow_prob_reconnect)),
+            ("prob_disconnect", debug_expr(prob_disconnect)),
+            ("prob_reconnect", debug_expr(prob_reconnect)),
+            ("shrink_prob_disconnect", debug_expr(shrink_prob_disconnect)),
+            ("shrink_prob_reconnect", debug_expr(shrink_prob_reconnect)),
+            ("starting_complete", debug_expr(starting_complete)),
+            ("grow_complete", debug_expr(grow_complete)),
+            ("stable_steps", debug_expr(stable_steps)),
+            ("log_file", option_expr(log_file, &string_expr)),
+            ("log_file_max_bytes", debug_expr(log_file_max_bytes)),
+            ("log_levels", option_expr(log_levels, &string_expr)),
+            ("malicious_join_prob", debug_expr(malicious_join_prob)),
+            ("malicious_behaviour", params_enum_expr("CandidateBehaviour", malicious_behaviour)),
+            ("restart_mode", params_enum_expr("RestartMode", restart_mode)),
+            ("chaos", debug_expr(chaos)),
+            ("chaos_prob", debug_expr(chaos_prob)),
+            ("chaos_duration", debug_expr(chaos_duration)),
+            ("chaos_max_partition", debug_expr(chaos_max_partition)),
+            ("chaos_max_loss", debug_expr(chaos_max_loss)),
+            ("chaos_max_duplicate", debug_expr(chaos_max_duplicate)),
+            ("data_op_prob", debug_expr(data_op_prob)),
+            ("sybil_fraction", debug_expr(sybil_fraction)),
+            ("sybil_window", debug_expr(sybil_window)),
+            ("sybil_target", option_expr(sybil_target, prefix_expr)),
+            ("overload_drop_prob", debug_expr(overload_drop_prob)),
+            ("overload_window", debug_expr(overload_window)),
+            ("slow_node_ratio", debug_expr(slow_node_ratio)),
+            ("fast_node_ratio", debug_expr(fast_node_ratio)),
+            ("message_capacity", debug_expr(message_capacity)),
+            ("max_clock_skew", debug_expr(max_clock_skew)),
+            ("track_votes", debug_expr(track_votes)),
+        ],
+    )
+}
+
+fn node_params_expr(params: &NodeParams) -> String {
+    // Listing every field means a new one can't be left out here.
+    let NodeParams {
+        ref min_section_size,
+        ref min_section_size_growth,
+        ref split_buffer,
+        ref split_buffer_growth,
+        ref join_timeout,
+        ref self_shutdown_timeout,
+        ref max_conflicting_blocks,
+        ref quorum_numerator,
+        ref quorum_denominator,
+        ref quorum_weight,
+        ref routing_bucket_size,
+        ref resource_proof_steps,
+        ref rmconv_timeout,
+        ref candidate_queue_timeout,
+        ref force_merge_numerator,
+        ref force_merge_denominator,
+        ref force_merge_delay,
+        ref relocate_new_nodes,
+        ref storm_threshold,
+        ref throttle_storms,
+        ref aggregate_signature_bytes,
+        ref history_depth,
+        ref proof_request_policy,
+        ref proof_request_window,
+        ref max_connections,
+        ref recover_chain_gaps,
+        ref recipient_policy,
+    } = *params;
+    struct_expr(
+        "ewok::params::NodeParams",
+        &[
+            ("min_section_size", debug_expr(min_section_size)),
+            ("min_section_size_growth", params_enum_expr("SizeGrowth", min_section_size_growth)),
+            ("split_buffer", debug_expr(split_buffer)),
+            ("split_buffer_growth", params_enum_expr("SizeGrowth", split_buffer_growth)),
+            ("join_timeout", debug_expr(join_timeout)),
+            ("self_shutdown_timeout", debug_expr(self_shutdown_timeout)),
+            ("max_conflicting_blocks", debug_expr(max_conflicting_blocks)),
+            ("quorum_numerator", debug_expr(quorum_numerator)),
+            ("quorum_denominator", debug_expr(quorum_denominator)),
+            ("quorum_weight", params_enum_expr("QuorumWeight", quorum_weight)),
+            ("routing_bucket_size", debug_expr(routing_bucket_size)),
+            ("resource_proof_steps", debug_expr(resource_proof_steps)),
+            ("rmconv_timeout", debug_expr(rmconv_timeout)),
+            ("candidate_queue_timeout", debug_expr(candidate_queue_timeout)),
+            ("force_merge_numerator", debug_expr(force_merge_numerator)),
+            ("force_merge_denominator", debug_expr(force_merge_denominator)),
+            ("force_merge_delay", debug_expr(force_merge_delay)),
+            ("relocate_new_nodes", debug_expr(relocate_new_nodes)),
+            ("storm_threshold", debug_expr(storm_threshold)),
+            ("throttle_storms", debug_expr(throttle_storms)),
+            ("aggregate_signature_bytes", debug_expr(aggregate_signature_bytes)),
+            ("history_depth", debug_expr(history_depth)),
+            ("proof_request_policy", params_enum_expr("ProofRequestPolicy", proof_request_policy)),
+            ("proof_request_window", debug_expr(proof_request_window)),
+            ("max_connections", debug_expr(max_connections)),
+            ("recover_chain_gaps", debug_expr(recover_chain_gaps)),
+            ("recipient_policy", params_enum_expr("RecipientRule", recipient_policy)),
+        ],
+    )
+}
+
+fn prefix_expr(prefix: &Prefix) -> String {
+    if prefix.bit_count() == 0 {
+        "ewok::name::Prefix::empty()".to_string()
+    } else {
+        format!("\"{}\".parse::<ewok::name::Prefix>().unwrap()", prefix)
+    }
+}
+
+fn event_expr(event: &Event) -> String {
+    match *event {
+        Event::AddNode(name) => format!("AddNode(Name(0x{:01$x}))", name.0, NAME_BITS / 4),
+        Event::AddNodeUntil(name, step) => {
+            format!("AddNodeUntil(Name(0x{:01$x}), {2})", name.0, NAME_BITS / 4, step)
+        }
+        Event::RemoveNode(name) => format!("RemoveNode(Name(0x{:01$x}))", name.0, NAME_BITS / 4),
+        Event::RemoveNodeFrom(ref prefix) => format!("RemoveNodeFrom({})", prefix_expr(prefix)),
+        Event::CrashNode(name) => format!("CrashNode(Name(0x{:01$x}))", name.0, NAME_BITS / 4),
+        Event::AddStaleNode(name) => {
+            format!("AddStaleNode(Name(0x{:01$x}))", name.0, NAME_BITS / 4)
+        }
+        Event::RestartNode(name) => format!("RestartNode(Name(0x{:01$x}))", name.0, NAME_BITS / 4),
+        Event::Put(name) => format!("Put(Name(0x{:01$x}))", name.0, NAME_BITS / 4),
+        Event::Get(name) => format!("Get(Name(0x{:01$x}))", name.0, NAME_BITS / 4),
+        Event::Partition(ref prefix) => format!("Partition({})", prefix_expr(prefix)),
+        Event::HealPartition => "HealPartition".to_string(),
+        Event::AddClient(name) => format!("AddClient(Name(0x{:01$x}))", name.0, NAME_BITS / 4),
+        Event::RemoveClient(name) => {
+            format!("RemoveClient(Name(0x{:01$x}))", name.0, NAME_BITS / 4)
+        }
+        Event::IntroduceRoots => "IntroduceRoots".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ewok::event::Event::*;
+    use ewok::name::Name;
+    use ewok::params::{QuorumWeight, RecipientRule, RestartMode, SizeGrowth};
+    use ewok::testing::{p, quiet_params};
+
+    // `tests/minimized.rs` holds the test written for this reproduction, so building the tests
+    // checks that what's written compiles.
+    #[test]
+    fn written_test_compiles() {
+        let reproduction = Reproduction {
+            sections: btreemap!{ p("0") => 8, p("1") => 8 },
+            params: SimulationParams {
+                stable_steps: 200,
+                log_file: Some("ewok.log".to_string()),
+                restart_mode: RestartMode::Persistent,
+                sybil_target: Some(p("01")),
+                ..quiet_params()
+            },
+            node_params: NodeParams {
+                split_buffer_growth: SizeGrowth::PerSections(2),
+                quorum_weight: QuorumWeight::Age,
+                recipient_policy: RecipientRule::AllNeighbours,
+                ..NodeParams::default()
+            },
+            seed: [1, 2, 3, 4],
+        };
+        let events = vec![
+            (10, AddNode(Name(0x0123456789abcdef))),
+            (10, RemoveNodeFrom(p("1"))),
+            (20, CrashNode(Name(0x0123456789abcdef))),
+            (30, RestartNode(Name(0x0123456789abcdef))),
+        ];
+        let mut written = vec![];
+        write_test(&mut written, &reproduction, &events).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        // Names are written with as many digits as they have, which is more with `wide-names`.
+        let expected = include_str!("../../tests/minimized.rs").replace(
+            "Name(0x0123456789abcdef)",
+            &format!("Name(0x{:01$x})", 0x0123456789abcdef_u64, NAME_BITS / 4),
+        );
+        assert!(expected.ends_with(&written));
+    }
+}
@@ -0,0 +1,115 @@
+//! Recommended usage:
+//!
+//! schedule --prefixes 00,01,10,11 --joins 40 --drops 20 --min-removal-gap 10 -o churn.json
+//! EWOK_TOPOLOGY=sections.json ewok --schedule churn.json
+//!
+//! Every event is placed on a random step within the constraints, so running the tool several
+//! times builds up a corpus of schedules for fuzzing. Pass `--seed` to reproduce a schedule
+//! exactly.
+
+#![cfg_attr(feature="cargo-clippy", allow(doc_markdown))]
+
+extern crate ewok;
+extern crate clap;
+
+use clap::{App, Arg, ArgMatches};
+use ewok::name::Prefix;
+use ewok::random::{parse_seed, reseed};
+use ewok::schedule_generator::{Constraints, generate};
+use std::process;
+use std::str::FromStr;
+
+/// Parse the value of an argument, or use the default if it isn't given.
+fn parse_arg<T: FromStr>(matches: &ArgMatches, name: &str, default: T) -> T {
+    matches.value_of(name).map_or(default, |value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid value for --{}: {:?}", name, value))
+    })
+}
+
+fn main() {
+    let matches = App::new("ewok_schedule")
+        .about("This tool generates a random event schedule that obeys the given constraints, \
+                and writes it as a JSON file that Ewok can run.")
+        .arg(Arg::with_name("prefixes")
+                 .short("p")
+                 .long("prefixes")
+                 .value_name("PREFIXES")
+                 .help("Comma-separated prefixes of the sections that nodes join and are \
+                        removed from (default the empty prefix)."))
+        .arg(Arg::with_name("start")
+                 .long("start")
+                 .value_name("STEP")
+                 .help("First step on which events can happen (default 1)."))
+        .arg(Arg::with_name("steps")
+                 .long("steps")
+                 .value_name("N")
+                 .help("Number of steps to spread the events over (default 100)."))
+        .arg(Arg::with_name("joins")
+                 .long("joins")
+                 .value_name("N")
+                 .help("Total number of nodes that join (default 10)."))
+        .arg(Arg::with_name("drops")
+                 .long("drops")
+                 .value_name("N")
+                 .help("Total number of nodes that are removed (default 10)."))
+        .arg(Arg::with_name("max_per_step")
+                 .long("max-per-step")
+                 .value_name("N")
+                 .help("Maximum number of events on a single step (default 1)."))
+        .arg(Arg::with_name("min_removal_gap")
+                 .long("min-removal-gap")
+                 .value_name("N")
+                 .help("Minimum number of steps between two removals from the same prefix \
+                        (default 0)."))
+        .arg(Arg::with_name("seed")
+                 .long("seed")
+                 .value_name("SEED")
+                 .help("Seed to generate with, e.g. \"[1, 2, 3, 4]\"."))
+        .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("PATH")
+                 .help("File to write the schedule to (default schedule.json)."))
+        .get_matches();
+
+    if let Some(value) = matches.value_of("seed") {
+        let seed = parse_seed(value)
+            .unwrap_or_else(|| panic!("seed {} isn't in the form '[1, 2, 3, 4]'", value));
+        reseed(seed);
+    }
+
+    let defaults = Constraints::default();
+    let prefixes = match matches.value_of("prefixes") {
+        Some(value) => {
+            value
+                .split(',')
+                .map(|s| {
+                    Prefix::from_str(s.trim())
+                        .unwrap_or_else(|_| panic!("invalid prefix: {:?}", s))
+                })
+                .collect()
+        }
+        None => defaults.prefixes,
+    };
+    let constraints = Constraints {
+        prefixes,
+        start_step: parse_arg(&matches, "start", defaults.start_step),
+        steps: parse_arg(&matches, "steps", defaults.steps),
+        joins: parse_arg(&matches, "joins", defaults.joins),
+        drops: parse_arg(&matches, "drops", defaults.drops),
+        max_events_per_step: parse_arg(&matches, "max_per_step", defaults.max_events_per_step),
+        min_removal_gap: parse_arg(&matches, "min_removal_gap", defaults.min_removal_gap),
+    };
+
+    let schedule = generate(&constraints).unwrap_or_else(|e| {
+        eprintln!("Couldn't generate a schedule: {}", e);
+        process::exit(1);
+    });
+    let path = matches.value_of("output").unwrap_or("schedule.json");
+    schedule
+        .save(path)
+        .unwrap_or_else(|e| panic!("couldn't write schedule {}: {}", path, e));
+    println!("Wrote {} events to {}", schedule.events().len(), path);
+}
@@ -0,0 +1,296 @@
+//! Recommended usage:
+//!
+//! sweep -x max_delay=5,10,20,40 -y join_timeout=10:50:10 --runs 10 -o delay_vs_timeout
+//!
+//! This writes the raw results to `delay_vs_timeout.csv` and a heatmap of the failure rate
+//! to `delay_vs_timeout.svg`. With `--db results.db`, the per-step metrics and final report of
+//! every run are also written to a SQLite database (see `ewok::results_db`).
+
+#![cfg_attr(feature="cargo-clippy", allow(doc_markdown))]
+
+extern crate ewok;
+extern crate clap;
+
+use clap::{App, Arg};
+use ewok::logging::init_logging_with;
+use ewok::params::{NodeParams, SimulationParams, apply_env_overrides, set_param};
+use ewok::sweep::{Axis, Sweep, SweepResult};
+use std::fs::File;
+use std::io::{Write, BufWriter};
+
+const CELL_WIDTH: usize = 90;
+const CELL_HEIGHT: usize = 50;
+const MARGIN: usize = 120;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    Failures,
+    Convergence,
+}
+
+/// Parse an axis of the form `name=v1,v2,v3` or `name=start:stop:step`.
+fn parse_axis(arg: &str) -> Axis {
+    let mut parts = arg.splitn(2, '=');
+    let param = parts.next().unwrap().to_string();
+    let values = parts
+        .next()
+        .unwrap_or_else(|| panic!("axis {:?} isn't in the form name=values", arg));
+
+    let values = if values.contains(':') {
+        let range: Vec<f64> = values
+            .split(':')
+            .map(|s| s.parse().expect("invalid number in range"))
+            .collect();
+        assert_eq!(range.len(), 3, "range {:?} isn't in the form start:stop:step", values);
+        let (start, stop, step) = (range[0], range[1], range[2]);
+        assert!(step > 0.0, "range step must be positive");
+        let integral = !values.contains('.');
+        (0..)
+            .map(|i| start + i as f64 * step)
+            .take_while(|&v| v <= stop + step * 1e-9)
+            .map(|v| if integral {
+                format!("{}", v as i64)
+            } else {
+                format!("{}", v)
+            })
+            .collect()
+    } else {
+        values.split(',').map(|s| s.trim().to_string()).collect()
+    };
+
+    Axis { param, values }
+}
+
+fn main() {
+    let matches = App::new("ewok_sweep")
+        .about("This tool runs Ewok simulations for every combination of values of two \
+                parameters, and writes the results as a CSV file and an SVG heatmap.")
+        .arg(Arg::with_name("x")
+                 .short("x")
+                 .value_name("PARAM=VALUES")
+                 .required(true)
+                 .help("Parameter for the horizontal axis, with values given as a list \
+                        (a,b,c) or a range (start:stop:step)."))
+        .arg(Arg::with_name("y")
+                 .short("y")
+                 .value_name("PARAM=VALUES")
+                 .required(true)
+                 .help("Parameter for the vertical axis, in the same form as -x."))
+        .arg(Arg::with_name("runs")
+                 .short("r")
+                 .long("runs")
+                 .value_name("N")
+                 .help("Number of runs for each combination of values (default 5)."))
+        .arg(Arg::with_name("set")
+                 .short("s")
+                 .long("set")
+                 .value_name("PARAM=VALUE")
+                 .multiple(true)
+                 .number_of_values(1)
+                 .help("Override a parameter for every run."))
+        .arg(Arg::with_name("metric")
+                 .short("m")
+                 .long("metric")
+                 .possible_values(&["failures", "convergence"])
+                 .help("Quantity shown in the heatmap (default failures)."))
+        .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("NAME")
+                 .help("Base name for the output files (default sweep)."))
+        .arg(Arg::with_name("db")
+                 .long("db")
+                 .value_name("PATH")
+                 .help("Also write the metrics of every run to this SQLite database. Requires \
+                        the sqlite feature."))
+        .get_matches();
+
+    if let Some(path) = matches.value_of("db") {
+        results_to_db(path);
+    }
+
+    let mut params = SimulationParams::default();
+    let mut node_params = NodeParams::default();
+    apply_env_overrides(&mut params, &mut node_params).unwrap_or_else(|e| panic!("{}", e));
+    for setting in matches.values_of("set").into_iter().flat_map(|v| v) {
+        let mut parts = setting.splitn(2, '=');
+        let name = parts.next().unwrap();
+        let value = parts
+            .next()
+            .unwrap_or_else(|| panic!("{:?} isn't in the form name=value", setting));
+        set_param(&mut params, &mut node_params, name, value).unwrap_or_else(
+            |e| panic!("{}", e),
+        );
+    }
+    init_logging_with(&params);
+
+    let sweep = Sweep {
+        params,
+        node_params,
+        x: parse_axis(matches.value_of("x").unwrap()),
+        y: parse_axis(matches.value_of("y").unwrap()),
+        runs: matches
+            .value_of("runs")
+            .map_or(5, |n| n.parse().expect("invalid number of runs")),
+    };
+    let metric = match matches.value_of("metric") {
+        Some("convergence") => Metric::Convergence,
+        _ => Metric::Failures,
+    };
+    let output = matches.value_of("output").unwrap_or("sweep");
+
+    let results = sweep
+        .run(|result| {
+            println!(
+                "{}={} {}={}: {}/{} failed",
+                sweep.x.param,
+                result.x,
+                sweep.y.param,
+                result.y,
+                result.failures,
+                result.runs
+            );
+        })
+        .unwrap_or_else(|e| panic!("{}", e));
+
+    write_csv(&format!("{}.csv", output), &sweep, &results);
+    write_heatmap(&format!("{}.svg", output), &sweep, &results, metric);
+    println!("Done!");
+}
+
+#[cfg(feature = "sqlite")]
+fn results_to_db(path: &str) {
+    ewok::results_db::results_to(path)
+        .unwrap_or_else(|e| panic!("couldn't open results database {}: {}", path, e));
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn results_to_db(_: &str) {
+    panic!("--db needs ewok to be built with the sqlite feature");
+}
+
+fn write_csv(path: &str, sweep: &Sweep, results: &[SweepResult]) {
+    let mut writer = BufWriter::new(File::create(path).unwrap());
+    writeln!(
+        writer,
+        "{},{},runs,failures,failure_rate,mean_convergence",
+        sweep.x.param,
+        sweep.y.param
+    ).unwrap();
+    for r in results {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            r.x,
+            r.y,
+            r.runs,
+            r.failures,
+            r.failure_rate(),
+            r.mean_convergence.map_or(String::new(), |c| c.to_string())
+        ).unwrap();
+    }
+}
+
+fn write_heatmap(path: &str, sweep: &Sweep, results: &[SweepResult], metric: Metric) {
+    let num_x = sweep.x.values.len();
+    let num_y = sweep.y.values.len();
+    let width = MARGIN + num_x * CELL_WIDTH + 20;
+    let height = MARGIN + num_y * CELL_HEIGHT + 20;
+    let max_convergence = results
+        .iter()
+        .filter_map(|r| r.mean_convergence)
+        .fold(1.0, f64::max);
+
+    let mut writer = BufWriter::new(File::create(path).unwrap());
+    writeln!(
+        writer,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         font-family=\"sans-serif\" font-size=\"12\">",
+        width,
+        height
+    ).unwrap();
+    writeln!(
+        writer,
+        "<text x=\"{}\" y=\"20\" text-anchor=\"middle\">{} ({} runs each)</text>",
+        width / 2,
+        match metric {
+            Metric::Failures => "Failure rate",
+            Metric::Convergence => "Mean steps to converge",
+        },
+        sweep.runs
+    ).unwrap();
+    writeln!(
+        writer,
+        "<text x=\"20\" y=\"{0}\" text-anchor=\"middle\" transform=\"rotate(-90 20 {0})\">{1}</text>",
+        MARGIN + num_y * CELL_HEIGHT / 2,
+        sweep.y.param
+    ).unwrap();
+    writeln!(
+        writer,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+        MARGIN + num_x * CELL_WIDTH / 2,
+        MARGIN - 40,
+        sweep.x.param
+    ).unwrap();
+
+    for (i, x) in sweep.x.values.iter().enumerate() {
+        writeln!(
+            writer,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+            MARGIN + i * CELL_WIDTH + CELL_WIDTH / 2,
+            MARGIN - 10,
+            x
+        ).unwrap();
+    }
+    for (j, y) in sweep.y.values.iter().enumerate() {
+        writeln!(
+            writer,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>",
+            MARGIN - 10,
+            MARGIN + j * CELL_HEIGHT + CELL_HEIGHT / 2 + 4,
+            y
+        ).unwrap();
+    }
+
+    // Results are in row-major order: all x values for the first y value, and so on.
+    for (k, result) in results.iter().enumerate() {
+        let (i, j) = (k % num_x, k / num_x);
+        let (fill, label) = match metric {
+            Metric::Failures => {
+                let rate = result.failure_rate();
+                (shade(rate, (255, 0, 0)), format!("{:.2}", rate))
+            }
+            Metric::Convergence => {
+                match result.mean_convergence {
+                    Some(steps) => {
+                        (shade(steps / max_convergence, (0, 0, 255)), format!("{:.1}", steps))
+                    }
+                    None => ("#999999".to_string(), "-".to_string()),
+                }
+            }
+        };
+        writeln!(
+            writer,
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"white\"/>",
+            MARGIN + i * CELL_WIDTH,
+            MARGIN + j * CELL_HEIGHT,
+            CELL_WIDTH,
+            CELL_HEIGHT,
+            fill
+        ).unwrap();
+        writeln!(
+            writer,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+            MARGIN + i * CELL_WIDTH + CELL_WIDTH / 2,
+            MARGIN + j * CELL_HEIGHT + CELL_HEIGHT / 2 + 4,
+            label
+        ).unwrap();
+    }
+    writeln!(writer, "</svg>").unwrap();
+}
+
+/// Colour interpolated from white (at 0.0) to `full` (at 1.0).
+fn shade(fraction: f64, full: (u8, u8, u8)) -> String {
+    let mix = |c: u8| (255.0 - (255.0 - c as f64) * fraction.max(0.0).min(1.0)) as u8;
+    format!("#{:02x}{:02x}{:02x}", mix(full.0), mix(full.1), mix(full.2))
+}
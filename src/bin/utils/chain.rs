@@ -1,55 +1,60 @@
-use std::collections::BTreeSet;
-use std::fmt;
-use std::hash::{Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
+use ewok::block::Block;
+use ewok::name::{Name, Prefix, NAME_BITS};
+use std::collections::BTreeMap;
+use std::fmt::Write;
 
-fn hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
+/// The first six hex characters of a name, used as its colour whatever width it's displayed with.
+fn short_hex(name: &Name) -> String {
+    let mut hex = format!("{:01$x}", name.0, NAME_BITS / 4);
+    hex.truncate(6);
+    hex
 }
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Members(pub BTreeSet<String>);
-
-impl fmt::Display for Members {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (count, name) in self.0.iter().enumerate() {
-            write!(f, "<font color=\"#{}\">{}</font>, ", name, name)?;
-            if count % 3 == 0 {
-                write!(f, "<br/>")?;
-            }
+/// HTML-like DOT label describing a block.
+pub fn block_label(block: &Block) -> String {
+    let mut members = String::new();
+    for (count, name) in block.members.iter().enumerate() {
+        let _ = write!(members, "<font color=\"#{}\">{}</font>, ", short_hex(name), name);
+        if count % 3 == 0 {
+            members.push_str("<br/>");
         }
-        Ok(())
     }
-}
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Block {
-    pub prefix: String,
-    pub version: u64,
-    pub members: Members,
+    format!(
+        "<<font point-size=\"40\">p[{}] v{}</font><br/>Members: <br/>{}>",
+        block.prefix,
+        block.version,
+        members
+    )
 }
 
-impl Block {
-    pub fn get_id(&self) -> String {
-        format!("prefix{}_v{}_{}",
-                self.prefix,
-                self.version,
-                hash(&self.members))
-    }
-
-    #[allow(unused)]
-    pub fn get_label(&self) -> String {
-        format!("<<font point-size=\"40\">p[{}] v{}</font><br/>Members: <br/>{}>",
-                self.prefix,
-                self.version,
-                self.members)
-    }
+/// The most recent agreed block for each section, as reconstructed from a trace.
+#[derive(Default)]
+pub struct SectionMap {
+    pub blocks: BTreeMap<Prefix, Block>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Vote {
-    pub from: String,
-    pub to: String,
+impl SectionMap {
+    /// Update the map with a block that some node has seen agreed, returning true if it changed.
+    ///
+    /// The block replaces any blocks with compatible prefixes and lower versions, unless a
+    /// compatible block with a version at least as high is already present.
+    pub fn insert(&mut self, block: &Block) -> bool {
+        if self.blocks.values().any(|b| {
+            b.prefix.is_compatible(&block.prefix) && b.version >= block.version
+        })
+        {
+            return false;
+        }
+        let superseded: Vec<Prefix> = self.blocks
+            .keys()
+            .filter(|prefix| prefix.is_compatible(&block.prefix))
+            .cloned()
+            .collect();
+        for prefix in superseded {
+            self.blocks.remove(&prefix);
+        }
+        self.blocks.insert(block.prefix, block.clone());
+        true
+    }
 }
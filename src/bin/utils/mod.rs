@@ -1,2 +1,5 @@
+// Not every binary uses every utility.
+#![allow(dead_code)]
+
 pub mod chain;
-pub mod log_parse;
+pub mod summary;
@@ -0,0 +1,69 @@
+use ewok::block::{Block, BlockId};
+use ewok::message::MessageKind;
+use ewok::name::Prefix;
+use ewok::trace::{Record, TraceReader};
+use std::collections::BTreeMap;
+use utils::chain::SectionMap;
+
+/// Figures describing a whole run, gathered from its trace.
+#[derive(Default)]
+pub struct RunSummary {
+    /// Number of the last step.
+    pub steps: u64,
+    pub final_nodes: usize,
+    pub max_nodes: usize,
+    pub total_sent: usize,
+    /// Total size of the messages sent, in bytes.
+    pub total_bytes: usize,
+    pub sent_by_kind: BTreeMap<MessageKind, usize>,
+    pub peak_queue: usize,
+    /// Step at which each prefix first had an agreed block.
+    pub prefix_first_seen: BTreeMap<Prefix, u64>,
+    /// Step at which any of the current sections last changed.
+    pub last_section_change: u64,
+    /// The current sections at the end of the run.
+    pub sections: SectionMap,
+}
+
+impl RunSummary {
+    pub fn from_trace(trace: TraceReader) -> Self {
+        let mut summary = RunSummary::default();
+        let mut blocks: BTreeMap<BlockId, Block> = BTreeMap::new();
+
+        for record in trace {
+            match record {
+                Record::Step { step, nodes, .. } => {
+                    summary.steps = step;
+                    summary.final_nodes = nodes;
+                    summary.max_nodes = summary.max_nodes.max(nodes);
+                }
+                Record::Block { id, block } => {
+                    blocks.insert(id, block);
+                }
+                Record::Agreement { step, to, .. } => {
+                    let block = &blocks[&to];
+                    summary.prefix_first_seen.entry(block.prefix).or_insert(step);
+                    if summary.sections.insert(block) {
+                        summary.last_section_change = step;
+                    }
+                }
+                Record::Sent { count, bytes, .. } => {
+                    summary.total_sent += count;
+                    summary.total_bytes += bytes;
+                }
+                Record::SentByKind { message_kind, count, .. } => {
+                    *summary.sent_by_kind.entry(message_kind).or_insert(0) += count;
+                }
+                Record::Queue { size, .. } => {
+                    summary.peak_queue = summary.peak_queue.max(size);
+                }
+                _ => (),
+            }
+        }
+        summary
+    }
+
+    pub fn sent_of_kind(&self, kind: MessageKind) -> usize {
+        self.sent_by_kind.get(&kind).cloned().unwrap_or(0)
+    }
+}
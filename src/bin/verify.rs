@@ -0,0 +1,183 @@
+//! Recommended usage:
+//!
+//! EWOK_TRACE=trace_file ewok
+//! verify trace_file
+//!
+//! Every agreement in the trace is checked against the blocks and voters recorded alongside it:
+//! the voters must all be members of the relevant block, they must form a quorum under the
+//! configured quorum ratio, and the `to` block must be a valid successor of the `from` block. The tool
+//! exits with a non-zero status if any agreement fails these checks.
+//!
+//! The quorum is computed from the node parameters recorded at the start of the trace. Traces
+//! without them fall back to the defaults, so a run that changed the quorum ratio should be
+//! verified with the same `EWOK_PARAM_QUORUM_*` variables set.
+//!
+//! When votes are weighed by age, the members' ages are rebuilt from the agreements in the order
+//! they were recorded: a member's age is the number of versions of its section since the one it
+//! was added in, and members of blocks with no recorded history count as added in that block.
+//! Nodes only learn ages from agreements made on earlier steps, so an agreement's ages are only
+//! taken into account once its step is over.
+
+#![cfg_attr(feature="cargo-clippy", allow(doc_markdown))]
+
+extern crate ewok;
+extern crate clap;
+
+use clap::{App, Arg};
+use ewok::block::{BlockId, Vote};
+use ewok::blocks::Blocks;
+use ewok::name::Name;
+use ewok::params::{NodeParams, QuorumWeight, SimulationParams, apply_env_overrides};
+use ewok::replay::Replayer;
+use std::collections::{BTreeMap, BTreeSet};
+use std::process;
+
+fn main() {
+    let matches = App::new("ewok_verify")
+        .about("This tool takes a trace file from an Ewok simulation and independently checks \
+                that every agreement recorded in it was backed by a quorum of votes.")
+        .arg(Arg::with_name("INPUT")
+                 .help("Sets the trace file to use")
+                 .required(true)
+                 .index(1))
+        .get_matches();
+
+    let replayer = Replayer::open(matches.value_of("INPUT").unwrap()).unwrap();
+    let node_params = replayer.node_params().cloned().unwrap_or_else(|| {
+        let mut node_params = NodeParams::default();
+        apply_env_overrides(&mut SimulationParams::default(), &mut node_params)
+            .unwrap_or_else(|e| panic!("{}", e));
+        node_params
+    });
+    let blocks = replayer.blocks();
+
+    let mut votes = BTreeSet::new();
+    let mut join_versions = JoinVersions::default();
+    let mut this_step = (0, vec![]);
+    let mut num_failures = 0;
+
+    for agreement in replayer.agreements() {
+        let vote = &agreement.vote;
+        if agreement.step != this_step.0 {
+            for vote in this_step.1.drain(..) {
+                join_versions.record(&blocks, &vote);
+            }
+            this_step.0 = agreement.step;
+        }
+        this_step.1.push(vote.clone());
+        let age = join_versions.age_in(&blocks, vote.from);
+        if let Err(problem) =
+            check_agreement(&blocks, vote, &agreement.voters, &age, &node_params)
+        {
+            num_failures += 1;
+            println!("step {}: Node({}) agreed {:?}: {}",
+                     agreement.step,
+                     agreement.node,
+                     vote.as_debug(&blocks),
+                     problem);
+        }
+        votes.insert(vote.clone());
+    }
+    let num_agreements = replayer.agreements().len();
+
+    println!("Checked {} agreements on {} distinct votes: {} failed.",
+             num_agreements,
+             votes.len(),
+             num_failures);
+    if num_failures > 0 {
+        process::exit(1);
+    }
+}
+
+/// Check a single agreement, describing the problem with it if it isn't valid.
+fn check_agreement(blocks: &Blocks,
+                   vote: &Vote,
+                   voters: &BTreeSet<Name>,
+                   age: &dyn Fn(&Name) -> u64,
+                   params: &NodeParams)
+                   -> Result<(), String> {
+    let from = vote.from.into_block(blocks);
+    let to = vote.to.into_block(blocks);
+
+    if !to.prefix.is_neighbour(&from.prefix) && !to.is_admissible_after(from) {
+        return Err("the to block isn't a valid successor of the from block".to_string());
+    }
+
+    let members = vote.quorum_members(blocks);
+    let outsiders: Vec<_> = voters.difference(members).collect();
+    if !outsiders.is_empty() {
+        return Err(format!("voters {:?} aren't members", outsiders));
+    }
+
+    match params.quorum_weight {
+        QuorumWeight::Members => {
+            if !is_quorum(voters.len(), members.len(), params) {
+                return Err(format!("only {} of {} members voted", voters.len(), members.len()));
+            }
+        }
+        QuorumWeight::Age => {
+            let voters_age = voters.iter().map(age).sum::<u64>() as usize;
+            let total_age = members.iter().map(age).sum::<u64>() as usize;
+            if !is_quorum(voters_age, total_age, params) {
+                return Err(format!("voters' total age {} of {} isn't a quorum",
+                                   voters_age,
+                                   total_age));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Versions of their sections in which members joined, rebuilt from the agreements.
+#[derive(Default)]
+struct JoinVersions {
+    /// The version in which each member of an agreed block joined.
+    blocks: BTreeMap<BlockId, BTreeMap<Name, u64>>,
+    /// The version in which each node last joined, for the members that a merge brings in from
+    /// the sibling.
+    nodes: BTreeMap<Name, u64>,
+}
+
+impl JoinVersions {
+    /// Record the version in which each member of the `to` block of an agreed vote joined, from
+    /// those of the `from` block, unless it's already known. Witnessing votes say nothing about
+    /// the `to` block's history.
+    fn record(&mut self, blocks: &Blocks, vote: &Vote) {
+        let from = vote.from.into_block(blocks);
+        let to = vote.to.into_block(blocks);
+        if self.blocks.contains_key(&vote.to) || !to.is_admissible_after(from) {
+            return;
+        }
+        let from_joined = self.blocks.get(&vote.from);
+        let mut to_joined = BTreeMap::new();
+        for name in to.members.iter() {
+            let joined = if from.members.contains(name) {
+                from_joined.and_then(|joined| joined.get(name)).cloned().unwrap_or(from.version)
+            } else if to.prefix == from.prefix {
+                to.version
+            } else {
+                self.nodes.get(name).cloned().unwrap_or(to.version)
+            };
+            to_joined.insert(*name, joined);
+            self.nodes.insert(*name, joined);
+        }
+        self.blocks.insert(vote.to, to_joined);
+    }
+
+    /// The age of a member of `block`: the number of versions since the one it joined in,
+    /// counting both.
+    fn age_in<'a>(&'a self, blocks: &Blocks, block: BlockId) -> impl Fn(&Name) -> u64 + 'a {
+        let version = block.into_block(blocks).version;
+        let joined = self.blocks.get(&block);
+        move |name| version + 1 - joined.and_then(|joined| joined.get(name)).map_or(version, |&v| v)
+    }
+}
+
+/// Whether `num_voters` is strictly more than the quorum ratio of `num_members`.
+///
+/// Deliberately computed without `NodeParams::quorum`, so that a bug in the simulation's own
+/// threshold shows up here rather than being reproduced.
+fn is_quorum(num_voters: usize, num_members: usize, params: &NodeParams) -> bool {
+    num_voters * params.quorum_denominator > num_members * params.quorum_numerator
+}
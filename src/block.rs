@@ -1,24 +1,53 @@
 use name::{Prefix, Name};
 use blocks::Blocks;
+use params::{NodeParams, QuorumWeight};
 
-use std::collections::BTreeSet;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
 use std::collections::hash_map::DefaultHasher;
+use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 
-#[derive(Clone, Debug, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct BlockId(u64);
 
 impl BlockId {
+    /// The ID as an integer, as it's encoded in messages.
+    pub fn to_bits(&self) -> u64 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        BlockId(bits)
+    }
+
     pub fn into_block<'a>(&self, blocks: &'a Blocks) -> &'a Block {
         blocks.get(self).unwrap()
     }
+
+    /// Whether `voters` make up a quorum of the block's members, with their votes weighed as
+    /// `params` says.
+    pub fn is_quorum(&self, blocks: &Blocks, voters: &BTreeSet<Name>, params: &NodeParams) -> bool {
+        match params.quorum_weight {
+            QuorumWeight::Members => is_quorum_of(voters, &self.into_block(blocks).members, params),
+            QuorumWeight::Age => is_age_quorum_of(voters, &blocks.member_ages(self), params),
+        }
+    }
 }
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Block {
     pub prefix: Prefix,
     pub version: u64,
-    pub members: BTreeSet<Name>,
+    /// Shared between blocks and their clones, so that copying a block is cheap.
+    pub members: Arc<BTreeSet<Name>>,
 }
 
 impl Block {
@@ -38,10 +67,10 @@ impl Block {
     }
 
     pub fn should_split(&self, min_split_size: usize) -> bool {
-        let p0 = self.prefix.pushed(false);
+        let [p0, _] = self.prefix.split();
         let mut len0 = 0;
         let mut len1 = 0;
-        for name in &self.members {
+        for name in self.members.iter() {
             if p0.matches(*name) {
                 len0 += 1;
             } else {
@@ -58,7 +87,7 @@ impl Block {
     }
 }
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Vote {
     pub from: BlockId,
     pub to: BlockId,
@@ -78,17 +107,32 @@ impl Vote {
         )
     }
 
-    pub fn is_quorum(&self, blocks: &Blocks, voters: &BTreeSet<Name>) -> bool {
+    /// The members whose votes count towards a quorum for this vote.
+    ///
+    /// This is the `from` block's members, except when a node is being removed, in which case
+    /// it's the remaining members in the `to` block.
+    pub fn quorum_members<'a>(&self, blocks: &'a Blocks) -> &'a BTreeSet<Name> {
         let from = self.from.into_block(blocks);
         let to = self.to.into_block(blocks);
-        let members = if to.members.len() == from.members.len() - 1 &&
+        if to.members.len() == from.members.len() - 1 &&
             from.members.difference(&to.members).count() == 1
         {
             &to.members
         } else {
             &from.members
-        };
-        is_quorum_of(voters, members)
+        }
+    }
+
+    pub fn is_quorum(
+        &self,
+        blocks: &Blocks,
+        voters: &BTreeSet<Name>,
+        params: &NodeParams,
+    ) -> bool {
+        match params.quorum_weight {
+            QuorumWeight::Members => is_quorum_of(voters, self.quorum_members(blocks), params),
+            QuorumWeight::Age => is_age_quorum_of(voters, &blocks.quorum_ages(self), params),
+        }
     }
 }
 
@@ -109,32 +153,57 @@ impl Block {
         Block {
             prefix: Prefix::default(),
             version: 0,
-            members: btreeset!{name},
+            members: Arc::new(btreeset!{name}),
         }
     }
 
     /// Create a new block with a node added.
     pub fn add_node(&self, added: Name) -> Self {
-        let mut members = self.members.clone();
+        let mut members = (*self.members).clone();
         members.insert(added);
         Block {
             prefix: self.prefix,
             version: self.version + 1,
-            members,
+            members: Arc::new(members),
         }
     }
 
     /// Create a new block with a node removed.
     pub fn remove_node(&self, removed: Name) -> Self {
-        let mut members = self.members.clone();
+        let mut members = (*self.members).clone();
         assert!(members.remove(&removed));
         Block {
             prefix: self.prefix,
             version: self.version + 1,
-            members,
+            members: Arc::new(members),
         }
     }
 
+    /// Create the half of this block that has the given child prefix after a split.
+    pub fn split_into(&self, prefix: Prefix) -> Self {
+        assert_eq!(prefix.popped(), self.prefix);
+        Block {
+            prefix,
+            version: self.version + 1,
+            members: Arc::new(
+                self.members
+                    .iter()
+                    .filter(|name| prefix.matches(**name))
+                    .cloned()
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Create the block resulting from merging this block with a sibling.
+    pub fn merge_with(&self, sibling: &Block) -> Self {
+        assert_eq!(self.prefix.sibling(), Some(sibling.prefix));
+        Block {
+            prefix: self.prefix.popped(),
+            version: cmp::max(self.version, sibling.version) + 1,
+            members: Arc::new(self.members.union(&sibling.members).cloned().collect()),
+        }
+    }
 
     /// Is this block admissible after the given other block?
     ///
@@ -195,12 +264,27 @@ impl Block {
 }
 
 /// Return true if `voters` form a quorum of `members`.
-fn is_quorum_of(voters: &BTreeSet<Name>, members: &BTreeSet<Name>) -> bool {
+fn is_quorum_of(voters: &BTreeSet<Name>, members: &BTreeSet<Name>, params: &NodeParams) -> bool {
     #[cfg(not(feature = "fast"))]
     let valid_voters = voters & members;
     #[cfg(feature = "fast")]
     let valid_voters = voters;
 
     assert_eq!(voters.len(), valid_voters.len());
-    valid_voters.len() * 2 > members.len()
+    valid_voters.len() >= params.quorum(members.len())
+}
+
+/// Return true if the total age of `voters` is a quorum of the total age of the members, whose
+/// ages are given by `ages`.
+fn is_age_quorum_of(
+    voters: &BTreeSet<Name>,
+    ages: &BTreeMap<Name, u64>,
+    params: &NodeParams,
+) -> bool {
+    let voters_age: u64 = voters.iter().filter_map(|voter| ages.get(voter)).sum();
+    let total_age: u64 = ages.values().sum();
+    #[cfg(not(feature = "fast"))]
+    assert!(voters.iter().all(|voter| ages.contains_key(voter)));
+
+    voters_age * params.quorum_denominator as u64 > total_age * params.quorum_numerator as u64
 }
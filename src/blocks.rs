@@ -1,127 +1,321 @@
-use std::ops::Deref;
 use std::collections::{BTreeSet, BTreeMap, HashMap};
 use std::borrow::Borrow;
+use std::sync::Arc;
+use fxhash::FxHashMap;
 
 use block::{BlockId, Block, Vote};
 use name::{Name, Prefix};
+use params::NodeParams;
 
 pub type ValidBlocks = BTreeSet<BlockId>;
 pub type CurrentBlocks = BTreeSet<BlockId>;
 
 /// Mapping from votes to voters: (vote.from -> (vote.to -> names)).
-pub type VoteCounts = BTreeMap<BlockId, BTreeMap<BlockId, BTreeSet<Name>>>;
+///
+/// Iteration order is arbitrary, so anything built from it that affects the run must be sorted.
+pub type VoteCounts = FxHashMap<BlockId, FxHashMap<BlockId, BTreeSet<Name>>>;
+
+/// The votes for the history of a block back to the last split, as found by
+/// `Blocks::trace_chain_segment`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChainSegment {
+    pub votes: BTreeSet<(Vote, BTreeSet<Name>)>,
+    /// The oldest block reached, if the history stopped short of the split because we know of
+    /// no vote leading to it.
+    pub gap: Option<BlockId>,
+}
 
-pub struct Blocks(HashMap<BlockId, Block>);
+/// A way of creating a speculative block from an existing one.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Derivation {
+    /// Add a node.
+    Add(Name),
+    /// Remove a node.
+    Remove(Name),
+    /// Keep only the members matching a child prefix.
+    Split(Prefix),
+    /// Merge with a sibling block.
+    Merge(BlockId),
+}
 
-impl Deref for Blocks {
-    type Target = HashMap<BlockId, Block>;
+/// Health score below which a section is at risk of failing.
+pub const AT_RISK_SCORE: f64 = 0.5;
+
+/// Health of a section, as scored by `Blocks::section_health`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SectionHealth {
+    /// Members of the section's newest block, as a fraction of the minimum section size.
+    pub size: f64,
+    /// Fraction of the pairs of members that are connected to each other.
+    pub connectivity: f64,
+    /// Number of versions between the section's oldest and newest blocks.
+    pub staleness: u64,
+    /// Overall score, from 0 for a failing section to 1 for a healthy one.
+    pub score: f64,
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl SectionHealth {
+    /// Whether the section is unhealthy enough that it may soon force-merge or lose consistency.
+    pub fn is_at_risk(&self) -> bool {
+        self.score < AT_RISK_SCORE
     }
 }
 
+/// Mapping from a block and a derivation to the ID of the derived block.
+type Derived = FxHashMap<(BlockId, Derivation), BlockId>;
+
+/// Mapping from an agreed block to the version of its section in which each of its members
+/// joined, for weighing votes by age.
+type JoinVersions = FxHashMap<BlockId, BTreeMap<Name, u64>>;
+
+/// Store of block contents, keyed by ID.
+///
+/// Overlays created with `overlay` share the blocks inserted so far, but keep blocks inserted into
+/// them to themselves until they're passed back to `absorb`. This lets nodes create blocks from
+/// several threads at once.
+///
+/// Blocks created with `derive` are remembered by how they were created, so that the many nodes
+/// voting for the same speculative block on every step only build it once.
+///
+/// The agreements recorded with `record_agreement` give the members of agreed blocks their ages
+/// (see `member_ages`). Ages aren't part of the blocks themselves, so they follow the agreed
+/// history rather than the way a block happened to be derived.
+pub struct Blocks {
+    shared: Arc<HashMap<BlockId, Block>>,
+    added: HashMap<BlockId, Block>,
+    shared_derived: Arc<Derived>,
+    derived: Derived,
+    shared_joined: Arc<JoinVersions>,
+    joined: JoinVersions,
+    /// The version in which each node last joined, for members brought in by a merge.
+    last_joined: FxHashMap<Name, u64>,
+}
+
 impl Blocks {
     pub fn new() -> Blocks {
-        Blocks(HashMap::new())
+        Blocks {
+            shared: Arc::new(HashMap::new()),
+            added: HashMap::new(),
+            shared_derived: Arc::new(Derived::default()),
+            derived: Derived::default(),
+            shared_joined: Arc::new(JoinVersions::default()),
+            joined: JoinVersions::default(),
+            last_joined: FxHashMap::default(),
+        }
+    }
+
+    pub fn get(&self, id: &BlockId) -> Option<&Block> {
+        self.added.get(id).or_else(|| self.shared.get(id))
     }
 
     pub fn insert(&mut self, block: Block) -> BlockId {
         let id = block.get_id();
-        self.0.insert(id, block);
+        if !self.shared.contains_key(&id) {
+            self.added.insert(id, block);
+        }
         id
     }
 
-    /// Compute the set of blocks that become valid as a result of adding `new_vote`.
+    /// Iterate over every block, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Block> {
+        self.shared.values().chain(self.added.values())
+    }
+
+    /// Insert the block derived from the block `from`, returning its ID.
+    ///
+    /// The block is only built if it hasn't been derived in the same way before.
+    pub fn derive(&mut self, from: BlockId, derivation: Derivation) -> BlockId {
+        let key = (from, derivation);
+        if let Some(&id) = self.derived.get(&key).or_else(
+            || self.shared_derived.get(&key),
+        )
+        {
+            return id;
+        }
+        let block = {
+            let from = from.into_block(self);
+            match derivation {
+                Derivation::Add(name) => from.add_node(name),
+                Derivation::Remove(name) => from.remove_node(name),
+                Derivation::Split(prefix) => from.split_into(prefix),
+                Derivation::Merge(sibling) => from.merge_with(sibling.into_block(self)),
+            }
+        };
+        let id = self.insert(block);
+        self.derived.insert(key, id);
+        id
+    }
+
+    /// Record that `vote` was agreed, giving the members of its `to` block the versions of their
+    /// section in which they joined.
+    ///
+    /// Members carried over from the `from` block keep their versions, members added to the
+    /// section join in the `to` block, and members that a merge brings in from the sibling keep
+    /// the version they were last recorded with. Only the first agreement on a block counts, and
+    /// witnessing votes say nothing about the `to` block's history. Agreements should be
+    /// recorded here rather than in overlays, so that every node weighs votes the same way.
+    ///
+    /// Ages are global knowledge rather than part of each node's view: once any node agrees on a
+    /// block, every node weighs votes by its members' ages, including nodes that haven't seen
+    /// the agreement yet. This is a shortcut, which assumes that the members of a section learn
+    /// of its agreements soon enough after each other that weighing votes by ages they haven't
+    /// seen agreed doesn't change the outcome.
+    pub fn record_agreement(&mut self, vote: &Vote) {
+        if self.joined.contains_key(&vote.to) || self.shared_joined.contains_key(&vote.to) {
+            return;
+        }
+        let from_joined = self.join_versions(&vote.from);
+        let joined: BTreeMap<Name, u64> = {
+            let from = vote.from.into_block(self);
+            let to = vote.to.into_block(self);
+            if !to.is_admissible_after(from) {
+                return;
+            }
+            to.members
+                .iter()
+                .map(|name| {
+                    let version = from_joined.get(name).cloned().or_else(|| {
+                        if to.prefix == from.prefix {
+                            None
+                        } else {
+                            self.last_joined.get(name).cloned()
+                        }
+                    });
+                    (*name, version.unwrap_or(to.version))
+                })
+                .collect()
+        };
+        self.last_joined.extend(joined.iter().map(|(&name, &version)| (name, version)));
+        self.joined.insert(vote.to, joined);
+    }
+
+    /// The version of its section in which each member of `block` joined.
+    ///
+    /// This is only known for blocks that agreements were recorded for. The members of other
+    /// blocks, like genesis blocks and blocks loaded from a snapshot, count as having joined in
+    /// the block itself.
+    pub fn join_versions(&self, block: &BlockId) -> BTreeMap<Name, u64> {
+        match self.joined.get(block).or_else(|| self.shared_joined.get(block)) {
+            Some(joined) => joined.clone(),
+            None => {
+                let block = block.into_block(self);
+                block.members.iter().map(|&name| (name, block.version)).collect()
+            }
+        }
+    }
+
+    /// The age of each member of `block`: the number of versions of its section that it's been
+    /// a member for, including this one.
+    pub fn member_ages(&self, block: &BlockId) -> BTreeMap<Name, u64> {
+        let version = block.into_block(self).version;
+        self.join_versions(block)
+            .into_iter()
+            .map(|(name, joined)| (name, version + 1 - joined))
+            .collect()
+    }
+
+    /// The ages of the members whose votes count towards a quorum for `vote`.
+    ///
+    /// The `to` block of a removal hasn't been agreed yet, so the remaining members' ages come
+    /// from the `from` block.
+    pub fn quorum_ages(&self, vote: &Vote) -> BTreeMap<Name, u64> {
+        let members = vote.quorum_members(self);
+        let mut ages = self.member_ages(&vote.from);
+        ages.retain(|name, _| members.contains(name));
+        ages
+    }
+
+    /// Create an overlay that can see every block inserted so far.
+    pub fn overlay(&mut self) -> Blocks {
+        if !self.added.is_empty() {
+            // Only copies the shared blocks if an overlay is still holding on to them.
+            Arc::make_mut(&mut self.shared).extend(self.added.drain());
+        }
+        if !self.derived.is_empty() {
+            Arc::make_mut(&mut self.shared_derived).extend(self.derived.drain());
+        }
+        if !self.joined.is_empty() {
+            Arc::make_mut(&mut self.shared_joined).extend(self.joined.drain());
+        }
+        Blocks {
+            shared: Arc::clone(&self.shared),
+            added: HashMap::new(),
+            shared_derived: Arc::clone(&self.shared_derived),
+            derived: Derived::default(),
+            shared_joined: Arc::clone(&self.shared_joined),
+            joined: JoinVersions::default(),
+            last_joined: FxHashMap::default(),
+        }
+    }
+
+    /// Insert the blocks that were inserted into an overlay.
+    pub fn absorb(&mut self, overlay: Blocks) {
+        for (_, block) in overlay.added {
+            self.insert(block);
+        }
+        self.derived.extend(overlay.derived);
+    }
+
+    /// Compute the set of blocks that become valid as a result of adding `new_votes`.
     ///
     /// * `valid_blocks`: the set of valid blocks.
-    /// * `vote_counts`: the vote counts, including the vote for `new_vote` that was just added.
-    /// * `new_vote`: a vote that just voted for by a node.
+    /// * `vote_counts`: the vote counts, including the votes in `new_votes`.
+    /// * `new_votes`: votes that have gained voters since the valid blocks were last updated.
+    ///
+    /// Every vote with a quorum from a valid block already leads to a valid block, so the only
+    /// votes that need checking are the new votes from valid blocks, and the votes from blocks
+    /// that become valid along the way.
     ///
     /// Return value:
-    /// Set of votes that become valid as a result of `new_vote`. The `to` blocks of these
+    /// Set of votes that become valid as a result of `new_votes`. The `to` blocks of these
     /// votes are the new valid blocks that should be added to `valid_blocks`.
     pub fn new_valid_blocks(
         &self,
         valid_blocks: &ValidBlocks,
         vote_counts: &VoteCounts,
         new_votes: BTreeSet<Vote>,
+        params: &NodeParams,
     ) -> BTreeSet<(Vote, BTreeSet<Name>)> {
-        // Set of valid blocks to branch out from.
-        // Stored as a set of votes where the frontier blocks are the "to" component,
-        // and the nodes that voted for them are held alongside (a little hacky).
-        let mut frontier: BTreeSet<(Vote, BTreeSet<Name>)> = BTreeSet::new();
-
-        // Set of votes for new valid blocks.
+        let mut pending: Vec<Vote> = new_votes
+            .into_iter()
+            .filter(|vote| valid_blocks.contains(&vote.from))
+            .collect();
+        let mut newly_valid = BTreeSet::new();
         let mut new_valid_votes = BTreeSet::new();
 
-        // If a new vote extends an existing valid block, we need to add it to the frontier set
-        // so we can branch out from it.
-        for new_vote in new_votes {
-            if valid_blocks.contains(&new_vote.from) {
-                // This dummy vote is a bit of hack, we really just need init_vote.to = new_vote.from.
-                let init_vote = Vote {
-                    from: new_vote.from,
-                    to: new_vote.from,
-                };
-                frontier.insert((init_vote, BTreeSet::new()));
+        while let Some(vote) = pending.pop() {
+            if valid_blocks.contains(&vote.to) {
+                continue;
             }
-        }
-
-        let mut visited_edges = BTreeSet::new();
-        // the frontier votes' `to` blocks are already validated
-        while !frontier.is_empty() {
-            let mut new_frontier = BTreeSet::new();
-
-            for &(ref vote, _) in frontier.iter() {
-                visited_edges.insert(vote.clone());
+            let voters = match vote_counts.get(&vote.from).and_then(|map| map.get(&vote.to)) {
+                Some(voters) => voters,
+                None => continue,
+            };
+            if !self.is_valid_successor(&vote, voters, params) {
+                continue;
             }
 
-            for (vote, voters) in frontier {
-                // Branch out to all now valid successors of this block which we haven't visited
-                // yet.
-                new_frontier.extend(self.successors(vote_counts, vote.to).into_iter().filter(
-                    |&(ref vote, _)| !visited_edges.contains(vote),
-                ));
-
-                // Frontier block is valid. If new, add its vote to the set of new valid votes.
-                if !valid_blocks.contains(&vote.to) {
-                    new_valid_votes.insert((vote, voters));
-                }
+            // The first time a block becomes valid, check the votes that lead on from it.
+            let from = vote.to;
+            if newly_valid.insert(from) {
+                pending.extend(vote_counts.get(&from).into_iter().flat_map(|map| {
+                    map.keys().map(move |&to| Vote { from, to })
+                }));
             }
-
-            frontier = new_frontier;
+            new_valid_votes.insert((vote, voters.clone()));
         }
 
         new_valid_votes
     }
 
-    /// Return all votes for blocks that succeed the given block.
+    /// Return true if `vote`'s `to` block may succeed its `from` block, and `voters` are a quorum.
     ///
     /// a succeeds b == b witnesses a.
-    fn successors<'a>(
-        &self,
-        vote_counts: &'a VoteCounts,
-        from: BlockId,
-    ) -> Vec<(Vote, BTreeSet<Name>)> {
-        let from_block = from.into_block(self);
-        vote_counts
-            .get(&from)
-            .into_iter()
-            .flat_map(|inner_map| inner_map.iter())
-            .map(|(id, votes)| (id.into_block(self), votes))
-            .filter(move |&(succ, _)| {
-                succ.prefix.is_neighbour(&from_block.prefix) || succ.is_admissible_after(from_block)
-            })
-            .map(move |(succ, voters)| {
-                let vote = Vote {
-                    from: from,
-                    to: succ.get_id(),
-                };
-                (vote, voters.clone())
-            })
-            .filter(|&(ref vote, ref voters)| vote.is_quorum(self, voters))
-            .collect()
+    fn is_valid_successor(&self, vote: &Vote, voters: &BTreeSet<Name>, params: &NodeParams) -> bool {
+        let from = vote.from.into_block(self);
+        let to = vote.to.into_block(self);
+        (to.prefix.is_neighbour(&from.prefix) || to.is_admissible_after(from)) &&
+            vote.is_quorum(self, voters, params)
     }
 
     /// Compute the set of candidates for current blocks from a set of valid blocks.
@@ -179,6 +373,14 @@ impl Blocks {
             .collect()
     }
 
+    /// Number of distinct sections and nodes in the given blocks.
+    pub fn network_size(&self, blocks: &BTreeSet<BlockId>) -> (usize, usize) {
+        let contents = self.block_contents(blocks);
+        let prefixes: BTreeSet<_> = contents.iter().map(|b| b.prefix).collect();
+        let nodes: BTreeSet<_> = contents.iter().flat_map(|b| b.members.iter()).collect();
+        (prefixes.len(), nodes.len())
+    }
+
     /// Blocks that match our name, but that we are not necessarily a part of.
     pub fn section_blocks<'a>(
         &'a self,
@@ -203,6 +405,60 @@ impl Blocks {
             .collect()
     }
 
+    /// Score the health of the section with the given prefix, as described by `blocks`, which
+    /// are typically the current blocks of every node.
+    ///
+    /// The score falls as the section shrinks below the minimum section size, as its members
+    /// lose their connections to each other (`connections` maps each live node to its
+    /// connections), and as nodes fall behind the newest version of the section. Returns `None`
+    /// if there are no blocks for the prefix.
+    pub fn section_health(
+        &self,
+        blocks: &BTreeSet<BlockId>,
+        prefix: Prefix,
+        connections: &BTreeMap<Name, BTreeSet<Name>>,
+        params: &NodeParams,
+    ) -> Option<SectionHealth> {
+        let section_blocks = self.blocks_for_prefix(blocks, prefix);
+        let newest = section_blocks.iter().max_by_key(|block| block.version)?;
+        let oldest = section_blocks.iter().map(|block| block.version).min()?;
+
+        let (num_sections, num_nodes) = self.network_size(blocks);
+        let min_section_size = params.min_section_size_for(num_sections, num_nodes);
+        let size = newest.members.len() as f64 / min_section_size.max(1) as f64;
+
+        let is_connected = |n1: &Name, n2: &Name| {
+            connections.get(n1).is_some_and(|peers| peers.contains(n2)) &&
+                connections.get(n2).is_some_and(|peers| peers.contains(n1))
+        };
+        let members: Vec<_> = newest.members.iter().collect();
+        let mut pairs = 0;
+        let mut connected = 0;
+        for (i, n1) in members.iter().enumerate() {
+            for n2 in &members[i + 1..] {
+                pairs += 1;
+                if is_connected(n1, n2) {
+                    connected += 1;
+                }
+            }
+        }
+        let connectivity = if pairs == 0 {
+            1.0
+        } else {
+            connected as f64 / pairs as f64
+        };
+
+        let staleness = newest.version - oldest;
+        let score = size.min(1.0) * connectivity / (1 + staleness) as f64;
+
+        Some(SectionHealth {
+            size,
+            connectivity,
+            staleness,
+            score,
+        })
+    }
+
     /// Find predecessors of the given block with a quorum of votes.
     ///
     /// Return the predecessors (blocks), as well as the votes (edges) from those predecessors to `block`.
@@ -210,6 +466,7 @@ impl Blocks {
         &self,
         block: &BlockId,
         rev_votes: &VoteCounts,
+        params: &NodeParams,
     ) -> BTreeSet<(BlockId, Vote, BTreeSet<Name>)> {
         rev_votes.get(block).map_or_else(BTreeSet::new, |map| {
             map.into_iter()
@@ -218,7 +475,7 @@ impl Blocks {
                         from: *block_from,
                         to: *block,
                     };
-                    vote.is_quorum(self, votes)
+                    vote.is_quorum(self, votes, params)
                 })
                 .map(|(block_from, votes)| {
                     (
@@ -235,11 +492,33 @@ impl Blocks {
     }
 
     /// Get all the votes for the history of `block` back to the last split.
+    ///
+    /// If a predecessor is missing, a warning is logged and the history is truncated there. Use
+    /// `trace_chain_segment` to find out where.
     pub fn chain_segment(
         &self,
         block: &BlockId,
         rev_votes: &VoteCounts,
+        params: &NodeParams,
     ) -> BTreeSet<(Vote, BTreeSet<Name>)> {
+        let segment = self.trace_chain_segment(block, rev_votes, params);
+        if let Some(gap) = segment.gap {
+            warn!(
+                "WARNING: couldn't find a predecessor for: {:?}",
+                gap.into_block(self)
+            );
+        }
+        segment.votes
+    }
+
+    /// Get all the votes for the history of `block` back to the last split, along with the
+    /// oldest block reached if a predecessor is missing.
+    pub fn trace_chain_segment(
+        &self,
+        block: &BlockId,
+        rev_votes: &VoteCounts,
+        params: &NodeParams,
+    ) -> ChainSegment {
         let mut segment_votes = btreeset!{};
 
         let mut oldest_block = block.into_block(self);
@@ -247,7 +526,7 @@ impl Blocks {
 
         // Go back in history until we find the block that our section split out of.
         while block.prefix.is_prefix_of(&oldest_block.prefix) && oldest_block.version > 0 {
-            let predecessors = self.predecessors(&oldest_block.get_id(), rev_votes);
+            let predecessors = self.predecessors(&oldest_block.get_id(), rev_votes, params);
             match predecessors.into_iter().find(|&(_, ref vote, _)| {
                 !vote.is_witnessing(self)
             }) {
@@ -256,16 +535,18 @@ impl Blocks {
                     oldest_block = predecessor.into_block(self);
                 }
                 None => {
-                    warn!(
-                        "WARNING: couldn't find a predecessor for: {:?}",
-                        oldest_block
-                    );
-                    break;
+                    return ChainSegment {
+                        votes: segment_votes,
+                        gap: Some(oldest_block.get_id()),
+                    };
                 }
             }
         }
 
-        segment_votes
+        ChainSegment {
+            votes: segment_votes,
+            gap: None,
+        }
     }
 
     pub fn block_contents<'a, K, I: IntoIterator<Item = K>>(&'a self, blocks: I) -> Vec<&'a Block>
@@ -282,9 +563,12 @@ impl Blocks {
 #[cfg(test)]
 mod test {
     use super::*;
+    use name::{NameBits, NAME_BITS};
+    use params::QuorumWeight;
+    use std::sync::Arc;
 
     fn short_name(name: u8) -> Name {
-        Name((name as u64) << (64 - 8))
+        Name(NameBits::from(name) << (NAME_BITS - 8))
     }
 
     #[test]
@@ -293,12 +577,12 @@ mod test {
         let block1 = blocks.insert(Block {
             prefix: Prefix::empty(),
             version: 0,
-            members: btreeset!{ Name(0), short_name(0b10000000) },
+            members: Arc::new(btreeset!{ Name(0), short_name(0b10000000) }),
         });
         let block2 = blocks.insert(Block {
             prefix: Prefix::short(1, 0),
             version: 1,
-            members: btreeset!{ Name(0) },
+            members: Arc::new(btreeset!{ Name(0) }),
         });
         let valid_blocks = btreeset![block1, block2];
 
@@ -317,36 +601,37 @@ mod test {
         let b1 = Block {
             prefix: Prefix::empty(),
             version: 0,
-            members: b1_members.clone(),
+            members: Arc::new(b1_members.clone()),
         };
         let b2_members = btreeset!{ Name(0), Name(1), Name(2) };
         let b2 = Block {
             prefix: Prefix::short(1, 0),
             version: 1,
-            members: b2_members.clone(),
+            members: Arc::new(b2_members.clone()),
         };
         let b3_members = &b2_members | &btreeset!{ Name(5) };
         let b3 = Block {
             prefix: Prefix::short(1, 0),
             version: 2,
-            members: b3_members.clone(),
+            members: Arc::new(b3_members.clone()),
         };
         let mut blocks = Blocks::new();
         let b1_id = blocks.insert(b1);
         let b2_id = blocks.insert(b2);
         let b3_id = blocks.insert(b3);
 
-        let rev_votes =
+        let rev_votes: VoteCounts =
             btreemap! {
             b2_id => btreemap! {
                 b1_id => b1_members.clone(),
-            },
+            }.into_iter().collect(),
             b3_id => btreemap! {
                 b2_id => b2_members.clone(),
-            },
-        };
+            }.into_iter().collect(),
+        }.into_iter()
+                .collect();
 
-        let segment_votes = blocks.chain_segment(&b3_id, &rev_votes);
+        let segment_votes = blocks.chain_segment(&b3_id, &rev_votes, &NodeParams::default());
 
         let v12 = Vote {
             from: b1_id,
@@ -359,8 +644,209 @@ mod test {
         let expected =
             btreeset! {
             (v12, b1_members),
-            (v23, b2_members),
+            (v23.clone(), b2_members.clone()),
         };
         assert_eq!(segment_votes, expected);
+
+        // Without the vote into b2, the history stops there.
+        let mut rev_votes = rev_votes;
+        rev_votes.remove(&b2_id);
+        let segment = blocks.trace_chain_segment(&b3_id, &rev_votes, &NodeParams::default());
+        assert_eq!(segment.votes, btreeset!{ (v23, b2_members) });
+        assert_eq!(segment.gap, Some(b2_id));
+    }
+
+    #[test]
+    fn votes_out_of_order() {
+        let mut blocks = Blocks::new();
+        let b1 = Block::genesis(Name(0)).add_node(Name(1)).add_node(Name(2));
+        let b2 = b1.add_node(Name(3));
+        let b3 = b2.add_node(Name(4));
+        let v12 = Vote {
+            from: blocks.insert(b1.clone()),
+            to: blocks.insert(b2.clone()),
+        };
+        let v23 = Vote {
+            from: v12.to,
+            to: blocks.insert(b3),
+        };
+        let valid_blocks = btreeset!{ v12.from };
+        let params = NodeParams::default();
+
+        // The vote from b2 can't be valid until b2 is.
+        let mut vote_counts = VoteCounts::default();
+        vote_counts.entry(v23.from).or_insert_with(Default::default).insert(
+            v23.to,
+            (*b2.members).clone(),
+        );
+        let new_valid =
+            blocks.new_valid_blocks(&valid_blocks, &vote_counts, btreeset!{ v23.clone() }, &params);
+        assert!(new_valid.is_empty());
+
+        // Once the vote for b2 arrives, both votes become valid.
+        vote_counts.entry(v12.from).or_insert_with(Default::default).insert(
+            v12.to,
+            (*b1.members).clone(),
+        );
+        let new_valid =
+            blocks.new_valid_blocks(&valid_blocks, &vote_counts, btreeset!{ v12.clone() }, &params);
+        let expected = btreeset!{
+            (v12, (*b1.members).clone()),
+            (v23, (*b2.members).clone()),
+        };
+        assert_eq!(new_valid, expected);
+    }
+
+    #[test]
+    fn derive_shares_blocks() {
+        let mut blocks = Blocks::new();
+        let genesis = blocks.insert(Block::genesis(Name(0)).add_node(short_name(0b10000000)));
+        let added = blocks.derive(genesis, Derivation::Add(Name(1)));
+        assert_eq!(added, genesis.into_block(&blocks).add_node(Name(1)).get_id());
+
+        // Blocks derived in overlays are seen by later overlays once absorbed.
+        let mut overlay = blocks.overlay();
+        let [p0, p1] = Prefix::empty().split();
+        let s0 = overlay.derive(genesis, Derivation::Split(p0));
+        let s1 = overlay.derive(genesis, Derivation::Split(p1));
+        blocks.absorb(overlay);
+        let mut overlay = blocks.overlay();
+        assert_eq!(overlay.derive(genesis, Derivation::Split(p0)), s0);
+        assert!(overlay.added.is_empty());
+
+        let merged = overlay.derive(s0, Derivation::Merge(s1));
+        assert_eq!(merged, overlay.derive(s1, Derivation::Merge(s0)));
+        assert_eq!(
+            *merged.into_block(&overlay).members,
+            btreeset!{ Name(0), short_name(0b10000000) }
+        );
+    }
+
+    #[test]
+    fn member_ages() {
+        let agree = |blocks: &mut Blocks, from, derivation| {
+            let to = blocks.derive(from, derivation);
+            blocks.record_agreement(&Vote { from, to });
+            to
+        };
+        let mut blocks = Blocks::new();
+        let elder = short_name(0b10000000);
+        let genesis = blocks.insert(Block::genesis(Name(0)).add_node(elder));
+        let added = agree(&mut blocks, genesis, Derivation::Add(Name(1)));
+        let removed = agree(&mut blocks, added, Derivation::Remove(Name(0)));
+        assert_eq!(blocks.member_ages(&genesis), btreemap!{ Name(0) => 1, elder => 1 });
+        assert_eq!(blocks.member_ages(&removed), btreemap!{ Name(1) => 2, elder => 3 });
+
+        // Reaching the same block another way doesn't change the agreed history.
+        let elder_only = blocks.derive(genesis, Derivation::Remove(Name(0)));
+        assert_eq!(agree(&mut blocks, elder_only, Derivation::Add(Name(1))), removed);
+        assert_eq!(blocks.member_ages(&removed), btreemap!{ Name(1) => 2, elder => 3 });
+
+        // Members keep ageing through splits and merges.
+        let [p0, p1] = Prefix::empty().split();
+        let s0 = agree(&mut blocks, removed, Derivation::Split(p0));
+        let s1 = agree(&mut blocks, removed, Derivation::Split(p1));
+        assert_eq!(blocks.member_ages(&s1), btreemap!{ elder => 4 });
+        let merged = agree(&mut blocks, s0, Derivation::Merge(s1));
+        assert_eq!(blocks.member_ages(&merged), btreemap!{ Name(1) => 4, elder => 5 });
+    }
+
+    // Ages are fixed by the first node to agree on a block, and nodes that haven't agreed on it
+    // yet already see them.
+    #[test]
+    fn ages_from_first_agreement() {
+        let mut blocks = Blocks::new();
+        let genesis = blocks.insert(Block::genesis(Name(0)));
+        let to = blocks.derive(genesis, Derivation::Add(Name(1)));
+        let vote = Vote { from: genesis, to };
+        assert_eq!(blocks.member_ages(&to), btreemap!{ Name(0) => 1, Name(1) => 1 });
+
+        // One node agrees on the vote at one step...
+        blocks.record_agreement(&vote);
+        let ages = btreemap!{ Name(0) => 2, Name(1) => 1 };
+        assert_eq!(blocks.member_ages(&to), ages);
+
+        // ...and on the next, another node that's still waiting for the agreement weighs votes
+        // by the same ages, in its overlay.
+        let waiting = blocks.overlay();
+        assert_eq!(waiting.member_ages(&to), ages);
+
+        // Once that node agrees as well, nothing changes.
+        blocks.record_agreement(&vote);
+        assert_eq!(blocks.member_ages(&to), ages);
+        assert_eq!(blocks.overlay().member_ages(&to), ages);
+    }
+
+    #[test]
+    fn age_weighted_quorum() {
+        let params = NodeParams {
+            quorum_weight: QuorumWeight::Age,
+            ..NodeParams::default()
+        };
+        let mut blocks = Blocks::new();
+        let elders: BTreeSet<Name> = (0..4).map(Name).collect();
+        let mut block = blocks.insert(Block {
+            prefix: Prefix::empty(),
+            version: 0,
+            members: Arc::new(elders.clone()),
+        });
+        let newcomers: BTreeSet<Name> = (4..9).map(Name).collect();
+        for &name in &newcomers {
+            let from = block;
+            block = blocks.derive(from, Derivation::Add(name));
+            blocks.record_agreement(&Vote { from, to: block });
+        }
+
+        // The newcomers outnumber the elders, but the elders are older.
+        assert!(block.is_quorum(&blocks, &newcomers, &NodeParams::default()));
+        assert!(!block.is_quorum(&blocks, &newcomers, &params));
+        assert!(!block.is_quorum(&blocks, &elders, &NodeParams::default()));
+        assert!(block.is_quorum(&blocks, &elders, &params));
+
+        // Removing a newcomer is decided by the remaining members' ages.
+        let to = blocks.derive(block, Derivation::Remove(Name(8)));
+        let removal = Vote { from: block, to };
+        assert!(!removal.is_quorum(&blocks, &(4..8).map(Name).collect(), &params));
+        assert!(removal.is_quorum(&blocks, &elders, &params));
+    }
+
+    #[test]
+    fn section_health() {
+        let params = NodeParams {
+            min_section_size: 4,
+            ..NodeParams::default()
+        };
+        let mut blocks = Blocks::new();
+        let mut genesis = Block::genesis(Name(0));
+        for i in 1..4 {
+            genesis = genesis.add_node(Name(i));
+        }
+        let old = blocks.insert(genesis);
+        let new = blocks.derive(old, Derivation::Add(Name(4)));
+        let connected_to = |name: Name, peers: NameBits| -> BTreeSet<Name> {
+            (0..peers).map(Name).filter(|&peer| peer != name).collect()
+        };
+
+        // Some nodes are still on the old version, and the new member isn't connected yet.
+        let mut connections: BTreeMap<_, _> =
+            (0..4).map(|i| (Name(i), connected_to(Name(i), 4))).collect();
+        let health = blocks
+            .section_health(&btreeset!{ old, new }, Prefix::empty(), &connections, &params)
+            .unwrap();
+        assert_eq!(health.size, 1.25);
+        assert_eq!(health.connectivity, 0.6);
+        assert_eq!(health.staleness, 1);
+        assert!(health.is_at_risk());
+
+        connections = (0..5).map(|i| (Name(i), connected_to(Name(i), 5))).collect();
+        let health = blocks
+            .section_health(&btreeset!{ new }, Prefix::empty(), &connections, &params)
+            .unwrap();
+        assert_eq!(health.score, 1.0);
+        assert!(!health.is_at_risk());
+
+        let p0 = Prefix::empty().pushed(false);
+        assert!(blocks.section_health(&btreeset!{ new }, p0, &connections, &params).is_none());
+
     }
 }
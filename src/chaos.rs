@@ -0,0 +1,107 @@
+//! Chaos mode: adversarial network conditions chosen at random during the stable phase.
+//!
+//! Every action is drawn from the seeded random number generator, and logged and recorded in the
+//! trace as it's taken, so a failing run can be reproduced from its seed and the actions that
+//! led up to the failure read off its log.
+
+use name::Name;
+use params::SimulationParams;
+use random::{random, sample, sample_single};
+
+use std::cmp;
+use std::collections::BTreeSet;
+
+/// A single chaos action taken by the simulation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ChaosAction {
+    /// Lose the connection between a pair of nodes, as a random disconnection would.
+    Disconnect(Name, Name),
+    /// Cut the nodes off from every other node. The lost connections are re-established in the
+    /// same way as those lost to random disconnections.
+    Partition(BTreeSet<Name>),
+    /// Lose each delivered message with probability `prob`, until step `until`.
+    MessageLoss { prob: f64, until: u64 },
+    /// Deliver each delivered message twice with probability `prob`, until step `until`.
+    Duplicate { prob: f64, until: u64 },
+    /// Hold every message to `node` until its delivery deadline, until step `until`.
+    SlowNode { node: Name, until: u64 },
+}
+
+impl ChaosAction {
+    /// Pick a random action to take at `step`, within the bounds set by `params`.
+    ///
+    /// Returns `None` if there are too few nodes for any action.
+    pub fn random(params: &SimulationParams, step: u64, nodes: &[Name]) -> Option<Self> {
+        if nodes.len() < 2 {
+            return None;
+        }
+        let until = step + 1 + random::<u64>() % cmp::max(params.chaos_duration, 1);
+        let action = match random::<u32>() % 5 {
+            0 => {
+                let pair = sample(nodes.iter().cloned(), 2);
+                ChaosAction::Disconnect(pair[0], pair[1])
+            }
+            1 => {
+                // Leave at least one node on the other side.
+                let max_size = cmp::min(params.chaos_max_partition, nodes.len() - 1);
+                let size = 1 + random::<usize>() % cmp::max(max_size, 1);
+                ChaosAction::Partition(sample(nodes.iter().cloned(), size).into_iter().collect())
+            }
+            2 => {
+                ChaosAction::MessageLoss {
+                    prob: random::<f64>() * params.chaos_max_loss,
+                    until,
+                }
+            }
+            3 => {
+                ChaosAction::Duplicate {
+                    prob: random::<f64>() * params.chaos_max_duplicate,
+                    until,
+                }
+            }
+            _ => {
+                ChaosAction::SlowNode {
+                    node: sample_single(nodes.iter().cloned())?,
+                    until,
+                }
+            }
+        };
+        Some(action)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn actions_within_bounds() {
+        let params = SimulationParams {
+            chaos_duration: 10,
+            chaos_max_partition: 3,
+            chaos_max_loss: 0.1,
+            chaos_max_duplicate: 0.2,
+            ..SimulationParams::default()
+        };
+        let nodes: Vec<Name> = (0..8).map(Name).collect();
+        let step = 100;
+        assert_eq!(ChaosAction::random(&params, step, &nodes[..1]), None);
+        for _ in 0..500 {
+            match ChaosAction::random(&params, step, &nodes).unwrap() {
+                ChaosAction::Disconnect(n1, n2) => assert_ne!(n1, n2),
+                ChaosAction::Partition(partition) => {
+                    assert!(!partition.is_empty() && partition.len() <= 3)
+                }
+                ChaosAction::MessageLoss { prob, until } => {
+                    assert!(prob <= 0.1 && until > step && until <= step + 10)
+                }
+                ChaosAction::Duplicate { prob, until } => {
+                    assert!(prob <= 0.2 && until > step && until <= step + 10)
+                }
+                ChaosAction::SlowNode { node, until } => {
+                    assert!(nodes.contains(&node) && until > step && until <= step + 10)
+                }
+            }
+        }
+    }
+}
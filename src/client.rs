@@ -0,0 +1,111 @@
+//! Clients: nodes that follow the section they belong to without ever being members of it.
+//!
+//! A client connects to every member of its section, and each member tells it about every new
+//! block for the section. Clients never vote or appear in blocks, so they don't change the
+//! behaviour of the network, only the number of messages its members send.
+
+use block::{Block, BlockId};
+use blocks::Blocks;
+use message::Message;
+use message::MessageContent::*;
+use name::Name;
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClientNode {
+    pub name: Name,
+    /// The latest block we've been told of for our section, if any.
+    pub section: Option<BlockId>,
+    /// Section members we've asked to keep us up to date.
+    pub connected_to: BTreeSet<Name>,
+    /// Number of block updates we've received, including repeats of blocks we already knew.
+    pub updates_received: u64,
+}
+
+impl fmt::Display for ClientNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Client({})", self.name)
+    }
+}
+
+impl ClientNode {
+    /// Create a client of the given section, along with the messages connecting it to the
+    /// section's members.
+    pub fn new(name: Name, section: &Block) -> (Self, Vec<Message>) {
+        let mut client = ClientNode {
+            name,
+            section: Some(section.get_id()),
+            connected_to: BTreeSet::new(),
+            updates_received: 0,
+        };
+        let messages = client.connect(section);
+        (client, messages)
+    }
+
+    /// Connect to the members of `section` that we aren't already connected to.
+    fn connect(&mut self, section: &Block) -> Vec<Message> {
+        let content = Arc::new(ClientConnect);
+        let new_members: Vec<Name> = section
+            .members
+            .iter()
+            .filter(|name| !self.connected_to.contains(name))
+            .cloned()
+            .collect();
+        self.connected_to = (*section.members).clone();
+        new_members
+            .into_iter()
+            .map(|member| {
+                Message {
+                    sender: self.name,
+                    recipient: member,
+                    content: Arc::clone(&content),
+                    trace_id: 0,
+                }
+            })
+            .collect()
+    }
+
+    /// Handle a message from a section member, returning any messages we'd like to send.
+    pub fn handle_message(&mut self, message: Message, blocks: &Blocks) -> Vec<Message> {
+        let id = match *message.content {
+            BlockUpdate(id) => id,
+            _ => return vec![],
+        };
+        self.updates_received += 1;
+        let block = match blocks.get(&id) {
+            Some(block) => block,
+            None => return vec![],
+        };
+        let is_newer = match self.section {
+            Some(current) => {
+                blocks.get(&current).is_none_or(|current| block.version > current.version)
+            }
+            None => true,
+        };
+        if !block.prefix.matches(self.name) || !is_newer {
+            return vec![];
+        }
+        trace!("{}: section is now {:?}", self, block);
+        self.section = Some(id);
+        self.connect(block)
+    }
+
+    /// Messages disconnecting us from the members we're connected to.
+    pub fn disconnect(&self) -> Vec<Message> {
+        let content = Arc::new(Disconnect);
+        self.connected_to
+            .iter()
+            .map(|&member| {
+                Message {
+                    sender: self.name,
+                    recipient: member,
+                    content: Arc::clone(&content),
+                    trace_id: 0,
+                }
+            })
+            .collect()
+    }
+}
@@ -1,28 +1,117 @@
-use name::{Name, Prefix};
+use name::{Name, Prefix, PrefixSet};
 use node::Node;
 use blocks::Blocks;
 use block::Block;
+use params::NodeParams;
 use std::collections::{BTreeMap, BTreeSet};
-use itertools::Itertools;
+use std::error::Error;
+use std::fmt;
+
+/// Error from a network whose nodes don't describe a consistent set of sections. The problems
+/// found are logged at `error` level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Inconsistent;
+
+impl fmt::Display for Inconsistent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "network not consistent")
+    }
+}
+
+impl Error for Inconsistent {}
 
 /// Check that all the nodes have a consistent view of the network.
 pub fn check_consistency(
     blocks: &Blocks,
     nodes: &BTreeMap<Name, Node>,
-    min_section_size: usize,
-) -> Result<BTreeMap<Prefix, Block>, ()> {
+    params: &NodeParams,
+) -> Result<BTreeMap<Prefix, Block>, Inconsistent> {
+    let current_blocks = nodes.values().flat_map(|node| {
+        blocks.block_contents(&node.current_blocks).into_iter().cloned()
+    });
+    check_sections(current_blocks, |name| nodes.contains_key(name), params)
+}
+
+/// The sections that the nodes currently agree on.
+///
+/// Each live node votes for the current block of its own section, if it's a member of it, and
+/// the block with the most votes for each prefix is chosen. Unlike `check_consistency`, this
+/// doesn't require the nodes to agree, so it can describe a network that's still changing.
+pub fn agreed_sections(blocks: &Blocks, nodes: &BTreeMap<Name, Node>) -> BTreeMap<Prefix, Block> {
+    let mut votes: BTreeMap<Prefix, BTreeMap<&Block, usize>> = BTreeMap::new();
+    for node in nodes.values() {
+        if let Some(&block) = node.our_current_blocks(blocks).first() {
+            if block.members.contains(&node.our_name) {
+                *votes.entry(block.prefix).or_default().entry(block).or_insert(0) += 1;
+            }
+        }
+    }
+    votes
+        .into_iter()
+        .filter_map(|(prefix, block_votes)| {
+            block_votes
+                .into_iter()
+                .max_by_key(|&(_, count)| count)
+                .map(|(block, _)| (prefix, block.clone()))
+        })
+        .collect()
+}
+
+/// Whether a quorum of the section's members are alive and connected to each other, so that a
+/// client request routed to the section can succeed.
+///
+/// A member counts if it's alive and connected to enough of the other live members to make up a
+/// quorum with them. Quorums are weighed by `params.quorum_weight`.
+pub fn is_available(
+    blocks: &Blocks,
+    section: &Block,
+    nodes: &BTreeMap<Name, Node>,
+    params: &NodeParams,
+) -> bool {
+    let section_id = section.get_id();
+    let live: Vec<&Node> = section.members.iter().filter_map(|name| nodes.get(name)).collect();
+    let responsive: BTreeSet<Name> = live.iter()
+        .filter(|node| {
+            let connected: BTreeSet<Name> = live.iter()
+                .map(|peer| peer.our_name)
+                .filter(|name| *name == node.our_name || node.connections.contains(name))
+                .collect();
+            section_id.is_quorum(blocks, &connected, params)
+        })
+        .map(|node| node.our_name)
+        .collect();
+    section_id.is_quorum(blocks, &responsive, params)
+}
+
+/// Check that the current blocks of all the nodes, taken together, describe a consistent network.
+///
+/// `is_alive` tells whether a node is still part of the network. Sections must have at least
+/// the minimum section size for a network of the size they describe.
+pub fn check_sections<I, F>(
+    current_blocks: I,
+    is_alive: F,
+    params: &NodeParams,
+) -> Result<BTreeMap<Prefix, Block>, Inconsistent>
+where
+    I: IntoIterator<Item = Block>,
+    F: Fn(&Name) -> bool,
+{
     let mut sections = btreemap!{};
     let mut result = btreemap!{};
     let mut failed = false;
 
-    for node in nodes.values() {
-        for block in blocks.block_contents(&node.current_blocks) {
-            let section_versions = sections.entry(block.prefix).or_insert_with(BTreeSet::new);
-            section_versions.insert(block.clone());
-        }
+    for block in current_blocks {
+        let section_versions = sections.entry(block.prefix).or_insert_with(BTreeSet::new);
+        section_versions.insert(block);
     }
 
     let num_sections = sections.len();
+    let num_nodes = sections
+        .values()
+        .flat_map(|blocks| blocks.iter().flat_map(|block| block.members.iter()))
+        .collect::<BTreeSet<_>>()
+        .len();
+    let min_section_size = params.min_section_size_for(num_sections, num_nodes);
 
     for (prefix, blocks) in sections {
         if blocks.len() > 1 {
@@ -44,8 +133,8 @@ pub fn check_consistency(
         }
 
         // Check that all members are alive.
-        for member in &block.members {
-            if !nodes.contains_key(member) {
+        for member in block.members.iter() {
+            if !is_alive(member) {
                 failed = true;
                 error!(
                     "node {:?} is dead but appears in the block for {:?}",
@@ -58,18 +147,54 @@ pub fn check_consistency(
         result.insert(prefix, block);
     }
 
-    for (p1, p2) in result.keys().tuple_combinations() {
-        if p1.is_compatible(p2) {
-            failed = true;
-            error!("prefixes {:?} and {:?} overlap", p1, p2);
-        }
+    let prefixes: PrefixSet = result.keys().cloned().collect();
+    for (p1, p2) in prefixes.overlapping_pairs() {
+        failed = true;
+        error!("prefixes {:?} and {:?} overlap", p1, p2);
+    }
+    if !prefixes.is_complete_cover() {
+        failed = true;
+        error!("prefixes {:?} don't cover the whole namespace", prefixes);
     }
 
     if failed {
         error!("network not consistent: see above");
-        Err(())
+        Err(Inconsistent)
     } else {
         info!("network is consistent!");
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use params::SizeGrowth;
+    use std::sync::Arc;
+
+    fn section(prefix: &str, size: usize) -> Block {
+        let prefix: Prefix = prefix.parse().unwrap();
+        Block {
+            prefix,
+            version: 0,
+            members: Arc::new(prefix.spread_names().take(size).collect()),
+        }
+    }
+
+    #[test]
+    fn min_section_size_grows() {
+        let sections = vec![section("0", 4), section("1", 4)];
+        let params = NodeParams {
+            min_section_size: 3,
+            ..NodeParams::default()
+        };
+        assert!(check_sections(sections.clone(), |_| true, &params).is_ok());
+
+        // 8 nodes at one extra member per 4 nodes makes the minimum 5.
+        let params = NodeParams {
+            min_section_size_growth: SizeGrowth::PerNodes(4),
+            ..params
+        };
+        assert!(check_sections(sections, |_| true, &params).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Which of the protocol's rules fired during a run, and how often, so that tests can check that
+//! a scenario exercises the code paths it was written for.
+//!
+//! Each node counts the rules it applies: the votes it constructs by each voting rule, the
+//! bootstrap messages it sends to joining nodes and the proofs it requests. The simulation adds
+//! up the counts of every node, including those that have since been removed.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A rule of the protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Rule {
+    /// Voting to add a candidate to our section.
+    Add,
+    /// Voting to remove a member of our section, including to relocate it.
+    Remove,
+    /// Voting to split our section.
+    Split,
+    /// Voting to merge a section that's too small with its sibling.
+    Merge,
+    /// Voting to merge with a sibling that we've lost our connections to.
+    ForceMerge,
+    /// Voting to witness another section's new block.
+    Witness,
+    /// Sending our votes to a joining node.
+    Bootstrap,
+    /// Requesting the proof of a block that we don't know to be valid.
+    ProofRequest,
+}
+
+impl Rule {
+    /// Every rule, in order.
+    pub const ALL: [Rule; 8] = [
+        Rule::Add,
+        Rule::Remove,
+        Rule::Split,
+        Rule::Merge,
+        Rule::ForceMerge,
+        Rule::Witness,
+        Rule::Bootstrap,
+        Rule::ProofRequest,
+    ];
+}
+
+/// Number of times each rule has fired.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleCoverage {
+    counts: BTreeMap<Rule, u64>,
+}
+
+impl RuleCoverage {
+    pub fn new() -> Self {
+        RuleCoverage::default()
+    }
+
+    /// Record that `rule` has fired once more.
+    pub fn fire(&mut self, rule: Rule) {
+        *self.counts.entry(rule).or_insert(0) += 1;
+    }
+
+    /// Number of times `rule` has fired.
+    pub fn count(&self, rule: Rule) -> u64 {
+        self.counts.get(&rule).cloned().unwrap_or(0)
+    }
+
+    /// Whether `rule` has fired at all.
+    pub fn fired(&self, rule: Rule) -> bool {
+        self.count(rule) > 0
+    }
+
+    /// The rules that haven't fired, in order.
+    pub fn unfired(&self) -> Vec<Rule> {
+        Rule::ALL.iter().cloned().filter(|&rule| !self.fired(rule)).collect()
+    }
+
+    /// Add the counts of `other` to ours.
+    pub fn add(&mut self, other: &RuleCoverage) {
+        for (&rule, &count) in &other.counts {
+            *self.counts.entry(rule).or_insert(0) += count;
+        }
+    }
+}
+
+/// Lists the count of every rule, e.g. `Add: 12, Remove: 3, Split: 0, ...`.
+impl fmt::Display for RuleCoverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts: Vec<String> = Rule::ALL
+            .iter()
+            .map(|&rule| format!("{:?}: {}", rule, self.count(rule)))
+            .collect();
+        write!(f, "{}", counts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_and_unfired() {
+        let mut coverage = RuleCoverage::new();
+        coverage.fire(Rule::Add);
+        coverage.fire(Rule::Add);
+        coverage.fire(Rule::Witness);
+
+        let mut other = RuleCoverage::new();
+        other.fire(Rule::Add);
+        other.fire(Rule::ProofRequest);
+        coverage.add(&other);
+
+        assert_eq!(coverage.count(Rule::Add), 3);
+        assert_eq!(coverage.count(Rule::Split), 0);
+        assert!(coverage.fired(Rule::ProofRequest));
+        assert_eq!(
+            coverage.unfired(),
+            vec![Rule::Remove, Rule::Split, Rule::Merge, Rule::ForceMerge, Rule::Bootstrap]
+        );
+        assert_eq!(
+            coverage.to_string(),
+            "Add: 3, Remove: 0, Split: 0, Merge: 0, ForceMerge: 0, Witness: 1, Bootstrap: 0, \
+             ProofRequest: 1"
+        );
+    }
+}
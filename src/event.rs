@@ -3,13 +3,48 @@ use node::Node;
 use message::Message;
 use message::MessageContent::*;
 use std::collections::BTreeMap;
+use std::sync::Arc;
 use self::Event::*;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Event {
     AddNode(Name),
+    /// A node with the given name joins, and gives up and shuts down at the given step if it
+    /// hasn't joined a section by then, rather than after its usual `self_shutdown_timeout`.
+    AddNodeUntil(Name, u64),
     RemoveNode(Name),
     RemoveNodeFrom(Prefix),
+    /// The node with the given name crashes, and leaves the network until it's restarted.
+    CrashNode(Name),
+    /// A node with the given name starts late, knowing only the original genesis blocks, and
+    /// announces itself only to those blocks' members that are still alive. It has to catch up
+    /// from their bootstrap messages, and proofs of the blocks it's sent votes for.
+    AddStaleNode(Name),
+    /// A crashed node restarts under the same name, and rejoins with whatever state the
+    /// `restart_mode` parameter lets it keep.
+    RestartNode(Name),
+    /// A client stores the data with the given name in the section it belongs to. Clients
+    /// aren't modelled as nodes, so this sends no messages: it succeeds if a quorum of the
+    /// section's members are alive and connected to each other.
+    Put(Name),
+    /// A client retrieves the data with the given name, succeeding just as `Put` does.
+    Get(Name),
+    /// The nodes whose names match the prefix are cut off from every other node, until the
+    /// partition is healed. Nodes that join in the meantime aren't cut off.
+    Partition(Prefix),
+    /// Every partition heals, and the nodes it cut off reconnect.
+    HealPartition,
+    /// A client with the given name connects to the section it belongs to, and follows the
+    /// section's blocks from then on. Clients never join sections, so this is only sent to the
+    /// section's members by the client itself.
+    AddClient(Name),
+    /// The client with the given name disconnects from its section.
+    RemoveClient(Name),
+    /// The networks that started unaware of each other (see `Simulation::new_from_roots`) are
+    /// introduced: every node sends its votes to every node of the other networks, as it would to
+    /// a joining node. The votes lead back to genesis blocks that their recipients don't know, so
+    /// they're only accepted if they can be proven.
+    IntroduceRoots,
     //Reconnect(Name, Name)
     //Disconnect(Name, Name)
 }
@@ -18,9 +53,12 @@ impl Event {
     /// Convert the event into a vec of notifications for all the nodes it should be sent to.
     pub fn broadcast(&self, nodes: &BTreeMap<Name, Node>) -> Vec<Message> {
         match *self {
-            AddNode(name) => add_node(name, nodes),
-            RemoveNode(name) => remove_node(name, nodes),
+            AddNode(name) | AddNodeUntil(name, _) | RestartNode(name) => add_node(name, nodes),
+            RemoveNode(name) | CrashNode(name) => remove_node(name, nodes),
             RemoveNodeFrom(_) => panic!("you need to normalise events before broadcasting"),
+            // The simulation announces stale nodes itself, as only it knows the genesis blocks.
+            AddStaleNode(_) | Partition(_) | HealPartition | Put(_) | Get(_) | AddClient(_) |
+            RemoveClient(_) | IntroduceRoots => vec![],
         }
     }
 
@@ -42,7 +80,8 @@ fn add_node(joining_node: Name, nodes: &BTreeMap<Name, Node>) -> Vec<Message> {
             Message {
                 sender: joining_node,
                 recipient: neighbour,
-                content: NodeJoined,
+                content: Arc::new(NodeJoined),
+                trace_id: 0,
             }
         })
         .collect()
@@ -64,7 +103,8 @@ fn remove_node(to_remove: Name, nodes: &BTreeMap<Name, Node>) -> Vec<Message> {
             Message {
                 sender: to_remove,
                 recipient: neighbour,
-                content: Disconnect,
+                content: Arc::new(Disconnect),
+                trace_id: 0,
             }
         })
         .collect()
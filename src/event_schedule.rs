@@ -1,30 +1,239 @@
 //! Tools for specifying events in advance.
+//!
+//! Schedules can be saved to and loaded from JSON files, with events and assertions keyed by the
+//! step they occur at, e.g.
+//!
+//! ```json
+//! {
+//!   "schedule": { "10": [{ "AddNode": 1 }], "30": [{ "RemoveNodeFrom": "01" }] },
+//!   "assertions": { "100": [{ "MinMembers": ["01", 7] }] }
+//! }
+//! ```
+//!
+//! A schedule can also name a script to run at the end of every step, which can schedule further
+//! events in reaction to what happened (see `script`, behind the `scripting` feature), e.g.
+//! `"script": "split_partition.rhai"`.
+//!
+//! It can also switch the random number generator to a new seed at the start of chosen steps, e.g.
+//! `"reseeds": { "1000": [1, 2, 3, 4] }`. Runs from the same initial seed are identical up to
+//! that step, so varying the new seed explores how sensitive the outcome is to later randomness.
 
+use block::Block;
 use event::Event;
-use std::collections::BTreeMap;
+use name::Prefix;
+
+use serde_json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
 
 /// A schedule for the occurrence of events like node additions and removals.
 ///
-/// You specify the event, and the step number at which you'd like it to occur.
+/// You specify the event, and the step number at which you'd like it to occur. The schedule can
+/// also contain assertions about the state of the network, which are checked at the end of their
+/// step.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct EventSchedule {
     pub schedule: BTreeMap<u64, Vec<Event>>,
+    #[serde(default)]
+    pub assertions: BTreeMap<u64, Vec<Assertion>>,
+    /// Path of a script to run at the end of every step.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+    /// Seeds to switch the random number generator to, keyed by the step they're switched at.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reseeds: BTreeMap<u64, [u32; 4]>,
+}
+
+/// A condition on the sections that the nodes agree on (see `consistency::agreed_sections`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Assertion {
+    /// The sections have exactly these prefixes.
+    Prefixes(BTreeSet<Prefix>),
+    /// The section with this prefix has at least this many members.
+    MinMembers(Prefix, usize),
+    /// The section with this prefix has at most this many members.
+    MaxMembers(Prefix, usize),
+}
+
+impl Assertion {
+    /// Whether the assertion holds for the given sections.
+    pub fn holds(&self, sections: &BTreeMap<Prefix, Block>) -> bool {
+        match *self {
+            Assertion::Prefixes(ref prefixes) => {
+                sections.len() == prefixes.len() &&
+                    prefixes.iter().all(|prefix| sections.contains_key(prefix))
+            }
+            Assertion::MinMembers(ref prefix, min) => {
+                sections.get(prefix).is_some_and(|block| block.members.len() >= min)
+            }
+            Assertion::MaxMembers(ref prefix, max) => {
+                sections.get(prefix).is_some_and(|block| block.members.len() <= max)
+            }
+        }
+    }
 }
 
 impl EventSchedule {
     pub fn new(schedule: BTreeMap<u64, Vec<Event>>) -> Self {
-        EventSchedule { schedule }
+        EventSchedule {
+            schedule,
+            assertions: BTreeMap::new(),
+            script: None,
+            reseeds: BTreeMap::new(),
+        }
     }
 
     pub fn empty() -> Self {
-        EventSchedule { schedule: BTreeMap::new() }
+        Self::new(BTreeMap::new())
+    }
+
+    /// Load a schedule from a JSON file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        serde_json::from_reader(BufReader::new(File::open(path)?)).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e)
+        })
+    }
+
+    /// Save the schedule to a JSON file, which `load` reads back.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+
+    /// Create a schedule from a list of events and the steps they occur at.
+    pub fn from_events<I>(events: I) -> Self
+    where
+        I: IntoIterator<Item = (u64, Event)>,
+    {
+        let mut schedule = BTreeMap::new();
+        for (step, event) in events {
+            schedule.entry(step).or_insert_with(Vec::new).push(event);
+        }
+        Self::new(schedule)
+    }
+
+    /// Add `event` to occur at the given step.
+    pub fn add_event(&mut self, step: u64, event: Event) {
+        self.schedule.entry(step).or_default().push(event);
+    }
+
+    /// Check `assertion` at the end of the given step.
+    pub fn add_assertion(&mut self, step: u64, assertion: Assertion) {
+        self.assertions.entry(step).or_default().push(assertion);
+    }
+
+    /// Switch the random number generator to `seed` at the start of the given step.
+    pub fn add_reseed(&mut self, step: u64, seed: [u32; 4]) {
+        self.reseeds.insert(step, seed);
+    }
+
+    /// The seed to switch to at the start of the given step, if any.
+    pub fn reseed_at(&self, step: u64) -> Option<[u32; 4]> {
+        self.reseeds.get(&step).cloned()
+    }
+
+    /// All events in the schedule along with their steps, in order.
+    pub fn events(&self) -> Vec<(u64, Event)> {
+        self.schedule
+            .iter()
+            .flat_map(|(&step, events)| events.iter().map(move |ev| (step, ev.clone())))
+            .collect()
     }
 
+    /// Whether there are no events, assertions or script, in which case events are random.
+    ///
+    /// Reseeds don't count, so that runs with random events can switch seeds too.
     pub fn is_empty(&self) -> bool {
-        self.schedule.is_empty()
+        self.schedule.is_empty() && self.assertions.is_empty() && self.script.is_none()
+    }
+
+    /// The first step after `step` with events, assertions or a reseed scheduled, if there is
+    /// one.
+    pub fn next_step(&self, step: u64) -> Option<u64> {
+        let next_event = self.schedule.range(step + 1..).next().map(|(&step, _)| step);
+        let next_assertion = self.assertions.range(step + 1..).next().map(|(&step, _)| step);
+        let next_reseed = self.reseeds.range(step + 1..).next().map(|(&step, _)| step);
+        next_event.into_iter().chain(next_assertion).chain(next_reseed).min()
     }
 
     /// Fetch events occuring at the given step.
     pub fn get_events(&self, step: u64) -> Vec<Event> {
         self.schedule.get(&step).cloned().unwrap_or_else(Vec::new)
     }
+
+    /// Fetch assertions to check at the end of the given step.
+    pub fn get_assertions(&self, step: u64) -> Vec<Assertion> {
+        self.assertions.get(&step).cloned().unwrap_or_else(Vec::new)
+    }
+
+    /// Number of assertions scheduled after the given step.
+    pub fn assertions_after(&self, step: u64) -> usize {
+        self.assertions.range(step + 1..).map(|(_, assertions)| assertions.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use name::Name;
+    use std::{env, fs, process};
+    use std::sync::Arc;
+
+    #[test]
+    fn assertions_hold() {
+        let p0: Prefix = "0".parse().unwrap();
+        let p1: Prefix = "1".parse().unwrap();
+        let sections = btreemap! {
+            p0 => Block {
+                prefix: p0,
+                version: 1,
+                members: Arc::new((0..3).map(Name).collect()),
+            },
+            p1 => Block {
+                prefix: p1,
+                version: 1,
+                members: Arc::new((0..5).map(|i| Name(!0 - i)).collect()),
+            },
+        };
+        assert!(Assertion::Prefixes(btreeset!{ p0, p1 }).holds(&sections));
+        assert!(!Assertion::Prefixes(btreeset!{ p0 }).holds(&sections));
+        // A collected set holds the same prefixes as one built by inserting them.
+        let p00: Prefix = "00".parse().unwrap();
+        let p01: Prefix = "01".parse().unwrap();
+        let three_sections = btreemap! {
+            p00 => Block { prefix: p00, ..sections[&p0].clone() },
+            p01 => Block { prefix: p01, ..sections[&p0].clone() },
+            p1 => sections[&p1].clone(),
+        };
+        let collected = vec![p00, p01, p1].into_iter().collect();
+        assert!(Assertion::Prefixes(collected).holds(&three_sections));
+        assert!(Assertion::MinMembers(p1, 5).holds(&sections));
+        assert!(!Assertion::MinMembers(p0, 4).holds(&sections));
+        assert!(Assertion::MaxMembers(p0, 3).holds(&sections));
+        assert!(!Assertion::MaxMembers(p1, 4).holds(&sections));
+        assert!(!Assertion::MinMembers(Prefix::empty(), 0).holds(&sections));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let p01: Prefix = "01".parse().unwrap();
+        let mut schedule = EventSchedule::from_events(vec![
+            (10, Event::AddNode(Name(1))),
+            (30, Event::RemoveNodeFrom(p01)),
+            (30, Event::AddNode(Name(2))),
+        ]);
+        schedule.add_assertion(100, Assertion::MinMembers(p01, 7));
+        schedule.add_event(50, Event::RemoveNodeFrom(p01));
+        schedule.script = Some("heal.rhai".to_string());
+        schedule.add_reseed(40, [1, 2, 3, 4]);
+        assert_eq!(schedule.next_step(30), Some(40));
+        let path = env::temp_dir().join(format!("ewok-schedule-{}.json", process::id()));
+        schedule.save(&path).unwrap();
+        assert_eq!(EventSchedule::load(&path).unwrap(), schedule);
+        fs::remove_file(&path).unwrap();
+    }
 }
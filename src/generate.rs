@@ -2,42 +2,88 @@
 
 use block::{Block, BlockId};
 use blocks::{Blocks, CurrentBlocks};
-use name::{Name, Prefix};
+use name::{Name, Prefix, PrefixSet};
 use node::Node;
-use params::NodeParams;
+use params::{NameDistribution, NodeParams};
 use random::random;
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
 
 /// Generate a bunch of nodes based on sizes specified for sections.
 ///
 /// `sections`: map from prefix to desired size for that section.
+/// `names`: how to choose the names of the nodes within each section.
 pub fn generate_network(
     blocks: &mut Blocks,
     sections: &BTreeMap<Prefix, usize>,
+    names: NameDistribution,
     params: &NodeParams,
 ) -> (BTreeMap<Name, Node>, BTreeSet<BlockId>) {
     // Check that the supplied prefixes describe a whole network.
+    let prefixes: PrefixSet = sections.keys().cloned().collect();
     assert!(
-        Prefix::empty().is_covered_by(sections.keys()),
+        prefixes.is_complete_cover(),
         "Prefixes should cover the whole namespace"
     );
+    assert!(prefixes.is_disjoint(), "Prefixes shouldn't overlap");
 
-    let mut nodes_by_section = btreemap!{};
+    network_from_blocks(blocks, &generate_sections(sections, names), params)
+}
+
+/// Generate several networks that each cover part of the namespace, and start unaware of each
+/// other: the nodes of each only know its own genesis blocks.
+///
+/// `roots`: map from the prefix that each network covers to the sizes of its sections. The
+/// prefixes of the networks must be disjoint, as must those of every section, which must cover
+/// the whole namespace between them, each lying within its network's prefix.
+///
+/// Returns the nodes, and the genesis blocks of each network.
+pub fn generate_roots(
+    blocks: &mut Blocks,
+    roots: &BTreeMap<Prefix, BTreeMap<Prefix, usize>>,
+    names: NameDistribution,
+    params: &NodeParams,
+) -> (BTreeMap<Name, Node>, BTreeMap<Prefix, BTreeSet<BlockId>>) {
+    let prefixes: PrefixSet = roots.values().flat_map(BTreeMap::keys).cloned().collect();
+    assert!(
+        prefixes.is_complete_cover(),
+        "Prefixes should cover the whole namespace"
+    );
+    assert!(prefixes.is_disjoint(), "Prefixes shouldn't overlap");
+    let root_prefixes: PrefixSet = roots.keys().cloned().collect();
+    assert!(root_prefixes.is_disjoint(), "Networks' prefixes shouldn't overlap");
 
-    for (prefix, &size) in sections {
-        let node_names: BTreeSet<_> = (0..size).map(|_| prefix.substituted_in(random())).collect();
-        nodes_by_section.insert(*prefix, node_names);
+    let mut nodes = BTreeMap::new();
+    let mut genesis = BTreeMap::new();
+    for (root, sections) in roots {
+        assert!(
+            sections.keys().all(|prefix| root.is_prefix_of(prefix)),
+            "Sections should lie within their network's prefix {:?}",
+            root
+        );
+        let (root_nodes, root_genesis) =
+            network_from_blocks(blocks, &generate_sections(sections, names), params);
+        nodes.extend(root_nodes);
+        genesis.insert(*root, root_genesis);
     }
+    (nodes, genesis)
+}
 
-    let current_blocks: CurrentBlocks = construct_blocks(nodes_by_section.clone())
-        .into_iter()
-        .map(|b| blocks.insert(b))
+/// Create a node for every member of the given genesis blocks.
+pub fn network_from_blocks(
+    blocks: &mut Blocks,
+    genesis_blocks: &BTreeSet<Block>,
+    params: &NodeParams,
+) -> (BTreeMap<Name, Node>, BTreeSet<BlockId>) {
+    let current_blocks: CurrentBlocks = genesis_blocks
+        .iter()
+        .map(|b| blocks.insert(b.clone()))
         .collect();
 
-    let nodes = nodes_by_section
-        .into_iter()
-        .flat_map(|(_, names)| names)
+    let nodes = genesis_blocks
+        .iter()
+        .flat_map(|block| block.members.iter().cloned())
         .map(|name| {
             (
                 name,
@@ -49,14 +95,23 @@ pub fn generate_network(
     (nodes, current_blocks)
 }
 
-/// Construct a set of blocks to describe the given sections.
-fn construct_blocks(nodes: BTreeMap<Prefix, BTreeSet<Name>>) -> BTreeSet<Block> {
-    nodes
-        .into_iter()
-        .map(|(prefix, members)| {
+/// Generate the genesis blocks for sections of the sizes specified, with newly chosen names.
+pub fn generate_sections(
+    sections: &BTreeMap<Prefix, usize>,
+    names: NameDistribution,
+) -> BTreeSet<Block> {
+    sections
+        .iter()
+        .map(|(prefix, &size)| {
+            let members: BTreeSet<_> = match names {
+                NameDistribution::Random => {
+                    (0..size).map(|_| prefix.substituted_in(random())).collect()
+                }
+                NameDistribution::Spread => prefix.spread_names().take(size).collect(),
+            };
             Block {
-                prefix,
-                members,
+                prefix: *prefix,
+                members: Arc::new(members),
                 version: 0,
             }
         })
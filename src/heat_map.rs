@@ -0,0 +1,160 @@
+//! Number of messages sent between sections, for seeing which sections bear the most traffic,
+//! e.g. whether those on the boundary of a cascade of merges bear more than their share.
+//!
+//! If the `EWOK_HEAT_MAP` environment variable is set, the simulation writes a CSV file to the
+//! path it names, with the columns `step,sender,recipient,messages`. Steps are grouped into
+//! windows of `EWOK_HEAT_MAP_WINDOW` steps (default 50), and once a window ends a row is written
+//! for each pair of sender and recipient prefixes with messages between them during it, with the
+//! first step of the window. Each node counts towards the agreed section that its name matches at
+//! the end of the step the message was sent on, or `-` if there's none, e.g. part-way through a
+//! split.
+
+use block::Block;
+use name::{Name, Prefix};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Column names, written as the first line of the file.
+pub const HEADER: &str = "step,sender,recipient,messages";
+
+/// Default number of steps in a window.
+const DEFAULT_WINDOW: u64 = 50;
+
+/// A heat map file that grows as the simulation progresses.
+pub struct HeatMapFile {
+    writer: BufWriter<File>,
+    window: u64,
+    /// First step of the window being counted.
+    window_start: u64,
+    /// Number of messages sent between each pair of sections during the window so far.
+    counts: BTreeMap<(Option<Prefix>, Option<Prefix>), usize>,
+}
+
+impl HeatMapFile {
+    pub fn create<P: AsRef<Path>>(path: P, window: u64) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", HEADER)?;
+        Ok(HeatMapFile {
+            writer,
+            window: window.max(1),
+            window_start: 0,
+            counts: BTreeMap::new(),
+        })
+    }
+
+    /// Create a heat map file from the `EWOK_HEAT_MAP` and `EWOK_HEAT_MAP_WINDOW` variables.
+    pub fn from_env() -> Option<Self> {
+        let path = env::var("EWOK_HEAT_MAP").ok()?;
+        let window = match env::var("EWOK_HEAT_MAP_WINDOW") {
+            Ok(value) => {
+                value.parse().unwrap_or_else(|_| {
+                    panic!("EWOK_HEAT_MAP_WINDOW {} isn't a number of steps.", value)
+                })
+            }
+            Err(_) => DEFAULT_WINDOW,
+        };
+        Some(HeatMapFile::create(&path, window).unwrap_or_else(|e| {
+            panic!("couldn't create heat map {}: {}", path, e)
+        }))
+    }
+
+    /// Count the messages sent between each pair of nodes during `step`, writing the rows for
+    /// the window once it ends.
+    ///
+    /// `sections` are the sections agreed on at the end of `step`, as found by
+    /// `consistency::agreed_sections`.
+    pub fn update(
+        &mut self,
+        step: u64,
+        pair_counts: &BTreeMap<(Name, Name), usize>,
+        sections: &BTreeMap<Prefix, Block>,
+    ) -> io::Result<()> {
+        // Steps on which nothing happens are skipped, so the last window may have ended already.
+        if step >= self.window_start + self.window {
+            self.write_window()?;
+            self.window_start = step - step % self.window;
+        }
+        let prefix = |name: Name| sections.keys().find(|prefix| prefix.matches(name)).cloned();
+        for (&(sender, recipient), &count) in pair_counts {
+            *self.counts
+                .entry((prefix(sender), prefix(recipient)))
+                .or_insert(0) += count;
+        }
+        if step + 1 == self.window_start + self.window {
+            self.write_window()?;
+            self.window_start = step + 1;
+        }
+        Ok(())
+    }
+
+    /// Write the rows for the window so far, once the simulation has finished.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.write_window()
+    }
+
+    /// Write a row for every pair of sections with messages between them in the current window,
+    /// and start counting afresh.
+    fn write_window(&mut self) -> io::Result<()> {
+        if self.counts.is_empty() {
+            return Ok(());
+        }
+        let show = |prefix: Option<Prefix>| prefix.map_or("-".to_string(), |p| p.to_string());
+        for (&(sender, recipient), &count) in &self.counts {
+            writeln!(
+                self.writer,
+                "{},{},{},{}",
+                self.window_start,
+                show(sender),
+                show(recipient),
+                count
+            )?;
+        }
+        self.counts.clear();
+        // Flush every window, so that the file of a run that panics is still usable.
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use name::NameBits;
+    use std::fs;
+    use std::process;
+    use std::sync::Arc;
+    use testing::p;
+
+    #[test]
+    fn counts_by_window() {
+        let path = env::temp_dir().join(format!("ewok-heat-map-{}.csv", process::id()));
+        let mut heat_map = HeatMapFile::create(&path, 10).unwrap();
+        let section = |prefix: &str, member: NameBits| {
+            let block = Block {
+                prefix: p(prefix),
+                version: 0,
+                members: Arc::new(btreeset!{ Name(member) }),
+            };
+            (p(prefix), block)
+        };
+        let (a, b, c) = (p("00").lower_bound(), p("01").lower_bound(), p("1").lower_bound());
+        let sections: BTreeMap<_, _> = vec![section("0", a.0), section("1", c.0)]
+            .into_iter()
+            .collect();
+
+        heat_map.update(3, &btreemap!{ (a, b) => 2, (a, c) => 1 }, &sections).unwrap();
+        heat_map.update(9, &btreemap!{ (b, a) => 3 }, &sections).unwrap();
+        // Steps 10 to 24 were skipped.
+        heat_map.update(25, &btreemap!{ (c, a) => 4 }, &sections).unwrap();
+        heat_map.finish().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "step,sender,recipient,messages\n0,0,0,5\n0,0,1,1\n20,1,0,4\n"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
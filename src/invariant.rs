@@ -0,0 +1,59 @@
+//! Violations of the invariants that nodes and the simulation check as they run.
+//!
+//! A violation means the protocol has gone wrong in a way that makes the rest of the run
+//! meaningless, so the simulation stops at the end of the step it was found on and reports the
+//! run as failed, rather than panicking. That way batch runs can record it and carry on with their
+//! remaining seeds.
+
+use name::{Name, Prefix};
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// A node had more than one current block for its own section.
+    CurrentBlocks { node: Name, count: usize },
+    /// A node had `max_conflicting_blocks` valid blocks for the same prefix and version.
+    ConflictingBlocks {
+        node: Name,
+        prefix: Prefix,
+        version: u64,
+        count: usize,
+    },
+    /// Votes were still being sent and received after the given number of extra steps during
+    /// which no churn was triggered.
+    NotQuiescent { extra_steps: u64 },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvariantViolation::CurrentBlocks { node, count } => {
+                write!(f, "Node({}) has {} current blocks for own section", node, count)
+            }
+            InvariantViolation::ConflictingBlocks {
+                node,
+                prefix,
+                version,
+                count,
+            } => {
+                write!(
+                    f,
+                    "Node({}) has {} valid blocks for {:?} with version {}",
+                    node,
+                    count,
+                    prefix,
+                    version
+                )
+            }
+            InvariantViolation::NotQuiescent { extra_steps } => {
+                write!(
+                    f,
+                    "votes were still being sent and received after {} extra steps during which \
+                     no churn was triggered",
+                    extra_steps
+                )
+            }
+        }
+    }
+}
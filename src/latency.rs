@@ -0,0 +1,187 @@
+//! Agreement latency: how long each vote takes to go from being cast to being known as agreed by
+//! every member of the sections it concerns.
+//!
+//! Votes are only tracked if the `track_votes` parameter is set, or the `EWOK_LATENCY` environment
+//! variable is. In the latter case the histogram of a run's latencies is appended to a CSV file at
+//! the path it names when the run finishes, with the columns
+//! `max_delay,delay_distribution,latency,votes`. Each run appends its own rows, so simulations
+//! with several settings can be run against the same file at once; summing the `votes` of the
+//! rows with the same setting and latency gives one histogram per setting.
+
+use block::{BlockId, Vote};
+use blocks::Blocks;
+use name::Name;
+use params::SimulationParams;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Column names, written as the first line of the file.
+pub const HEADER: &str = "max_delay,delay_distribution,latency,votes";
+
+/// Histogram of latencies, as a map from a number of steps to the number of votes that took it.
+pub type Histogram = BTreeMap<u64, usize>;
+
+/// The steps at which votes were first cast and last learnt to be agreed.
+#[derive(Clone, Debug, Default)]
+pub struct AgreementLatencies {
+    /// First step each vote was seen, and the last step a member learnt of its agreement.
+    votes: BTreeMap<Vote, (u64, Option<u64>)>,
+}
+
+impl AgreementLatencies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that a node sent the vote at `step`.
+    pub fn cast(&mut self, vote: &Vote, step: u64) {
+        self.votes.entry(vote.clone()).or_insert((step, None));
+    }
+
+    /// Record that `node` learnt of the vote's agreement at `step`.
+    ///
+    /// Only the members of the vote's `from` and `to` blocks count, as other nodes only hear of
+    /// the agreement as neighbours. A vote that's agreed without ever being sent, e.g. by a
+    /// section of one, counts as cast on the step it's first agreed.
+    pub fn learned(&mut self, vote: &Vote, node: Name, blocks: &Blocks, step: u64) {
+        let is_member = |id: BlockId| id.into_block(blocks).members.contains(&node);
+        if !is_member(vote.from) && !is_member(vote.to) {
+            return;
+        }
+        let entry = self.votes.entry(vote.clone()).or_insert((step, None));
+        entry.1 = Some(entry.1.map_or(step, |last| last.max(step)));
+    }
+
+    /// The votes that were cast but never reached quorum, along with the steps they were cast.
+    pub fn unagreed(&self) -> Vec<(&Vote, u64)> {
+        self.votes
+            .iter()
+            .filter(|&(_, &(_, last))| last.is_none())
+            .map(|(vote, &(first, _))| (vote, first))
+            .collect()
+    }
+
+    /// Number of steps taken by every vote that reached quorum.
+    pub fn histogram(&self) -> Histogram {
+        let mut histogram = Histogram::new();
+        for &(first, last) in self.votes.values() {
+            if let Some(last) = last {
+                *histogram.entry(last - first).or_insert(0) += 1;
+            }
+        }
+        histogram
+    }
+}
+
+/// Whether votes should be tracked, either for the `EWOK_LATENCY` file or because `params` ask
+/// for it.
+pub fn is_enabled(params: &SimulationParams) -> bool {
+    params.track_votes || env::var_os("EWOK_LATENCY").is_some()
+}
+
+/// Add a run's histogram to the file at the path in the `EWOK_LATENCY` variable, if it's set.
+pub fn write_to_env(params: &SimulationParams, histogram: &Histogram) {
+    if let Ok(path) = env::var("EWOK_LATENCY") {
+        add_to_file(&path, params, histogram).unwrap_or_else(|e| {
+            panic!("couldn't write latency histogram {}: {}", path, e)
+        });
+    }
+}
+
+/// Append the rows of a histogram for the message delay setting in `params` to the file at
+/// `path`, starting it with the header if it's new.
+///
+/// The rows are written with a single call, so runs appending to the same file at once don't
+/// interleave their lines.
+pub fn add_to_file<P: AsRef<Path>>(
+    path: P,
+    params: &SimulationParams,
+    histogram: &Histogram,
+) -> io::Result<()> {
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    let mut rows = String::new();
+    if file.metadata()?.len() == 0 {
+        rows.push_str(HEADER);
+        rows.push('\n');
+    }
+    let distribution = format!("{:?}", params.delay_distribution).to_lowercase();
+    for (latency, votes) in histogram {
+        rows.push_str(&format!("{},{},{},{}\n", params.max_delay, distribution, latency, votes));
+    }
+    file.write_all(rows.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use block::Block;
+    use params::DelayDistribution;
+    use name::Prefix;
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn histograms_per_delay_setting() {
+        let mut blocks = Blocks::new();
+        let b0 = Block {
+            prefix: Prefix::empty(),
+            version: 0,
+            members: Arc::new(btreeset!{ Name(1), Name(2) }),
+        };
+        let b1 = Block {
+            prefix: Prefix::empty(),
+            version: 1,
+            members: Arc::new(btreeset!{ Name(1), Name(2), Name(3) }),
+        };
+        let vote = Vote {
+            from: blocks.insert(b0.clone()),
+            to: blocks.insert(b1),
+        };
+        let b2 = b0.remove_node(Name(2));
+        let solo_vote = Vote {
+            from: b0.get_id(),
+            to: blocks.insert(b2),
+        };
+
+        let mut latencies = AgreementLatencies::new();
+        latencies.cast(&vote, 3);
+        latencies.learned(&vote, Name(1), &blocks, 5);
+        latencies.learned(&vote, Name(3), &blocks, 8);
+        // Not a member of either block.
+        latencies.learned(&vote, Name(4), &blocks, 20);
+        latencies.cast(&vote, 4);
+        latencies.learned(&solo_vote, Name(1), &blocks, 6);
+        assert_eq!(latencies.histogram(), btreemap!{ 0 => 1, 5 => 1 });
+        assert!(latencies.unagreed().is_empty());
+
+        let path = env::temp_dir().join(format!("ewok-latency-{}.csv", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        let params = SimulationParams::default();
+        let uniform = SimulationParams {
+            max_delay: 10,
+            delay_distribution: DelayDistribution::Uniform,
+            ..SimulationParams::default()
+        };
+        add_to_file(&path, &params, &latencies.histogram()).unwrap();
+        add_to_file(&path, &params, &btreemap!{ 5 => 2 }).unwrap();
+        add_to_file(&path, &uniform, &btreemap!{ 7 => 1 }).unwrap();
+
+        let csv = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            vec![
+                HEADER,
+                "5,geometric,0,1",
+                "5,geometric,5,1",
+                "5,geometric,5,2",
+                "10,uniform,7,1",
+            ]
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,22 +4,68 @@ extern crate itertools;
 extern crate maplit;
 #[macro_use]
 extern crate log;
+#[cfg(not(target_arch = "wasm32"))]
 extern crate env_logger;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate rayon;
+extern crate fxhash;
+#[cfg(feature = "web")]
+extern crate wasm_bindgen;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "scripting")]
+extern crate rhai;
 
+pub mod adapter;
+pub mod analysis;
 pub mod block;
 pub mod blocks;
+pub mod chaos;
+pub mod client;
 pub mod consistency;
+pub mod coverage;
 pub mod event;
 pub mod event_schedule;
 pub mod generate;
+pub mod heat_map;
+pub mod invariant;
+pub mod latency;
 pub mod logging;
 pub mod message;
+pub mod message_filter;
+pub mod metrics;
+pub mod mock;
 pub mod name;
 pub mod network;
 pub mod node;
+pub mod orphans;
+pub mod overload;
 pub mod params;
+pub mod partition;
+pub mod peer_state;
 pub mod random;
 pub mod random_events;
+pub mod replay;
+#[cfg(feature = "sqlite")]
+pub mod results_db;
+pub mod routing_table;
+pub mod scenarios;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod schedule_generator;
+pub mod section_map;
+pub mod shrink;
 pub mod simulation;
+pub mod snapshot;
 pub mod split;
+pub mod sweep;
+pub mod testing;
+pub mod topology;
+pub mod trace;
+pub mod wire;
 pub mod merge;
+#[cfg(feature = "web")]
+pub mod web;
@@ -1,20 +1,653 @@
+#[cfg(not(target_arch = "wasm32"))]
 use std::env;
-use log::LogRecord;
-use env_logger::{LogBuilder, LogTarget};
+#[cfg(not(target_arch = "wasm32"))]
+use log::{self, Log, LogMetadata, LogRecord};
+#[cfg(not(target_arch = "wasm32"))]
+use env_logger::{LogBuilder, LogTarget, Logger};
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Mutex;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::{self, ThreadId};
+use name::{Name, NameBits, Prefix, NAME_BITS};
+use params::{NodeParams, SimulationParams};
+#[cfg(not(target_arch = "wasm32"))]
+use random::seed;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use simulation::Phase;
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, LineWriter, Write};
+use std::path::{Path, PathBuf};
 
-/// If the `RUST_LOG` environment variable is set, enable logging.
-pub fn init_logging() {
-    if let Ok(rust_log) = env::var("RUST_LOG") {
+thread_local! {
+    /// The step that the simulation on this thread is on, for inclusion in structured log lines.
+    ///
+    /// Like the random number generator, this is per-thread, so simulations running in parallel
+    /// (in tests or sweeps) don't stamp each other's lines. Threads doing work for a simulation
+    /// get its step through `set_log_context`.
+    static LOG_STEP: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Set the step included in structured log lines written from this thread.
+pub fn set_log_step(step: u64) {
+    LOG_STEP.with(|s| s.set(step));
+}
+
+thread_local! {
+    /// The seed of the run on this thread, which names its log file.
+    static LOG_SEED: Cell<Option<[u32; 4]>> = const { Cell::new(None) };
+}
+
+/// Set the seed of the run on this thread, so that its lines go to the log file for that seed.
+///
+/// Threads that never set it log to the file for their random number generator's seed, so work
+/// that a run hands to other threads should set it there too (see `LogContext`).
+pub fn set_log_seed(seed: [u32; 4]) {
+    LOG_SEED.with(|s| s.set(Some(seed)));
+}
+
+/// The step and seed that lines logged from a thread are stamped with and written for.
+///
+/// A run that hands work to other threads, like the workers that handle a step's messages, takes
+/// its context with `log_context` and sets it on each worker with `set_log_context`, so that the
+/// lines they log have the run's step and go to the run's log file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LogContext {
+    pub step: u64,
+    pub seed: Option<[u32; 4]>,
+}
+
+/// The log context of this thread.
+pub fn log_context() -> LogContext {
+    LogContext {
+        step: LOG_STEP.with(|s| s.get()),
+        seed: LOG_SEED.with(|s| s.get()),
+    }
+}
+
+/// Set the log context of this thread, e.g. to that of the run it's doing work for.
+pub fn set_log_context(context: LogContext) {
+    LOG_STEP.with(|s| s.set(context.step));
+    LOG_SEED.with(|s| s.set(context.seed));
+}
+
+/// A log line in the JSON-lines format.
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    step: u64,
+    level: &'a str,
+    /// The module that logged the line, e.g. `ewok::node`.
+    target: &'a str,
+    /// The node that the line is about, if it starts with one.
+    node: Option<&'a str>,
+    message: &'a str,
+}
+
+/// Start of the log line written at the start of every step.
+pub const STEP_MARKER: &str = "#ewok-step ";
+
+/// Start of the log line written once at the start of a run.
+pub const RUN_MARKER: &str = "#ewok-run ";
+
+/// Record logged at `info` level at the start of every step, as `STEP_MARKER` followed by JSON.
+///
+/// Unlike the free-text `-- step N --` line, the format of this line is stable: fields may be
+/// added, but existing fields won't be renamed or removed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StepMarker {
+    pub step: u64,
+    pub phase: Phase,
+    pub nodes: usize,
+    /// Number of messages queued at the start of the step.
+    pub queue_size: usize,
+    pub seed: [u32; 4],
+}
+
+/// Record logged at `info` level before the first step, as `RUN_MARKER` followed by JSON.
+///
+/// This has the same stability guarantee as `StepMarker`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunMarker {
+    pub seed: [u32; 4],
+    pub params: SimulationParams,
+    pub node_params: NodeParams,
+}
+
+impl StepMarker {
+    pub fn to_line(&self) -> String {
+        marker_line(STEP_MARKER, self)
+    }
+
+    /// Parse a log line, returning `None` if it isn't a step marker.
+    pub fn parse(line: &str) -> Option<Self> {
+        parse_marker(STEP_MARKER, line)
+    }
+}
+
+impl RunMarker {
+    pub fn to_line(&self) -> String {
+        marker_line(RUN_MARKER, self)
+    }
+
+    /// Parse a log line, returning `None` if it isn't a run marker.
+    pub fn parse(line: &str) -> Option<Self> {
+        parse_marker(RUN_MARKER, line)
+    }
+}
+
+fn marker_line<T: Serialize>(marker: &str, value: &T) -> String {
+    format!("{}{}", marker, serde_json::to_string(value).expect("failed to serialise marker"))
+}
+
+/// Parse a marker line, which may also be the message of a JSON log line.
+fn parse_marker<T: DeserializeOwned>(marker: &str, line: &str) -> Option<T> {
+    if let Some(rest) = line.strip_prefix(marker) {
+        return serde_json::from_str(rest).ok();
+    }
+    let json: Value = serde_json::from_str(line).ok()?;
+    let message = json.get("message")?.as_str()?;
+    serde_json::from_str(message.strip_prefix(marker)?).ok()
+}
+
+/// Split a log message of the form `Node(name): message` into the node's name and the rest.
+pub fn split_node(message: &str) -> (Option<&str>, &str) {
+    if message.starts_with("Node(") {
+        if let Some(end) = message.find("): ") {
+            return (Some(&message[5..end]), &message[end + 3..]);
+        }
+    }
+    (None, message)
+}
+
+/// Which nodes to emit log lines for.
+///
+/// Lines that are about a node (those starting `Node(name): `) are only emitted if the node is one
+/// of `names` or is in one of `prefixes`. Lines about several nodes, like disconnections, are
+/// emitted if any of the nodes they mention are, and lines that don't mention nodes always are.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeFilter {
+    /// Leading hex digits of the names to keep, as printed in the logs.
+    pub names: Vec<String>,
+    pub prefixes: Vec<Prefix>,
+}
+
+impl NodeFilter {
+    /// Parse comma-separated lists of names (in hex, possibly abbreviated) and prefixes (in
+    /// binary), e.g. `"e83915,d67553"` and `"01,10"`.
+    ///
+    /// Names can be given as they're displayed with any number of digits, so `e83915..` and the
+    /// full-width `0xe8391539f4c22a1b` both work, whatever width the logs print names with.
+    pub fn parse(names: &str, prefixes: &str) -> Result<Self, String> {
+        let names = names
+            .split(',')
+            .map(|name| {
+                let name = name.trim().trim_end_matches("..");
+                name.strip_prefix("0x").unwrap_or(name).to_lowercase()
+            })
+            .filter(|name| !name.is_empty())
+            .map(|name| if name.chars().all(|c| c.is_ascii_hexdigit()) {
+                Ok(name)
+            } else {
+                Err(format!("invalid name: {:?}", name))
+            })
+            .collect::<Result<_, _>>()?;
+        let prefixes = prefixes
+            .split(',')
+            .map(str::trim)
+            .filter(|prefix| !prefix.is_empty())
+            .map(|prefix| {
+                prefix.parse().map_err(|_| format!("invalid prefix: {:?}", prefix))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(NodeFilter { names, prefixes })
+    }
+
+    /// Create a filter from the `EWOK_LOG_NODES` and `EWOK_LOG_PREFIXES` variables, if either is
+    /// set.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_env() -> Option<Self> {
+        let names = env::var("EWOK_LOG_NODES").ok();
+        let prefixes = env::var("EWOK_LOG_PREFIXES").ok();
+        if names.is_none() && prefixes.is_none() {
+            return None;
+        }
+        let filter = NodeFilter::parse(
+            names.as_ref().map_or("", |s| s),
+            prefixes.as_ref().map_or("", |s| s),
+        );
+        Some(filter.unwrap_or_else(|e| panic!("invalid log filter: {}", e)))
+    }
+
+    /// Whether to emit a log line with the given message.
+    pub fn allows(&self, message: &str) -> bool {
+        if let Some(name) = split_node(message).0 {
+            return self.allows_node(name);
+        }
+        let mut mentioned = message.split("Node(").skip(1).filter_map(|rest| {
+            rest.find(')').map(|end| &rest[..end])
+        });
+        match mentioned.next() {
+            Some(first) => self.allows_node(first) || mentioned.any(|name| self.allows_node(name)),
+            None => true,
+        }
+    }
+
+    /// Whether to emit lines about the node printed as `name`.
+    fn allows_node(&self, name: &str) -> bool {
+        let digits = name.trim_end_matches("..");
+        let name_matches = self.names.iter().any(|name| {
+            name.starts_with(digits) || digits.starts_with(name.as_str())
+        });
+        name_matches || self.prefix_matches(digits)
+    }
+
+    /// Whether the name starting with the hex `digits` could be in one of our prefixes.
+    fn prefix_matches(&self, digits: &str) -> bool {
+        let digits = &digits[..digits.len().min(NAME_BITS / 4)];
+        let padded = format!("{:0<1$}", digits, NAME_BITS / 4);
+        let bits = match NameBits::from_str_radix(&padded, 16) {
+            Ok(bits) => bits,
+            Err(_) => return false,
+        };
+        let known = Prefix::new(4 * digits.len(), Name(bits));
+        self.prefixes.iter().any(|prefix| prefix.is_compatible(&known))
+    }
+}
+
+/// A log file that is rotated once it reaches a maximum size.
+///
+/// When the file would grow past `max_bytes`, it's renamed to `<path>.1`, any existing `<path>.1`
+/// to `<path>.2` and so on, keeping up to `ROTATED_LOG_FILES` old files.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: Option<u64>,
+    file: LineWriter<File>,
+    written: u64,
+}
+
+/// Number of rotated log files to keep.
+pub const ROTATED_LOG_FILES: usize = 5;
+
+impl RotatingFile {
+    pub fn create<P: Into<PathBuf>>(path: P, max_bytes: Option<u64>) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        Ok(RotatingFile {
+            file: LineWriter::new(File::create(&path)?),
+            path,
+            max_bytes,
+            written: 0,
+        })
+    }
+
+    /// Write a line, rotating the file first if the line would take it past the maximum size.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if let Some(max_bytes) = self.max_bytes {
+            if self.written > 0 && self.written + len > max_bytes {
+                self.rotate()?;
+            }
+        }
+        writeln!(self.file, "{}", line)?;
+        self.written += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        for i in (1..ROTATED_LOG_FILES).rev() {
+            let from = rotated_path(&self.path, i);
+            if from.exists() {
+                fs::rename(from, rotated_path(&self.path, i + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        self.file = LineWriter::new(File::create(&self.path)?);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, i: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", i));
+    PathBuf::from(name)
+}
+
+/// Path of the log file for a run, with `{seed}` in `template` replaced by the seed.
+pub fn log_file_path(template: &str, seed: [u32; 4]) -> PathBuf {
+    let seed = seed.iter().map(|n| n.to_string()).collect::<Vec<_>>().join("-");
+    PathBuf::from(template.replace("{seed}", &seed))
+}
+
+/// Log files named after the seed of the run that each line comes from.
+///
+/// Each thread writes to the file for the seed of its current run (see `set_log_seed`), so runs
+/// one after another in the same process, or in parallel on different threads, each get their own
+/// file. Threads writing for the same run, like the workers that handle a step's messages, share
+/// one open file, which is only closed once no thread's current run uses it.
+#[cfg(not(target_arch = "wasm32"))]
+struct LogFiles {
+    template: String,
+    max_bytes: Option<u64>,
+    open: Mutex<OpenLogFiles>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct OpenLogFiles {
+    /// The path of the file each thread last wrote to.
+    current: HashMap<ThreadId, PathBuf>,
+    files: HashMap<PathBuf, RotatingFile>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LogFiles {
+    fn new(template: String, max_bytes: Option<u64>) -> Self {
+        LogFiles {
+            template,
+            max_bytes,
+            open: Mutex::new(OpenLogFiles::default()),
+        }
+    }
+
+    fn write_line(&self, line: &str) {
+        let run_seed = LOG_SEED.with(|s| s.get()).unwrap_or_else(seed);
+        let path = log_file_path(&self.template, run_seed);
+        let mut open = self.open.lock().unwrap();
+        let open = &mut *open;
+        if let Some(previous) = open.current.insert(thread::current().id(), path.clone()) {
+            if previous != path && !open.current.values().any(|current| *current == previous) {
+                open.files.remove(&previous);
+            }
+        }
+        let max_bytes = self.max_bytes;
+        open.files
+            .entry(path)
+            .or_insert_with_key(|path| {
+                RotatingFile::create(path, max_bytes).unwrap_or_else(|e| {
+                    panic!("couldn't create log file {}: {}", path.display(), e)
+                })
+            })
+            .write_line(line)
+            .expect("failed to write log file");
+    }
+}
+
+/// Logger that drops lines rejected by a `NodeFilter`, and can write to files.
+#[cfg(not(target_arch = "wasm32"))]
+struct EwokLogger {
+    inner: Logger,
+    json: bool,
+    filter: Option<NodeFilter>,
+    files: Option<LogFiles>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Log for EwokLogger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        if let Some(ref filter) = self.filter {
+            if !filter.allows(&message) {
+                return;
+            }
+        }
+        match self.files {
+            Some(ref files) => files.write_line(&format_line(record, &message, self.json)),
+            None => self.inner.log(record),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn format_line(record: &LogRecord, message: &str, json: bool) -> String {
+    if json {
+        json_line(&record.level().to_string(), record.target(), message)
+    } else {
         // Disable extraneous formatting.
-        let format = |record: &LogRecord| format!("{}", record.args());
+        message.to_string()
+    }
+}
+
+/// Format a log line as a single line of JSON.
+pub fn json_line(level: &str, target: &str, message: &str) -> String {
+    let (node, message) = split_node(message);
+    let line = JsonLine {
+        step: LOG_STEP.with(|s| s.get()),
+        level,
+        target,
+        node,
+        message,
+    };
+    serde_json::to_string(&line).expect("failed to serialise log line")
+}
+
+/// If the `RUST_LOG` environment variable is set, enable logging to stdout.
+///
+/// Lines are written as plain text, or as JSON objects one per line if `EWOK_LOG_FORMAT` is
+/// `json` (see `json_line`). Lines about nodes can be restricted to a few nodes or sections with
+/// `EWOK_LOG_NODES` and `EWOK_LOG_PREFIXES` (see `NodeFilter`).
+#[cfg(not(target_arch = "wasm32"))]
+pub fn init_logging() {
+    init_logging_to(None, None);
+}
+
+/// Enable logging like `init_logging`, but with the per-module log levels and the log file given
+/// in `params`, if there are any.
+///
+/// Logging is enabled if either `RUST_LOG` or `params.log_levels` is set, so a test can turn on
+/// logging for just the modules it's interested in, e.g. with `ewok::node=debug`, or quieten a
+/// noisy module while keeping `RUST_LOG=debug` for the rest, with `ewok::network=warn`.
+///
+/// `{seed}` in the log file's name is replaced by the seed of each run as it starts, rather than
+/// once here, so every run in the process gets its own file.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn init_logging_with(params: &SimulationParams) {
+    let files = params.log_file.as_ref().map(|template| {
+        LogFiles::new(template.clone(), params.log_file_max_bytes)
+    });
+    init_logging_to(params.log_levels.as_deref(), files);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn init_logging_to(log_levels: Option<&str>, files: Option<LogFiles>) {
+    let rust_log = env::var("RUST_LOG").ok();
+    if rust_log.is_some() || log_levels.is_some() {
+        let json = match env::var("EWOK_LOG_FORMAT") {
+            Ok(ref format) if format == "json" => true,
+            Ok(ref format) if format == "text" => false,
+            Ok(format) => panic!("EWOK_LOG_FORMAT {} isn't 'json' or 'text'.", format),
+            Err(_) => false,
+        };
+        let format = move |record: &LogRecord| format_line(record, &record.args().to_string(), json);
 
         let mut builder = LogBuilder::new();
-        builder.format(format).target(LogTarget::Stdout).parse(
-            &rust_log,
-        );
+        builder.format(format).target(LogTarget::Stdout);
+        // Directives parsed later win over earlier ones for the same module.
+        for spec in rust_log.as_deref().into_iter().chain(log_levels) {
+            builder.parse(spec);
+        }
 
-        if builder.init().is_err() {
+        let filter = NodeFilter::from_env();
+        let result = if filter.is_none() && files.is_none() {
+            builder.init()
+        } else {
+            let inner = builder.build();
+            log::set_logger(|max_level| {
+                max_level.set(inner.filter());
+                Box::new(EwokLogger {
+                    inner,
+                    json,
+                    filter,
+                    files,
+                })
+            })
+        };
+        if result.is_err() {
             // already initialised
         }
     }
 }
+
+/// There's no environment to configure logging from in WebAssembly, so it stays disabled.
+#[cfg(target_arch = "wasm32")]
+pub fn init_logging() {}
+
+/// There's no file system in WebAssembly, so logging stays disabled.
+#[cfg(target_arch = "wasm32")]
+pub fn init_logging_with(_: &SimulationParams) {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_lines() {
+        set_log_step(7);
+        let line: Value = serde_json::from_str(
+            &json_line("DEBUG", "ewok::node", "Node(abc123..): lost our \"connection\""),
+        ).unwrap();
+        assert_eq!(line["step"], 7);
+        assert_eq!(line["node"], "abc123..");
+        assert_eq!(line["message"], "lost our \"connection\"");
+
+        let line: Value = serde_json::from_str(&json_line("INFO", "ewok", "-- step 3 --")).unwrap();
+        assert_eq!(line["node"], Value::Null);
+    }
+
+    #[test]
+    fn markers() {
+        let marker = StepMarker {
+            step: 3,
+            phase: Phase::Stable { since_step: 2 },
+            nodes: 20,
+            queue_size: 100,
+            seed: [1, 2, 3, 4],
+        };
+        let line = marker.to_line();
+        assert_eq!(
+            line,
+            "#ewok-step {\"step\":3,\"phase\":{\"Stable\":{\"since_step\":2}},\"nodes\":20,\
+             \"queue_size\":100,\"seed\":[1,2,3,4]}"
+        );
+        assert_eq!(StepMarker::parse(&line), Some(marker.clone()));
+        assert_eq!(StepMarker::parse(&json_line("INFO", "ewok", &line)), Some(marker));
+        assert_eq!(StepMarker::parse("-- step 3 --"), None);
+        assert_eq!(RunMarker::parse(&line), None);
+    }
+
+    #[test]
+    fn node_filter() {
+        let filter = NodeFilter::parse("e83915", "01").unwrap();
+        assert!(filter.allows("Node(e83915..): hello"));
+        assert!(filter.allows("-- step 3 --"));
+        // 0x5 is 0101, so in section 01.
+        assert!(filter.allows("Node(5abcde..): hello"));
+        assert!(!filter.allows("Node(d67553..): hello"));
+        assert!(!filter.allows("Node(8abcde..): hello"));
+        assert!(filter.allows("Node(8abcde..) and Node(5abcde..) disconnecting"));
+        assert!(!filter.allows("Node(8abcde..) and Node(9abcde..) disconnecting"));
+
+        // Full-width names, in the logs or the filter, match abbreviated ones.
+        assert!(filter.allows("Node(e8391539f4c22a1b): hello"));
+        assert!(!filter.allows("Node(d67553a9e0b1c2d3): hello"));
+        assert!(filter.allows("Node(5abcde0123456789): hello"));
+        let filter = NodeFilter::parse("0xe8391539f4c22a1b, d67553..", "").unwrap();
+        assert!(filter.allows("Node(e83915..): hello"));
+        assert!(filter.allows("Node(d67553a9e0b1c2d3): hello"));
+        assert!(!filter.allows("Node(e83916..): hello"));
+
+        assert!(NodeFilter::parse("xyz", "").is_err());
+        assert!(NodeFilter::parse("", "012").is_err());
+    }
+
+    #[test]
+    fn log_file_per_run() {
+        let dir = ::std::env::temp_dir().join(format!("ewok-runs-{}", ::std::process::id()));
+        let files = LogFiles::new(dir.join("{seed}.log").to_str().unwrap().to_string(), None);
+        set_log_seed([1, 2, 3, 4]);
+        files.write_line("first");
+        set_log_seed([5, 6, 7, 8]);
+        files.write_line("second");
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                set_log_seed([9, 9, 9, 9]);
+                files.write_line("parallel");
+            });
+        });
+        files.write_line("second again");
+
+        assert_eq!(fs::read_to_string(dir.join("1-2-3-4.log")).unwrap(), "first\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("5-6-7-8.log")).unwrap(),
+            "second\nsecond again\n"
+        );
+        assert_eq!(fs::read_to_string(dir.join("9-9-9-9.log")).unwrap(), "parallel\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn log_file_from_workers() {
+        use rayon::prelude::*;
+
+        let dir = ::std::env::temp_dir().join(format!("ewok-workers-{}", ::std::process::id()));
+        let files = LogFiles::new(dir.join("{seed}.log").to_str().unwrap().to_string(), None);
+        set_log_seed([4, 3, 2, 1]);
+        files.write_line("start");
+        let context = log_context();
+        (0..100).into_par_iter().for_each(|i| {
+            set_log_context(context);
+            files.write_line(&i.to_string());
+        });
+        files.write_line("end");
+
+        // Every line went to the one file, without the workers truncating it.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        let contents = fs::read_to_string(dir.join("4-3-2-1.log")).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.first(), Some(&"start"));
+        assert_eq!(lines.last(), Some(&"end"));
+        let mut workers: Vec<u32> = lines[1..lines.len() - 1]
+            .iter()
+            .map(|line| line.parse().unwrap())
+            .collect();
+        workers.sort();
+        assert_eq!(workers, (0..100).collect::<Vec<_>>());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_log_file() {
+        let dir = ::std::env::temp_dir().join(format!("ewok-logs-{}", ::std::process::id()));
+        let path = log_file_path(dir.join("{seed}.log").to_str().unwrap(), [1, 2, 3, 4]);
+        assert_eq!(path, dir.join("1-2-3-4.log"));
+
+        let mut file = RotatingFile::create(&path, Some(10)).unwrap();
+        // Two lines fit in each file.
+        for line in &["aaaa", "bbbb", "cccc", "dddd", "eeee"] {
+            file.write_line(line).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "eeee\n");
+        assert_eq!(fs::read_to_string(rotated_path(&path, 1)).unwrap(), "cccc\ndddd\n");
+        assert_eq!(fs::read_to_string(rotated_path(&path, 2)).unwrap(), "aaaa\nbbbb\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
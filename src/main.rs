@@ -1,28 +1,125 @@
+extern crate clap;
 extern crate ewok;
 
+use clap::{App, Arg};
+use ewok::event_schedule::EventSchedule;
 use ewok::simulation::Simulation;
-use ewok::params::{SimulationParams, NodeParams};
-use ewok::logging::init_logging;
+use ewok::params::{SimulationParams, NodeParams, apply_env_overrides};
+use ewok::logging::init_logging_with;
+use ewok::name::Prefix;
+use ewok::random::{parse_seed, reseed};
+#[cfg(feature = "scripting")]
+use ewok::script::Script;
+use ewok::scenarios::{self, SCENARIOS};
+use ewok::topology::Topology;
+use std::env;
+use std::path::Path;
 
 fn main() {
-    init_logging();
-
-    let params = SimulationParams {
-        max_delay: 5,
-        grow_prob_join: 0.1,
-        grow_prob_drop: 0.02,
-        prob_churn: 0.05,
-        shrink_prob_join: 0.02,
-        shrink_prob_drop: 0.1,
-        prob_disconnect: 0.05,
-        // Gives ~95% chance that a pair will reconnect within 5 steps
-        prob_reconnect: 0.45,
-        starting_complete: 16,
-        grow_complete: 30,
-        stable_steps: 100,
+    let scenario_help = SCENARIOS
+        .iter()
+        .map(|scenario| format!("    {}: {}", scenario.name, scenario.description))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let scenario_names: Vec<&str> = SCENARIOS.iter().map(|scenario| scenario.name).collect();
+    let matches = App::new("ewok")
+        .about("Runs an Ewok simulation, configured by `EWOK_PARAM_<NAME>` environment \
+                variables.")
+        .arg(Arg::with_name("scenario")
+                 .long("scenario")
+                 .value_name("NAME")
+                 .possible_values(&scenario_names)
+                 .help("Run a named scenario rather than growing a network from a single node."))
+        .arg(Arg::with_name("schedule")
+                 .long("schedule")
+                 .value_name("FILE")
+                 .conflicts_with("scenario")
+                 .help("Run the events in a schedule file, e.g. one written by the schedule \
+                        tool."))
+        .arg(Arg::with_name("seed")
+                 .long("seed")
+                 .value_name("SEED")
+                 .help("Seed to run with, e.g. \"[1, 2, 3, 4]\" (takes precedence over \
+                        `EWOK_SEED`)."))
+        .arg(Arg::with_name("chaos")
+                 .long("chaos")
+                 .help("Take random chaos actions during the stable phase, within the bounds \
+                        set by the `chaos_*` parameters."))
+        .after_help(&*format!("SCENARIOS:\n{}", scenario_help))
+        .get_matches();
+
+    let scenario = matches.value_of("scenario").map(|name| {
+        scenarios::find(name).expect("clap only accepts registered scenarios")
+    });
+
+    // Scenarios start from their own parameters, which the environment can still override.
+    let (mut params, mut node_params) = match scenario {
+        Some(scenario) => scenario.params(),
+        None => (SimulationParams::default(), NodeParams::default()),
+    };
+    apply_env_overrides(&mut params, &mut node_params).unwrap_or_else(|e| panic!("{}", e));
+    if matches.is_present("chaos") {
+        params.chaos = true;
+    }
+
+    if let Some(value) = matches.value_of("seed") {
+        let seed = parse_seed(value)
+            .unwrap_or_else(|| panic!("seed {} isn't in the form '[1, 2, 3, 4]'", value));
+        reseed(seed);
+    }
+
+    init_logging_with(&params);
+
+    let schedule = matches.value_of("schedule").map(|path| {
+        EventSchedule::load(path)
+            .unwrap_or_else(|e| panic!("couldn't load schedule {}: {}", path, e))
+    });
+    // Scripts are named relative to the schedule file.
+    let script = matches.value_of("schedule").and_then(|path| {
+        let script = schedule.as_ref()?.script.as_ref()?;
+        Some(Path::new(path).parent().unwrap_or(Path::new("")).join(script))
+    });
+    let topology = env::var("EWOK_TOPOLOGY").ok();
+    let mut simulation = match (scenario, topology) {
+        (Some(_), Some(_)) => panic!("EWOK_TOPOLOGY can't be used with --scenario"),
+        (Some(scenario), None) => scenario.simulation(params, node_params),
+        // Start from the sections listed in `EWOK_TOPOLOGY`, if given, rather than a single
+        // node.
+        (None, Some(path)) => {
+            let topology = Topology::load(&path)
+                .unwrap_or_else(|e| panic!("couldn't load topology {}: {}", path, e));
+            let schedule = schedule.unwrap_or_else(EventSchedule::empty);
+            Simulation::from_topology(&topology, schedule, params, node_params)
+        }
+        (None, None) => {
+            match schedule {
+                Some(schedule) => {
+                    let genesis = Some((Prefix::empty(), 1)).into_iter().collect();
+                    Simulation::new_from(genesis, schedule, params, node_params)
+                }
+                None => Simulation::new(params, node_params),
+            }
+        }
     };
 
-    let mut simulation = Simulation::new(params, NodeParams::default());
+    if let Some(path) = script {
+        add_script(&mut simulation, &path);
+    }
 
     simulation.run().unwrap();
 }
+
+#[cfg(feature = "scripting")]
+fn add_script(simulation: &mut Simulation, path: &Path) {
+    let script = Script::load(path)
+        .unwrap_or_else(|e| panic!("couldn't load script {}: {}", path.display(), e));
+    simulation.add_hook(script);
+}
+
+#[cfg(not(feature = "scripting"))]
+fn add_script(_: &mut Simulation, path: &Path) {
+    panic!(
+        "can't run script {}: ewok was built without the `scripting` feature",
+        path.display()
+    );
+}
@@ -1,38 +1,42 @@
 use name::Name;
-use block::{Block, Vote};
-use blocks::{CurrentBlocks, Blocks};
-use std::collections::BTreeSet;
-use std::cmp;
+use block::{Block, BlockId, Vote};
+use blocks::{CurrentBlocks, Blocks, Derivation};
+use coverage::Rule;
+use params::NodeParams;
+use std::collections::{BTreeMap, BTreeSet};
 
+/// Vote for merges, both of sections that are too small and of our sections with siblings in
+/// `lost_siblings`, the blocks we've lost our connection to for long enough to force a merge.
+///
+/// Each vote comes with the rule it was made by, which is `ForceMerge` if both rules made it.
 pub fn merge_blocks(
     blocks: &mut Blocks,
     current_blocks: &CurrentBlocks,
-    connections: &BTreeSet<Name>,
+    lost_siblings: &BTreeSet<BlockId>,
     our_name: Name,
-    min_section_size: usize,
-) -> Vec<Vote> {
-    let mut result = merge_rule(blocks, current_blocks, our_name, min_section_size);
-    result.extend(force_merge_rule(
-        blocks,
-        current_blocks,
-        connections,
-        our_name,
-    ));
-    result.into_iter().collect()
+    params: &NodeParams,
+) -> Vec<(Rule, Vote)> {
+    let (num_sections, num_nodes) = blocks.network_size(current_blocks);
+    let min_section_size = params.min_section_size_for(num_sections, num_nodes);
+    let merges = merge_rule(blocks, current_blocks, our_name, min_section_size);
+    let forced = force_merge_rule(blocks, current_blocks, lost_siblings, our_name);
+    let mut result: BTreeMap<Vote, Rule> =
+        merges.into_iter().map(|vote| (vote, Rule::Merge)).collect();
+    result.extend(forced.into_iter().map(|vote| (vote, Rule::ForceMerge)));
+    result.into_iter().map(|(vote, rule)| (rule, vote)).collect()
 }
 
 fn force_merge_rule(
     blocks: &mut Blocks,
     current_blocks: &CurrentBlocks,
-    connections: &BTreeSet<Name>,
+    lost_siblings: &BTreeSet<BlockId>,
     our_name: Name,
 ) -> BTreeSet<Vote> {
-    let (votes, blocks_to_insert) = {
-        let mut votes = BTreeSet::new();
-        let mut blocks_to_insert = BTreeSet::new();
+    let merges = {
+        let mut merges = BTreeSet::new();
         for candidate in blocks.block_contents(current_blocks).into_iter().filter(
             |&b| {
-                !b.prefix.matches(our_name) && lost_quorum(b, connections)
+                !b.prefix.matches(our_name) && lost_siblings.contains(&b.get_id())
             },
         )
         {
@@ -41,31 +45,22 @@ fn force_merge_rule(
                 .into_iter()
                 .filter(|b| b.prefix.sibling() == Some(candidate.prefix))
             {
-                let target = merged_block(candidate, our_block);
-                let target_id = target.get_id();
-                blocks_to_insert.insert(target);
-                let vote = Vote {
-                    from: our_block.get_id(),
-                    to: target_id,
-                };
-                votes.insert(vote);
+                merges.insert((our_block.get_id(), candidate.get_id()));
             }
         }
-        (votes, blocks_to_insert)
+        merges
     };
-    for block in blocks_to_insert {
-        blocks.insert(block);
-    }
-    votes
+    merge_votes(blocks, merges)
 }
 
-fn lost_quorum(block: &Block, connections: &BTreeSet<Name>) -> bool {
+/// Whether we're connected to few enough of `block`'s members to force a merge with it.
+pub fn lost_quorum(block: &Block, connections: &BTreeSet<Name>, params: &NodeParams) -> bool {
     let num_active = block
         .members
         .iter()
         .filter(|&name| connections.contains(name))
         .count();
-    num_active <= block.members.len() / 2
+    params.is_force_merge_loss(num_active, block.members.len())
 }
 
 fn merge_rule(
@@ -74,57 +69,49 @@ fn merge_rule(
     our_name: Name,
     min_section_size: usize,
 ) -> BTreeSet<Vote> {
-    let (votes, blocks_to_insert) = {
+    let merges = {
         // find blocks that describe sections below threshold
         let candidates = find_small_blocks(blocks, current_blocks, min_section_size);
-        let mut votes = BTreeSet::new();
-        let mut blocks_to_insert = BTreeSet::new();
+        let mut merges = BTreeSet::new();
         for candidate in candidates {
             if candidate.members.contains(&our_name) {
                 // if the block contains our name, vote for merging with all current siblings
                 let sibling_prefix = candidate.prefix.sibling().unwrap();
                 for block in blocks.blocks_for_prefix(current_blocks, sibling_prefix) {
-                    let target = merged_block(candidate, block);
-                    let target_id = target.get_id();
-                    blocks_to_insert.insert(target);
-                    let vote = Vote {
-                        from: candidate.get_id(),
-                        to: target_id,
-                    };
-                    votes.insert(vote);
+                    merges.insert((candidate.get_id(), block.get_id()));
                 }
-            } else if let Some(candidate_sibling_pfx) = candidate.prefix.sibling() {
+            } else {
                 // The block doesn't contain our name - it might be our sibling or a sibling of our
                 // ancestor (for each our block it might be different). If that is the case, we vote
                 // for merging from our block with every sibling of that block.
                 for block in blocks.our_blocks(current_blocks, our_name) {
-                    if candidate_sibling_pfx.is_prefix_of(&block.prefix) {
-                        if let Some(block_sibling) = block.prefix.sibling() {
-                            for sibling_block in blocks.blocks_for_prefix(
-                                current_blocks,
-                                block_sibling,
-                            )
-                            {
-                                let target = merged_block(sibling_block, block);
-                                let target_id = target.get_id();
-                                blocks_to_insert.insert(target);
-                                let vote = Vote {
-                                    from: block.get_id(),
-                                    to: target_id,
-                                };
-                                votes.insert(vote);
-                            }
+                    if candidate.prefix.is_sibling_of_ancestor_of(&block.prefix) {
+                        let block_sibling = block.prefix.sibling().unwrap();
+                        for sibling_block in
+                            blocks.blocks_for_prefix(current_blocks, block_sibling)
+                        {
+                            merges.insert((block.get_id(), sibling_block.get_id()));
                         }
                     }
                 }
             }
         }
-        (votes, blocks_to_insert)
+        merges
     };
-    for block in blocks_to_insert {
-        blocks.insert(block);
-    }
-    votes
+    merge_votes(blocks, merges)
+}
+
+/// Vote for each block to merge with its sibling, given as `(block, sibling)` pairs.
+fn merge_votes(blocks: &mut Blocks, merges: BTreeSet<(BlockId, BlockId)>) -> BTreeSet<Vote> {
+    merges
+        .into_iter()
+        .map(|(from, sibling)| {
+            Vote {
+                from,
+                to: blocks.derive(from, Derivation::Merge(sibling)),
+            }
+        })
+        .collect()
 }
 
 fn find_small_blocks<'a>(
@@ -140,12 +127,3 @@ fn find_small_blocks<'a>(
         })
         .collect()
 }
-
-fn merged_block(b0: &Block, b1: &Block) -> Block {
-    assert_eq!(b0.prefix.sibling(), Some(b1.prefix));
-    Block {
-        prefix: b0.prefix.popped(),
-        version: cmp::max(b0.version, b1.version) + 1,
-        members: b0.members.union(&b1.members).cloned().collect(),
-    }
-}
@@ -1,22 +1,46 @@
-use block::{BlockId, Vote};
-use blocks::{VoteCounts, CurrentBlocks, Blocks};
-use name::{Name, Prefix};
+use block::{Block, BlockId, Vote};
+use blocks::{CurrentBlocks, Blocks};
+use name::{Name, NameBits, Prefix};
+use params::RecipientRule;
 use self::MessageContent::*;
+#[cfg(feature = "scale")]
+use std::cmp::Ordering;
 use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Message {
     pub sender: Name,
     pub recipient: Name,
-    pub content: MessageContent,
+    /// Shared by every recipient of a broadcast, so sending to many nodes doesn't copy it.
+    pub content: Arc<MessageContent>,
+    /// Identifies the chain of messages that this one belongs to (see `TraceId`).
+    #[serde(default)]
+    pub trace_id: TraceId,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Identifier shared by a message and every message sent while handling it, transitively.
+///
+/// Messages that aren't sent in response to another message, like votes and the messages for
+/// events, start a new trace. Their ID is 0 until the network assigns one as they're sent, and
+/// a broadcast gets a single ID for all of its recipients.
+pub type TraceId = u64;
+
+/// Votes and their voters as sent to a joining node in a `BootstrapMsg`, shared between every
+/// node joining before the sender's votes next change.
+pub type BootstrapVotes = Arc<Vec<(Vote, BTreeSet<Name>)>>;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum MessageContent {
     /// Vote for a block to succeed another block.
     VoteMsg(Vote),
     /// Notification that we believe this vote to be agreed by all the listed members.
     VoteAgreedMsg((Vote, BTreeSet<Name>)),
+    /// Notification that this vote is agreed, proven by an aggregate of its voters' signatures
+    /// rather than a list of them. Sent instead of `VoteAgreedMsg` if the
+    /// `aggregate_signature_bytes` parameter is set.
+    VoteAggregatedMsg((Vote, AggregateSignature)),
     /// Collection of agreed votes, sent during a merge.
     VoteBundle(Vec<(Vote, BTreeSet<Name>)>),
     /// Request for a proof for the given block
@@ -26,7 +50,10 @@ pub enum MessageContent {
     /// Message sent from joining node (sender) to all section members (recipients).
     NodeJoined,
     /// Message sent to a joining node to get it up to date on the current blocks.
-    BootstrapMsg(VoteCounts),
+    ///
+    /// Contains all the sender's votes and their voters, sorted by vote. The votes are shared
+    /// with the sender, who reuses them for other joining nodes.
+    BootstrapMsg(BootstrapVotes),
     /// Connect and disconnect represent the connection or disconnection of two nodes.
     /// Can be sent from node-to-node or from the simulation to a pair of nodes (for disconnects
     /// and reconnects).
@@ -34,28 +61,285 @@ pub enum MessageContent {
     Connect,
     /// ^See above.
     Disconnect,
+    /// Request from a section member (sender) for a joining node to prove its resources.
+    ResourceProofChallenge,
+    /// One part of a joining node's response to a resource proof challenge, numbered from 1. The
+    /// joining node sends one part per step, for `NodeParams::resource_proof_steps` steps.
+    ResourceProofResponse(u64),
+    /// Approval of a candidate by the listed members of its section, sent to the other members.
+    ///
+    /// Members only vote to add a candidate once a quorum of their section has approved it.
+    ApproveCandidate(Name, BTreeSet<Name>),
+    /// Instruction from a member of a section to one of its new members (the first name) to
+    /// relocate to another section under a new name (the second name).
+    ///
+    /// The new member relocates once a quorum of its section has sent it.
+    Relocate(Name, Name),
+    /// Message sent from a relocated node (sender) to the members of its new section, with the
+    /// name it was relocated from.
+    NodeRelocated(Name),
+    /// Message sent from a client (sender) to the members of the section it connects to, asking
+    /// to be told about changes to the section.
+    ClientConnect,
+    /// Notification sent from a section member to a connected client that the section's current
+    /// block is now the given block.
+    BlockUpdate(BlockId),
+}
+
+/// A single signature aggregating those of every voter for a vote, as in BLS.
+///
+/// Checking an aggregate needs the signers' public keys, so the signers are kept alongside it.
+/// Like trace IDs, they're bookkeeping for the simulation: only the `len` bytes of the signature
+/// itself are sent, which should include anything a real implementation would send to identify
+/// the signers, like a bitmap of the block's members.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct AggregateSignature {
+    pub signers: BTreeSet<Name>,
+    /// Size of the signature in bytes.
+    pub len: usize,
+}
+
+/// The variant of a `MessageContent`, without its contents.
+///
+/// Used to break message counts down by which part of the protocol sent them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum MessageKind {
+    Vote,
+    VoteAgreed,
+    VoteBundle,
+    RequestProof,
+    NoProof,
+    NodeJoined,
+    Bootstrap,
+    Connect,
+    Disconnect,
+    ResourceProofChallenge,
+    ResourceProofResponse,
+    ApproveCandidate,
+    Relocate,
+    NodeRelocated,
+    ClientConnect,
+    BlockUpdate,
+    VoteAggregated,
+}
+
+impl MessageKind {
+    /// All kinds of message, in the order they're declared.
+    pub fn all() -> &'static [MessageKind] {
+        use self::MessageKind::*;
+        &[
+            Vote,
+            VoteAgreed,
+            VoteBundle,
+            RequestProof,
+            NoProof,
+            NodeJoined,
+            Bootstrap,
+            Connect,
+            Disconnect,
+            ResourceProofChallenge,
+            ResourceProofResponse,
+            ApproveCandidate,
+            Relocate,
+            NodeRelocated,
+            ClientConnect,
+            BlockUpdate,
+            VoteAggregated,
+        ]
+    }
+}
+
+impl fmt::Display for MessageKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
 }
 
 // XOR distance between the lower bounds of two prefixes.
-fn prefix_dist(p1: &Prefix, p2: &Prefix) -> u64 {
+fn prefix_dist(p1: &Prefix, p2: &Prefix) -> NameBits {
     p1.lower_bound().0 ^ p2.lower_bound().0
 }
 
+/// Number of members of a section that relay each agreement to a neighbour, in scale mode.
+#[cfg(feature = "scale")]
+const RELAYS_PER_NEIGHBOUR: usize = 3;
+
+/// Whether we're one of the members of our `section` that relays agreements to `neighbour`.
+///
+/// Normally every member does, but in scale mode only the few members closest to the neighbour
+/// do, which still reaches all of the neighbour's members.
+#[cfg(feature = "scale")]
+fn is_relay(section: &Block, neighbour: &Prefix, our_name: Name) -> bool {
+    let target = neighbour.lower_bound();
+    let closer = section
+        .members
+        .iter()
+        .filter(|&&name| target.cmp_distance(name, our_name) == Ordering::Less)
+        .count();
+    closer < RELAYS_PER_NEIGHBOUR
+}
+
+#[cfg(not(feature = "scale"))]
+fn is_relay(_: &Block, _: &Prefix, _: Name) -> bool {
+    true
+}
+
+/// Rule for which members of our current blocks we relay an agreement to.
+///
+/// Votes always go to the members of the blocks they're between, and anything else to all the
+/// members of our current blocks, so this is the only part of choosing recipients that's a matter
+/// of heuristics, and can be evaluated independently of the voting logic.
+pub trait RecipientPolicy {
+    /// Recipients of the agreement of a vote from `from` to `to`, which we're a member of one of,
+    /// given the contents of our current blocks.
+    fn agreement_recipients(
+        &self,
+        from: &Block,
+        to: &Block,
+        current_blocks: &[&Block],
+        our_name: Name,
+    ) -> BTreeSet<Name>;
+}
+
+/// Relay each agreement to the neighbours of `from` and `to` that our section is the closest to,
+/// so that each neighbour hears of it from a single section (`closest_neighbours`).
+pub struct ClosestNeighbours;
+
+/// Relay each agreement to every neighbour of `from` and `to` (`all_neighbours`).
+pub struct AllNeighbours;
+
+/// Relay each agreement only within the sections compatible with `from` or `to`, leaving
+/// neighbours to learn of it some other way (`own_section`).
+pub struct OwnSection;
+
+impl RecipientPolicy for ClosestNeighbours {
+    fn agreement_recipients(
+        &self,
+        from: &Block,
+        to: &Block,
+        current_blocks: &[&Block],
+        our_name: Name,
+    ) -> BTreeSet<Name> {
+        // The current sections compatible with either the `from` prefix or the `to`
+        // prefix. These don't depend on the neighbour, so only work them out once.
+        let compatible: Vec<_> = current_blocks
+            .iter()
+            .filter(|b2| {
+                b2.prefix.is_compatible(&from.prefix) || b2.prefix.is_compatible(&to.prefix)
+            })
+            .collect();
+
+        // Send vote agreements to any neighbour section N with block `b1`, such that:
+        // 1. N is a neighbour of the `from` or `to` prefix, and
+        // 2. Our current prefix is the closest (by XOR distance) to N's prefix,
+        // amongst the set of current sections which have prefixes compatible with
+        // either the `from` prefix or the `to` prefix.
+        current_blocks
+            .iter()
+            .filter(|b1| {
+                let cond1 =
+                    b1.prefix.is_neighbour(&from.prefix) || b1.prefix.is_neighbour(&to.prefix);
+                // (2), which is only worth checking for neighbours.
+                cond1 &&
+                    compatible
+                        .iter()
+                        // Min by XOR distance.
+                        .min_by_key(|b2| prefix_dist(&b1.prefix, &b2.prefix))
+                        // Are we in the section that's closest by XOR distance?
+                        .is_some_and(|closest| {
+                            closest.prefix.matches(our_name) &&
+                                is_relay(closest, &b1.prefix, our_name)
+                        })
+            })
+            .flat_map(|block| block.members.iter().cloned())
+            .collect()
+    }
+}
+
+impl RecipientPolicy for AllNeighbours {
+    fn agreement_recipients(
+        &self,
+        from: &Block,
+        to: &Block,
+        current_blocks: &[&Block],
+        _: Name,
+    ) -> BTreeSet<Name> {
+        current_blocks
+            .iter()
+            .filter(|b| b.prefix.is_neighbour(&from.prefix) || b.prefix.is_neighbour(&to.prefix))
+            .flat_map(|block| block.members.iter().cloned())
+            .collect()
+    }
+}
+
+impl RecipientPolicy for OwnSection {
+    fn agreement_recipients(
+        &self,
+        from: &Block,
+        to: &Block,
+        current_blocks: &[&Block],
+        _: Name,
+    ) -> BTreeSet<Name> {
+        current_blocks
+            .iter()
+            .filter(|b| {
+                b.prefix.is_compatible(&from.prefix) || b.prefix.is_compatible(&to.prefix)
+            })
+            .flat_map(|block| block.members.iter().cloned())
+            .collect()
+    }
+}
+
+impl RecipientRule {
+    /// The policy that implements this rule.
+    pub fn policy(self) -> &'static dyn RecipientPolicy {
+        match self {
+            RecipientRule::ClosestNeighbours => &ClosestNeighbours,
+            RecipientRule::AllNeighbours => &AllNeighbours,
+            RecipientRule::OwnSection => &OwnSection,
+        }
+    }
+}
+
 impl MessageContent {
+    pub fn kind(&self) -> MessageKind {
+        match *self {
+            VoteMsg(_) => MessageKind::Vote,
+            VoteAgreedMsg(_) => MessageKind::VoteAgreed,
+            VoteBundle(_) => MessageKind::VoteBundle,
+            RequestProof(..) => MessageKind::RequestProof,
+            NoProof(_) => MessageKind::NoProof,
+            NodeJoined => MessageKind::NodeJoined,
+            BootstrapMsg(_) => MessageKind::Bootstrap,
+            Connect => MessageKind::Connect,
+            Disconnect => MessageKind::Disconnect,
+            ResourceProofChallenge => MessageKind::ResourceProofChallenge,
+            ResourceProofResponse(_) => MessageKind::ResourceProofResponse,
+            ApproveCandidate(..) => MessageKind::ApproveCandidate,
+            Relocate(..) => MessageKind::Relocate,
+            NodeRelocated(_) => MessageKind::NodeRelocated,
+            ClientConnect => MessageKind::ClientConnect,
+            BlockUpdate(_) => MessageKind::BlockUpdate,
+            VoteAggregatedMsg(_) => MessageKind::VoteAggregated,
+        }
+    }
+
     pub fn recipients(
         &self,
         blocks: &Blocks,
         current_blocks: &CurrentBlocks,
         our_name: Name,
+        policy: &dyn RecipientPolicy,
     ) -> BTreeSet<Name> {
         match *self {
             // Send votes to members of the `from` and `to` blocks.
             VoteMsg(ref vote) => {
                 let from = vote.from.into_block(blocks);
                 let to = vote.to.into_block(blocks);
-                &from.members | &to.members
+                &*from.members | &*to.members
             }
-            VoteAgreedMsg((Vote { ref from, ref to }, _)) => {
+            VoteAgreedMsg((Vote { ref from, ref to }, _)) |
+            VoteAggregatedMsg((Vote { ref from, ref to }, _)) => {
                 let from = from.into_block(blocks);
                 let to = to.into_block(blocks);
 
@@ -64,36 +348,12 @@ impl MessageContent {
                     return btreeset!{};
                 }
 
-                let current_blocks = blocks.block_contents(current_blocks);
-
-                // Send vote agreements to any neighbour section N with block `b1`, such that:
-                // 1. N is a neighbour of the `from` or `to` prefix, and
-                // 2. Our current prefix is the closest (by XOR distance) to N's prefix,
-                // amongst the set of current sections which have prefixes compatible with
-                // either the `from` prefix or the `to` prefix.
-                current_blocks
-                    .iter()
-                    .filter(|b1| {
-                        let cond1 =
-                            b1.prefix.is_neighbour(&from.prefix) ||
-                            b1.prefix.is_neighbour(&to.prefix);
-                        // (2)
-                        let cond2 = current_blocks
-                            .iter()
-                            .filter(|b2| {
-                                b2.prefix.is_compatible(&from.prefix) ||
-                                b2.prefix.is_compatible(&to.prefix)
-                            })
-                            // Min by XOR distance.
-                            .min_by_key(|b2| prefix_dist(&b1.prefix, &b2.prefix))
-                            // Are we in the section that's closest by XOR distance?
-                            .map(|closest| closest.prefix.matches(our_name))
-                            .unwrap_or(false);
-
-                        cond1 && cond2
-                    })
-                    .flat_map(|block| block.members.iter().cloned())
-                    .collect()
+                policy.agreement_recipients(
+                    from,
+                    to,
+                    &blocks.block_contents(current_blocks),
+                    our_name,
+                )
             }
             // Send anything else to all connected neighbours.
             _ => {
@@ -106,3 +366,36 @@ impl MessageContent {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::p;
+
+    #[test]
+    fn agreement_recipients_by_policy() {
+        let block = |prefix: &str, version| {
+            Block {
+                prefix: p(prefix),
+                version,
+                members: Arc::new(btreeset!{ p(prefix).lower_bound() }),
+            }
+        };
+        let name = |prefix: &str| p(prefix).lower_bound();
+        // We're in 00, which has just split from 0.
+        let (from, to) = (block("0", 0), block("00", 1));
+        let current = vec![to.clone(), block("01", 1), block("10", 0), block("11", 0)];
+        let current: Vec<_> = current.iter().collect();
+        let recipients = |policy: &dyn RecipientPolicy| {
+            policy.agreement_recipients(&from, &to, &current, name("00"))
+        };
+
+        // 01 and 11 are closer to our sibling 01, which relays to them instead.
+        assert_eq!(recipients(&ClosestNeighbours), btreeset!{ name("10") });
+        assert_eq!(
+            recipients(&AllNeighbours),
+            btreeset!{ name("01"), name("10"), name("11") }
+        );
+        assert_eq!(recipients(&OwnSection), btreeset!{ name("00"), name("01") });
+    }
+}
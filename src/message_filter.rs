@@ -0,0 +1,120 @@
+//! Filter for messages that a node has sent recently, so that it doesn't send them again.
+
+use fxhash::FxHashMap;
+use message::Message;
+use name::Name;
+use std::collections::VecDeque;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::hash::{Hash, Hasher};
+
+/// Number of steps for which a sent message is remembered.
+const MESSAGE_FILTER_STEPS: u64 = 100;
+
+/// Messages sent within the last `MESSAGE_FILTER_STEPS` steps, identified by their recipient and
+/// a hash of their content.
+///
+/// Entries expire by age rather than by number, so a node that sends a lot of messages on every
+/// step (as nodes do in large networks) doesn't forget them any sooner.
+#[derive(Default)]
+pub struct MessageFilter {
+    /// Map from recipient and content hash to the step that the message was first sent.
+    sent: FxHashMap<(Name, u64), u64>,
+    /// Messages in the order they were sent, for expiring them.
+    by_step: VecDeque<(u64, (Name, u64))>,
+}
+
+impl MessageFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `message` is being sent at `step`, returning `false` if it was already sent.
+    ///
+    /// A message that was already sent keeps the step it was first sent at, so that a message
+    /// offered again on every step still expires and can be sent again.
+    pub fn insert(&mut self, message: &Message, step: u64) -> bool {
+        self.expire(step);
+        let key = (message.recipient, content_hash(message));
+        match self.sent.entry(key) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(step);
+                self.by_step.push_back((step, key));
+                true
+            }
+        }
+    }
+
+    /// Number of messages remembered.
+    pub fn len(&self) -> usize {
+        self.sent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sent.is_empty()
+    }
+
+    /// Forget messages that were first sent too long before `step`.
+    fn expire(&mut self, step: u64) {
+        while let Some(&(sent_step, key)) = self.by_step.front() {
+            if sent_step + MESSAGE_FILTER_STEPS > step {
+                break;
+            }
+            self.by_step.pop_front();
+            self.sent.remove(&key);
+        }
+    }
+}
+
+fn content_hash(message: &Message) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    message.content.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use message::MessageContent::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn expires_by_age() {
+        let message = |recipient: u64| {
+            Message {
+                sender: Name(0),
+                recipient: Name(recipient.into()),
+                content: Arc::new(Connect),
+                trace_id: 0,
+            }
+        };
+        let mut filter = MessageFilter::new();
+        assert!(filter.insert(&message(1), 0));
+        assert!(filter.insert(&message(2), 0));
+        assert!(!filter.insert(&message(1), 1));
+
+        // However many messages are sent in between, a message is remembered until it expires.
+        for recipient in 3..5000 {
+            assert!(filter.insert(&message(recipient), MESSAGE_FILTER_STEPS - 1));
+        }
+        assert!(!filter.insert(&message(2), MESSAGE_FILTER_STEPS - 1));
+        assert!(filter.insert(&message(1), MESSAGE_FILTER_STEPS + 1));
+        assert_eq!(filter.len(), 4998);
+    }
+
+    #[test]
+    fn repeats_expire() {
+        let message = Message {
+            sender: Name(0),
+            recipient: Name(1),
+            content: Arc::new(Connect),
+            trace_id: 0,
+        };
+        let mut filter = MessageFilter::new();
+        let sent: Vec<_> = (0..3 * MESSAGE_FILTER_STEPS)
+            .filter(|&step| filter.insert(&message, step))
+            .collect();
+        assert_eq!(sent, vec![0, MESSAGE_FILTER_STEPS, 2 * MESSAGE_FILTER_STEPS]);
+        assert_eq!(filter.by_step.len(), 1);
+    }
+}
@@ -0,0 +1,261 @@
+//! Live metrics for monitoring long-running simulations.
+//!
+//! If the `EWOK_METRICS` environment variable is set, the simulation periodically rewrites the
+//! file it names with a few gauges and counters in the Prometheus text format, e.g. for the
+//! node exporter's textfile collector, or just for `watch cat`. The file is rewritten every
+//! `EWOK_METRICS_INTERVAL` steps (default 10), and replaced atomically so that readers never see
+//! a partial file.
+
+use name::Prefix;
+use peer_state::Confirmation;
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Default number of steps between rewrites of the metrics file.
+const DEFAULT_INTERVAL: u64 = 10;
+
+/// Current values of the metrics.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub step: u64,
+    pub nodes: usize,
+    pub queue_size: usize,
+    /// Number of distinct prefixes among the current blocks of all nodes.
+    pub sections: usize,
+    /// Number of splits seen so far.
+    pub splits: u64,
+    /// Number of merges seen so far.
+    pub merges: u64,
+    /// Number of nodes that are members of all of their section's current blocks.
+    pub confirmed: usize,
+    /// Number of nodes that are members of some of their section's current blocks, but not all.
+    pub partially_confirmed: usize,
+    /// Longest that any node has currently been partially confirmed for, in steps.
+    pub longest_partial_confirmation: u64,
+    /// Number of proofs requested so far.
+    pub proof_requests: u64,
+    /// Number of proofs not requested so far because of the proof request policy.
+    pub proof_request_skips: u64,
+    /// Number of chain segments found with a predecessor missing so far.
+    pub truncated_segments: u64,
+}
+
+impl Metrics {
+    /// Render the metrics in the Prometheus text format.
+    pub fn to_prometheus(&self) -> String {
+        let metrics: [(&str, &str, &str, u64); 12] = [
+            ("ewok_step", "gauge", "Current simulation step.", self.step),
+            ("ewok_nodes", "gauge", "Number of live nodes.", self.nodes as u64),
+            (
+                "ewok_queue_size",
+                "gauge",
+                "Number of messages queued in the network.",
+                self.queue_size as u64,
+            ),
+            (
+                "ewok_sections",
+                "gauge",
+                "Number of distinct prefixes among the nodes' current blocks.",
+                self.sections as u64,
+            ),
+            ("ewok_splits_total", "counter", "Number of section splits.", self.splits),
+            ("ewok_merges_total", "counter", "Number of section merges.", self.merges),
+            (
+                "ewok_confirmed_nodes",
+                "gauge",
+                "Number of nodes in all of their section's current blocks.",
+                self.confirmed as u64,
+            ),
+            (
+                "ewok_partially_confirmed_nodes",
+                "gauge",
+                "Number of nodes in some of their section's current blocks, but not all.",
+                self.partially_confirmed as u64,
+            ),
+            (
+                "ewok_longest_partial_confirmation_steps",
+                "gauge",
+                "Longest that a node has been partially confirmed for.",
+                self.longest_partial_confirmation,
+            ),
+            (
+                "ewok_proof_requests_total",
+                "counter",
+                "Number of proofs requested.",
+                self.proof_requests,
+            ),
+            (
+                "ewok_proof_request_skips_total",
+                "counter",
+                "Number of proofs not requested because of the proof request policy.",
+                self.proof_request_skips,
+            ),
+            (
+                "ewok_truncated_segments_total",
+                "counter",
+                "Number of chain segments found with a predecessor missing.",
+                self.truncated_segments,
+            ),
+        ];
+        let mut out = String::new();
+        for &(name, kind, help, value) in &metrics {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        }
+        out
+    }
+}
+
+/// A metrics file that is rewritten as the simulation progresses.
+pub struct MetricsFile {
+    path: PathBuf,
+    interval: u64,
+    metrics: Metrics,
+    prefixes: BTreeSet<Prefix>,
+}
+
+impl MetricsFile {
+    pub fn new<P: Into<PathBuf>>(path: P, interval: u64) -> Self {
+        MetricsFile {
+            path: path.into(),
+            interval: interval.max(1),
+            metrics: Metrics::default(),
+            prefixes: BTreeSet::new(),
+        }
+    }
+
+    /// Create a metrics file from the `EWOK_METRICS` and `EWOK_METRICS_INTERVAL` variables.
+    pub fn from_env() -> Option<Self> {
+        let path = env::var("EWOK_METRICS").ok()?;
+        let interval = match env::var("EWOK_METRICS_INTERVAL") {
+            Ok(value) => {
+                value.parse().unwrap_or_else(|_| {
+                    panic!("EWOK_METRICS_INTERVAL {} isn't a number of steps.", value)
+                })
+            }
+            Err(_) => DEFAULT_INTERVAL,
+        };
+        Some(MetricsFile::new(path, interval))
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Set the number of proofs requested and skipped so far, before the next `update`.
+    pub fn count_proof_requests(&mut self, requests: u64, skips: u64) {
+        self.metrics.proof_requests = requests;
+        self.metrics.proof_request_skips = skips;
+    }
+
+    /// Set the number of chain segments found with a predecessor missing so far, before the next
+    /// `update`.
+    pub fn count_truncated_segments(&mut self, truncated_segments: u64) {
+        self.metrics.truncated_segments = truncated_segments;
+    }
+
+    /// Update the metrics at the end of a step, rewriting the file if it's due.
+    ///
+    /// `prefixes` are the prefixes of the current blocks of all nodes. Splits and merges are
+    /// counted by comparing them to the prefixes of the previous step. `confirmations` are those
+    /// of all nodes.
+    pub fn update(
+        &mut self,
+        step: u64,
+        nodes: usize,
+        queue_size: usize,
+        prefixes: BTreeSet<Prefix>,
+        confirmations: &[Confirmation],
+    ) -> io::Result<()> {
+        for prefix in prefixes.difference(&self.prefixes) {
+            // Count each split and merge once, by the first child of the split section and by
+            // the parent of the merged sections.
+            let parent = prefix.popped();
+            if parent != *prefix && parent.pushed(false) == *prefix &&
+                self.prefixes.contains(&parent)
+            {
+                self.metrics.splits += 1;
+            }
+            if self.prefixes.contains(&prefix.pushed(false)) {
+                self.metrics.merges += 1;
+            }
+        }
+        self.metrics.step = step;
+        self.metrics.nodes = nodes;
+        self.metrics.queue_size = queue_size;
+        self.metrics.sections = prefixes.len();
+        self.prefixes = prefixes;
+        self.metrics.confirmed = confirmations
+            .iter()
+            .filter(|&&confirmation| confirmation == Confirmation::Confirmed)
+            .count();
+        let partial: Vec<u64> = confirmations
+            .iter()
+            .filter_map(|confirmation| confirmation.partial_steps(step))
+            .collect();
+        self.metrics.partially_confirmed = partial.len();
+        self.metrics.longest_partial_confirmation = partial.into_iter().max().unwrap_or(0);
+
+        if step.is_multiple_of(self.interval) {
+            self.write()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Rewrite the file with the current metrics.
+    pub fn write(&self) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(self.metrics.to_prometheus().as_bytes())?;
+        }
+        fs::rename(tmp_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_splits_and_merges() {
+        let path = env::temp_dir().join(format!("ewok-metrics-{}.prom", ::std::process::id()));
+        let mut metrics = MetricsFile::new(&path, 2);
+        let empty = Prefix::empty();
+        let (p0, p1) = (empty.pushed(false), empty.pushed(true));
+
+        metrics.update(0, 8, 0, btreeset!{ empty }, &[]).unwrap();
+        // Part-way through the split, some nodes still have the old block.
+        metrics.update(1, 20, 5, btreeset!{ empty, p0, p1 }, &[]).unwrap();
+        let confirmations = [
+            Confirmation::Confirmed,
+            Confirmation::PartiallyConfirmed { since: 1 },
+            Confirmation::PartiallyConfirmed { since: 2 },
+            Confirmation::Unconfirmed,
+        ];
+        metrics.count_proof_requests(30, 4);
+        metrics.update(2, 20, 0, btreeset!{ p0, p1 }, &confirmations).unwrap();
+        assert_eq!(metrics.metrics().splits, 1);
+        assert_eq!(metrics.metrics().merges, 0);
+        assert_eq!(metrics.metrics().confirmed, 1);
+        assert_eq!(metrics.metrics().partially_confirmed, 2);
+        let file = fs::read_to_string(&path).unwrap();
+        assert!(file.contains("\newok_sections 2\n"));
+        assert!(file.contains("\newok_longest_partial_confirmation_steps 1\n"));
+        assert!(file.contains("\newok_proof_requests_total 30\n"));
+
+        metrics.update(3, 12, 0, btreeset!{ empty }, &[]).unwrap();
+        assert_eq!(metrics.metrics().merges, 1);
+        // Not rewritten on odd steps.
+        assert!(fs::read_to_string(&path).unwrap().contains("\newok_step 2\n"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+//! Mock nodes: nodes whose handling of chosen kinds of message is scripted, for tests that probe
+//! how honest nodes react to specific behaviour from a peer.
+//!
+//! A mock is attached to a node of the simulation with `Simulation::add_mock`. The node joins,
+//! votes and is counted like any other, but the messages of each kind that it receives or sends
+//! can be dropped, held back for a number of steps, or, for requests for proof, refused with
+//! `NoProof`. Messages of other kinds are handled and sent as normal. For example, a member that
+//! never proves blocks and whose agreements arrive late:
+//!
+//! ```text
+//! MockNode::new()
+//!     .on_receive(MessageKind::RequestProof, MockBehaviour::RefuseProof)
+//!     .on_send(MessageKind::VoteAgreed, MockBehaviour::Delay(20))
+//! ```
+//!
+//! A mock scripts a real `Node` rather than replacing it: the simulation's nodes are all `Node`s,
+//! and `adapter::MembershipNode` exchanges opaque bytes, which can't be scripted by kind. How
+//! many messages of each kind a mock has acted on is kept, so that tests can check that the
+//! script came into play.
+
+use message::{Message, MessageContent, MessageKind};
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// What a mock node does with a message of some kind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MockBehaviour {
+    /// Drop the message.
+    Drop,
+    /// Hold the message back for the given number of steps.
+    Delay(u64),
+    /// Answer a request for proof with `NoProof` instead of handling it. Other messages, and
+    /// messages being sent, are dropped.
+    RefuseProof,
+}
+
+/// Scripted behaviours for the messages a node receives and sends, by kind.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MockNode {
+    on_receive: BTreeMap<MessageKind, MockBehaviour>,
+    on_send: BTreeMap<MessageKind, MockBehaviour>,
+    /// Received messages that are being held back, with the step they're handled at.
+    held_received: Vec<(u64, Message)>,
+    /// Sent messages that are being held back, with the step they're sent at.
+    held_sent: Vec<(u64, Message)>,
+    /// Number of messages of each kind that were received and dropped, held back or refused.
+    scripted_received: BTreeMap<MessageKind, u64>,
+    /// Number of messages of each kind that were being sent and were dropped or held back.
+    scripted_sent: BTreeMap<MessageKind, u64>,
+}
+
+impl MockNode {
+    pub fn new() -> Self {
+        MockNode::default()
+    }
+
+    /// Apply `behaviour` to every message of the given kind that the node receives.
+    pub fn on_receive(mut self, kind: MessageKind, behaviour: MockBehaviour) -> Self {
+        self.on_receive.insert(kind, behaviour);
+        self
+    }
+
+    /// Apply `behaviour` to every message of the given kind that the node sends.
+    pub fn on_send(mut self, kind: MessageKind, behaviour: MockBehaviour) -> Self {
+        self.on_send.insert(kind, behaviour);
+        self
+    }
+
+    /// Filter the messages delivered to the node at `step`, returning the messages for it to
+    /// handle, including held messages that are due, and the replies to send on its behalf.
+    pub fn receive(&mut self, step: u64, delivered: Vec<Message>) -> (Vec<Message>, Vec<Message>) {
+        let mut handled = release(&mut self.held_received, step);
+        let mut replies = vec![];
+        for message in delivered {
+            let kind = message.content.kind();
+            if self.on_receive.contains_key(&kind) {
+                *self.scripted_received.entry(kind).or_insert(0) += 1;
+            }
+            match self.on_receive.get(&kind) {
+                None => handled.push(message),
+                Some(&MockBehaviour::Drop) => (),
+                Some(&MockBehaviour::Delay(steps)) => {
+                    self.held_received.push((step + steps, message))
+                }
+                Some(&MockBehaviour::RefuseProof) => {
+                    if let MessageContent::RequestProof(block, _) = *message.content {
+                        replies.push(Message {
+                            sender: message.recipient,
+                            recipient: message.sender,
+                            content: Arc::new(MessageContent::NoProof(block)),
+                            trace_id: message.trace_id,
+                        });
+                    }
+                }
+            }
+        }
+        (handled, replies)
+    }
+
+    /// Filter a message that the node is sending at `step`, returning it if it should be sent
+    /// straight away.
+    pub fn send(&mut self, step: u64, message: Message) -> Option<Message> {
+        let kind = message.content.kind();
+        if self.on_send.contains_key(&kind) {
+            *self.scripted_sent.entry(kind).or_insert(0) += 1;
+        }
+        match self.on_send.get(&kind) {
+            None => Some(message),
+            Some(&MockBehaviour::Delay(steps)) => {
+                self.held_sent.push((step + steps, message));
+                None
+            }
+            Some(&MockBehaviour::Drop) | Some(&MockBehaviour::RefuseProof) => None,
+        }
+    }
+
+    /// Take the sent messages that were held back until `step` or before.
+    pub fn release_sent(&mut self, step: u64) -> Vec<Message> {
+        release(&mut self.held_sent, step)
+    }
+
+    /// Earliest step after `step` at which a held message is released, if any are held.
+    pub fn next_release(&self, step: u64) -> Option<u64> {
+        self.held_received
+            .iter()
+            .chain(&self.held_sent)
+            .map(|&(release_step, _)| cmp::max(release_step, step + 1))
+            .min()
+    }
+
+    /// Number of received messages of the given kind that the script applied to.
+    pub fn scripted_received(&self, kind: MessageKind) -> u64 {
+        self.scripted_received.get(&kind).cloned().unwrap_or(0)
+    }
+
+    /// Number of messages of the given kind being sent that the script applied to.
+    pub fn scripted_sent(&self, kind: MessageKind) -> u64 {
+        self.scripted_sent.get(&kind).cloned().unwrap_or(0)
+    }
+
+    /// Whether any messages are being held back.
+    pub fn is_holding(&self) -> bool {
+        !self.held_received.is_empty() || !self.held_sent.is_empty()
+    }
+}
+
+/// Take the messages held until `step` or before, in the order they were held.
+fn release(held: &mut Vec<(u64, Message)>, step: u64) -> Vec<Message> {
+    let (due, later) = held.drain(..).partition(|&(release_step, _)| release_step <= step);
+    *held = later;
+    due.into_iter().map(|(_, message)| message).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use block::Block;
+    use blocks::Blocks;
+    use message::MessageContent::*;
+    use name::{Name, Prefix};
+
+    fn message(content: MessageContent) -> Message {
+        Message {
+            sender: Name(1),
+            recipient: Name(2),
+            content: Arc::new(content),
+            trace_id: 7,
+        }
+    }
+
+    #[test]
+    fn scripted_by_kind() {
+        let mut mock = MockNode::new()
+            .on_receive(MessageKind::RequestProof, MockBehaviour::RefuseProof)
+            .on_receive(MessageKind::Connect, MockBehaviour::Delay(3))
+            .on_receive(MessageKind::Disconnect, MockBehaviour::Drop)
+            .on_send(MessageKind::NodeJoined, MockBehaviour::Delay(2));
+        let block = Blocks::new().insert(Block {
+            prefix: Prefix::empty(),
+            version: 0,
+            members: Arc::new(btreeset!{ Name(1), Name(2) }),
+        });
+        let delivered = vec![
+            message(RequestProof(block, Default::default())),
+            message(Connect),
+            message(Disconnect),
+            message(NoProof(block)),
+        ];
+
+        let (handled, replies) = mock.receive(10, delivered);
+        assert_eq!(handled, vec![message(NoProof(block))]);
+        assert_eq!(replies.len(), 1);
+        assert_eq!((replies[0].sender, replies[0].recipient), (Name(2), Name(1)));
+        assert_eq!(*replies[0].content, NoProof(block));
+        assert_eq!(replies[0].trace_id, 7);
+
+        assert_eq!(mock.send(11, message(Connect)), Some(message(Connect)));
+        assert_eq!(mock.send(11, message(NodeJoined)), None);
+        assert_eq!(mock.next_release(11), Some(13));
+        assert!(mock.release_sent(12).is_empty());
+        assert_eq!(mock.release_sent(13), vec![message(NodeJoined)]);
+        assert_eq!(mock.scripted_received(MessageKind::RequestProof), 1);
+        assert_eq!(mock.scripted_received(MessageKind::NoProof), 0);
+        assert_eq!(mock.scripted_sent(MessageKind::NodeJoined), 1);
+        assert_eq!(mock.scripted_sent(MessageKind::Connect), 0);
+
+        assert!(mock.receive(12, vec![]).0.is_empty());
+        assert_eq!(mock.receive(13, vec![]).0, vec![message(Connect)]);
+        assert!(!mock.is_holding());
+        assert_eq!(mock.next_release(13), None);
+    }
+}
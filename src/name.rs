@@ -1,13 +1,77 @@
+use fxhash;
+use itertools::Itertools;
 use rand::{Rand, Rng};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error;
 use std::cmp::Ordering;
+use std::collections::{BTreeSet, VecDeque};
+use std::collections::btree_set;
+use std::env;
 use std::fmt::{self, Binary, Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
 use std::mem;
-use std::u64;
+use std::str::FromStr;
+use std::sync::atomic::{self, AtomicUsize};
+
+/// Integer type that names are made of: `u64`, or `u128` with the `wide-names` feature.
+#[cfg(not(feature = "wide-names"))]
+pub type NameBits = u64;
+#[cfg(feature = "wide-names")]
+pub type NameBits = u128;
+
+/// Number of bits in a name.
+pub const NAME_BITS: usize = mem::size_of::<NameBits>() * 8;
+
+/// Number of hex digits that names are displayed with by default.
+const DEFAULT_DISPLAY_DIGITS: usize = 6;
+
+/// Value of `DISPLAY_DIGITS` until it's read from `EWOK_NAME_DIGITS` or set.
+const UNSET_DISPLAY_DIGITS: usize = usize::MAX;
+
+/// Number of hex digits that names are displayed with.
+///
+/// This is shared by every thread, so names logged by the worker threads that handle a step's
+/// messages are displayed the same as the rest.
+static DISPLAY_DIGITS: AtomicUsize = AtomicUsize::new(UNSET_DISPLAY_DIGITS);
+
+fn display_digits() -> usize {
+    match DISPLAY_DIGITS.load(atomic::Ordering::Relaxed) {
+        UNSET_DISPLAY_DIGITS => {
+            let digits = match env::var("EWOK_NAME_DIGITS") {
+                Ok(ref value) if value == "full" => NAME_BITS / 4,
+                Ok(value) => {
+                    value.parse().unwrap_or_else(|_| {
+                        panic!("EWOK_NAME_DIGITS {} isn't a number or \"full\".", value)
+                    })
+                }
+                Err(_) => DEFAULT_DISPLAY_DIGITS,
+            };
+            // Keep the number of digits if another thread has set it in the meantime.
+            match DISPLAY_DIGITS.compare_exchange(
+                UNSET_DISPLAY_DIGITS,
+                digits,
+                atomic::Ordering::Relaxed,
+                atomic::Ordering::Relaxed,
+            ) {
+                Ok(_) => digits,
+                Err(current) => current,
+            }
+        }
+        digits => digits,
+    }
+}
+
+/// Set the number of hex digits that names are displayed with on every thread, overriding
+/// `EWOK_NAME_DIGITS`. Names are displayed in full if it's at least `NAME_BITS / 4`.
+pub fn set_display_digits(digits: usize) {
+    DISPLAY_DIGITS.store(digits, atomic::Ordering::Relaxed);
+}
 
-/// Node names are u64s.
+/// Node names are `NameBits` integers, u64s by default.
 #[derive(PartialOrd, Ord, PartialEq, Eq, Copy, Clone, Hash, Default)]
-pub struct Name(pub u64);
+#[cfg_attr(not(feature = "wide-names"), derive(Serialize, Deserialize))]
+pub struct Name(pub NameBits);
 
 #[allow(dead_code)]
 impl Name {
@@ -26,7 +90,7 @@ impl Name {
 
     /// Returns `true` if the `i`-th bit is `1`.
     pub fn bit(&self, i: usize) -> bool {
-        let pow_i = 1 << (mem::size_of::<Self>() * 8 - 1 - i); // 1 on bit i.
+        let pow_i = 1 << (NAME_BITS - 1 - i); // 1 on bit i.
         self.0 & pow_i != 0
     }
 
@@ -34,10 +98,10 @@ impl Name {
     ///
     /// If `index` exceeds the number of bits in `self`, an unmodified copy of `self` is returned.
     pub fn with_bit(mut self, i: usize, bit: bool) -> Self {
-        if i >= mem::size_of::<Self>() * 8 {
+        if i >= NAME_BITS {
             return self;
         }
-        let pow_i = 1 << (mem::size_of::<Self>() * 8 - 1 - i); // 1 on bit i.
+        let pow_i = 1 << (NAME_BITS - 1 - i); // 1 on bit i.
         if bit {
             self.0 |= pow_i;
         } else {
@@ -46,14 +110,26 @@ impl Name {
         self
     }
 
+    /// The name that a node with this name is given when it's relocated to another section.
+    ///
+    /// It's a hash of the name, so that every member of the node's section agrees on it, and it
+    /// decides the section the node is relocated to.
+    pub fn relocated(&self) -> Self {
+        let mut bits: NameBits = 0;
+        for i in 0..NAME_BITS as u64 / 64 {
+            bits = (bits << 32 << 32) | NameBits::from(fxhash::hash64(&(self.0, i)));
+        }
+        Name(bits)
+    }
+
     /// Returns a copy of `self`, with the `index`-th bit flipped.
     ///
     /// If `index` exceeds the number of bits in `self`, an unmodified copy of `self` is returned.
     pub fn with_flipped_bit(mut self, i: usize) -> Self {
-        if i >= mem::size_of::<Self>() * 8 {
+        if i >= NAME_BITS {
             return self;
         }
-        let pow_i = 1 << (mem::size_of::<Self>() * 8 - 1 - i); // 1 on bit i.
+        let pow_i = 1 << (NAME_BITS - 1 - i); // 1 on bit i.
         self.0 ^= pow_i;
         self
     }
@@ -61,8 +137,7 @@ impl Name {
     /// Returns a copy of self with first `n` bits preserved, and remaining bits
     /// set to 0 (val == false) or 1 (val == true).
     pub fn set_remaining(mut self, n: usize, val: bool) -> Self {
-        let bits = mem::size_of::<u64>() * 8;
-        if n < bits {
+        if n < NAME_BITS {
             let mask = !0 >> n;
             if val { self.0 |= mask } else { self.0 &= !mask }
         }
@@ -70,35 +145,78 @@ impl Name {
     }
 }
 
-/// Prints full 64 character binary representation of `Name`, including leading zeros.
+/// Prints full binary representation of `Name`, including leading zeros.
 impl Binary for Name {
     fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
-        write!(formatter, "{1:00$b}", mem::size_of::<Self>() * 8, self.0)
+        write!(formatter, "{1:00$b}", NAME_BITS, self.0)
     }
 }
 
-/// Prints abbreviated hex representation of `Name`.   This is the first six characters of the full
-/// hex representation including leading zeros.
+/// Prints the same hex representation of `Name` as `Display`.
 impl Debug for Name {
     fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
         (self as &Display).fmt(formatter)
     }
 }
 
-/// Prints abbreviated hex representation of `Name`.   This is the first six characters of the full
-/// hex representation including leading zeros.
+/// Prints the hex representation of `Name` including leading zeros, abbreviated to its first six
+/// characters followed by `..` unless `EWOK_NAME_DIGITS` says otherwise. Names displayed in full
+/// can be parsed back with `FromStr`.
 impl Display for Name {
     fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
-        let mut hex = format!("{1:00$x}", mem::size_of::<Self>() * 2, self.0);
-        hex.truncate(6);
-        write!(formatter, "{}..", hex)
+        let mut hex = format!("{1:00$x}", NAME_BITS / 4, self.0);
+        let digits = display_digits();
+        if digits >= hex.len() {
+            write!(formatter, "{}", hex)
+        } else {
+            hex.truncate(digits);
+            write!(formatter, "{}..", hex)
+        }
+    }
+}
+
+/// Parses a name from its full hex representation, with or without a leading `0x`.
+impl FromStr for Name {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let digits = s.strip_prefix("0x").unwrap_or(s);
+        NameBits::from_str_radix(digits, 16).map(Name).map_err(|_| ())
+    }
+}
+
+/// Wide names are serialised as strings of decimal digits, because serde can't buffer `u128`s as
+/// it has to for the internally tagged trace records.
+#[cfg(feature = "wide-names")]
+impl Serialize for Name {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_string())
+    }
+}
+
+#[cfg(feature = "wide-names")]
+impl<'de> Deserialize<'de> for Name {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let digits = String::deserialize(deserializer)?;
+        digits.parse().map(Name).map_err(|_| {
+            D::Error::custom(format!("invalid name: {:?}", digits))
+        })
     }
 }
 
 impl Rand for Name {
+    #[cfg(not(feature = "wide-names"))]
     fn rand<R: Rng>(rng: &mut R) -> Name {
         Name(rng.gen())
     }
+
+    // `rand` can't generate `u128`s directly.
+    #[cfg(feature = "wide-names")]
+    fn rand<R: Rng>(rng: &mut R) -> Name {
+        let high: u64 = rng.gen();
+        let low: u64 = rng.gen();
+        Name((NameBits::from(high) << 64) | NameBits::from(low))
+    }
 }
 
 // A group prefix, i.e. a sequence of bits specifying the part of the network's name space
@@ -126,7 +244,7 @@ impl Prefix {
 
     /// Create a `Prefix` using the given byte as the highest order byte of the prefix.
     pub fn short(bit_count: usize, name: u8) -> Prefix {
-        let long_name = (name as u64) << (64 - 8);
+        let long_name = NameBits::from(name) << (NAME_BITS - 8);
         Prefix::new(bit_count, Name(long_name))
     }
 
@@ -153,6 +271,48 @@ impl Prefix {
         self
     }
 
+    /// Returns the two prefixes that this one splits into, ending in `0` and `1` respectively.
+    pub fn split(&self) -> [Prefix; 2] {
+        [self.pushed(false), self.pushed(true)]
+    }
+
+    /// Iterates over the proper ancestors of this prefix, from its parent up to the empty prefix.
+    pub fn ancestors(&self) -> Ancestors {
+        Ancestors { prefix: *self }
+    }
+
+    /// Iterates over the proper descendants of this prefix with at most `max_len` bits, ordered
+    /// by length and then by name.
+    ///
+    /// E.g. `Prefix::empty().descendants(2)` yields 0, 1, 00, 01, 10 and 11.
+    pub fn descendants(&self, max_len: usize) -> Descendants {
+        let mut queue = VecDeque::new();
+        if self.bit_count < max_len {
+            queue.extend(&self.split());
+        }
+        Descendants { queue, max_len }
+    }
+
+    /// Returns all the prefixes of the same length that differ from this one in exactly one bit.
+    ///
+    /// E.g. the neighbours of 000 are 100, 010 and 001.
+    pub fn all_neighbours(&self) -> Vec<Prefix> {
+        (0..self.bit_count)
+            .map(|i| Prefix::new(self.bit_count, self.name.with_flipped_bit(i)))
+            .collect()
+    }
+
+    /// Iterates over names matching the prefix that are spread evenly across it, each one
+    /// halving one of the largest gaps left by the names before it.
+    ///
+    /// E.g. for the empty prefix, the names start at 0, then 1/2, 1/4 and 3/4 of the way along.
+    pub fn spread_names(&self) -> SpreadNames {
+        SpreadNames {
+            prefix: *self,
+            index: 0,
+        }
+    }
+
     /// Returns the smallest name matching the prefix
     pub fn lower_bound(&self) -> Name {
         self.name.set_remaining(self.bit_count(), false)
@@ -265,21 +425,166 @@ impl Prefix {
     }
 }
 
+/// A set of prefixes, with operations for checking how they divide up the namespace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrefixSet {
+    prefixes: BTreeSet<Prefix>,
+}
+
+impl PrefixSet {
+    pub fn new() -> Self {
+        PrefixSet::default()
+    }
+
+    /// Adds a prefix to the set, returning `false` if it was already present.
+    pub fn insert(&mut self, prefix: Prefix) -> bool {
+        self.prefixes.insert(prefix)
+    }
+
+    /// Removes a prefix from the set, returning `false` if it wasn't present.
+    pub fn remove(&mut self, prefix: &Prefix) -> bool {
+        self.prefixes.remove(prefix)
+    }
+
+    pub fn contains(&self, prefix: &Prefix) -> bool {
+        self.prefixes.contains(prefix)
+    }
+
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    pub fn iter(&self) -> btree_set::Iter<'_, Prefix> {
+        self.prefixes.iter()
+    }
+
+    /// Replaces `prefix` with the two prefixes it splits into, returning `false` and leaving the
+    /// set unchanged if `prefix` wasn't present.
+    pub fn insert_split(&mut self, prefix: Prefix) -> bool {
+        if !self.prefixes.remove(&prefix) {
+            return false;
+        }
+        self.prefixes.extend(&prefix.split());
+        true
+    }
+
+    /// Returns `true` if every name is matched by at least one of the prefixes.
+    pub fn is_complete_cover(&self) -> bool {
+        Prefix::empty().is_covered_by(&self.prefixes)
+    }
+
+    /// Returns `true` if no name is matched by more than one of the prefixes.
+    pub fn is_disjoint(&self) -> bool {
+        self.overlapping_pairs().is_empty()
+    }
+
+    /// Returns every pair of prefixes that both match some name.
+    pub fn overlapping_pairs(&self) -> Vec<(Prefix, Prefix)> {
+        self.prefixes
+            .iter()
+            .tuple_combinations()
+            .filter(|&(p1, p2)| p1.is_compatible(p2))
+            .map(|(p1, p2)| (*p1, *p2))
+            .collect()
+    }
+
+    /// Returns the longest prefix in the set that matches `name`, if any.
+    pub fn covering_prefix(&self, name: Name) -> Option<Prefix> {
+        self.prefixes
+            .iter()
+            .filter(|prefix| prefix.matches(name))
+            .max_by_key(|prefix| prefix.bit_count())
+            .cloned()
+    }
+}
+
+impl FromIterator<Prefix> for PrefixSet {
+    fn from_iter<I: IntoIterator<Item = Prefix>>(iter: I) -> Self {
+        PrefixSet { prefixes: iter.into_iter().collect() }
+    }
+}
+
+impl<'a> IntoIterator for &'a PrefixSet {
+    type Item = &'a Prefix;
+    type IntoIter = btree_set::Iter<'a, Prefix>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.prefixes.iter()
+    }
+}
+
+/// Iterator returned by `Prefix::spread_names`.
+pub struct SpreadNames {
+    prefix: Prefix,
+    index: NameBits,
+}
+
+impl Iterator for SpreadNames {
+    type Item = Name;
+
+    fn next(&mut self) -> Option<Name> {
+        let bit_count = self.prefix.bit_count;
+        if bit_count == NAME_BITS && self.index > 0 ||
+            bit_count > 0 && bit_count < NAME_BITS && self.index >> (NAME_BITS - bit_count) > 0
+        {
+            return None;
+        }
+        // Reversing the bits of a counter visits the middle of each gap in turn.
+        let suffix = self.index.reverse_bits().checked_shr(bit_count as u32).unwrap_or(0);
+        self.index += 1;
+        Some(self.prefix.substituted_in(Name(suffix)))
+    }
+}
+
+/// Iterator returned by `Prefix::ancestors`.
+pub struct Ancestors {
+    prefix: Prefix,
+}
+
+impl Iterator for Ancestors {
+    type Item = Prefix;
+
+    fn next(&mut self) -> Option<Prefix> {
+        if self.prefix.bit_count == 0 {
+            return None;
+        }
+        self.prefix = self.prefix.popped();
+        Some(self.prefix)
+    }
+}
+
+/// Iterator returned by `Prefix::descendants`.
+pub struct Descendants {
+    queue: VecDeque<Prefix>,
+    max_len: usize,
+}
+
+impl Iterator for Descendants {
+    type Item = Prefix;
+
+    fn next(&mut self) -> Option<Prefix> {
+        let prefix = self.queue.pop_front()?;
+        if prefix.bit_count < self.max_len {
+            self.queue.extend(&prefix.split());
+        }
+        Some(prefix)
+    }
+}
+
 impl PartialEq<Prefix> for Prefix {
     fn eq(&self, other: &Self) -> bool {
         self.is_compatible(other) && self.bit_count == other.bit_count
     }
 }
 
+/// Agrees with the derived `Ord`, so that sorting and `BTreeSet`s see the same order as `<`.
 impl PartialOrd<Prefix> for Prefix {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self == other {
-            Some(Ordering::Equal)
-        } else if self.is_compatible(other) {
-            None
-        } else {
-            Some(self.name.cmp(&other.name))
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -292,10 +597,33 @@ impl Hash for Prefix {
 }
 
 impl Binary for Prefix {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        write!(formatter, "Prefix({})", self)
+    }
+}
+
+/// Prints just the significant bits of the prefix, e.g. `0110`.
+impl Display for Prefix {
     fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
         let mut binary = format!("{:b}", self.name);
         binary.truncate(self.bit_count);
-        write!(formatter, "Prefix({})", binary)
+        write!(formatter, "{}", binary)
+    }
+}
+
+/// Parses a prefix from its string of bits, e.g. `0110`.
+impl FromStr for Prefix {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        if s.len() > NAME_BITS {
+            return Err(());
+        }
+        s.chars().try_fold(Prefix::empty(), |prefix, c| match c {
+            '0' => Ok(prefix.pushed(false)),
+            '1' => Ok(prefix.pushed(true)),
+            _ => Err(()),
+        })
     }
 }
 
@@ -305,6 +633,22 @@ impl Debug for Prefix {
     }
 }
 
+/// Prefixes are serialised as their string of bits, e.g. "0110".
+impl Serialize for Prefix {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Prefix {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bits = String::deserialize(deserializer)?;
+        bits.parse().map_err(|_| {
+            D::Error::custom(format!("invalid prefix: {:?}", bits))
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -321,4 +665,114 @@ mod test {
         assert!(p1.is_sibling_of_ancestor_of(&p000));
         assert!(!p10.is_sibling_of_ancestor_of(&p000));
     }
+
+    #[test]
+    fn parse() {
+        assert_eq!("".parse(), Ok(Prefix::empty()));
+        assert_eq!("0110".parse(), Ok(Prefix::short(4, 0b01100000)));
+        assert_eq!("0120".parse::<Prefix>(), Err(()));
+
+        let name = Name(0x0123_4567_89ab_cdef);
+        assert_eq!(format!("{:x}", name.0).parse(), Ok(name));
+        assert_eq!("0x123456789abcdef".parse(), Ok(name));
+        assert_eq!("0xg".parse::<Name>(), Err(()));
+    }
+
+    #[test]
+    fn display_digits() {
+        let name = Prefix::short(8, 0x12).lower_bound();
+        assert_eq!(name.to_string(), "120000..");
+        set_display_digits(NAME_BITS / 4);
+        assert_eq!(name.to_string().parse(), Ok(name));
+        // Names formatted on other threads, like the simulation's workers, have as many digits.
+        let formatted = ::std::thread::spawn(move || name.to_string()).join().unwrap();
+        assert_eq!(formatted.parse(), Ok(name));
+        set_display_digits(DEFAULT_DISPLAY_DIGITS);
+    }
+
+    #[test]
+    fn prefix_set() {
+        let mut prefixes: PrefixSet = vec![Prefix::short(1, 0)].into_iter().collect();
+        assert!(!prefixes.is_complete_cover());
+        prefixes.insert(Prefix::short(1, 0b10000000));
+        assert!(prefixes.is_complete_cover() && prefixes.is_disjoint());
+
+        assert!(prefixes.insert_split(Prefix::short(1, 0b10000000)));
+        assert!(!prefixes.insert_split(Prefix::short(1, 0b10000000)));
+        assert_eq!(prefixes.len(), 3);
+        assert!(prefixes.is_complete_cover() && prefixes.is_disjoint());
+
+        let name = Prefix::short(8, 0b11010000).lower_bound();
+        assert_eq!(prefixes.covering_prefix(name), Some(Prefix::short(2, 0b11000000)));
+
+        prefixes.insert(Prefix::short(3, 0b11000000));
+        assert_eq!(
+            prefixes.overlapping_pairs(),
+            vec![(Prefix::short(2, 0b11000000), Prefix::short(3, 0b11000000))]
+        );
+        assert_eq!(prefixes.covering_prefix(name), Some(Prefix::short(3, 0b11000000)));
+
+        // Collecting compatible prefixes in any order gives the same set.
+        let p0 = Prefix::short(1, 0);
+        let p01 = Prefix::short(2, 0b01000000);
+        assert_eq!(p0.partial_cmp(&p01), Some(p0.cmp(&p01)));
+        let forward: PrefixSet = vec![p0, p01, p0].into_iter().collect();
+        let backward: PrefixSet = vec![p01, p0, p01].into_iter().collect();
+        assert_eq!(forward, backward);
+        assert_eq!(forward.len(), 2);
+    }
+
+    #[test]
+    fn relocated() {
+        let name = Name(1);
+        assert_eq!(name.relocated(), name.relocated());
+        assert_ne!(name.relocated(), name);
+        assert_ne!(name.relocated(), Name(2).relocated());
+    }
+
+    #[test]
+    fn spread_names() {
+        let top = 1 << (NAME_BITS - 1);
+        let names: Vec<_> = Prefix::empty().spread_names().take(4).collect();
+        assert_eq!(names, vec![Name(0), Name(top), Name(top >> 1), Name(top | top >> 1)]);
+
+        let p011 = Prefix::short(3, 0b01100000);
+        let names: Vec<_> = p011.spread_names().take(2).collect();
+        assert_eq!(names, vec![p011.lower_bound(), p011.lower_bound().with_bit(3, true)]);
+        assert!(names.iter().all(|name| p011.matches(*name)));
+
+        let full = Prefix::new(NAME_BITS - 1, Name(0));
+        assert_eq!(full.spread_names().count(), 2);
+    }
+
+    #[test]
+    fn iteration_helpers() {
+        let p0 = Prefix::short(1, 0);
+        let p011 = Prefix::short(3, 0b01100000);
+        assert_eq!(
+            p011.ancestors().collect::<Vec<_>>(),
+            vec![Prefix::short(2, 0b01000000), p0, Prefix::empty()]
+        );
+        assert_eq!(Prefix::empty().ancestors().count(), 0);
+
+        assert_eq!(p0.split(), [Prefix::short(2, 0), Prefix::short(2, 0b01000000)]);
+        let length_3: Vec<_> = Prefix::empty()
+            .descendants(3)
+            .filter(|p| p.bit_count() == 3)
+            .collect();
+        assert_eq!(length_3.len(), 8);
+        assert_eq!(length_3[3], p011);
+        assert_eq!(p0.descendants(3).count(), 6);
+        assert_eq!(p011.descendants(3).count(), 0);
+
+        assert_eq!(
+            p011.all_neighbours(),
+            vec![
+                Prefix::short(3, 0b11100000),
+                Prefix::short(3, 0b00100000),
+                Prefix::short(3, 0b01000000),
+            ]
+        );
+        assert!(p011.all_neighbours().iter().all(|p| p.is_neighbour(&p011)));
+    }
 }
@@ -1,7 +1,12 @@
+use fxhash::FxHashMap;
+use std::cmp;
 use std::collections::BTreeMap;
 use std::mem;
-use message::Message;
+use std::sync::Arc;
+use message::{Message, MessageContent, MessageKind, TraceId};
 use name::Name;
+use params::{DelayDistribution, SimulationParams};
+use wire;
 
 use random::do_with_probability;
 
@@ -9,25 +14,87 @@ use random::do_with_probability;
 pub struct Network {
     /// Maximum delay in steps before a message is guaranteed to have been delivered.
     max_delay: u64,
-    /// Probability that a message is delivered on a given step.
+    /// Shape of the distribution of delays before the deadline.
+    delay_distribution: DelayDistribution,
+    /// Probability that a message is delivered on a given step, for geometric delays.
     prob_deliver: f64,
+    /// Probability that a connection delivers its second burst ahead of its first on a step.
+    burst_reorder_prob: f64,
     /// Map from a connection between two nodes and step # to messages inserted at that step.
-    messages: BTreeMap<(Name, Name), BTreeMap<u64, Vec<Message>>>,
+    messages: FxHashMap<(Name, Name), BTreeMap<u64, Vec<Message>>>,
+    /// Number of messages sent by each node since the counts were last taken.
+    sent_counts: BTreeMap<Name, SentCount>,
+    /// Number of messages of each kind sent since the counts were last taken.
+    kind_counts: BTreeMap<MessageKind, SentCount>,
+    /// ID to give the next message sent without a trace ID.
+    next_trace_id: TraceId,
+    /// Probability that a delivered message is lost, and the last step it applies on.
+    loss: Option<(f64, u64)>,
+    /// Probability that a delivered message is delivered twice, and the last step it applies on.
+    duplicate: Option<(f64, u64)>,
+    /// Nodes whose incoming messages are held until their deadline, and the last step for each.
+    slow_nodes: BTreeMap<Name, u64>,
+    /// Number of times that a connection was left with messages older than `max_delay` after
+    /// delivering what it could.
+    overdue: u64,
+    /// Number of times that a connection delivered its second burst ahead of its first.
+    reordered_bursts: u64,
+    /// Number of messages sent between each pair of nodes since the counts were last taken, if
+    /// they're being counted.
+    pair_counts: Option<BTreeMap<(Name, Name), usize>>,
+}
+
+/// Age of the oldest undelivered message on a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueAge {
+    pub sender: Name,
+    pub recipient: Name,
+    /// Step the oldest message was sent at.
+    pub sent: u64,
+    /// Number of steps since it was sent.
+    pub age: u64,
+    /// Number of messages queued on the connection.
+    pub messages: usize,
+}
+
+/// Number of messages sent, and their total size in bytes when encoded with `wire`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SentCount {
+    pub messages: usize,
+    pub bytes: usize,
+}
+
+impl SentCount {
+    pub fn add(&mut self, other: SentCount) {
+        self.messages += other.messages;
+        self.bytes += other.bytes;
+    }
 }
 
 impl Network {
-    pub fn new(max_delay: u64) -> Self {
+    pub fn new(params: &SimulationParams) -> Self {
         Network {
-            max_delay,
-            prob_deliver: Self::delivery_probability(max_delay),
-            messages: BTreeMap::new(),
+            max_delay: params.max_delay,
+            delay_distribution: params.delay_distribution,
+            prob_deliver: Self::delivery_probability(params.max_delay, params.delivery_percentile),
+            burst_reorder_prob: params.burst_reorder_prob,
+            messages: FxHashMap::default(),
+            sent_counts: BTreeMap::new(),
+            kind_counts: BTreeMap::new(),
+            next_trace_id: 1,
+            loss: None,
+            duplicate: None,
+            slow_nodes: BTreeMap::new(),
+            overdue: 0,
+            reordered_bursts: 0,
+            pair_counts: None,
         }
     }
 
-    fn delivery_probability(max_delay: u64) -> f64 {
+    fn delivery_probability(max_delay: u64, delivery_percentile: f64) -> f64 {
         // Probability that a message won't be delivered by the randomised delivery
         // after `max_delay` tries.
-        let p_drop = 0.05_f64;
+        let p_drop = 1.0 - delivery_percentile;
 
         // Compute probability of success, p, to use for each trial by solving:
         // p_drop = (1 - p)^max_delay
@@ -41,23 +108,144 @@ impl Network {
     /// Get messages delivered at the given step (randomised).
     pub fn receive(&mut self, step: u64) -> Vec<Message> {
         let start_step = step.saturating_sub(self.max_delay);
-        let prob_deliver = self.prob_deliver;
         let max_delay = self.max_delay;
+        let geometric_prob = self.prob_deliver;
+        let prob_deliver: Box<dyn Fn(u64) -> f64> = match self.delay_distribution {
+            DelayDistribution::Geometric => Box::new(move |_| geometric_prob),
+            // Spread the remaining probability evenly over the steps left before the deadline.
+            DelayDistribution::Uniform => {
+                Box::new(move |age| 1.0 / (max_delay + 1).saturating_sub(age).max(1) as f64)
+            }
+        };
 
-        self.messages
-            .values_mut()
-            .flat_map(|messages| {
-                Self::receive_from_conn(messages, prob_deliver, max_delay, start_step, step)
+        self.slow_nodes.retain(|_, &mut until| until >= step);
+        let slow_nodes = &self.slow_nodes;
+        let burst_reorder_prob = self.burst_reorder_prob;
+
+        // Visit connections in order, so that random delivery is reproducible.
+        let mut connections: Vec<_> = self.messages.iter_mut().collect();
+        connections.sort_by_key(|&(conn, _)| *conn);
+        let mut overdue = 0;
+        let mut reordered_bursts = 0;
+        let delivered = connections
+            .into_iter()
+            .flat_map(|(&(sender, recipient), messages)| {
+                let mut delivered = vec![];
+                if slow_nodes.contains_key(&recipient) {
+                    delivered = Self::receive_overdue(messages, max_delay, start_step, step);
+                } else {
+                    if burst_reorder_prob > 0.0 && do_with_probability(burst_reorder_prob) {
+                        delivered =
+                            Self::receive_second_burst(messages, max_delay, start_step, step);
+                        if !delivered.is_empty() {
+                            reordered_bursts += 1;
+                        }
+                    }
+                    delivered.extend(Self::receive_from_conn(
+                        messages,
+                        &*prob_deliver,
+                        max_delay,
+                        start_step,
+                        step,
+                    ));
+                }
+                // Every message should be delivered by its deadline, but one held up behind an
+                // older message that wasn't would be too.
+                if let Some(sent) = Self::oldest_sent(messages) {
+                    if step >= max_delay && sent < start_step {
+                        warn!(
+                            "Network: {} messages from {} to {} held past max_delay, the oldest \
+                             sent at step {}",
+                            messages.values().map(Vec::len).sum::<usize>(),
+                            sender,
+                            recipient,
+                            sent
+                        );
+                        overdue += 1;
+                    }
+                }
+                delivered
             })
-            .collect()
+            .collect();
+        self.overdue += overdue;
+        self.reordered_bursts += reordered_bursts;
+        self.lose_and_duplicate(delivered, step)
+    }
+
+    /// Step that the oldest message still queued on a connection was sent at, forgetting the
+    /// steps before it whose messages have all been delivered.
+    fn oldest_sent(conn_messages: &mut BTreeMap<u64, Vec<Message>>) -> Option<u64> {
+        while let Some(entry) = conn_messages.first_entry() {
+            if !entry.get().is_empty() {
+                return Some(*entry.key());
+            }
+            entry.remove();
+        }
+        None
+    }
+
+    /// Lose or duplicate delivered messages, while either is configured.
+    fn lose_and_duplicate(&mut self, delivered: Vec<Message>, step: u64) -> Vec<Message> {
+        let loss = match self.loss {
+            Some((prob, until)) if until >= step => prob,
+            _ => 0.0,
+        };
+        let duplicate = match self.duplicate {
+            Some((prob, until)) if until >= step => prob,
+            _ => 0.0,
+        };
+        if loss == 0.0 && duplicate == 0.0 {
+            return delivered;
+        }
+        let mut messages = Vec::with_capacity(delivered.len());
+        for message in delivered {
+            if do_with_probability(loss) {
+                debug!(
+                    "Network: lost {} from {} to {}",
+                    message.content.kind(),
+                    message.sender,
+                    message.recipient
+                );
+                continue;
+            }
+            if do_with_probability(duplicate) {
+                debug!(
+                    "Network: duplicated {} from {} to {}",
+                    message.content.kind(),
+                    message.sender,
+                    message.recipient
+                );
+                messages.push(message.clone());
+            }
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// Lose each delivered message with probability `prob`, up to and including step `until`.
+    pub fn lose_messages(&mut self, prob: f64, until: u64) {
+        self.loss = Some((prob, until));
+    }
+
+    /// Deliver each delivered message twice with probability `prob`, up to and including step
+    /// `until`.
+    pub fn duplicate_messages(&mut self, prob: f64, until: u64) {
+        self.duplicate = Some((prob, until));
+    }
+
+    /// Hold every message to `node` until its deadline, up to and including step `until`.
+    pub fn slow_down(&mut self, node: Name, until: u64) {
+        self.slow_nodes.insert(node, until);
     }
 
     /// Get messages delivered on a single connection at a given step.
     ///
     /// `conn_messages`: the messages for a single connection as contained in `self.messages`.
+    /// `prob_deliver`: the probability of delivering a message that was sent the given number
+    /// of steps ago.
     fn receive_from_conn(
         conn_messages: &mut BTreeMap<u64, Vec<Message>>,
-        prob_deliver: f64,
+        prob_deliver: &dyn Fn(u64) -> f64,
         max_delay: u64,
         start_step: u64,
         end_step: u64,
@@ -79,8 +267,9 @@ impl Network {
             }
 
             let num_messages = messages.len();
+            let prob = prob_deliver(end_step - step_sent);
             let num_delivered = (1..messages.len() + 1)
-                .take_while(|_| do_with_probability(prob_deliver))
+                .take_while(|_| do_with_probability(prob))
                 .last()
                 .unwrap_or(0);
 
@@ -99,12 +288,87 @@ impl Network {
         all_deliver
     }
 
+    /// Take the second burst of messages queued on a single connection, to be delivered ahead of
+    /// the first, which stays queued until its usual delivery.
+    ///
+    /// Nothing is taken if there are fewer than two bursts, or if the first has reached its
+    /// deadline, so no message is delivered later than it would be otherwise.
+    fn receive_second_burst(
+        conn_messages: &mut BTreeMap<u64, Vec<Message>>,
+        max_delay: u64,
+        start_step: u64,
+        end_step: u64,
+    ) -> Vec<Message> {
+        let (first_sent, second_sent) = {
+            let mut bursts = conn_messages
+                .range(start_step..end_step)
+                .filter(|(_, messages)| !messages.is_empty())
+                .map(|(&sent, _)| sent);
+            match (bursts.next(), bursts.next()) {
+                (Some(first_sent), Some(second_sent)) => (first_sent, second_sent),
+                _ => return vec![],
+            }
+        };
+        if first_sent == start_step && end_step >= max_delay {
+            return vec![];
+        }
+        let second = conn_messages.remove(&second_sent).unwrap_or_default();
+        debug!("Network: delivering a burst of {} messages out of order", second.len());
+        second
+    }
+
+    /// Get only the messages on a single connection that have reached their deadline, which
+    /// must be delivered at the given step.
+    fn receive_overdue(
+        conn_messages: &mut BTreeMap<u64, Vec<Message>>,
+        max_delay: u64,
+        start_step: u64,
+        end_step: u64,
+    ) -> Vec<Message> {
+        if end_step < max_delay {
+            return vec![];
+        }
+        conn_messages
+            .get_mut(&start_step)
+            .map(mem::take)
+            .unwrap_or_default()
+    }
+
     /// Send messages at the given step.
+    ///
+    /// Messages without a trace ID start a new trace, which is shared by every recipient of a
+    /// broadcast.
     pub fn send(&mut self, step: u64, messages: Vec<Message>) {
-        let mut msg_counts = BTreeMap::new();
-        for message in messages {
-            let count = msg_counts.entry(message.sender).or_insert(0);
-            *count += 1;
+        let mut msg_counts: BTreeMap<Name, SentCount> = BTreeMap::new();
+        // Broadcasts share their content between consecutive messages, so only measure it once.
+        let mut last_content: Option<(Arc<MessageContent>, usize, TraceId)> = None;
+        for mut message in messages {
+            let (content_len, trace_id) = match last_content {
+                Some((ref content, len, trace_id)) if Arc::ptr_eq(content, &message.content) => {
+                    (len, trace_id)
+                }
+                _ => (wire::content_len(&message.content), self.next_trace_id),
+            };
+            if message.trace_id == 0 {
+                message.trace_id = trace_id;
+            }
+            // Never reuse the ID of a trace that's already started.
+            self.next_trace_id = cmp::max(self.next_trace_id, message.trace_id + 1);
+            last_content = Some((Arc::clone(&message.content), content_len, message.trace_id));
+            let count = SentCount {
+                messages: 1,
+                bytes: wire::encoded_len_with_content(content_len),
+            };
+            msg_counts.entry(message.sender).or_default().add(count);
+            self.kind_counts
+                .entry(message.content.kind())
+                .or_default()
+                .add(count);
+            if let Some(ref mut pair_counts) = self.pair_counts {
+                *pair_counts
+                    .entry((message.sender, message.recipient))
+                    .or_insert(0) += 1;
+            }
             let conn_messages = self.messages
                 .entry((message.sender, message.recipient))
                 .or_insert_with(BTreeMap::new);
@@ -112,10 +376,81 @@ impl Network {
             step_messages.push(message);
         }
         for (name, count) in msg_counts {
-            trace!("Network: sent {} messages from {}", count, name);
+            trace!("Network: sent {} messages from {}", count.messages, name);
+            self.sent_counts.entry(name).or_default().add(count);
         }
     }
 
+    /// Take the number of messages sent by each node since this was last called.
+    pub fn take_sent_counts(&mut self) -> BTreeMap<Name, SentCount> {
+        mem::take(&mut self.sent_counts)
+    }
+
+    /// Take the number of messages of each kind sent since this was last called.
+    pub fn take_kind_counts(&mut self) -> BTreeMap<MessageKind, SentCount> {
+        mem::take(&mut self.kind_counts)
+    }
+
+    /// Start counting the messages sent between each pair of nodes.
+    pub fn count_pairs(&mut self) {
+        self.pair_counts.get_or_insert_with(BTreeMap::new);
+    }
+
+    /// Take the number of messages sent between each pair of nodes since this was last called,
+    /// or nothing if they aren't being counted.
+    pub fn take_pair_counts(&mut self) -> BTreeMap<(Name, Name), usize> {
+        self.pair_counts
+            .as_mut()
+            .map_or_else(BTreeMap::new, mem::take)
+    }
+
+    /// The messages still in the queue, with the steps they were sent at, in delivery order for
+    /// each connection.
+    pub fn queued_messages(&self) -> Vec<(u64, &Message)> {
+        let mut connections: Vec<_> = self.messages.iter().collect();
+        connections.sort_by_key(|&(conn, _)| *conn);
+        connections
+            .into_iter()
+            .flat_map(|(_, conn_messages)| {
+                conn_messages.iter().flat_map(|(&step_sent, messages)| {
+                    messages.iter().map(move |message| (step_sent, message))
+                })
+            })
+            .collect()
+    }
+
+    /// The connections whose oldest undelivered messages are oldest at `step`, up to `count` of
+    /// them, oldest first.
+    pub fn oldest_queued(&self, step: u64, count: usize) -> Vec<QueueAge> {
+        let mut ages: Vec<QueueAge> = self.messages
+            .iter()
+            .filter_map(|(&(sender, recipient), conn_messages)| {
+                let (&sent, _) = conn_messages.iter().find(|(_, messages)| !messages.is_empty())?;
+                Some(QueueAge {
+                    sender,
+                    recipient,
+                    sent,
+                    age: step.saturating_sub(sent),
+                    messages: conn_messages.values().map(Vec::len).sum(),
+                })
+            })
+            .collect();
+        ages.sort_by_key(|age| (age.sent, age.sender, age.recipient));
+        ages.truncate(count);
+        ages
+    }
+
+    /// Number of times that a connection was left with messages older than `max_delay`, which
+    /// should have been delivered.
+    pub fn overdue(&self) -> u64 {
+        self.overdue
+    }
+
+    /// Number of times that a connection delivered its second burst ahead of its first.
+    pub fn reordered_bursts(&self) -> u64 {
+        self.reordered_bursts
+    }
+
     /// Whether the message/event queue is empty.
     pub fn queue_is_empty(&self) -> bool {
         self.messages.values().flat_map(BTreeMap::values).all(
@@ -137,13 +472,15 @@ impl Network {
 mod test {
     use super::*;
     use message::MessageContent;
+    use std::sync::Arc;
     use message::MessageContent::*;
 
     fn test_message(content: MessageContent) -> Message {
         Message {
             sender: Name(0),
             recipient: Name(1),
-            content,
+            content: Arc::new(content),
+            trace_id: 0,
         }
     }
 
@@ -166,7 +503,7 @@ mod test {
 
             let delivered = Network::receive_from_conn(
                 &mut conn_messages,
-                prob_deliver,
+                &|_| prob_deliver,
                 max_delay,
                 start_step,
                 end_step,
@@ -197,7 +534,7 @@ mod test {
 
             let delivered = Network::receive_from_conn(
                 &mut conn_messages,
-                prob_deliver,
+                &|_| prob_deliver,
                 max_delay,
                 start_step,
                 end_step,
@@ -209,4 +546,116 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn second_burst_delivered_first() {
+        let connect = test_message(Connect);
+        let disconnect = test_message(Disconnect);
+        let max_delay = 20;
+        let start_step = 45;
+        let end_step = start_step + max_delay;
+
+        let mut conn_messages = btreemap! {
+            50 => vec![connect.clone()],
+            51 => vec![disconnect.clone(), connect.clone()],
+        };
+        let delivered =
+            Network::receive_second_burst(&mut conn_messages, max_delay, start_step, end_step);
+        assert_eq!(delivered, vec![disconnect.clone(), connect.clone()]);
+        assert_eq!(conn_messages, btreemap!{ 50 => vec![connect.clone()] });
+
+        // A single burst, or a first burst at its deadline, is left alone.
+        let mut conn_messages = btreemap! {
+            50 => vec![connect.clone(), disconnect.clone()],
+        };
+        let delivered =
+            Network::receive_second_burst(&mut conn_messages, max_delay, start_step, end_step);
+        assert!(delivered.is_empty());
+        let mut conn_messages = btreemap! {
+            start_step => vec![connect.clone()],
+            51 => vec![disconnect.clone()],
+        };
+        let delivered =
+            Network::receive_second_burst(&mut conn_messages, max_delay, start_step, end_step);
+        assert!(delivered.is_empty());
+        assert_eq!(conn_messages[&51], vec![disconnect]);
+    }
+
+    #[test]
+    fn slow_node_receives_at_deadline() {
+        let params = SimulationParams {
+            max_delay: 5,
+            delivery_percentile: 1.0,
+            ..SimulationParams::default()
+        };
+        let mut network = Network::new(&params);
+        network.slow_down(Name(1), 100);
+        network.send(0, vec![test_message(Connect)]);
+
+        for step in 1..5 {
+            assert!(network.receive(step).is_empty());
+        }
+        assert_eq!(network.receive(5).len(), 1);
+    }
+
+    #[test]
+    fn oldest_queued() {
+        let params = SimulationParams {
+            max_delay: 5,
+            delivery_percentile: 1.0,
+            ..SimulationParams::default()
+        };
+        let mut network = Network::new(&params);
+        network.slow_down(Name(1), 100);
+        let mut to_two = test_message(Connect);
+        to_two.recipient = Name(2);
+        network.send(0, vec![test_message(Connect)]);
+        network.send(1, vec![test_message(Disconnect)]);
+        network.send(2, vec![to_two]);
+
+        let ages = network.oldest_queued(3, 1);
+        assert_eq!(ages.len(), 1);
+        assert_eq!((ages[0].recipient, ages[0].sent, ages[0].age), (Name(1), 0, 3));
+        assert_eq!(ages[0].messages, 2);
+        assert_eq!(network.oldest_queued(3, 5).len(), 2);
+
+        // Skipping the steps that the first two messages were due on leaves them overdue.
+        network.receive(7);
+        assert_eq!(network.overdue(), 1);
+        assert_eq!(network.oldest_queued(7, 1)[0].sent, 0);
+    }
+
+    #[test]
+    fn assigns_trace_ids() {
+        let mut network = Network::new(&SimulationParams::default());
+        let broadcast = Arc::new(Connect);
+        let mut response = test_message(Disconnect);
+        response.trace_id = 7;
+        let messages = vec![
+            Message {
+                sender: Name(0),
+                recipient: Name(1),
+                content: Arc::clone(&broadcast),
+                trace_id: 0,
+            },
+            Message {
+                sender: Name(0),
+                recipient: Name(2),
+                content: broadcast,
+                trace_id: 0,
+            },
+            response,
+            test_message(Connect),
+        ];
+        network.send(0, messages);
+
+        let trace_ids: Vec<_> = network
+            .queued_messages()
+            .into_iter()
+            .map(|(_, message)| (message.recipient, message.trace_id))
+            .collect();
+        // Both recipients of the broadcast are in the same trace, and a new trace doesn't reuse
+        // an existing trace's ID.
+        assert_eq!(trace_ids, vec![(Name(1), 1), (Name(1), 7), (Name(1), 8), (Name(2), 1)]);
+    }
 }
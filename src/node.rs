@@ -1,20 +1,33 @@
-use message::Message;
+use message::{AggregateSignature, BootstrapVotes, Message};
 use message::MessageContent;
 use message::MessageContent::*;
-use name::Name;
+use message_filter::MessageFilter;
+use name::{Name, Prefix};
 use block::{Block, BlockId, Vote};
-use blocks::{Blocks, VoteCounts, ValidBlocks, CurrentBlocks};
-use params::NodeParams;
+use blocks::{Blocks, Derivation, VoteCounts, ValidBlocks, CurrentBlocks};
+use coverage::{Rule, RuleCoverage};
+use invariant::InvariantViolation;
+use params::{CandidateBehaviour, NodeParams, ProofRequestPolicy, SpeedClass};
+use peer_state::{Confirmation, PeerStates};
+use routing_table::RoutingTable;
 use split::split_blocks;
-use merge::merge_blocks;
+use merge::{lost_quorum, merge_blocks};
 
+use fxhash::FxHashMap;
+use std::cmp;
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
 use std::mem;
 use std::fmt;
-
-const MESSAGE_FILTER_LEN: usize = 1024;
+use std::sync::Arc;
+
+/// A step on which a node was about to send more than `storm_threshold` messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Storm {
+    /// Number of messages the node was about to send.
+    pub messages: usize,
+    /// Number of witnessing votes it deferred to the next step.
+    pub deferred_votes: usize,
+}
 
 pub struct Node {
     /// Our node's name.
@@ -34,18 +47,103 @@ pub struct Node {
     /// Recently received votes that haven't yet been applied to the sets of valid and current
     /// blocks.
     pub recent_votes: BTreeSet<Vote>,
+    /// Snapshot of our votes for bootstrapping joining nodes, until our votes next change.
+    pub bootstrap_votes: Option<BootstrapVotes>,
     /// Peers that we're currently connected to.
     pub connections: BTreeSet<Name>,
     /// Nodes that we've sent connection requests to.
     pub connect_requests: BTreeSet<Name>,
+    /// Peers that have disconnected from us, and our current blocks when they did. When our
+    /// connections are limited, we don't ask them to connect again until our current blocks
+    /// change, because they may have no room for us (see `NodeParams::max_connections`).
+    pub refused_connections: BTreeMap<Name, CurrentBlocks>,
     /// Candidates who we are waiting to add to our current blocks.
     pub candidates: BTreeMap<Name, Candidate>,
-    /// Filter for hashes of recent messages we've already sent and shouldn't resend.
-    pub message_filter: VecDeque<u64>,
+    /// Nodes that joined while another candidate for our section was pending, in the order they
+    /// joined, along with the step they were queued.
+    pub queued_candidates: VecDeque<(Name, u64)>,
+    /// Approvals of nodes that we don't know as candidates yet, along with the step we first
+    /// received one, so that approvals which arrive before the node joins aren't lost.
+    pub early_approvals: BTreeMap<Name, (u64, BTreeSet<Name>)>,
+    /// Members who have challenged us to prove our resources, and the number of parts of our
+    /// response we've sent each of them.
+    pub proof_challengers: BTreeMap<Name, u64>,
+    /// Progress towards the next part of our resource proof, as a fraction of a part.
+    pub proof_progress: f64,
+    /// Connection states of the other members of our section.
+    pub peer_states: PeerStates,
+    /// Whether we're a member of all of our section's current blocks, or only some of them.
+    pub confirmation: Confirmation,
+    /// Current blocks of our siblings that we've lost our connections to, and the step we lost
+    /// them.
+    pub lost_siblings: BTreeMap<BlockId, u64>,
+    /// Sibling blocks that we've been disconnected from for long enough to vote to force-merge
+    /// with them.
+    pub force_merge_siblings: BTreeSet<BlockId>,
+    /// Number of times we've started voting to force-merge with a sibling.
+    pub force_merges: u64,
+    /// Number of proofs we didn't request because the block was too far ahead of our current
+    /// blocks.
+    pub version_gap_skips: u64,
+    /// Number of proofs we didn't request because the vote referencing the block hadn't reached
+    /// quorum (see `ProofRequestPolicy::Agreed`).
+    pub unagreed_proof_skips: u64,
+    /// Number of chain segments we truncated because a predecessor was missing (see
+    /// `Node::chain_segment`).
+    pub truncated_segments: u64,
+    /// Blocks whose chain segment we're collecting again once proof of the oldest block we
+    /// reached arrives, along with that block (see `NodeParams::recover_chain_gaps`).
+    pub pending_segments: BTreeMap<BlockId, BlockId>,
+    /// Number of joining nodes we've queued while another candidate was pending (see
+    /// `NodeParams::candidate_queue_timeout`).
+    pub queued_joins: u64,
+    /// Number of times each protocol rule has fired for us.
+    pub rules: RuleCoverage,
+    /// Number of superseded blocks we've pruned from our valid blocks (see
+    /// `NodeParams::history_depth`).
+    pub pruned_blocks: u64,
+    /// Blocks we've requested proof of, and the peers that couldn't prove them because they'd
+    /// pruned them, so that we ask someone else.
+    pub proof_refusals: BTreeMap<BlockId, BTreeSet<Name>>,
+    /// Nodes that other sections have relocated to us, which we don't relocate again.
+    pub relocated_in: BTreeSet<Name>,
+    /// New members of our section that we're relocating, and the names they're relocated to.
+    pub relocating: BTreeMap<Name, Name>,
+    /// Members of our section that have told us to relocate.
+    pub relocate_requests: BTreeSet<Name>,
+    /// The name we're relocating to, once a quorum of our section has told us to.
+    pub relocate_to: Option<Name>,
+    /// Filter for recent messages we've already sent and shouldn't resend.
+    pub message_filter: MessageFilter,
+    /// Witnessing votes held back during a message storm, to be sent on the next step.
+    pub deferred_votes: Vec<Vote>,
+    /// The message storm we were in on the current step, if any.
+    pub storm: Option<Storm>,
+    /// Clients of our section that we tell about its new blocks.
+    pub clients: BTreeSet<Name>,
+    /// The block of our section that our clients were last told of.
+    pub client_block: Option<BlockId>,
+    /// Nodes we can reach directly, if we're modelling a routing table.
+    pub routing_table: Option<RoutingTable>,
     /// Network configuration parameters.
     pub params: NodeParams,
+    /// Whether we follow the protocol, or misbehave as a malicious node.
+    pub behaviour: CandidateBehaviour,
+    /// The other attackers in our group, if we're an eclipse attacker.
+    pub allies: BTreeSet<Name>,
+    /// How quickly we work.
+    pub speed: SpeedClass,
+    /// Number of steps that our clock is ahead of the network's, or behind if negative. Our
+    /// join, self-shutdown and candidate queue timeouts expire that many steps early (or late):
+    /// see `local_step`.
+    pub clock_skew: i64,
+    /// Messages delivered to us that we haven't had the capacity to handle yet, oldest first.
+    pub backlog: VecDeque<Message>,
     /// Step that this node was created.
     pub step_created: u64,
+    /// Step at which we give up joining, if it was set explicitly rather than by
+    /// `self_shutdown_timeout`.
+    pub give_up_step: Option<u64>,
 }
 
 impl fmt::Display for Node {
@@ -56,12 +154,38 @@ impl fmt::Display for Node {
 
 pub struct Candidate {
     step_added: u64,
+    /// Number of parts of its resource proof that we've received.
+    proof_parts: u64,
+    /// Members of our section that have approved the candidate, possibly including us.
+    approvals: BTreeSet<Name>,
+    /// Whether a quorum of our section has approved the candidate.
+    approved: bool,
 }
 
 impl Candidate {
+    /// The step that the candidate was added.
+    pub fn step_added(&self) -> u64 {
+        self.step_added
+    }
+
+    /// Members of our section that have approved the candidate, possibly including us.
+    pub fn approvals(&self) -> &BTreeSet<Name> {
+        &self.approvals
+    }
+
     fn is_recent(&self, join_timeout: u64, step: u64) -> bool {
         self.step_added + join_timeout >= step
     }
+
+    fn has_proved_resources(&self, resource_proof_steps: u64) -> bool {
+        self.proof_parts >= resource_proof_steps
+    }
+
+    /// Whether the candidate is approved by a quorum of `section`.
+    fn is_approved_by(&self, blocks: &Blocks, section: &Block, params: &NodeParams) -> bool {
+        let approvals = &self.approvals & &section.members;
+        section.get_id().is_quorum(blocks, &approvals, params)
+    }
 }
 
 /// Compute the set of nodes that are in any current block.
@@ -69,7 +193,7 @@ pub fn nodes_in_any(all_blocks: &Blocks, blocks: &BTreeSet<BlockId>) -> BTreeSet
     all_blocks
         .block_contents(blocks.into_iter().cloned())
         .into_iter()
-        .fold(BTreeSet::new(), |acc, block| &acc | &block.members)
+        .fold(BTreeSet::new(), |acc, block| &acc | &*block.members)
 }
 
 impl Node {
@@ -83,6 +207,11 @@ impl Node {
     ) -> Self {
         // FIXME: prune connections
         let connections = nodes_in_any(blocks, &current_blocks);
+        let routing_table = params.routing_bucket_size.map(|bucket_size| {
+            let mut routing_table = RoutingTable::new(name, bucket_size);
+            routing_table.update(blocks, &current_blocks);
+            routing_table
+        });
 
         Node {
             our_name: name,
@@ -92,19 +221,56 @@ impl Node {
             current_candidate_blocks: current_blocks,
             connections,
             connect_requests: BTreeSet::new(),
+            refused_connections: BTreeMap::new(),
             candidates: BTreeMap::new(),
-            vote_counts: BTreeMap::new(),
-            rev_vote_counts: BTreeMap::new(),
+            queued_candidates: VecDeque::new(),
+            early_approvals: BTreeMap::new(),
+            proof_challengers: BTreeMap::new(),
+            proof_progress: 0.0,
+            vote_counts: VoteCounts::default(),
+            rev_vote_counts: VoteCounts::default(),
             recent_votes: BTreeSet::new(),
-            message_filter: VecDeque::with_capacity(MESSAGE_FILTER_LEN),
+            bootstrap_votes: None,
+            peer_states: PeerStates::new(params.rmconv_timeout),
+            confirmation: Confirmation::Unconfirmed,
+            lost_siblings: BTreeMap::new(),
+            force_merge_siblings: BTreeSet::new(),
+            force_merges: 0,
+            version_gap_skips: 0,
+            unagreed_proof_skips: 0,
+            truncated_segments: 0,
+            pending_segments: BTreeMap::new(),
+            queued_joins: 0,
+            rules: RuleCoverage::new(),
+            pruned_blocks: 0,
+            proof_refusals: BTreeMap::new(),
+            relocated_in: BTreeSet::new(),
+            relocating: BTreeMap::new(),
+            relocate_requests: BTreeSet::new(),
+            relocate_to: None,
+            message_filter: MessageFilter::new(),
+            deferred_votes: vec![],
+            storm: None,
+            clients: BTreeSet::new(),
+            client_block: None,
+            routing_table,
             params,
+            behaviour: CandidateBehaviour::Honest,
+            allies: BTreeSet::new(),
+            speed: SpeedClass::Normal,
+            clock_skew: 0,
+            backlog: VecDeque::new(),
             step_created: step,
+            give_up_step: None,
         }
     }
 
-    /// Minimum size that all sections must be before splitting.
-    fn min_split_size(&self) -> usize {
-        self.params.min_section_size + self.params.split_buffer
+    /// Minimum size that all sections must be before splitting, given the size of the network
+    /// according to our current blocks.
+    fn min_split_size(&self, blocks: &Blocks) -> usize {
+        let (num_sections, num_nodes) = blocks.network_size(&self.current_blocks);
+        self.params.min_section_size_for(num_sections, num_nodes) +
+            self.params.split_buffer_for(num_sections, num_nodes)
     }
 
     /// Insert a vote into our local cache of votes.
@@ -113,17 +279,18 @@ impl Node {
         I: IntoIterator<Item = Name>,
     {
         self.recent_votes.insert(vote.clone());
+        self.bootstrap_votes = None;
         let voters = self.vote_counts
             .entry(vote.from)
-            .or_insert_with(BTreeMap::new)
+            .or_default()
             .entry(vote.to)
-            .or_insert_with(BTreeSet::new);
+            .or_default();
         voters.extend(voted_for);
         let rev_voters = self.rev_vote_counts
             .entry(vote.to)
-            .or_insert_with(BTreeMap::new)
+            .or_default()
             .entry(vote.from)
-            .or_insert_with(BTreeSet::new);
+            .or_default();
         rev_voters.extend(voters.clone());
     }
 
@@ -132,8 +299,12 @@ impl Node {
     fn update_valid_blocks(&mut self, blocks: &Blocks) -> BTreeSet<(Vote, BTreeSet<Name>)> {
         // Update valid blocks.
         let new_votes = mem::replace(&mut self.recent_votes, btreeset!{});
-        let new_valid_votes =
-            blocks.new_valid_blocks(&self.valid_blocks, &self.vote_counts, new_votes);
+        let new_valid_votes = blocks.new_valid_blocks(
+            &self.valid_blocks,
+            &self.vote_counts,
+            new_votes,
+            &self.params,
+        );
         self.valid_blocks.extend(new_valid_votes.iter().map(
             |&(ref vote, _)| {
                 vote.to.clone()
@@ -166,6 +337,10 @@ impl Node {
             &mut self.current_blocks,
             blocks.compute_current_blocks(&self.current_candidate_blocks),
         );
+
+        if let Some(ref mut routing_table) = self.routing_table {
+            routing_table.update(blocks, &self.current_blocks);
+        }
     }
 
     /// Drop blocks for sections that we aren't neighbours of.
@@ -181,11 +356,47 @@ impl Node {
         }
     }
 
+    /// Drop all but the newest `depth` superseded blocks for each prefix from our valid blocks.
+    ///
+    /// Our votes are kept, so we can still prove the blocks we keep by way of those we drop.
+    fn prune_history(&mut self, blocks: &Blocks, depth: usize) {
+        let mut superseded: BTreeMap<Prefix, Vec<(u64, BlockId)>> = BTreeMap::new();
+        for &id in &self.valid_blocks {
+            if self.current_blocks.contains(&id) || self.current_candidate_blocks.contains(&id) {
+                continue;
+            }
+            let block = id.into_block(blocks);
+            superseded.entry(block.prefix).or_default().push((block.version, id));
+        }
+        for (_, mut ids) in superseded {
+            if ids.len() <= depth {
+                continue;
+            }
+            ids.sort();
+            let excess = ids.len() - depth;
+            for (_, id) in ids.into_iter().take(excess) {
+                self.valid_blocks.remove(&id);
+                self.pruned_blocks += 1;
+            }
+        }
+        let valid_blocks = &self.valid_blocks;
+        self.proof_refusals.retain(|block, _| !valid_blocks.contains(block));
+    }
+
+    /// Whether `block` is older than one of our current blocks for a compatible prefix, so that
+    /// we don't need it proved.
+    fn is_superseded(&self, blocks: &Blocks, block: BlockId) -> bool {
+        let block = block.into_block(blocks);
+        blocks.block_contents(&self.current_blocks).into_iter().any(|current| {
+            current.prefix.is_compatible(&block.prefix) && current.version > block.version
+        })
+    }
+
     fn is_candidate(&self, name: &Name, step: u64) -> bool {
         self.candidates
             .get(name)
             .map(|candidate| {
-                candidate.is_recent(self.params.join_timeout, step) &&
+                candidate.is_recent(self.params.join_timeout, self.local_step(step)) &&
                     self.connections.contains(name)
             })
             .unwrap_or(false)
@@ -193,13 +404,19 @@ impl Node {
 
     /// Get connection and disconnection messages for peers.
     fn connects_and_disconnects(&mut self, blocks: &Blocks, step: u64) -> Vec<Message> {
-        let neighbours = nodes_in_any(blocks, &self.current_blocks);
+        let members = &nodes_in_any(blocks, &self.current_blocks) - &self.refused_peers(blocks);
         let our_name = self.our_name;
 
         // FIXME: put this somewhere else?
-        for node in &neighbours {
+        for node in &members {
             self.candidates.remove(node);
+            self.early_approvals.remove(node);
         }
+        let (join_timeout, now) = (self.params.join_timeout, self.local_step(step));
+        self.early_approvals.retain(|_, &mut (received, _)| received + join_timeout >= now);
+        let current_blocks = &self.current_blocks;
+        self.refused_connections.retain(|_, refused_at| refused_at == current_blocks);
+        let neighbours = self.limit_connections(blocks, members);
 
         let to_disconnect: BTreeSet<Name> = {
             self.connections
@@ -221,7 +438,8 @@ impl Node {
             Message {
                 sender: our_name,
                 recipient: neighbour,
-                content: MessageContent::Disconnect,
+                content: Arc::new(MessageContent::Disconnect),
+                trace_id: 0,
             }
         });
 
@@ -245,7 +463,8 @@ impl Node {
             Message {
                 sender: our_name,
                 recipient: neighbour,
-                content: MessageContent::Connect,
+                content: Arc::new(MessageContent::Connect),
+                trace_id: 0,
             }
         });
 
@@ -253,7 +472,18 @@ impl Node {
     }
 
     /// Called once per step.
-    pub fn update_state(&mut self, blocks: &mut Blocks, step: u64) -> Vec<Message> {
+    ///
+    /// Returns the messages to send, along with the newly valid votes and their voters so that
+    /// the caller can record them in the trace.
+    pub fn update_state(
+        &mut self,
+        blocks: &Blocks,
+        step: u64,
+    ) -> (Vec<Message>, BTreeSet<(Vote, BTreeSet<Name>)>) {
+        if self.behaviour == CandidateBehaviour::Silent {
+            return (vec![], BTreeSet::new());
+        }
+
         // Update valid and current blocks.
         let new_valid_votes = self.update_valid_blocks(blocks);
 
@@ -261,35 +491,120 @@ impl Node {
         let mut messages = self.broadcast(
             blocks,
             new_valid_votes
-                .into_iter()
-                .inspect(|&(ref vote, _)| {
+                .iter()
+                .inspect(|(vote, _)| {
                     debug!(
                         "{}: new valid vote: {:?}",
                         self,
                         vote.as_debug(blocks),
                     );
                 })
-                .filter(|&(ref vote, _)| !vote.is_witnessing(blocks))
-                .map(VoteAgreedMsg)
+                .filter(|(vote, _)| !vote.is_witnessing(blocks))
+                .map(|(vote, voters)| self.agreement_msg(vote.clone(), voters.clone()))
                 .collect(),
             step,
         );
 
         // Prune blocks that are no longer relevant because of splitting.
         self.prune_split_blocks(blocks);
+        if let Some(depth) = self.params.history_depth {
+            self.prune_history(blocks, depth);
+        }
+
+        messages.extend(self.client_updates(blocks));
+
+        let section_blocks = self.our_current_section_blocks(blocks);
+        self.confirmation = self.confirmation.update(self.our_name, &section_blocks, step);
+        if let Some(&section) = self.our_current_blocks(blocks).first() {
+            let connections = &self.connections;
+            self.peer_states.update(
+                self.our_name,
+                section,
+                |name| connections.contains(name),
+                step,
+            );
+        }
+
+        // Relocate new members before they stop being candidates.
+        messages.extend(self.relocate_new_members(blocks));
+        self.check_relocate_requests(blocks);
 
         // Generate connect and disconnect messages.
         messages.extend(self.connects_and_disconnects(blocks, step));
 
-        messages
+        self.update_lost_siblings(blocks, step);
+
+        messages.extend(self.resource_proof_responses());
+        messages.extend(self.admit_queued_candidate(blocks, step));
+        messages.extend(self.approve_candidates(blocks, step));
+
+        (messages, new_valid_votes)
+    }
+
+    /// Tell our clients about our section's block if it's changed since we last did, dropping any
+    /// clients that no longer belong to our section.
+    fn client_updates(&mut self, blocks: &Blocks) -> Vec<Message> {
+        let section = match self.our_current_blocks(blocks).first() {
+            Some(&section) => section,
+            None => return vec![],
+        };
+        let id = section.get_id();
+        if self.client_block == Some(id) {
+            return vec![];
+        }
+        self.client_block = Some(id);
+        self.clients.retain(|&client| section.prefix.matches(client));
+        let content = Arc::new(BlockUpdate(id));
+        let our_name = self.our_name;
+        self.clients
+            .iter()
+            .map(|&client| {
+                Message {
+                    sender: our_name,
+                    recipient: client,
+                    content: Arc::clone(&content),
+                    trace_id: 0,
+                }
+            })
+            .collect()
+    }
+
+    /// Message telling others that `vote` is agreed by `voters`, aggregating their signatures if
+    /// the `aggregate_signature_bytes` parameter is set.
+    fn agreement_msg(&self, vote: Vote, voters: BTreeSet<Name>) -> MessageContent {
+        match self.params.aggregate_signature_bytes {
+            Some(len) => {
+                VoteAggregatedMsg((
+                    vote,
+                    AggregateSignature {
+                        signers: voters,
+                        len,
+                    },
+                ))
+            }
+            None => VoteAgreedMsg((vote, voters)),
+        }
     }
 
     /// Create messages for every relevant neighbour for every vote in the given vec.
     pub fn broadcast(&self, blocks: &Blocks, msgs: Vec<MessageContent>, step: u64) -> Vec<Message> {
         msgs.into_iter()
             .flat_map(move |content| {
-                let mut recipients =
-                    content.recipients(blocks, &self.current_blocks, self.our_name);
+                // Every recipient shares the same content.
+                let content = Arc::new(content);
+                let mut recipients = content.recipients(
+                    blocks,
+                    &self.current_blocks,
+                    self.our_name,
+                    self.params.recipient_policy.policy(),
+                );
+                // Agreements are relayed to other sections, but only to the nodes we know.
+                if let (&VoteAgreedMsg(_), Some(routing_table)) |
+                    (&VoteAggregatedMsg(_), Some(routing_table)) =
+                    (&*content, self.routing_table.as_ref())
+                {
+                    recipients.retain(|name| routing_table.contains(name));
+                }
                 recipients.extend(self.nodes_to_add(step));
                 recipients.remove(&self.our_name);
 
@@ -297,7 +612,8 @@ impl Node {
                     Message {
                         sender: self.our_name,
                         recipient,
-                        content: content.clone(),
+                        content: Arc::clone(&content),
+                        trace_id: 0,
                     }
                 })
             })
@@ -305,7 +621,7 @@ impl Node {
     }
 
     /// Check we don't have excessive valid blocks for any given (prefix, version) pair.
-    pub fn check_conflicting_block_count(&self, blocks: &Blocks) {
+    pub fn check_conflicting_block_count(&self, blocks: &Blocks) -> Result<(), InvariantViolation> {
         let mut conflicting_counts = BTreeMap::new();
         for block in self.valid_blocks.iter().map(|b| blocks.get(b).unwrap()) {
             let count = conflicting_counts
@@ -313,15 +629,15 @@ impl Node {
                 .or_insert(0);
             *count += 1;
             if *count == self.params.max_conflicting_blocks {
-                panic!(
-                    "{:?}\nhas {} valid blocks for {:?} with version {}.",
-                    self.as_debug(blocks),
-                    count,
-                    block.prefix,
-                    block.version
-                );
+                return Err(InvariantViolation::ConflictingBlocks {
+                    node: self.our_name,
+                    prefix: block.prefix,
+                    version: block.version,
+                    count: *count,
+                });
             }
         }
+        Ok(())
     }
 
     /// Blocks that we can legitimately vote on successors for, because we are part of them.
@@ -337,18 +653,20 @@ impl Node {
     }
 
     /// True if the given node could be added to the given block
-    fn could_be_added(&self, node: Name, block: &Block) -> bool {
+    fn could_be_added(&self, node: Name, block: &Block, min_split_size: usize) -> bool {
         !block.members.contains(&node) && block.prefix.matches(node) &&
-            !block.should_split(self.min_split_size())
+            !block.should_split(min_split_size)
     }
 
-    /// Vote to add the oldest candidate (from our perspective) that hasn't timed out.
+    /// Vote to add the oldest candidate (from our perspective) that hasn't timed out, and has
+    /// been approved by our section.
     fn nodes_to_add(&self, step: u64) -> Vec<Name> {
+        let now = self.local_step(step);
         self.candidates
             .iter()
             .filter(|&(name, candidate)| {
                 self.connections.contains(name) &&
-                    candidate.is_recent(self.params.join_timeout, step)
+                    candidate.is_recent(self.params.join_timeout, now) && candidate.approved
             })
             .min_by_key(|&(_, candidate)| candidate.step_added)
             .map(|(name, _)| *name)
@@ -356,13 +674,17 @@ impl Node {
             .collect()
     }
 
-    fn nodes_to_drop(&self, current_block: &Block) -> Vec<Name> {
+    /// Vote to remove members that we've been disconnected from for longer than the RmConv
+    /// timeout.
+    fn nodes_to_drop(&self, current_block: &Block, step: u64) -> Vec<Name> {
+        let lost = self.peer_states.nodes_to_drop(step);
         current_block
             .members
             .iter()
             .filter(|peer| {
                 **peer != self.our_name && !self.connections.contains(peer) &&
-                    !self.candidates.contains_key(peer)
+                    !self.candidates.contains_key(peer) && !self.relocating.contains_key(peer) &&
+                    lost.contains(peer)
             })
             .cloned()
             .collect()
@@ -386,57 +708,47 @@ impl Node {
         votes
     }
 
-    /// Construct new successor blocks based on our view of the network.
-    pub fn construct_new_votes(&self, blocks: &mut Blocks, step: u64) -> Vec<Vote> {
+    /// Construct new successor blocks based on our view of the network, along with the rules
+    /// that voted for them.
+    pub fn construct_new_votes(&self, blocks: &mut Blocks, step: u64) -> Vec<(Rule, Vote)> {
         let mut votes = vec![];
+        let min_split_size = self.min_split_size(blocks);
 
-        let blocks_to_add = {
-            let mut blocks_to_add = BTreeSet::new();
+        let derivations = {
+            let mut derivations = vec![];
             for block in self.our_current_blocks(blocks) {
                 for node in self.nodes_to_add(step) {
-                    if self.could_be_added(node, block) {
+                    if self.could_be_added(node, block, min_split_size) {
                         trace!("{}: voting to add {} to: {:?}", self, node, block);
-                        let added = block.add_node(node);
-                        let added_id = added.get_id();
-                        blocks_to_add.insert(added);
-                        votes.push(Vote {
-                            from: block.get_id(),
-                            to: added_id,
-                        });
+                        derivations.push((Rule::Add, block.get_id(), Derivation::Add(node)));
                     }
                 }
             }
-            blocks_to_add
-        };
-        for block in blocks_to_add {
-            blocks.insert(block);
-        }
-
-        let blocks_to_add = {
-            let mut blocks_to_add = BTreeSet::new();
             for block in self.our_current_blocks(blocks) {
-                for node in self.nodes_to_drop(&block) {
+                for node in self.nodes_to_drop(block, step) {
                     trace!("{}: voting to remove {} from: {:?}", self, node, block);
-                    let removed = block.remove_node(node);
-                    let removed_id = removed.get_id();
-                    blocks_to_add.insert(removed);
-                    votes.push(Vote {
-                        from: block.get_id(),
-                        to: removed_id,
-                    });
+                    derivations.push((Rule::Remove, block.get_id(), Derivation::Remove(node)));
+                }
+                for node in self.relocating.keys().filter(|node| block.members.contains(node)) {
+                    trace!("{}: voting to relocate {} from: {:?}", self, node, block);
+                    derivations.push((Rule::Remove, block.get_id(), Derivation::Remove(*node)));
                 }
             }
-            blocks_to_add
+            derivations
         };
-        for block in blocks_to_add {
-            blocks.insert(block);
+        for (rule, from, derivation) in derivations {
+            let vote = Vote {
+                from,
+                to: blocks.derive(from, derivation),
+            };
+            votes.push((rule, vote));
         }
 
         for vote in split_blocks(
             blocks,
             &self.current_blocks,
             self.our_name,
-            self.min_split_size(),
+            min_split_size,
         )
         {
             trace!(
@@ -445,15 +757,15 @@ impl Node {
                 vote.from.into_block(blocks),
                 vote.to.into_block(blocks)
             );
-            votes.push(vote);
+            votes.push((Rule::Split, vote));
         }
 
-        for vote in merge_blocks(
+        for (rule, vote) in merge_blocks(
             blocks,
             &self.current_blocks,
-            &self.connections,
+            &self.force_merge_siblings,
             self.our_name,
-            self.params.min_section_size,
+            &self.params,
         )
         {
             trace!(
@@ -462,7 +774,7 @@ impl Node {
                 vote.from.into_block(blocks),
                 vote.to.into_block(blocks)
             );
-            votes.push(vote);
+            votes.push((rule, vote));
         }
 
         for vote in self.witness_votes(blocks) {
@@ -472,96 +784,280 @@ impl Node {
                 vote.from.into_block(blocks),
                 vote.to.into_block(blocks)
             );
-            votes.push(vote);
+            votes.push((Rule::Witness, vote));
         }
 
         votes
     }
 
     /// Returns new votes to be broadcast after filtering them.
-    pub fn broadcast_new_votes(&mut self, blocks: &mut Blocks, step: u64) -> Vec<Message> {
-        let votes = self.construct_new_votes(blocks, step);
+    ///
+    /// `sending` is the number of messages we're already sending this step, which count towards
+    /// a message storm (see `NodeParams::storm_threshold`).
+    pub fn broadcast_new_votes(
+        &mut self,
+        blocks: &mut Blocks,
+        step: u64,
+        sending: usize,
+    ) -> Vec<Message> {
+        self.storm = None;
+        if self.behaviour == CandidateBehaviour::Silent {
+            return vec![];
+        }
+        let mut new_votes = vec![];
+        for (rule, vote) in self.construct_new_votes(blocks, step) {
+            self.rules.fire(rule);
+            new_votes.push(vote);
+        }
         let our_name = self.our_name;
 
-        let mut to_broadcast = vec![];
-
-        for vote in &votes {
+        for vote in &new_votes {
             self.add_vote(vote.clone(), Some(our_name));
         }
 
+        // Votes deferred from the previous step go out now, and aren't deferred again.
+        let previously_deferred = mem::take(&mut self.deferred_votes);
+
         // Construct vote messages and broadcast.
-        let vote_msgs: Vec<_> = votes.into_iter().map(VoteMsg).collect();
-        to_broadcast.extend(self.broadcast(blocks, vote_msgs, step));
+        let vote_msgs: Vec<_> = previously_deferred
+            .iter()
+            .chain(&new_votes)
+            .cloned()
+            .map(VoteMsg)
+            .collect();
+        let mut to_broadcast = self.broadcast(blocks, vote_msgs, step);
+
+        let messages = sending + to_broadcast.len();
+        if self.params.storm_threshold.is_some_and(|threshold| messages > threshold) {
+            if self.params.throttle_storms {
+                let (deferred, urgent): (Vec<_>, Vec<_>) =
+                    new_votes.into_iter().partition(|vote| vote.is_witnessing(blocks));
+                if !deferred.is_empty() {
+                    let vote_msgs = previously_deferred
+                        .into_iter()
+                        .chain(urgent)
+                        .map(VoteMsg)
+                        .collect();
+                    to_broadcast = self.broadcast(blocks, vote_msgs, step);
+                    self.deferred_votes = deferred;
+                }
+            }
+            let storm = Storm {
+                messages,
+                deferred_votes: self.deferred_votes.len(),
+            };
+            info!(
+                "{}: message storm at step {}: {} messages, deferring {} witnessing votes",
+                self,
+                step,
+                storm.messages,
+                storm.deferred_votes
+            );
+            self.storm = Some(storm);
+        }
 
-        self.filter_messages(to_broadcast)
+        self.filter_messages(to_broadcast, step)
     }
 
     /// Remove messages that have already been sent from `messages`, and update the filter.
-    fn filter_messages(&mut self, messages: Vec<Message>) -> Vec<Message> {
-        let mut filtered = vec![];
-        for message in messages {
-            let mut hasher = DefaultHasher::new();
-            message.hash(&mut hasher);
-            let hash = hasher.finish();
-            if message.content == Connect || message.content == Disconnect ||
-                !self.message_filter.contains(&hash) {
-                filtered.push(message);
-                if self.message_filter.len() == MESSAGE_FILTER_LEN {
-                    let _ = self.message_filter.pop_front();
-                }
-                self.message_filter.push_back(hash);
-            }
-        }
-        filtered
+    fn filter_messages(&mut self, messages: Vec<Message>, step: u64) -> Vec<Message> {
+        let message_filter = &mut self.message_filter;
+        messages
+            .into_iter()
+            .filter(|message| {
+                let is_new = message_filter.insert(message, step);
+                is_new || *message.content == Connect || *message.content == Disconnect
+            })
+            .collect()
     }
 
-    /// Create a message with all our votes to send to a new node.
-    fn construct_bootstrap_msg(&self, joining_node: Name) -> Message {
+    /// Create a message with all our votes to send to a new node, or to a node of a network that
+    /// we're being introduced to.
+    ///
+    /// Nodes joining before our votes next change are sent the same snapshot of them.
+    pub fn construct_bootstrap_msg(&mut self, joining_node: Name) -> Message {
+        self.rules.fire(Rule::Bootstrap);
+        let vote_counts = &self.vote_counts;
+        let votes = self.bootstrap_votes.get_or_insert_with(|| {
+            let mut votes: Vec<_> = vote_counts
+                .iter()
+                .flat_map(|(&from, map)| {
+                    map.iter().map(move |(&to, voters)| {
+                        (Vote { from, to }, voters.clone())
+                    })
+                })
+                .collect();
+            votes.sort();
+            Arc::new(votes)
+        });
         Message {
             sender: self.our_name,
             recipient: joining_node,
-            content: BootstrapMsg(self.vote_counts.clone()),
+            content: Arc::new(BootstrapMsg(Arc::clone(votes))),
+            trace_id: 0,
         }
     }
 
-    /// Apply a bootstrap message received from another node.
-    fn apply_bootstrap_msg(&mut self, vote_counts: VoteCounts) {
-        for (from, map) in vote_counts {
-            for (to, voters) in map {
-                let vote = Vote {
-                    from: from.clone(),
-                    to,
-                };
-                self.add_vote(vote, voters);
+    /// Apply a bootstrap message received from another node, and check that it holds the whole
+    /// history of our section back to the last split.
+    fn apply_bootstrap_msg(
+        &mut self,
+        blocks: &Blocks,
+        votes: &[(Vote, BTreeSet<Name>)],
+    ) -> Vec<Message> {
+        for (vote, voters) in votes {
+            self.add_vote(vote.clone(), voters.iter().cloned());
+        }
+        let (our_name, params) = (self.our_name, &self.params);
+        let ours: Vec<&Block> = votes
+            .iter()
+            .filter(|(vote, voters)| vote.is_quorum(blocks, voters, params))
+            .map(|(vote, _)| vote.to.into_block(blocks))
+            .filter(|block| block.prefix.matches(our_name))
+            .collect();
+        let newest = ours.iter().map(|block| block.version).max();
+        let heads: BTreeSet<BlockId> = ours.iter()
+            .filter(|block| Some(block.version) == newest)
+            .map(|block| block.get_id())
+            .collect();
+        let mut messages = vec![];
+        for head in heads {
+            if let Err(requests) = self.chain_segment(blocks, head) {
+                messages.extend(requests);
+            }
+        }
+        messages
+    }
+
+    /// Collect the chain segments that were waiting for proof of `block` again, now that the
+    /// votes leading to it have arrived.
+    fn retry_chain_segments(&mut self, blocks: &Blocks, block: BlockId) -> Vec<Message> {
+        let waiting: Vec<BlockId> = self.pending_segments
+            .iter()
+            .filter(|&(_, &gap)| gap == block)
+            .map(|(&head, _)| head)
+            .collect();
+        let mut messages = vec![];
+        for head in waiting {
+            self.pending_segments.remove(&head);
+            if let Err(requests) = self.chain_segment(blocks, head) {
+                messages.extend(requests);
             }
         }
+        messages
+    }
+
+    /// Truncate the chain segments that were waiting for proof of `block`, which the peer we
+    /// asked couldn't give.
+    fn abandon_chain_segments(&mut self, blocks: &Blocks, block: BlockId) {
+        let before = self.pending_segments.len();
+        self.pending_segments.retain(|_, &mut gap| gap != block);
+        let abandoned = (before - self.pending_segments.len()) as u64;
+        if abandoned > 0 {
+            warn!(
+                "{}: couldn't get proof of {:?}, truncating {} chain segments",
+                self,
+                block.into_block(blocks),
+                abandoned
+            );
+            self.truncated_segments += abandoned;
+        }
     }
 
-    /// Construct a RequestProof message
-    fn request_proof(&self, blocks: &Blocks, block: BlockId, node: Name) -> Vec<Message> {
+    /// Construct a RequestProof message, sent to `node` or the closest node to it that we know.
+    ///
+    /// `agreed` is whether the block is referenced by a vote that has reached quorum, which is
+    /// all that's requested under `ProofRequestPolicy::Agreed`.
+    fn request_proof(
+        &mut self,
+        blocks: &Blocks,
+        block: BlockId,
+        node: Name,
+        agreed: bool,
+    ) -> Vec<Message> {
+        let node = match self.routing_table {
+            Some(ref routing_table) if !routing_table.contains(&node) => {
+                match routing_table.closest(node) {
+                    Some(closest) => closest,
+                    None => return vec![],
+                }
+            }
+            _ => node,
+        };
         let max_version = blocks
             .block_contents(&self.current_blocks)
             .into_iter()
             .map(|b| b.version)
             .max();
-        // Request proof if the `from` block isn't valid and it's version is less than the
-        // max version we have - 10
-        // FIXME: this tries to prevent bootstrapping issues - find a less hacky way to do this
-        if self.valid_blocks.contains(&block) ||
-            max_version.map_or(true, |ver| block.into_block(blocks).version > ver + 10)
+        let too_far_ahead = max_version.is_none_or(|ver| {
+            block.into_block(blocks).version > ver + self.params.proof_request_window
+        });
+        if self.valid_blocks.contains(&block) {
+            vec![]
+        } else if self.params.history_depth.is_some() && self.is_superseded(blocks, block) {
+            // We may have pruned it, and don't need it now that we've moved past it.
+            vec![]
+        } else if self.params.proof_request_policy == ProofRequestPolicy::VersionGap &&
+                   too_far_ahead
         {
+            trace!("{}: not requesting proof of {:?}, too far ahead", self, block);
+            self.version_gap_skips += 1;
+            vec![]
+        } else if self.params.proof_request_policy == ProofRequestPolicy::Agreed && !agreed {
+            trace!("{}: not requesting proof of {:?}, vote not agreed", self, block);
+            self.unagreed_proof_skips += 1;
             vec![]
         } else {
+            self.rules.fire(Rule::ProofRequest);
             vec![
                 Message {
                     sender: self.our_name,
                     recipient: node,
-                    content: RequestProof(block, self.current_blocks.clone()),
+                    content: Arc::new(RequestProof(block, self.current_blocks.clone())),
+                    trace_id: 0,
                 },
             ]
         }
     }
 
+    /// Whether any vote from `block` has reached quorum among the votes we've counted.
+    fn has_agreed_vote_from(&self, blocks: &Blocks, block: BlockId) -> bool {
+        self.vote_counts.get(&block).is_some_and(|votes| {
+            votes.iter().any(|(&to, voters)| {
+                Vote { from: block, to }.is_quorum(blocks, voters, &self.params)
+            })
+        })
+    }
+
+    /// Ask another of our peers to prove `block`, after `refused` couldn't, because it may have
+    /// pruned the block from its history.
+    fn request_proof_elsewhere(
+        &mut self,
+        blocks: &Blocks,
+        block: BlockId,
+        refused: Name,
+    ) -> Vec<Message> {
+        let refusals = self.proof_refusals.entry(block).or_default();
+        refusals.insert(refused);
+        let our_name = self.our_name;
+        let peer = self.connections
+            .iter()
+            .cloned()
+            .find(|&peer| peer != our_name && !refusals.contains(&peer));
+        match peer {
+            Some(peer) => {
+                debug!("{}: asking {} to prove {:?} instead", self, peer, block);
+                let agreed = self.has_agreed_vote_from(blocks, block);
+                self.request_proof(blocks, block, peer, agreed)
+            }
+            None => {
+                debug!("{}: none of our peers could prove {:?}", self, block);
+                self.proof_refusals.remove(&block);
+                vec![]
+            }
+        }
+    }
+
     fn check_path(blocks: &Blocks, current_blocks: &CurrentBlocks, p: &[BlockId]) -> bool {
         let current_blocks_objs = blocks.block_contents(current_blocks);
         let plast = &p[p.len() - 1];
@@ -575,7 +1071,7 @@ impl Node {
     fn bundle_predecessors(&self, blocks: &Blocks, block: BlockId, node: Name) -> Message {
         let bundle = VoteBundle(
             blocks
-                .predecessors(&block, &self.rev_vote_counts)
+                .predecessors(&block, &self.rev_vote_counts, &self.params)
                 .into_iter()
                 .map(|(b, _, voters)| (Vote { from: b, to: block }, voters))
                 .collect::<Vec<_>>(),
@@ -583,7 +1079,64 @@ impl Node {
         Message {
             sender: self.our_name,
             recipient: node,
-            content: bundle,
+            content: Arc::new(bundle),
+            trace_id: 0,
+        }
+    }
+
+    /// Get all the votes for the history of `block` back to the last split.
+    ///
+    /// If a predecessor is missing, the segment is truncated there with a warning, unless
+    /// `recover_chain_gaps` is set, in which case we return messages requesting proof of the
+    /// oldest block we reached from one of its members. The segment is then collected again
+    /// when a bundle of votes leading to that block arrives, or truncated if the member can't
+    /// prove it.
+    pub fn chain_segment(
+        &mut self,
+        blocks: &Blocks,
+        block: BlockId,
+    ) -> Result<BTreeSet<(Vote, BTreeSet<Name>)>, Vec<Message>> {
+        let segment = blocks.trace_chain_segment(&block, &self.rev_vote_counts, &self.params);
+        let gap = match segment.gap {
+            Some(gap) => gap,
+            None => return Ok(segment.votes),
+        };
+        let owner = if self.params.recover_chain_gaps {
+            // The gap block is usually valid, so ask one of its members to prove it even though
+            // `request_proof` wouldn't: the bundle they reply with holds the votes leading to it.
+            let members = &gap.into_block(blocks).members;
+            members
+                .iter()
+                .find(|name| **name != self.our_name && self.connections.contains(name))
+                .or_else(|| members.iter().find(|name| **name != self.our_name))
+                .cloned()
+        } else {
+            None
+        };
+        match owner {
+            Some(owner) => {
+                debug!("{}: requesting proof of {:?} from {}", self, gap, owner);
+                self.rules.fire(Rule::ProofRequest);
+                self.pending_segments.insert(block, gap);
+                Err(vec![
+                    Message {
+                        sender: self.our_name,
+                        recipient: owner,
+                        content: Arc::new(RequestProof(gap, self.current_blocks.clone())),
+                        trace_id: 0,
+                    },
+                ])
+            }
+            None => {
+                warn!(
+                    "{}: couldn't find a predecessor for {:?}, truncating the history of {:?}",
+                    self,
+                    gap.into_block(blocks),
+                    block
+                );
+                self.truncated_segments += 1;
+                Ok(segment.votes)
+            }
         }
     }
 
@@ -592,17 +1145,18 @@ impl Node {
         &self,
         blocks: &Blocks,
         block: BlockId,
-        current_blocks: CurrentBlocks,
+        current_blocks: &CurrentBlocks,
         node: Name,
     ) -> Message {
         if !self.valid_blocks.contains(&block) {
             return Message {
                 sender: self.our_name,
                 recipient: node,
-                content: NoProof(block),
+                content: Arc::new(NoProof(block)),
+                trace_id: 0,
             };
         }
-        if Self::check_path(blocks, &current_blocks, &[block]) {
+        if Self::check_path(blocks, current_blocks, &[block]) {
             return self.bundle_predecessors(blocks, block, node);
         }
 
@@ -613,7 +1167,7 @@ impl Node {
 
         while had_predecessors &&
             !paths.iter().any(
-                |p| Self::check_path(blocks, &current_blocks, p),
+                |p| Self::check_path(blocks, current_blocks, p),
             )
         {
             had_predecessors = false;
@@ -621,7 +1175,7 @@ impl Node {
             for path in paths {
                 let plast = path.last().unwrap();
                 let predecessor_blocks = blocks
-                    .predecessors(plast, &self.rev_vote_counts)
+                    .predecessors(plast, &self.rev_vote_counts, &self.params)
                     .into_iter()
                     .map(|(b, _, _)| b);
 
@@ -639,13 +1193,14 @@ impl Node {
             return Message {
                 sender: self.our_name,
                 recipient: node,
-                content: NoProof(block),
+                content: Arc::new(NoProof(block)),
+                trace_id: 0,
             };
         }
 
         let mut path = paths
             .iter()
-            .find(|&p| Self::check_path(blocks, &current_blocks, p))
+            .find(|&p| Self::check_path(blocks, current_blocks, p))
             .unwrap()
             .clone();
 
@@ -653,7 +1208,8 @@ impl Node {
             return Message {
                 sender: self.our_name,
                 recipient: node,
-                content: NoProof(block),
+                content: Arc::new(NoProof(block)),
+                trace_id: 0,
             };
         }
 
@@ -687,10 +1243,24 @@ impl Node {
         Message {
             sender: self.our_name,
             recipient: node,
-            content: VoteBundle(bundle),
+            content: Arc::new(VoteBundle(bundle)),
+            trace_id: 0,
         }
     }
 
+    /// Take the blocks and votes of `crashed`, an earlier incarnation of this node, as if they'd
+    /// survived the crash.
+    pub fn restore_chain_state(&mut self, crashed: Node) {
+        self.valid_blocks = crashed.valid_blocks;
+        self.current_candidate_blocks = crashed.current_candidate_blocks;
+        self.current_blocks = crashed.current_blocks;
+        self.prev_current_blocks = crashed.prev_current_blocks;
+        self.vote_counts = crashed.vote_counts;
+        self.rev_vote_counts = crashed.rev_vote_counts;
+        self.recent_votes = crashed.recent_votes;
+        self.bootstrap_votes = None;
+    }
+
     /// Returns true if the peer is known and its state is `Disconnected`.
     pub fn is_disconnected_from(&self, name: &Name) -> bool {
         !self.connections.contains(name)
@@ -698,7 +1268,7 @@ impl Node {
 
     /// Returns true if this node should shutdown because it has failed to join a section.
     pub fn should_shutdown(&self, blocks: &Blocks, step: u64) -> bool {
-        let timeout_elapsed = step >= self.step_created + self.params.self_shutdown_timeout;
+        let timeout_elapsed = self.local_step(step) >= self.shutdown_step();
 
         let (no_blocks, insufficient_connections) = match self.our_current_blocks(blocks).first() {
             Some(block) => (false, self.connections.len() * 2 < block.members.len()),
@@ -708,10 +1278,338 @@ impl Node {
         timeout_elapsed && (no_blocks || insufficient_connections)
     }
 
+    /// Step from which we shut down if we still haven't joined a section.
+    fn shutdown_step(&self) -> u64 {
+        self.give_up_step.unwrap_or(self.step_created + self.params.self_shutdown_timeout)
+    }
+
+    /// The first step after `step` at which one of our timeouts expires, if any are pending.
+    ///
+    /// Without any new messages, this is the next step at which our behaviour can change.
+    pub fn next_timeout(&self, step: u64) -> Option<u64> {
+        // We work on our resource proof on every step until it's complete, send votes deferred
+        // by a message storm on the next step, and handle our backlog of messages as fast as we
+        // can.
+        if !self.proof_challengers.is_empty() || !self.deferred_votes.is_empty() ||
+            !self.backlog.is_empty()
+        {
+            return Some(step + 1);
+        }
+        // These timeouts expire by our own clock.
+        let skewed = self.candidates
+            .values()
+            .map(|candidate| candidate.step_added + self.params.join_timeout + 1)
+            .chain(Some(self.shutdown_step()))
+            .chain(self.queued_candidates.iter().filter_map(|&(_, step_queued)| {
+                self.params.candidate_queue_timeout.map(|timeout| step_queued + timeout + 1)
+            }))
+            .map(|timeout| self.network_step(timeout));
+        skewed
+            .chain(self.peer_states.next_timeout(step))
+            .chain(self.lost_siblings.values().map(|&since| {
+                since + self.params.force_merge_delay
+            }))
+            .filter(|&timeout| timeout > step)
+            .min()
+    }
+
+    /// The step that we believe it is when it's `step` for the rest of the network.
+    ///
+    /// The steps that we stamp, such as when we were created or when a candidate was added, are
+    /// the network's, so each timeout measured from them is shortened (or lengthened) by our
+    /// skew. This models our timeouts drifting: a clock that was merely offset would cancel out
+    /// if the stamps were taken from it too.
+    fn local_step(&self, step: u64) -> u64 {
+        cmp::max(step as i64 + self.clock_skew, 0) as u64
+    }
+
+    /// The step for the rest of the network when we believe it's `local_step`.
+    fn network_step(&self, local_step: u64) -> u64 {
+        cmp::max(local_step as i64 - self.clock_skew, 0) as u64
+    }
+
     pub fn step_created(&self) -> u64 {
         self.step_created
     }
 
+    /// Queue newly delivered messages behind any we haven't handled yet, and take as many as we
+    /// have the capacity to handle on this step: all of them, if `capacity` is `None`.
+    pub fn take_messages(
+        &mut self,
+        delivered: Vec<Message>,
+        capacity: Option<usize>,
+    ) -> Vec<Message> {
+        let capacity = match capacity {
+            Some(capacity) => self.speed.capacity(capacity),
+            None if self.backlog.is_empty() => return delivered,
+            None => usize::MAX,
+        };
+        self.backlog.extend(delivered);
+        let count = cmp::min(capacity, self.backlog.len());
+        self.backlog.drain(..count).collect()
+    }
+
+    /// Send the latest part of our resource proof to every member that has challenged us, if
+    /// we've finished any more of it. We finish parts at our speed, so a normal node finishes one
+    /// on every step.
+    fn resource_proof_responses(&mut self) -> Vec<Message> {
+        if self.proof_challengers.is_empty() {
+            self.proof_progress = 0.0;
+            return vec![];
+        }
+        self.proof_progress += self.speed.speed();
+        let parts = self.proof_progress.floor();
+        self.proof_progress -= parts;
+        if parts < 1.0 {
+            return vec![];
+        }
+
+        let our_name = self.our_name;
+        let resource_proof_steps = self.params.resource_proof_steps;
+        let mut messages = vec![];
+        for (&challenger, parts_sent) in &mut self.proof_challengers {
+            *parts_sent = cmp::min(*parts_sent + parts as u64, resource_proof_steps);
+            messages.push(Message {
+                sender: our_name,
+                recipient: challenger,
+                content: Arc::new(ResourceProofResponse(*parts_sent)),
+                trace_id: 0,
+            });
+        }
+        self.proof_challengers.retain(|_, &mut parts_sent| {
+            parts_sent < resource_proof_steps
+        });
+        messages
+    }
+
+    /// Track which of our siblings' current blocks we've lost our connections to, and for how
+    /// long, to decide which we should vote to force-merge with.
+    fn update_lost_siblings(&mut self, blocks: &Blocks, step: u64) {
+        let siblings: BTreeSet<_> = self.our_current_blocks(blocks)
+            .into_iter()
+            .filter_map(|block| block.prefix.sibling())
+            .collect();
+        let lost: BTreeSet<BlockId> = blocks
+            .block_contents(&self.current_blocks)
+            .into_iter()
+            .filter(|block| {
+                siblings.contains(&block.prefix) &&
+                    lost_quorum(block, &self.connections, &self.params)
+            })
+            .map(Block::get_id)
+            .collect();
+        self.lost_siblings.retain(|block_id, _| lost.contains(block_id));
+        for block_id in lost {
+            self.lost_siblings.entry(block_id).or_insert(step);
+        }
+
+        let force_merge_delay = self.params.force_merge_delay;
+        let force_merge_siblings: BTreeSet<BlockId> = self.lost_siblings
+            .iter()
+            .filter(|&(_, &since)| since + force_merge_delay <= step)
+            .map(|(&block_id, _)| block_id)
+            .collect();
+        let new_force_merges = force_merge_siblings
+            .difference(&self.force_merge_siblings)
+            .count();
+        if new_force_merges > 0 {
+            debug!("{}: lost {} sibling block(s), forcing a merge", self, new_force_merges);
+        }
+        self.force_merges += new_force_merges as u64;
+        self.force_merge_siblings = force_merge_siblings;
+    }
+
+    /// Start relocating the candidates that have just been added to our section, unless they've
+    /// already been relocated to us, by voting to remove them and telling them where to go.
+    fn relocate_new_members(&mut self, blocks: &Blocks) -> Vec<Message> {
+        if !self.params.relocate_new_nodes {
+            return vec![];
+        }
+        let section = match self.our_current_blocks(blocks).first() {
+            Some(&section) => section,
+            None => return vec![],
+        };
+        // Relocations are complete once the nodes have been removed.
+        self.relocating.retain(|name, _| section.members.contains(name));
+
+        let added: Vec<Name> = self.candidates
+            .keys()
+            .filter(|name| {
+                section.members.contains(name) && !self.relocating.contains_key(name)
+            })
+            .cloned()
+            .collect();
+        let mut messages = vec![];
+        for name in added {
+            if self.relocated_in.remove(&name) {
+                continue;
+            }
+            let new_name = name.relocated();
+            debug!("{}: relocating {} to {}", self, name, new_name);
+            self.relocating.insert(name, new_name);
+            messages.push(Message {
+                sender: self.our_name,
+                recipient: name,
+                content: Arc::new(Relocate(name, new_name)),
+                trace_id: 0,
+            });
+        }
+
+        let candidates = &self.candidates;
+        let queued_candidates = &self.queued_candidates;
+        self.relocated_in.retain(|name| {
+            candidates.contains_key(name) ||
+                queued_candidates.iter().any(|&(queued, _)| queued == *name)
+        });
+        messages
+    }
+
+    /// Decide to relocate once a quorum of our section has told us to.
+    fn check_relocate_requests(&mut self, blocks: &Blocks) {
+        if self.relocate_to.is_some() || self.relocate_requests.is_empty() {
+            return;
+        }
+        // Our section may have agreed to remove us before we've seen a block with us in it.
+        let section = match self.our_current_section_blocks(blocks).first() {
+            Some(&section) => section,
+            None => return,
+        };
+        let requests = &self.relocate_requests & &section.members;
+        if section.get_id().is_quorum(blocks, &requests, &self.params) {
+            let new_name = self.our_name.relocated();
+            debug!("{}: relocating to {}", self, new_name);
+            self.relocate_to = Some(new_name);
+        }
+    }
+
+    /// Handle a node joining the network, either as a new node or relocated from another
+    /// section.
+    fn node_joined(&mut self, joining_node: Name, blocks: &Blocks, step: u64) -> Vec<Message> {
+        // Only handle one candidate for our section at a time, if configured to.
+        let for_our_section = self.our_current_blocks(blocks).first().is_some_and(
+            |section| section.prefix.matches(joining_node),
+        );
+        if self.params.candidate_queue_timeout.is_some() && for_our_section &&
+            self.has_pending_candidate(blocks, step)
+        {
+            debug!("{}: queueing candidate {}", self, joining_node);
+            self.queued_candidates.push_back((joining_node, step));
+            self.queued_joins += 1;
+            vec![]
+        } else {
+            self.add_candidate(joining_node, step)
+        }
+    }
+
+    /// Whether a candidate for our section is still proving its resources or awaiting approval.
+    fn has_pending_candidate(&self, blocks: &Blocks, step: u64) -> bool {
+        let section = match self.our_current_blocks(blocks).first() {
+            Some(&section) => section,
+            None => return false,
+        };
+        let now = self.local_step(step);
+        self.candidates.iter().any(|(&name, candidate)| {
+            section.prefix.matches(name) && candidate.is_recent(self.params.join_timeout, now) &&
+                !candidate.approved
+        })
+    }
+
+    /// Start handling the next queued candidate once there are no pending candidates for our
+    /// section, forgetting any that have waited longer than the queue timeout.
+    fn admit_queued_candidate(&mut self, blocks: &Blocks, step: u64) -> Vec<Message> {
+        let timeout = match self.params.candidate_queue_timeout {
+            Some(timeout) => timeout,
+            None => return vec![],
+        };
+        let now = self.local_step(step);
+        self.queued_candidates.retain(|&(_, step_queued)| step_queued + timeout >= now);
+        if self.has_pending_candidate(blocks, step) {
+            return vec![];
+        }
+        match self.queued_candidates.pop_front() {
+            Some((joining_node, _)) => {
+                debug!("{}: admitting queued candidate {}", self, joining_node);
+                self.add_candidate(joining_node, step)
+            }
+            None => vec![],
+        }
+    }
+
+    /// Record a newly joined node as a candidate, and send it the messages it needs to join.
+    fn add_candidate(&mut self, joining_node: Name, step: u64) -> Vec<Message> {
+        // Mark the peer as having joined so that we vote to keep adding it.
+        let approvals = self.early_approvals
+            .remove(&joining_node)
+            .map(|(_, approvals)| approvals)
+            .unwrap_or_default();
+        self.candidates.insert(
+            joining_node,
+            Candidate {
+                step_added: step,
+                proof_parts: 0,
+                approvals,
+                approved: false,
+            },
+        );
+        self.connections.insert(joining_node);
+        self.connect_requests.insert(joining_node);
+
+        let connect_msg = Message {
+            sender: self.our_name,
+            recipient: joining_node,
+            content: Arc::new(Connect),
+            trace_id: 0,
+        };
+
+        // Send a bootstrap message to the joining node.
+        let mut messages = vec![connect_msg, self.construct_bootstrap_msg(joining_node)];
+        if self.params.resource_proof_steps > 0 {
+            messages.push(Message {
+                sender: self.our_name,
+                recipient: joining_node,
+                content: Arc::new(ResourceProofChallenge),
+                trace_id: 0,
+            });
+        }
+        messages
+    }
+
+    /// Approve the candidates for our section that have proved their resources, sending our
+    /// approval to the rest of the section.
+    fn approve_candidates(&mut self, blocks: &Blocks, step: u64) -> Vec<Message> {
+        let our_name = self.our_name;
+        let section = match self.our_current_blocks(blocks).first() {
+            Some(&section) => section,
+            None => return vec![],
+        };
+        let now = self.local_step(step);
+        let mut messages = vec![];
+        for (&name, candidate) in &mut self.candidates {
+            if !section.prefix.matches(name) || candidate.approvals.contains(&our_name) ||
+                !candidate.is_recent(self.params.join_timeout, now) ||
+                !candidate.has_proved_resources(self.params.resource_proof_steps)
+            {
+                continue;
+            }
+            trace!("Node({}): approving candidate {}", our_name, name);
+            candidate.approvals.insert(our_name);
+            candidate.approved |= candidate.is_approved_by(blocks, section, &self.params);
+            // Every member shares the same content.
+            let content = Arc::new(ApproveCandidate(name, candidate.approvals.clone()));
+            messages.extend(section.members.iter().filter(|&&member| member != our_name).map(
+                |&member| {
+                    Message {
+                        sender: our_name,
+                        recipient: member,
+                        content: Arc::clone(&content),
+                        trace_id: 0,
+                    }
+                },
+            ));
+        }
+        messages
+    }
+
     fn bundle_base(&self, blocks: &Blocks, bundle: &[(Vote, BTreeSet<Name>)]) -> Vec<BlockId> {
         let mut block_ids = BTreeSet::new();
         for &(ref vote, _) in bundle {
@@ -722,91 +1620,286 @@ impl Node {
             .into_iter()
             .filter(|&b| {
                 !bundle.into_iter().any(|&(ref vote, ref voters)| {
-                    vote.to == b && vote.is_quorum(blocks, voters)
+                    vote.to == b && vote.is_quorum(blocks, voters, &self.params)
                 })
             })
             .collect()
     }
 
-    fn should_be_connected(&self, node: Name, blocks: &Blocks) -> bool {
-        let neighbours = nodes_in_any(blocks, &self.current_blocks);
-        neighbours.contains(&node)
+    fn should_be_connected(&self, node: Name, blocks: &Blocks, step: u64) -> bool {
+        // Stay connected to candidates while they prove their resources and wait for approval,
+        // and until they're added, so that we don't vote to drop them as soon as they join.
+        let joining = self.candidates.get(&node).is_some_and(|candidate| {
+            candidate.is_recent(self.params.join_timeout, self.local_step(step))
+        });
+        let refused = self.refused_peers(blocks);
+        let members = &nodes_in_any(blocks, &self.current_blocks) - &refused;
+        (joining || self.limit_connections(blocks, members).contains(&node)) &&
+            !refused.contains(&node)
+    }
+
+    /// The members of our current blocks that we stay connected to, out of `members`.
+    ///
+    /// With `max_connections`, we keep the members of our own section first, then those of the
+    /// sections whose prefixes share the longest prefix with our name, breaking ties by the
+    /// distance of their names from ours. Peers that refused to connect to us are passed over,
+    /// as they may rank us lower than we rank them.
+    fn limit_connections(&self, blocks: &Blocks, members: BTreeSet<Name>) -> BTreeSet<Name> {
+        let max_connections = match self.params.max_connections {
+            Some(max_connections) => max_connections,
+            None => return members,
+        };
+        let our_name = self.our_name;
+        let mut closeness: BTreeMap<Name, usize> = BTreeMap::new();
+        for block in blocks.block_contents(&self.current_blocks) {
+            let block_closeness = if block.prefix.matches(our_name) {
+                usize::MAX
+            } else {
+                block.prefix.common_prefix(our_name)
+            };
+            for name in block.members.iter().filter(|name| members.contains(name)) {
+                let entry = closeness.entry(*name).or_insert(0);
+                *entry = cmp::max(*entry, block_closeness);
+            }
+        }
+        let mut ranked: Vec<(Name, usize)> = closeness
+            .into_iter()
+            .filter(|&(name, _)| {
+                name != our_name &&
+                    self.refused_connections.get(&name) != Some(&self.current_blocks)
+            })
+            .collect();
+        ranked.sort_by(|&(lhs, lhs_closeness), &(rhs, rhs_closeness)| {
+            rhs_closeness.cmp(&lhs_closeness).then_with(
+                || our_name.cmp_distance(lhs, rhs),
+            )
+        });
+        ranked
+            .into_iter()
+            .take(max_connections)
+            .map(|(name, _)| name)
+            .chain(members.get(&our_name).cloned())
+            .collect()
+    }
+
+    /// Members of our section that we refuse to connect to, if we're malicious.
+    ///
+    /// With `PartialConnections`, these are the first half of the other members by name, rounded
+    /// up, so that the members we do connect to can never make a quorum. With `Eclipse`, they're
+    /// all the other members that aren't our allies.
+    fn refused_peers(&self, blocks: &Blocks) -> BTreeSet<Name> {
+        if self.behaviour != CandidateBehaviour::PartialConnections &&
+            self.behaviour != CandidateBehaviour::Eclipse
+        {
+            return BTreeSet::new();
+        }
+        let others: Vec<Name> = match self.our_current_blocks(blocks).first() {
+            Some(section) => {
+                section
+                    .members
+                    .iter()
+                    .filter(|&&name| name != self.our_name)
+                    .cloned()
+                    .collect()
+            }
+            None => return BTreeSet::new(),
+        };
+        if self.behaviour == CandidateBehaviour::Eclipse {
+            return others.into_iter().filter(|name| !self.allies.contains(name)).collect();
+        }
+        let num_refused = others.len() - others.len() / 2;
+        others.into_iter().take(num_refused).collect()
     }
 
     /// Handle a message intended for us and return messages we'd like to send.
+    ///
+    /// The messages we send belong to the same trace as the message we're handling.
     pub fn handle_message(&mut self, message: Message, blocks: &Blocks, step: u64) -> Vec<Message> {
-        let to_send = match message.content {
+        trace!(
+            "{}: handling {} from {} in trace {}",
+            self,
+            message.content.kind(),
+            message.sender,
+            message.trace_id
+        );
+        if self.behaviour == CandidateBehaviour::Silent {
+            return vec![];
+        }
+        let mut to_send = match *message.content {
             NodeJoined => {
                 let joining_node = message.sender;
                 debug!("{}: received join message for: {}", self, joining_node);
-
-                // Mark the peer as having joined so that we vote to keep adding it.
-                self.candidates.insert(
-                    joining_node,
-                    Candidate { step_added: step },
+                self.node_joined(joining_node, blocks, step)
+            }
+            NodeRelocated(old_name) => {
+                let joining_node = message.sender;
+                if old_name.relocated() == joining_node {
+                    debug!("{}: {} relocated as {}", self, old_name, joining_node);
+                    self.relocated_in.insert(joining_node);
+                    self.node_joined(joining_node, blocks, step)
+                } else {
+                    debug!("{}: {} isn't a relocated name for {}", self, joining_node, old_name);
+                    vec![]
+                }
+            }
+            Relocate(node, new_name) => {
+                // We may not know that we've been added yet, so the requests are only counted
+                // once we do.
+                if node == self.our_name && new_name == self.our_name.relocated() {
+                    self.relocate_requests.insert(message.sender);
+                }
+                vec![]
+            }
+            ApproveCandidate(candidate, ref approvers) => {
+                trace!(
+                    "{}: {} approves candidate {} along with {:?}",
+                    self,
+                    message.sender,
+                    candidate,
+                    approvers
                 );
-                self.connections.insert(joining_node);
-                self.connect_requests.insert(joining_node);
-
-                let connect_msg = Message {
-                    sender: self.our_name,
-                    recipient: joining_node,
-                    content: Connect,
+                // Another member has chosen to handle this candidate, so stop holding it back in
+                // our queue, otherwise the section could never agree to add it.
+                let mut messages = vec![];
+                if let Some(index) = self.queued_candidates.iter().position(
+                    |&(name, _)| name == candidate,
+                )
+                {
+                    self.queued_candidates.remove(index);
+                    debug!("{}: admitting queued candidate {}", self, candidate);
+                    messages = self.add_candidate(candidate, step);
+                }
+                let section = match self.our_current_blocks(blocks).first() {
+                    Some(&section) => section,
+                    None => return messages,
                 };
-
-                // Send a bootstrap message to the joining node.
-                vec![connect_msg, self.construct_bootstrap_msg(joining_node)]
+                if let Some(entry) = self.candidates.get_mut(&candidate) {
+                    entry.approvals.extend(approvers.iter().cloned());
+                    if !entry.approved && entry.is_approved_by(blocks, section, &self.params) {
+                        debug!("Node({}): {} approved by our section", self.our_name, candidate);
+                        entry.approved = true;
+                    }
+                } else if section.prefix.matches(candidate) &&
+                           !section.members.contains(&candidate)
+                {
+                    // We haven't heard that the candidate joined yet, so keep the approvals until
+                    // we do.
+                    let now = self.local_step(step);
+                    self.early_approvals
+                        .entry(candidate)
+                        .or_insert_with(|| (now, BTreeSet::new()))
+                        .1
+                        .extend(approvers.iter().cloned());
+                }
+                messages
+            }
+            ResourceProofChallenge if self.behaviour == CandidateBehaviour::NoResourceProof => {
+                debug!("{}: ignoring the resource proof challenge from {}", self, message.sender);
+                vec![]
+            }
+            ResourceProofChallenge => {
+                debug!(
+                    "{}: challenged to prove our resources by {}",
+                    self,
+                    message.sender
+                );
+                self.proof_challengers.entry(message.sender).or_insert(0);
+                vec![]
+            }
+            ResourceProofResponse(part) => {
+                trace!(
+                    "{}: received part {} of the resource proof of {}",
+                    self,
+                    part,
+                    message.sender
+                );
+                if let Some(candidate) = self.candidates.get_mut(&message.sender) {
+                    candidate.proof_parts = cmp::max(candidate.proof_parts, part);
+                    if candidate.proof_parts == self.params.resource_proof_steps {
+                        debug!("{}: {} has proved its resources", self, message.sender);
+                    }
+                }
+                vec![]
             }
-            VoteMsg(vote) => {
+            VoteMsg(ref vote) => {
                 trace!(
                     "{}: received {:?} from {}",
                     self,
                     vote.as_debug(blocks),
                     message.sender
                 );
-                let messages = self.request_proof(blocks, vote.from, message.sender);
-                self.add_vote(vote, Some(message.sender));
-                messages
+                self.add_vote(vote.clone(), Some(message.sender));
+                let agreed = self.has_agreed_vote_from(blocks, vote.from);
+                self.request_proof(blocks, vote.from, message.sender, agreed)
             }
-            VoteAgreedMsg((vote, voters)) => {
+            VoteAgreedMsg((ref vote, ref voters)) |
+            VoteAggregatedMsg((ref vote, AggregateSignature { signers: ref voters, .. })) => {
                 trace!(
                     "{}: received agreement msg for {:?} from {}",
                     self,
                     vote.as_debug(blocks),
                     message.sender
                 );
-                let messages = self.request_proof(blocks, vote.from, message.sender);
-                self.add_vote(vote, voters);
+                let messages = self.request_proof(blocks, vote.from, message.sender, true);
+                self.add_vote(vote.clone(), voters.iter().cloned());
                 messages
             }
-            VoteBundle(bundle) => {
+            VoteBundle(ref bundle) => {
                 trace!("{}: received a vote bundle from {}", self, message.sender);
+                for (vote, voters) in bundle {
+                    self.add_vote(vote.clone(), voters.iter().cloned());
+                }
                 let mut messages = Vec::new();
-                for block in self.bundle_base(blocks, &bundle) {
-                    messages.extend(self.request_proof(blocks, block, message.sender));
+                for block in self.bundle_base(blocks, bundle) {
+                    let agreed = self.has_agreed_vote_from(blocks, block);
+                    messages.extend(self.request_proof(blocks, block, message.sender, agreed));
                 }
-                for (vote, voters) in bundle {
-                    self.add_vote(vote, voters);
+                let proved: BTreeSet<BlockId> = bundle.iter().map(|(vote, _)| vote.to).collect();
+                for block in proved {
+                    messages.extend(self.retry_chain_segments(blocks, block));
                 }
                 messages
             }
-            BootstrapMsg(vote_counts) => {
+            BootstrapMsg(ref votes) => {
                 debug!(
                     "{}: applying bootstrap message from {}",
                     self,
                     message.sender
                 );
-                self.apply_bootstrap_msg(vote_counts);
-                vec![]
+                self.apply_bootstrap_msg(blocks, votes)
             }
             Disconnect => {
                 debug!("{}: lost our connection to {}", self, message.sender);
                 self.connections.remove(&message.sender);
                 self.connect_requests.remove(&message.sender);
+                if self.params.max_connections.is_some() {
+                    self.refused_connections.insert(message.sender, self.current_blocks.clone());
+                }
+                self.clients.remove(&message.sender);
                 vec![]
             }
+            ClientConnect => {
+                debug!("{}: {} connected as a client", self, message.sender);
+                self.clients.insert(message.sender);
+                // Bring the client up to date straight away, if we know our section.
+                match self.our_current_blocks(blocks).first() {
+                    Some(section) => {
+                        vec![
+                            Message {
+                                sender: self.our_name,
+                                recipient: message.sender,
+                                content: Arc::new(BlockUpdate(section.get_id())),
+                                trace_id: 0,
+                            },
+                        ]
+                    }
+                    None => vec![],
+                }
+            }
+            // Only clients are sent block updates.
+            BlockUpdate(_) => vec![],
             Connect => {
-                if self.should_be_connected(message.sender, blocks) {
+                if self.should_be_connected(message.sender, blocks, step) {
                     if self.connections.insert(message.sender) {
                         debug!("{}: obtained a connection to {}", self, message.sender);
                     }
@@ -817,7 +1910,8 @@ impl Node {
                             Message {
                                 sender: self.our_name,
                                 recipient: message.sender,
-                                content: MessageContent::Connect,
+                                content: Arc::new(MessageContent::Connect),
+                                trace_id: 0,
                             },
                         ]
                     } else {
@@ -831,18 +1925,19 @@ impl Node {
                         Message {
                             sender: self.our_name,
                             recipient: message.sender,
-                            content: Disconnect,
+                            content: Arc::new(Disconnect),
+                            trace_id: 0,
                         }
                     ]
                 }
             }
-            RequestProof(block, current_blocks) => {
+            RequestProof(block, ref current_blocks) => {
                 trace!(
                     "{}: received a request for proof from {} for block {:?} with current blocks {:?}",
                     self,
                     message.sender,
                     block.into_block(blocks),
-                    blocks.block_contents(&current_blocks)
+                    blocks.block_contents(current_blocks)
                 );
                 vec![
                     self.construct_proof(blocks, block, current_blocks, message.sender),
@@ -855,11 +1950,19 @@ impl Node {
                     message.sender,
                     block.into_block(blocks)
                 );
-                vec![]
+                self.abandon_chain_segments(blocks, block);
+                if self.params.history_depth.is_some() {
+                    self.request_proof_elsewhere(blocks, block, message.sender)
+                } else {
+                    vec![]
+                }
             }
         };
 
-        self.filter_messages(to_send)
+        for response in &mut to_send {
+            response.trace_id = message.trace_id;
+        }
+        self.filter_messages(to_send, step)
     }
 
     pub fn as_debug<'a, 'b>(&'a self, blocks: &'b Blocks) -> DebugNode<'b, 'a> {
@@ -881,9 +1984,145 @@ impl<'a, 'b> fmt::Debug for DebugNode<'a, 'b> {
             self.node.our_name,
             self.node.valid_blocks.len(),
             self.node.vote_counts.len(),
-            self.node.vote_counts.values().map(BTreeMap::len).max(),
+            self.node.vote_counts.values().map(FxHashMap::len).max(),
             self.node.current_blocks.len(),
             self.blocks.block_contents(&self.node.current_blocks)
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use params::QuorumWeight;
+
+    /// A section's history from genesis, with a member added in each block, and the votes for
+    /// each block after the first.
+    fn section_history(blocks: &mut Blocks) -> (Vec<BlockId>, Vec<(Vote, BTreeSet<Name>)>) {
+        let mut block = Block::genesis(Name(0));
+        let mut ids = vec![blocks.insert(block.clone())];
+        let mut votes = vec![];
+        for i in 1..4 {
+            let next = block.add_node(Name(i));
+            let to = blocks.insert(next.clone());
+            votes.push((Vote { from: ids[ids.len() - 1], to }, (*block.members).clone()));
+            ids.push(to);
+            block = next;
+        }
+        (ids, votes)
+    }
+
+    fn message(sender: Name, content: MessageContent) -> Message {
+        Message {
+            sender,
+            recipient: Name(4),
+            content: Arc::new(content),
+            trace_id: 0,
+        }
+    }
+
+    // A joining node whose bootstrap message is missing the first vote of its section's history
+    // truncates the history there, unless it's recovering gaps, in which case it asks a member of
+    // the oldest block it reached to prove it and completes the history from the proof.
+    #[test]
+    fn bootstrap_gaps() {
+        let mut blocks = Blocks::new();
+        let (ids, votes) = section_history(&mut blocks);
+        let bootstrap = BootstrapMsg(Arc::new(votes[1..].to_vec()));
+
+        let mut node = Node::new(Name(4), &blocks, btreeset!{ ids[0] }, NodeParams::default(), 0);
+        assert!(node.handle_message(message(Name(0), bootstrap.clone()), &blocks, 0).is_empty());
+        assert_eq!(node.truncated_segments, 1);
+
+        let params = NodeParams {
+            recover_chain_gaps: true,
+            ..NodeParams::default()
+        };
+        let mut node = Node::new(Name(4), &blocks, btreeset!{ ids[0] }, params.clone(), 0);
+        let requests = node.handle_message(message(Name(0), bootstrap.clone()), &blocks, 0);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].recipient, Name(0));
+        assert_eq!(*requests[0].content, RequestProof(ids[1], btreeset!{ ids[0] }));
+        assert_eq!(node.pending_segments, btreemap!{ ids[3] => ids[1] });
+
+        let proof = VoteBundle(votes[..1].to_vec());
+        node.handle_message(message(Name(0), proof), &blocks, 1);
+        assert!(node.pending_segments.is_empty());
+        assert_eq!(node.truncated_segments, 0);
+        assert_eq!(node.chain_segment(&blocks, ids[3]), Ok(votes.iter().cloned().collect()));
+
+        // A member that can't prove the block leaves the history truncated.
+        let mut node = Node::new(Name(4), &blocks, btreeset!{ ids[0] }, params, 0);
+        node.handle_message(message(Name(0), bootstrap), &blocks, 0);
+        node.handle_message(message(Name(0), NoProof(ids[1])), &blocks, 1);
+        assert!(node.pending_segments.is_empty());
+        assert_eq!(node.truncated_segments, 1);
+    }
+
+    // A node that hasn't joined gives up once its self-shutdown timeout expires, which is that
+    // many steps early when its clock is ahead.
+    #[test]
+    fn skew_shortens_timeouts() {
+        let blocks = Blocks::new();
+        let params = NodeParams::default();
+        let timeout = params.self_shutdown_timeout;
+        let mut node = Node::new(Name(0), &blocks, BTreeSet::new(), params, 10);
+        assert!(!node.should_shutdown(&blocks, 10 + timeout - 5));
+        assert!(node.should_shutdown(&blocks, 10 + timeout));
+
+        node.clock_skew = 5;
+        assert!(node.should_shutdown(&blocks, 10 + timeout - 5));
+        assert!(!node.should_shutdown(&blocks, 10 + timeout - 6));
+        assert_eq!(node.next_timeout(10), Some(10 + timeout - 5));
+
+        node.clock_skew = -5;
+        assert!(!node.should_shutdown(&blocks, 10 + timeout));
+        assert!(node.should_shutdown(&blocks, 10 + timeout + 5));
+    }
+
+    // Candidate approvals and relocation requests are weighed like votes, so a section where the
+    // newcomers outnumber the elders needs the elders' approval when quorums are weighed by age.
+    #[test]
+    fn age_weighted_approvals() {
+        let mut blocks = Blocks::new();
+        let elders: BTreeSet<Name> = (0..4).map(Name).collect();
+        let mut block = blocks.insert(Block {
+            prefix: Prefix::empty(),
+            version: 0,
+            members: Arc::new(elders.clone()),
+        });
+        let newcomers: BTreeSet<Name> = (4..9).map(Name).collect();
+        for &name in &newcomers {
+            let from = block;
+            block = blocks.derive(from, Derivation::Add(name));
+            blocks.record_agreement(&Vote { from, to: block });
+        }
+        let section = block.into_block(&blocks);
+        let by_age = NodeParams {
+            quorum_weight: QuorumWeight::Age,
+            ..NodeParams::default()
+        };
+
+        let candidate = |approvals: &BTreeSet<Name>| Candidate {
+            step_added: 0,
+            proof_parts: 0,
+            approvals: approvals.clone(),
+            approved: false,
+        };
+        let params = NodeParams::default();
+        assert!(candidate(&newcomers).is_approved_by(&blocks, section, &params));
+        assert!(!candidate(&newcomers).is_approved_by(&blocks, section, &by_age));
+        assert!(!candidate(&elders).is_approved_by(&blocks, section, &params));
+        assert!(candidate(&elders).is_approved_by(&blocks, section, &by_age));
+
+        let relocated = |params: &NodeParams, requests: &BTreeSet<Name>| {
+            let mut node = Node::new(Name(4), &blocks, btreeset!{ block }, params.clone(), 0);
+            node.relocate_requests = requests.clone();
+            node.check_relocate_requests(&blocks);
+            node.relocate_to.is_some()
+        };
+        assert!(relocated(&params, &newcomers));
+        assert!(!relocated(&by_age, &newcomers));
+        assert!(relocated(&by_age, &elders));
+    }
+}
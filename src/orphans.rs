@@ -0,0 +1,199 @@
+//! Orphan votes: votes that were cast but never reached quorum by the end of a run.
+//!
+//! Some are expected, like votes for blocks that a section moved past before they could be
+//! agreed, but orphans that persist usually point to a bug in the protocol or in how the
+//! recipients of votes are chosen. Each orphan is given the most likely reason it wasn't agreed.
+
+use block::{Block, Vote};
+use blocks::Blocks;
+use name::{Name, Prefix};
+use node::Node;
+use params::NodeParams;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Why a vote is likely to have never reached quorum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OrphanReason {
+    /// The sections agreed on a block at least as new as the one voted for, for a compatible
+    /// prefix.
+    Superseded,
+    /// Too few of the members whose votes count towards its quorum are still in the network.
+    VotersDisconnected,
+    /// Enough members are still in the network, but too few of them voted for it.
+    InsufficientSupport,
+}
+
+impl fmt::Display for OrphanReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match *self {
+            OrphanReason::Superseded => "superseded by a newer block",
+            OrphanReason::VotersDisconnected => "voters disconnected",
+            OrphanReason::InsufficientSupport => "insufficient support",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// A vote that never reached quorum.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OrphanVote {
+    pub vote: Vote,
+    /// Prefix of the section that cast it (the prefix of its `from` block).
+    pub prefix: Prefix,
+    /// Step it was first cast.
+    pub cast_step: u64,
+    /// Largest number of voters for it that any live node knows of.
+    pub support: usize,
+    pub reason: OrphanReason,
+}
+
+/// Find the reasons that the given votes, cast at the given steps, never reached quorum, given
+/// the live nodes and the sections they agree on at the end of the run.
+///
+/// Votes that a live node has seen a quorum for are skipped: they weren't reported as agreed
+/// because the block they were for was already valid, e.g. when witnessing it.
+pub fn orphan_votes<'a, I>(
+    unagreed: I,
+    nodes: &BTreeMap<Name, Node>,
+    sections: &BTreeMap<Prefix, Block>,
+    blocks: &Blocks,
+    params: &NodeParams,
+) -> Vec<OrphanVote>
+where
+    I: IntoIterator<Item = (&'a Vote, u64)>,
+{
+    unagreed
+        .into_iter()
+        .filter_map(|(vote, cast_step)| {
+            let from = vote.from.into_block(blocks);
+            let to = vote.to.into_block(blocks);
+            let voters: Vec<_> = nodes
+                .values()
+                .filter_map(|node| node.vote_counts.get(&vote.from)?.get(&vote.to))
+                .collect();
+            if voters.iter().any(|voters| vote.is_quorum(blocks, voters, params)) {
+                return None;
+            }
+            let support = voters.iter().map(|voters| voters.len()).max().unwrap_or(0);
+            let remaining: BTreeSet<Name> = vote.quorum_members(blocks)
+                .iter()
+                .cloned()
+                .filter(|name| nodes.contains_key(name))
+                .collect();
+
+            let superseded = sections.values().any(|section| {
+                section.prefix.is_compatible(&to.prefix) && section.version >= to.version &&
+                    section != to
+            });
+            let reason = if superseded {
+                OrphanReason::Superseded
+            } else if !vote.is_quorum(blocks, &remaining, params) {
+                OrphanReason::VotersDisconnected
+            } else {
+                OrphanReason::InsufficientSupport
+            };
+            Some(OrphanVote {
+                vote: vote.clone(),
+                prefix: from.prefix,
+                cast_step,
+                support,
+                reason,
+            })
+        })
+        .collect()
+}
+
+/// Number of orphan votes for each prefix and reason.
+pub fn group(orphans: &[OrphanVote]) -> BTreeMap<(Prefix, OrphanReason), usize> {
+    let mut groups = BTreeMap::new();
+    for orphan in orphans {
+        *groups.entry((orphan.prefix, orphan.reason)).or_insert(0) += 1;
+    }
+    groups
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use name::NameBits;
+    use std::sync::Arc;
+
+    fn block(prefix: Prefix, version: u64, members: &[NameBits]) -> Block {
+        Block {
+            prefix,
+            version,
+            members: Arc::new(members.iter().cloned().map(Name).collect()),
+        }
+    }
+
+    fn vote(blocks: &mut Blocks, from: &Block, to: &Block) -> Vote {
+        Vote {
+            from: blocks.insert(from.clone()),
+            to: blocks.insert(to.clone()),
+        }
+    }
+
+    #[test]
+    fn reasons() {
+        let params = NodeParams::default();
+        let mut blocks = Blocks::new();
+        let b0 = block(Prefix::empty(), 0, &[1, 2, 3]);
+        let b1 = block(Prefix::empty(), 1, &[1, 2, 3, 4]);
+        let b1_other = block(Prefix::empty(), 1, &[1, 2, 3, 5]);
+        let b2 = block(Prefix::empty(), 2, &[1, 2, 3, 4, 6]);
+        let superseded = vote(&mut blocks, &b0, &b1_other);
+        let to_b1 = vote(&mut blocks, &b0, &b1);
+        let to_b2 = vote(&mut blocks, &b1, &b2);
+
+        let mut nodes: BTreeMap<Name, Node> = (1..4)
+            .map(|i| {
+                let current_blocks = btreeset!{ to_b1.from };
+                (Name(i), Node::new(Name(i), &blocks, current_blocks, params.clone(), 0))
+            })
+            .collect();
+        nodes
+            .get_mut(&Name(1))
+            .unwrap()
+            .vote_counts
+            .entry(to_b1.from)
+            .or_default()
+            .insert(to_b1.to, btreeset!{ Name(1) });
+        let sections = btreemap!{ Prefix::empty() => b1.clone() };
+        let quorum = vote(&mut blocks, &b0, &b2);
+        nodes
+            .get_mut(&Name(2))
+            .unwrap()
+            .vote_counts
+            .entry(quorum.from)
+            .or_default()
+            .insert(quorum.to, btreeset!{ Name(1), Name(2) });
+
+        let unagreed = vec![(&superseded, 5), (&to_b1, 6), (&to_b2, 7), (&quorum, 8)];
+        let orphans = orphan_votes(unagreed.clone(), &nodes, &sections, &blocks, &params);
+        let reasons: Vec<_> = orphans.iter().map(|orphan| orphan.reason).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                OrphanReason::Superseded,
+                OrphanReason::InsufficientSupport,
+                OrphanReason::InsufficientSupport,
+            ]
+        );
+        assert_eq!(orphans[1].support, 1);
+        assert_eq!(orphans[1].cast_step, 6);
+
+        // Two of b1's four members are left, which isn't a quorum, but two of b0's three are.
+        nodes.remove(&Name(3));
+        let orphans = orphan_votes(unagreed, &nodes, &sections, &blocks, &params);
+        assert_eq!(
+            group(&orphans),
+            btreemap!{
+                (Prefix::empty(), OrphanReason::Superseded) => 1,
+                (Prefix::empty(), OrphanReason::VotersDisconnected) => 1,
+                (Prefix::empty(), OrphanReason::InsufficientSupport) => 1,
+            }
+        );
+    }
+}
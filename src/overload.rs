@@ -0,0 +1,255 @@
+//! Overload-induced crashes: the more messages a node has handled recently, the more likely it is
+//! to fail (see the `overload_drop_prob` parameter).
+//!
+//! Load isn't spread evenly, so neither are these failures. Sections that were just formed by a
+//! merge have more members and more to agree on than their neighbours, and the question is whether
+//! their members fail together. Crashes in the same section, each within `overload_window` steps
+//! of the last, are grouped into correlated failures for the report at the end of the run.
+
+use block::Block;
+use blocks::Blocks;
+use name::{Name, Prefix, PrefixSet};
+use node::Node;
+use random::do_with_probability;
+
+use std::collections::{BTreeMap, VecDeque};
+
+/// A node crashing from overload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OverloadCrash {
+    pub step: u64,
+    pub node: Name,
+    /// Prefix of the node's section.
+    pub prefix: Prefix,
+    /// Number of messages the node handled within the window.
+    pub load: usize,
+    /// Whether the node's section was formed by a merge, and hasn't split since.
+    pub after_merge: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Overload {
+    /// Probability of crashing on a step, for each message handled within the window.
+    drop_prob: f64,
+    /// Number of recent steps whose messages count towards a node's load.
+    window: u64,
+    /// Number of messages each node handled on each recent step that it handled any.
+    handled: BTreeMap<Name, VecDeque<(u64, usize)>>,
+    /// Prefixes of the agreed sections at the end of the last step.
+    prefixes: PrefixSet,
+    /// Agreed sections that were formed by a merge, and haven't split since.
+    merged: PrefixSet,
+    crashes: Vec<OverloadCrash>,
+}
+
+impl Overload {
+    pub fn new(drop_prob: f64, window: u64) -> Self {
+        Overload {
+            drop_prob,
+            window,
+            handled: BTreeMap::new(),
+            prefixes: PrefixSet::new(),
+            merged: PrefixSet::new(),
+            crashes: vec![],
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.drop_prob > 0.0
+    }
+
+    /// Record that `node` handled `count` messages at `step`.
+    pub fn handled(&mut self, node: Name, step: u64, count: usize) {
+        if count > 0 {
+            self.handled.entry(node).or_default().push_back((step, count));
+        }
+    }
+
+    /// Number of messages `node` handled within the window ending at `step`.
+    pub fn load(&self, node: Name, step: u64) -> usize {
+        let oldest = step.saturating_sub(self.window);
+        self.handled.get(&node).map_or(0, |handled| {
+            handled
+                .iter()
+                .filter(|&&(handled_step, _)| handled_step >= oldest)
+                .map(|&(_, count)| count)
+                .sum()
+        })
+    }
+
+    /// Whether any node has handled messages within the window ending at `step`, and so could
+    /// crash on it.
+    pub fn is_loaded(&self, step: u64) -> bool {
+        let oldest = step.saturating_sub(self.window);
+        self.handled.values().any(|handled| {
+            handled.back().is_some_and(|&(handled_step, _)| handled_step >= oldest)
+        })
+    }
+
+    /// Note which of the sections agreed at the end of a step were formed by merges: those
+    /// covering a section agreed at the end of the previous step.
+    pub fn update_sections(&mut self, sections: &BTreeMap<Prefix, Block>) {
+        let mut prefixes = PrefixSet::new();
+        for &prefix in sections.keys() {
+            let is_merge = !self.prefixes.contains(&prefix) &&
+                self.prefixes.iter().any(|old| {
+                    prefix.is_prefix_of(old) && prefix != *old
+                });
+            if is_merge {
+                self.merged.insert(prefix);
+            }
+            prefixes.insert(prefix);
+        }
+        let split: Vec<Prefix> = self.merged
+            .iter()
+            .filter(|prefix| !prefixes.contains(prefix))
+            .cloned()
+            .collect();
+        for prefix in split {
+            self.merged.remove(&prefix);
+        }
+        self.prefixes = prefixes;
+    }
+
+    /// Choose the section members that crash at `step`, each with probability `drop_prob` times
+    /// its load.
+    pub fn crash_nodes(
+        &mut self,
+        step: u64,
+        nodes: &BTreeMap<Name, Node>,
+        blocks: &Blocks,
+    ) -> Vec<OverloadCrash> {
+        let oldest = step.saturating_sub(self.window);
+        self.handled.retain(|name, handled| {
+            while handled.front().is_some_and(|&(handled_step, _)| handled_step < oldest) {
+                handled.pop_front();
+            }
+            !handled.is_empty() && nodes.contains_key(name)
+        });
+
+        let mut crashes = vec![];
+        for (&name, node) in nodes {
+            let load = self.load(name, step);
+            if load == 0 || !do_with_probability((self.drop_prob * load as f64).min(1.0)) {
+                continue;
+            }
+            // Nodes that haven't joined a section yet have nothing much to be overloaded by.
+            let prefix = match node.our_current_blocks(blocks).first() {
+                Some(block) if block.members.contains(&name) => block.prefix,
+                _ => continue,
+            };
+            crashes.push(OverloadCrash {
+                step,
+                node: name,
+                prefix,
+                load,
+                after_merge: self.merged.contains(&prefix),
+            });
+        }
+        self.crashes.extend(crashes.iter().cloned());
+        crashes
+    }
+
+    pub fn crashes(&self) -> &[OverloadCrash] {
+        &self.crashes
+    }
+
+    /// Groups of two or more crashes in the same section, each within the window of the last.
+    pub fn correlated_failures(&self) -> Vec<Vec<OverloadCrash>> {
+        let mut by_section: BTreeMap<Prefix, Vec<OverloadCrash>> = BTreeMap::new();
+        for crash in &self.crashes {
+            by_section.entry(crash.prefix).or_default().push(*crash);
+        }
+        let mut groups = vec![];
+        for crashes in by_section.into_values() {
+            let mut group: Vec<OverloadCrash> = vec![];
+            for crash in crashes {
+                if group.last().is_some_and(|last| crash.step > last.step + self.window) {
+                    groups.push(group);
+                    group = vec![];
+                }
+                group.push(crash);
+            }
+            groups.push(group);
+        }
+        groups.retain(|group| group.len() >= 2);
+        groups
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::p;
+    use std::sync::Arc;
+
+    fn sections(prefixes: &[&str]) -> BTreeMap<Prefix, Block> {
+        let mut sections = BTreeMap::new();
+        for prefix in prefixes {
+            let block = Block {
+                prefix: p(prefix),
+                version: 0,
+                members: Arc::new(btreeset!{ Name(1) }),
+            };
+            sections.insert(p(prefix), block);
+        }
+        sections
+    }
+
+    fn crash(step: u64, prefix: &str) -> OverloadCrash {
+        OverloadCrash {
+            step,
+            node: Name(step.into()),
+            prefix: p(prefix),
+            load: 10,
+            after_merge: false,
+        }
+    }
+
+    #[test]
+    fn load_within_window() {
+        let mut overload = Overload::new(0.01, 5);
+        overload.handled(Name(1), 1, 10);
+        overload.handled(Name(1), 4, 3);
+        overload.handled(Name(1), 6, 0);
+        overload.handled(Name(2), 6, 7);
+        assert_eq!(overload.load(Name(1), 6), 13);
+        assert_eq!(overload.load(Name(1), 7), 3);
+        assert_eq!(overload.load(Name(2), 7), 7);
+        assert_eq!(overload.load(Name(3), 7), 0);
+        assert!(overload.is_loaded(11));
+        assert!(!overload.is_loaded(12));
+    }
+
+    #[test]
+    fn merged_sections() {
+        let mut overload = Overload::new(0.01, 5);
+        overload.update_sections(&sections(&["00", "01", "1"]));
+        assert!(overload.merged.is_empty());
+
+        overload.update_sections(&sections(&["0", "1"]));
+        assert!(overload.merged.contains(&p("0")));
+        assert!(!overload.merged.contains(&p("1")));
+
+        // Still merged until it splits again.
+        overload.update_sections(&sections(&["0", "1"]));
+        assert!(overload.merged.contains(&p("0")));
+        overload.update_sections(&sections(&["00", "01", "1"]));
+        assert!(overload.merged.is_empty());
+    }
+
+    #[test]
+    fn correlated_failures() {
+        let mut overload = Overload::new(0.01, 5);
+        overload.crashes = vec![
+            crash(10, "0"),
+            crash(12, "1"),
+            crash(14, "0"),
+            crash(19, "0"),
+            crash(30, "0"),
+            crash(40, "1"),
+        ];
+        let groups = overload.correlated_failures();
+        assert_eq!(groups, vec![vec![crash(10, "0"), crash(14, "0"), crash(19, "0")]]);
+    }
+}
@@ -1,33 +1,288 @@
+use name::Prefix;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
 use simulation::Phase;
 use simulation::Phase::*;
+use std::cmp;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SimulationParams {
     /// Maximum number of steps a message can be delayed by before it's delivered.
     pub max_delay: u64,
+    /// Shape of the distribution of message delays, up to `max_delay`.
+    pub delay_distribution: DelayDistribution,
+    /// Fraction of messages that the geometric delay distribution delivers before `max_delay`.
+    /// The rest are all delivered when they reach the deadline. Must be strictly between 0 and 1.
+    #[serde(deserialize_with = "deserialize_percentile")]
+    pub delivery_percentile: f64,
+    /// Probability on each step that a connection delivers the second burst of messages queued
+    /// on it (those sent on the same step) ahead of the first, which is otherwise always
+    /// delivered first.
+    #[serde(default)]
+    pub burst_reorder_prob: f64,
     /// Probability of a node joining on a given step during the network growth phase.
     pub grow_prob_join: f64,
     /// Probability of a node leaving on a given step during the network growth phase.
     pub grow_prob_drop: f64,
     /// Probability of a node joining or leaving on a given step.
     pub prob_churn: f64,
+    /// Keep the network at the size it had when the stable phase began, by making joins more
+    /// likely than drops while it's smaller and less likely while it's larger.
+    pub steady_state: bool,
     /// Probability of a node joining on a given step during the network shrinking phase.
     pub shrink_prob_join: f64,
     /// Probability of a node leaving on a given step during the network shrinking phase.
     pub shrink_prob_drop: f64,
-    /// Probability that a two-way connection will be lost on any given step.
+    /// Extra probability of a node leaving on a given step for each join in the last
+    /// `churn_memory` steps, and of a node joining for each recent drop. Models flapping nodes.
+    /// Keep `churn_correlation * churn_memory` well below 1, or the churn feeds itself.
+    pub churn_correlation: f64,
+    /// Number of steps that a join or drop affects the probability of the opposite event for.
+    pub churn_memory: u64,
+    /// Maximum number of nodes, beyond which no random joins happen (`none` for no limit).
+    pub max_network_size: Option<usize>,
+    /// How the names of generated and randomly joining nodes are chosen.
+    pub name_distribution: NameDistribution,
+    /// Probability that a two-way connection will be lost on a given step during the network
+    /// growth phase.
+    pub grow_prob_disconnect: f64,
+    /// Probability that a lost two-way connection will be re-established on a given step during
+    /// the network growth phase.
+    pub grow_prob_reconnect: f64,
+    /// Probability that a two-way connection will be lost on a given step.
     pub prob_disconnect: f64,
-    /// Probability that a lost two-way connection will be re-established on any given step.
+    /// Probability that a lost two-way connection will be re-established on a given step.
     pub prob_reconnect: f64,
+    /// Probability that a two-way connection will be lost on a given step during the network
+    /// shrinking phase.
+    pub shrink_prob_disconnect: f64,
+    /// Probability that a lost two-way connection will be re-established on a given step during
+    /// the network shrinking phase.
+    pub shrink_prob_reconnect: f64,
     /// Network starting phase is complete once the size of network reaches this value.
     pub starting_complete: usize,
     /// Network growth phase is complete once the size of network reaches this value.
     pub grow_complete: usize,
     /// Network stable phase is run for this number of steps.
     pub stable_steps: u64,
+    /// File to write logs to rather than stdout (`none` for stdout). `{seed}` is replaced by the
+    /// seed, e.g. `logs/{seed}.log`.
+    #[serde(default)]
+    pub log_file: Option<String>,
+    /// Size in bytes at which the log file is rotated (`none` to never rotate).
+    #[serde(default)]
+    pub log_file_max_bytes: Option<u64>,
+    /// Log levels per module, in the same form as `RUST_LOG` and taking precedence over it
+    /// (`none` to use `RUST_LOG` alone), e.g. `ewok::network=warn,ewok::node=debug`.
+    #[serde(default)]
+    pub log_levels: Option<String>,
+    /// Probability that a joining node is malicious, and behaves as `malicious_behaviour`
+    /// describes rather than following the protocol. Must be between 0 and 1.
+    #[serde(default, deserialize_with = "deserialize_prob")]
+    pub malicious_join_prob: f64,
+    /// How malicious joining nodes misbehave.
+    #[serde(default)]
+    pub malicious_behaviour: CandidateBehaviour,
+    /// What a crashed node remembers when it's restarted by a `RestartNode` event.
+    #[serde(default)]
+    pub restart_mode: RestartMode,
+    /// Whether to take random chaos actions during the stable phase (see `chaos`).
+    #[serde(default)]
+    pub chaos: bool,
+    /// Probability of taking a chaos action on a given step.
+    #[serde(default = "default_chaos_prob")]
+    pub chaos_prob: f64,
+    /// Maximum number of steps that message loss, duplicate delivery or a slow node lasts for.
+    #[serde(default = "default_chaos_duration")]
+    pub chaos_duration: u64,
+    /// Maximum number of nodes cut off from the rest of the network by a partition.
+    #[serde(default = "default_chaos_max_partition")]
+    pub chaos_max_partition: usize,
+    /// Maximum probability that a message is lost while message loss lasts.
+    #[serde(default = "default_chaos_max_prob")]
+    pub chaos_max_loss: f64,
+    /// Maximum probability that a message is delivered twice while duplicate delivery lasts.
+    #[serde(default = "default_chaos_max_prob")]
+    pub chaos_max_duplicate: f64,
+    /// Probability on each step, outside the finishing phase, of a client putting or getting a
+    /// randomly named piece of data (see `Event::Put`).
+    #[serde(default)]
+    pub data_op_prob: f64,
+    /// Number of sybil nodes that join in the `sybil_flood` scenario, as a fraction of the size of
+    /// the network they join.
+    #[serde(default)]
+    pub sybil_fraction: f64,
+    /// Number of steps within which every sybil node joins.
+    #[serde(default = "default_sybil_window")]
+    pub sybil_window: u64,
+    /// Prefix that sybil nodes choose their names in (`none` to spread them over every prefix).
+    #[serde(default)]
+    pub sybil_target: Option<Prefix>,
+    /// Probability of a node crashing on a step, for each message it handled within the last
+    /// `overload_window` steps. Models overloaded nodes failing.
+    #[serde(default)]
+    pub overload_drop_prob: f64,
+    /// Number of recent steps whose messages count towards a node's load.
+    #[serde(default = "default_overload_window")]
+    pub overload_window: u64,
+    /// Fraction of nodes that are slow (see `SpeedClass`).
+    #[serde(default)]
+    pub slow_node_ratio: f64,
+    /// Fraction of nodes that are fast (see `SpeedClass`).
+    #[serde(default)]
+    pub fast_node_ratio: f64,
+    /// Number of messages a normal node can handle on each step, or `none` for no limit. Nodes
+    /// queue the messages they don't have the capacity for, and handle them on later steps.
+    #[serde(default)]
+    pub message_capacity: Option<usize>,
+    /// Largest number of steps that a node's clock can be ahead of or behind the network's. Each
+    /// node's skew is chosen uniformly from this range, and its join, self-shutdown and candidate
+    /// queue timeouts expire that many steps early or late.
+    #[serde(default)]
+    pub max_clock_skew: u64,
+    /// Whether to record the steps at which every vote is cast and agreed, for the agreement
+    /// latency histogram and the report of votes that never reached quorum. Always on when
+    /// `EWOK_LATENCY` is set.
+    #[serde(default)]
+    pub track_votes: bool,
+}
+
+fn default_chaos_prob() -> f64 {
+    0.05
+}
+
+fn default_chaos_duration() -> u64 {
+    20
+}
+
+fn default_chaos_max_partition() -> usize {
+    2
+}
+
+fn default_chaos_max_prob() -> f64 {
+    0.05
+}
+
+fn default_sybil_window() -> u64 {
+    10
+}
+
+fn default_overload_window() -> u64 {
+    10
+}
+
+impl Default for SimulationParams {
+    fn default() -> SimulationParams {
+        SimulationParams {
+            max_delay: 5,
+            delay_distribution: DelayDistribution::Geometric,
+            delivery_percentile: 0.95,
+            burst_reorder_prob: 0.0,
+            grow_prob_join: 0.1,
+            grow_prob_drop: 0.02,
+            prob_churn: 0.05,
+            steady_state: false,
+            shrink_prob_join: 0.02,
+            shrink_prob_drop: 0.1,
+            churn_correlation: 0.0,
+            churn_memory: 5,
+            max_network_size: None,
+            name_distribution: NameDistribution::Random,
+            grow_prob_disconnect: 0.05,
+            grow_prob_reconnect: 0.45,
+            prob_disconnect: 0.05,
+            // Gives ~95% chance that a pair will reconnect within 5 steps
+            prob_reconnect: 0.45,
+            shrink_prob_disconnect: 0.05,
+            shrink_prob_reconnect: 0.45,
+            starting_complete: 16,
+            grow_complete: 30,
+            stable_steps: 100,
+            log_file: None,
+            log_file_max_bytes: None,
+            log_levels: None,
+            malicious_join_prob: 0.0,
+            malicious_behaviour: CandidateBehaviour::Honest,
+            restart_mode: RestartMode::Fresh,
+            chaos: false,
+            chaos_prob: default_chaos_prob(),
+            chaos_duration: default_chaos_duration(),
+            chaos_max_partition: default_chaos_max_partition(),
+            chaos_max_loss: default_chaos_max_prob(),
+            chaos_max_duplicate: default_chaos_max_prob(),
+            data_op_prob: 0.0,
+            sybil_fraction: 0.0,
+            sybil_window: default_sybil_window(),
+            sybil_target: None,
+            overload_drop_prob: 0.0,
+            overload_window: default_overload_window(),
+            slow_node_ratio: 0.0,
+            fast_node_ratio: 0.0,
+            message_capacity: None,
+            max_clock_skew: 0,
+            track_votes: false,
+        }
+    }
 }
 
 impl SimulationParams {
+    /// Set the parameter with the given field name from its string representation.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), ParamError> {
+        match name {
+            "max_delay" => self.max_delay = parse_param(name, value)?,
+            "delay_distribution" => self.delay_distribution = parse_param(name, value)?,
+            "delivery_percentile" => self.delivery_percentile = parse_percentile(name, value)?,
+            "burst_reorder_prob" => self.burst_reorder_prob = parse_param(name, value)?,
+            "grow_prob_join" => self.grow_prob_join = parse_param(name, value)?,
+            "grow_prob_drop" => self.grow_prob_drop = parse_param(name, value)?,
+            "prob_churn" => self.prob_churn = parse_param(name, value)?,
+            "steady_state" => self.steady_state = parse_param(name, value)?,
+            "shrink_prob_join" => self.shrink_prob_join = parse_param(name, value)?,
+            "shrink_prob_drop" => self.shrink_prob_drop = parse_param(name, value)?,
+            "churn_correlation" => self.churn_correlation = parse_param(name, value)?,
+            "churn_memory" => self.churn_memory = parse_param(name, value)?,
+            "max_network_size" => self.max_network_size = parse_optional_param(name, value)?,
+            "name_distribution" => self.name_distribution = parse_param(name, value)?,
+            "grow_prob_disconnect" => self.grow_prob_disconnect = parse_param(name, value)?,
+            "grow_prob_reconnect" => self.grow_prob_reconnect = parse_param(name, value)?,
+            "prob_disconnect" => self.prob_disconnect = parse_param(name, value)?,
+            "prob_reconnect" => self.prob_reconnect = parse_param(name, value)?,
+            "shrink_prob_disconnect" => self.shrink_prob_disconnect = parse_param(name, value)?,
+            "shrink_prob_reconnect" => self.shrink_prob_reconnect = parse_param(name, value)?,
+            "starting_complete" => self.starting_complete = parse_param(name, value)?,
+            "grow_complete" => self.grow_complete = parse_param(name, value)?,
+            "stable_steps" => self.stable_steps = parse_param(name, value)?,
+            "log_file" => self.log_file = parse_optional_param(name, value)?,
+            "log_file_max_bytes" => self.log_file_max_bytes = parse_optional_param(name, value)?,
+            "log_levels" => self.log_levels = parse_optional_param(name, value)?,
+            "malicious_join_prob" => self.malicious_join_prob = parse_prob(name, value)?,
+            "malicious_behaviour" => self.malicious_behaviour = parse_param(name, value)?,
+            "restart_mode" => self.restart_mode = parse_param(name, value)?,
+            "chaos" => self.chaos = parse_param(name, value)?,
+            "chaos_prob" => self.chaos_prob = parse_param(name, value)?,
+            "chaos_duration" => self.chaos_duration = parse_param(name, value)?,
+            "chaos_max_partition" => self.chaos_max_partition = parse_param(name, value)?,
+            "chaos_max_loss" => self.chaos_max_loss = parse_param(name, value)?,
+            "chaos_max_duplicate" => self.chaos_max_duplicate = parse_param(name, value)?,
+            "data_op_prob" => self.data_op_prob = parse_param(name, value)?,
+            "sybil_fraction" => self.sybil_fraction = parse_param(name, value)?,
+            "sybil_window" => self.sybil_window = parse_param(name, value)?,
+            "sybil_target" => self.sybil_target = parse_optional_param(name, value)?,
+            "overload_drop_prob" => self.overload_drop_prob = parse_param(name, value)?,
+            "overload_window" => self.overload_window = parse_param(name, value)?,
+            "slow_node_ratio" => self.slow_node_ratio = parse_param(name, value)?,
+            "fast_node_ratio" => self.fast_node_ratio = parse_param(name, value)?,
+            "message_capacity" => self.message_capacity = parse_optional_param(name, value)?,
+            "max_clock_skew" => self.max_clock_skew = parse_param(name, value)?,
+            "track_votes" => self.track_votes = parse_param(name, value)?,
+            _ => return Err(ParamError::Unknown(name.to_string())),
+        }
+        Ok(())
+    }
+
     pub fn prob_join(&self, phase: Phase) -> f64 {
         match phase {
             Starting => 0.1,
@@ -47,27 +302,299 @@ impl SimulationParams {
         }
     }
 
+    /// Extra probability of a join or drop for each recent event of the opposite kind.
+    pub fn churn_correlation(&self, phase: Phase) -> f64 {
+        match phase {
+            Starting | Finishing { .. } => 0.0,
+            Growth | Stable { .. } | Shrinking => self.churn_correlation,
+        }
+    }
+
     pub fn prob_disconnect(&self, phase: Phase) -> f64 {
         match phase {
             Starting | Finishing { .. } => 0.0,
-            Growth | Stable { .. } | Shrinking => self.prob_disconnect,
+            Growth => self.grow_prob_disconnect,
+            Stable { .. } => self.prob_disconnect,
+            Shrinking => self.shrink_prob_disconnect,
         }
     }
 
     pub fn prob_reconnect(&self, phase: Phase) -> f64 {
         match phase {
             Starting | Finishing { .. } => 0.0,
-            Growth | Stable { .. } | Shrinking => self.prob_reconnect,
+            Growth => self.grow_prob_reconnect,
+            Stable { .. } => self.prob_reconnect,
+            Shrinking => self.shrink_prob_reconnect,
+        }
+    }
+}
+
+/// How the delays of messages are distributed between sending and the `max_delay` deadline.
+///
+/// Messages on a single connection are always delivered in order, so a message can be held up
+/// by earlier ones beyond the delay it would otherwise have had.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DelayDistribution {
+    /// Every queued message has the same probability of being delivered on each step, chosen so
+    /// that `delivery_percentile` of messages arrive before the deadline.
+    Geometric,
+    /// Messages are equally likely to be delivered on any step up to the deadline.
+    Uniform,
+}
+
+impl FromStr for DelayDistribution {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "geometric" => Ok(DelayDistribution::Geometric),
+            "uniform" => Ok(DelayDistribution::Uniform),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Which blocks a node asks its peers to prove when it sees a vote from a block it doesn't know.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProofRequestPolicy {
+    /// Request proof of any unknown block, unless its version is more than
+    /// `proof_request_window` ahead of our current blocks (`version_gap`).
+    #[default]
+    VersionGap,
+    /// Request proof only of blocks referenced by votes that have reached quorum, however far
+    /// ahead they are (`agreed`).
+    Agreed,
+}
+
+impl FromStr for ProofRequestPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "version_gap" => Ok(ProofRequestPolicy::VersionGap),
+            "agreed" => Ok(ProofRequestPolicy::Agreed),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Which sections a node relays agreements to, see `message::RecipientPolicy`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecipientRule {
+    /// Neighbours of the agreed blocks that our section is the closest to (`closest_neighbours`).
+    #[default]
+    ClosestNeighbours,
+    /// All neighbours of the agreed blocks (`all_neighbours`).
+    AllNeighbours,
+    /// Only the sections compatible with the agreed blocks (`own_section`).
+    OwnSection,
+}
+
+impl FromStr for RecipientRule {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "closest_neighbours" => Ok(RecipientRule::ClosestNeighbours),
+            "all_neighbours" => Ok(RecipientRule::AllNeighbours),
+            "own_section" => Ok(RecipientRule::OwnSection),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How members' votes are weighed towards a quorum.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuorumWeight {
+    /// Every member's vote counts the same (`members`).
+    #[default]
+    Members,
+    /// Each member's vote counts for its age, the number of versions of its section that it's
+    /// been a member for, so a quorum is more than the quorum ratio of the members' total age
+    /// (`age`). See `Blocks::member_ages`.
+    Age,
+}
+
+impl FromStr for QuorumWeight {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "members" => Ok(QuorumWeight::Members),
+            "age" => Ok(QuorumWeight::Age),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How the names of new nodes are chosen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NameDistribution {
+    /// Names are uniformly random, so they can clump together.
+    Random,
+    /// Names are spread evenly across the namespace, or the prefix of the section being
+    /// generated (see `Prefix::spread_names`).
+    Spread,
+}
+
+impl FromStr for NameDistribution {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "random" => Ok(NameDistribution::Random),
+            "spread" => Ok(NameDistribution::Spread),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How a joining node behaves while it's a candidate, and after it's added.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CandidateBehaviour {
+    /// The node follows the protocol (`honest`).
+    #[default]
+    Honest,
+    /// The node ignores resource proof challenges, so it never proves its resources
+    /// (`no_resource_proof`).
+    NoResourceProof,
+    /// The node proves its resources, but refuses connections to half of the other members of
+    /// its section (`partial_connections`).
+    PartialConnections,
+    /// The node sends nothing at all after joining (`silent`).
+    Silent,
+    /// The node is one of a coordinated group of attackers. It joins like an honest node, but
+    /// once it's a member of its section it refuses connections to every member outside the group
+    /// (`eclipse`).
+    Eclipse,
+}
+
+impl FromStr for CandidateBehaviour {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "honest" => Ok(CandidateBehaviour::Honest),
+            "no_resource_proof" => Ok(CandidateBehaviour::NoResourceProof),
+            "partial_connections" => Ok(CandidateBehaviour::PartialConnections),
+            "silent" => Ok(CandidateBehaviour::Silent),
+            "eclipse" => Ok(CandidateBehaviour::Eclipse),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What a crashed node remembers when it restarts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RestartMode {
+    /// The node forgets everything, and bootstraps from its new section like any joining node
+    /// (`fresh`).
+    #[default]
+    Fresh,
+    /// The node keeps the blocks and votes it had before it crashed, even though they may be
+    /// stale by the time it restarts, but loses its connections (`persistent`).
+    Persistent,
+}
+
+impl FromStr for RestartMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "fresh" => Ok(RestartMode::Fresh),
+            "persistent" => Ok(RestartMode::Persistent),
+            _ => Err(()),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+/// How quickly a node works, relative to a normal node.
+///
+/// Speed scales the number of messages a node can handle on each step (`message_capacity`), and
+/// the number of steps it takes to prove its resources (`resource_proof_steps`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SpeedClass {
+    /// Twice as fast as a normal node.
+    Fast,
+    #[default]
+    Normal,
+    /// Half as fast as a normal node.
+    Slow,
+}
+
+impl SpeedClass {
+    /// Amount of work done on each step, where a normal node does 1.
+    pub fn speed(&self) -> f64 {
+        match *self {
+            SpeedClass::Fast => 2.0,
+            SpeedClass::Normal => 1.0,
+            SpeedClass::Slow => 0.5,
+        }
+    }
+
+    /// Number of messages a node of this class can handle on each step, if a normal node can
+    /// handle `capacity` of them. Every node can handle at least one.
+    pub fn capacity(&self, capacity: usize) -> usize {
+        cmp::max(1, (capacity as f64 * self.speed()).round() as usize)
+    }
+}
+
+/// How a section size parameter grows as the network does.
+///
+/// Nodes evaluate this from their own current blocks, so nodes with different views of the
+/// network can disagree about the adjusted value. The configured value is always a lower bound.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SizeGrowth {
+    /// The configured value is used as is (`fixed`).
+    Fixed,
+    /// One is added for every `n` sections (`sections:n`).
+    PerSections(usize),
+    /// One is added for every `n` nodes (`nodes:n`).
+    PerNodes(usize),
+}
+
+impl SizeGrowth {
+    /// Amount to add to the configured value for a network of the given size.
+    pub fn extra(&self, num_sections: usize, num_nodes: usize) -> usize {
+        match *self {
+            SizeGrowth::Fixed => 0,
+            SizeGrowth::PerSections(n) => num_sections / n,
+            SizeGrowth::PerNodes(n) => num_nodes / n,
+        }
+    }
+}
+
+impl FromStr for SizeGrowth {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        if s == "fixed" {
+            return Ok(SizeGrowth::Fixed);
+        }
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().ok_or(())?;
+        let n = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        match (kind, n) {
+            (_, 0) => Err(()),
+            ("sections", n) => Ok(SizeGrowth::PerSections(n)),
+            ("nodes", n) => Ok(SizeGrowth::PerNodes(n)),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct NodeParams {
     /// Minimum section size.
     pub min_section_size: usize,
+    /// How the minimum section size grows with the size of the network.
+    #[serde(deserialize_with = "deserialize_size_growth")]
+    pub min_section_size_growth: SizeGrowth,
     /// Number of nodes past the minimum that must be present in all sections when splitting.
     pub split_buffer: usize,
+    /// How the split buffer grows with the size of the network.
+    #[serde(deserialize_with = "deserialize_size_growth")]
+    pub split_buffer_growth: SizeGrowth,
     /// Number of steps to wait for a candidate to appear in at least one current section.
     pub join_timeout: u64,
     /// Number of steps to wait before shutting down if we fail to join.
@@ -75,47 +602,564 @@ pub struct NodeParams {
     /// The maximum number of permissible valid blocks for a single prefix and version pair.
     /// Exceeding this will cause the process to panic.
     pub max_conflicting_blocks: usize,
+    /// A quorum is more than `quorum_numerator / quorum_denominator` of a section's members.
+    pub quorum_numerator: usize,
+    /// See `quorum_numerator`. Must be non-zero.
+    #[serde(deserialize_with = "deserialize_denominator")]
+    pub quorum_denominator: usize,
+    /// How members' votes are weighed towards a quorum.
+    #[serde(default)]
+    pub quorum_weight: QuorumWeight,
+    /// Number of nodes from each bucket of other sections that a node keeps in its routing
+    /// table, or `none` for every node to know every member of its current blocks.
+    pub routing_bucket_size: Option<usize>,
+    /// Number of steps a candidate spends sending resource proof responses to each member of
+    /// the section it's joining, which only votes to add it once it has all of them (0 to add
+    /// candidates without resource proof). This should be well below `join_timeout`.
+    #[serde(default)]
+    pub resource_proof_steps: u64,
+    /// Number of steps to wait for a lost member of our section to reconnect before voting to
+    /// remove it (rule:RmConv).
+    #[serde(default)]
+    pub rmconv_timeout: u64,
+    /// Number of steps a joining node waits in our queue while another candidate for our section
+    /// is still proving its resources or awaiting approval, or `none` to accept candidates in
+    /// parallel.
+    #[serde(default)]
+    pub candidate_queue_timeout: Option<u64>,
+    /// We vote to force-merge with a sibling once we're connected to no more than
+    /// `force_merge_numerator / force_merge_denominator` of its members.
+    #[serde(default = "default_force_merge_numerator")]
+    pub force_merge_numerator: usize,
+    /// See `force_merge_numerator`. Must be non-zero.
+    #[serde(default = "default_force_merge_denominator",
+            deserialize_with = "deserialize_denominator")]
+    pub force_merge_denominator: usize,
+    /// Number of steps that we must stay disconnected from a sibling before voting to
+    /// force-merge with it, so that brief message delays don't trigger merges.
+    #[serde(default)]
+    pub force_merge_delay: u64,
+    /// Whether sections relocate each node they add to the section its relocated name belongs
+    /// to. Nodes that have been relocated once aren't relocated again.
+    #[serde(default)]
+    pub relocate_new_nodes: bool,
+    /// Number of messages a node can send while updating its state on a single step before it's
+    /// in a message storm, which is logged and traced, or `none` not to look for storms.
+    #[serde(default)]
+    pub storm_threshold: Option<usize>,
+    /// Whether a node in a message storm defers its witnessing votes, which are the least
+    /// urgent, to the next step.
+    #[serde(default)]
+    pub throttle_storms: bool,
+    /// Size in bytes of an aggregate signature, which nodes send to prove a vote's agreement
+    /// instead of the list of its voters (`VoteAggregatedMsg`), or `none` to send the list.
+    #[serde(default)]
+    pub aggregate_signature_bytes: Option<usize>,
+    /// Number of superseded blocks for each prefix that a node keeps in its valid blocks, or
+    /// `none` to keep them all. Older ones are pruned once a newer block is agreed, and a node
+    /// that can't prove a block because its peer pruned it asks another peer.
+    #[serde(default)]
+    pub history_depth: Option<usize>,
+    /// Which unknown blocks we request proof of.
+    #[serde(default)]
+    pub proof_request_policy: ProofRequestPolicy,
+    /// Number of versions that a block can be ahead of our current blocks for us to request
+    /// proof of it, under the `version_gap` policy. Requesting proof of blocks much further
+    /// ahead mostly causes trouble while bootstrapping.
+    #[serde(default = "default_proof_request_window")]
+    pub proof_request_window: u64,
+    /// Number of members of our current blocks that we stay connected to, or `none` to connect
+    /// to all of them. Members of our own section come first, then those of the sections whose
+    /// prefixes are closest to our name.
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// Whether a joining node that finds a predecessor missing from the history of its section
+    /// in a bootstrap message requests proof of the oldest block it reached and collects the
+    /// segment again once the proof arrives, rather than truncating the segment there.
+    #[serde(default)]
+    pub recover_chain_gaps: bool,
+    /// Which sections we relay agreements to.
+    #[serde(default)]
+    pub recipient_policy: RecipientRule,
+}
+
+fn default_force_merge_numerator() -> usize {
+    1
+}
+
+fn default_force_merge_denominator() -> usize {
+    2
+}
+
+fn default_proof_request_window() -> u64 {
+    10
+}
+
+/// Deserialize the denominator of a ratio, which can't be zero.
+fn deserialize_denominator<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    match usize::deserialize(deserializer)? {
+        0 => Err(D::Error::custom("denominator must be non-zero")),
+        n => Ok(n),
+    }
+}
+
+/// Deserialize a probability, which must be between 0 and 1.
+fn deserialize_prob<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    match f64::deserialize(deserializer)? {
+        p if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(D::Error::custom("probability must be between 0 and 1")),
+    }
+}
+
+/// Deserialize a percentile, which must be strictly between 0 and 1.
+fn deserialize_percentile<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    match f64::deserialize(deserializer)? {
+        p if p > 0.0 && p < 1.0 => Ok(p),
+        _ => Err(D::Error::custom("percentile must be between 0 and 1")),
+    }
+}
+
+/// Deserialize a size growth, which can't be per zero sections or nodes.
+fn deserialize_size_growth<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<SizeGrowth, D::Error> {
+    match SizeGrowth::deserialize(deserializer)? {
+        SizeGrowth::PerSections(0) | SizeGrowth::PerNodes(0) => {
+            Err(D::Error::custom("size growth must be per a non-zero count"))
+        }
+        growth => Ok(growth),
+    }
 }
 
 impl Default for NodeParams {
     fn default() -> NodeParams {
         NodeParams {
             min_section_size: 8,
+            min_section_size_growth: SizeGrowth::Fixed,
             split_buffer: 1,
+            split_buffer_growth: SizeGrowth::Fixed,
             join_timeout: 20,
             self_shutdown_timeout: 100,
             max_conflicting_blocks: 20,
+            quorum_numerator: 1,
+            quorum_denominator: 2,
+            quorum_weight: QuorumWeight::Members,
+            routing_bucket_size: None,
+            resource_proof_steps: 0,
+            rmconv_timeout: 0,
+            candidate_queue_timeout: None,
+            force_merge_numerator: default_force_merge_numerator(),
+            force_merge_denominator: default_force_merge_denominator(),
+            force_merge_delay: 0,
+            relocate_new_nodes: false,
+            storm_threshold: None,
+            throttle_storms: false,
+            aggregate_signature_bytes: None,
+            history_depth: None,
+            proof_request_policy: ProofRequestPolicy::VersionGap,
+            proof_request_window: default_proof_request_window(),
+            max_connections: None,
+            recover_chain_gaps: false,
+            recipient_policy: RecipientRule::ClosestNeighbours,
         }
     }
 }
 
 impl NodeParams {
+    /// Set the parameter with the given field name from its string representation.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), ParamError> {
+        match name {
+            "min_section_size" => self.min_section_size = parse_param(name, value)?,
+            "min_section_size_growth" => {
+                self.min_section_size_growth = parse_param(name, value)?
+            }
+            "split_buffer" => self.split_buffer = parse_param(name, value)?,
+            "split_buffer_growth" => self.split_buffer_growth = parse_param(name, value)?,
+            "join_timeout" => self.join_timeout = parse_param(name, value)?,
+            "self_shutdown_timeout" => self.self_shutdown_timeout = parse_param(name, value)?,
+            "max_conflicting_blocks" => self.max_conflicting_blocks = parse_param(name, value)?,
+            "quorum_numerator" => self.quorum_numerator = parse_param(name, value)?,
+            "quorum_denominator" => self.quorum_denominator = parse_denominator(name, value)?,
+            "quorum_weight" => self.quorum_weight = parse_param(name, value)?,
+            "routing_bucket_size" => {
+                self.routing_bucket_size = parse_optional_param(name, value)?
+            }
+            "resource_proof_steps" => self.resource_proof_steps = parse_param(name, value)?,
+            "rmconv_timeout" => self.rmconv_timeout = parse_param(name, value)?,
+            "candidate_queue_timeout" => {
+                self.candidate_queue_timeout = parse_optional_param(name, value)?
+            }
+            "force_merge_numerator" => self.force_merge_numerator = parse_param(name, value)?,
+            "force_merge_denominator" => {
+                self.force_merge_denominator = parse_denominator(name, value)?
+            }
+            "force_merge_delay" => self.force_merge_delay = parse_param(name, value)?,
+            "relocate_new_nodes" => self.relocate_new_nodes = parse_param(name, value)?,
+            "storm_threshold" => self.storm_threshold = parse_optional_param(name, value)?,
+            "throttle_storms" => self.throttle_storms = parse_param(name, value)?,
+            "aggregate_signature_bytes" => {
+                self.aggregate_signature_bytes = parse_optional_param(name, value)?
+            }
+            "history_depth" => self.history_depth = parse_optional_param(name, value)?,
+            "proof_request_policy" => self.proof_request_policy = parse_param(name, value)?,
+            "proof_request_window" => self.proof_request_window = parse_param(name, value)?,
+            "max_connections" => self.max_connections = parse_optional_param(name, value)?,
+            "recover_chain_gaps" => self.recover_chain_gaps = parse_param(name, value)?,
+            "recipient_policy" => self.recipient_policy = parse_param(name, value)?,
+            _ => return Err(ParamError::Unknown(name.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Minimum section size for a network of `num_sections` sections and `num_nodes` nodes.
+    pub fn min_section_size_for(&self, num_sections: usize, num_nodes: usize) -> usize {
+        self.min_section_size + self.min_section_size_growth.extra(num_sections, num_nodes)
+    }
+
+    /// Split buffer for a network of `num_sections` sections and `num_nodes` nodes.
+    pub fn split_buffer_for(&self, num_sections: usize, num_nodes: usize) -> usize {
+        self.split_buffer + self.split_buffer_growth.extra(num_sections, num_nodes)
+    }
+
+    /// Compute the number of nodes required to form a quorum of `num_nodes`.
+    ///
+    /// This is the smallest number strictly greater than the quorum fraction of `num_nodes`. You
+    /// should compare `num_votes >= quorum(num_nodes)`.
+    pub fn quorum(&self, num_nodes: usize) -> usize {
+        num_nodes * self.quorum_numerator / self.quorum_denominator + 1
+    }
+
+    /// Whether being connected to `num_connected` of a sibling's `num_nodes` members is few
+    /// enough that we should vote to force-merge with it.
+    pub fn is_force_merge_loss(&self, num_connected: usize, num_nodes: usize) -> bool {
+        num_connected <= num_nodes * self.force_merge_numerator / self.force_merge_denominator
+    }
+
     pub fn max_timeout(&self) -> u64 {
-        vec![self.join_timeout, self.self_shutdown_timeout]
-            .into_iter()
+        vec![
+            self.join_timeout,
+            self.self_shutdown_timeout,
+            self.rmconv_timeout,
+            self.candidate_queue_timeout.unwrap_or(0),
+            self.force_merge_delay,
+        ].into_iter()
             .max()
             .unwrap()
     }
 }
 
-/// Compute the number of nodes required to form a majority of `num_nodes`.
+/// Error from setting a parameter by name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParamError {
+    /// No parameter has the given name.
+    Unknown(String),
+    /// The value couldn't be parsed as the parameter's type.
+    Invalid { name: String, value: String },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParamError::Unknown(ref name) => write!(f, "unknown parameter: {}", name),
+            ParamError::Invalid {
+                ref name,
+                ref value,
+            } => write!(f, "invalid value for {}: {:?}", name, value),
+        }
+    }
+}
+
+/// Set a simulation or node parameter by name, e.g. `set_param(.., "max_delay", "50")`.
+pub fn set_param(
+    params: &mut SimulationParams,
+    node_params: &mut NodeParams,
+    name: &str,
+    value: &str,
+) -> Result<(), ParamError> {
+    match params.set(name, value) {
+        Err(ParamError::Unknown(_)) => node_params.set(name, value),
+        result => result,
+    }
+}
+
+/// Prefix of the environment variables that override parameters, e.g. `EWOK_PARAM_MAX_DELAY`.
+pub const ENV_PREFIX: &str = "EWOK_PARAM_";
+
+/// Apply overrides from any `EWOK_PARAM_<NAME>=<VALUE>` environment variables.
 ///
-/// You should compare `num_votes >= quorum(num_nodes)`.
-pub fn quorum(num_nodes: usize) -> usize {
-    (num_nodes / 2) + 1
+/// The name is matched case-insensitively against the parameters' field names, so
+/// `EWOK_PARAM_MAX_DELAY=50` sets `max_delay` to 50.
+pub fn apply_env_overrides(
+    params: &mut SimulationParams,
+    node_params: &mut NodeParams,
+) -> Result<(), ParamError> {
+    apply_overrides(params, node_params, env::vars())
+}
+
+/// Apply overrides from the variables in `vars` that start with `ENV_PREFIX`.
+fn apply_overrides<I>(
+    params: &mut SimulationParams,
+    node_params: &mut NodeParams,
+    vars: I,
+) -> Result<(), ParamError>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    for (key, value) in vars {
+        if let Some(name) = key.strip_prefix(ENV_PREFIX) {
+            set_param(params, node_params, &name.to_lowercase(), &value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse an optional parameter, where `none` means no value.
+fn parse_optional_param<T: FromStr>(name: &str, value: &str) -> Result<Option<T>, ParamError> {
+    if value == "none" {
+        Ok(None)
+    } else {
+        parse_param(name, value).map(Some)
+    }
+}
+
+fn parse_param<T: FromStr>(name: &str, value: &str) -> Result<T, ParamError> {
+    value.parse().map_err(|_| {
+        ParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    })
+}
+
+/// Parse the denominator of a ratio, which can't be zero.
+fn parse_denominator(name: &str, value: &str) -> Result<usize, ParamError> {
+    match parse_param(name, value)? {
+        0 => Err(ParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+        n => Ok(n),
+    }
+}
+
+/// Parse a probability, which must be between 0 and 1.
+fn parse_prob(name: &str, value: &str) -> Result<f64, ParamError> {
+    match parse_param(name, value)? {
+        p if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(ParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+/// Parse a percentile, which must be strictly between 0 and 1.
+fn parse_percentile(name: &str, value: &str) -> Result<f64, ParamError> {
+    match parse_param(name, value)? {
+        p if p > 0.0 && p < 1.0 => Ok(p),
+        _ => Err(ParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn set_by_name() {
+        let mut params = SimulationParams::default();
+        let mut node_params = NodeParams::default();
+        set_param(&mut params, &mut node_params, "max_delay", "50").unwrap();
+        set_param(&mut params, &mut node_params, "join_timeout", "7").unwrap();
+        assert_eq!(params.max_delay, 50);
+        assert_eq!(node_params.join_timeout, 7);
+        set_param(&mut params, &mut node_params, "max_network_size", "100").unwrap();
+        assert_eq!(params.max_network_size, Some(100));
+        set_param(&mut params, &mut node_params, "max_network_size", "none").unwrap();
+        assert_eq!(params.max_network_size, None);
+        set_param(&mut params, &mut node_params, "log_levels", "ewok::network=warn,debug").unwrap();
+        assert_eq!(params.log_levels, Some("ewok::network=warn,debug".to_string()));
+        set_param(&mut params, &mut node_params, "candidate_queue_timeout", "40").unwrap();
+        assert_eq!(node_params.candidate_queue_timeout, Some(40));
+        set_param(&mut params, &mut node_params, "malicious_behaviour", "silent").unwrap();
+        assert_eq!(params.malicious_behaviour, CandidateBehaviour::Silent);
+        set_param(&mut params, &mut node_params, "sybil_target", "01").unwrap();
+        assert_eq!(params.sybil_target, Some(Prefix::empty().pushed(false).pushed(true)));
+        set_param(&mut params, &mut node_params, "max_clock_skew", "3").unwrap();
+        assert_eq!(params.max_clock_skew, 3);
+        set_param(&mut params, &mut node_params, "history_depth", "2").unwrap();
+        assert_eq!(node_params.history_depth, Some(2));
+        assert_eq!(
+            set_param(&mut params, &mut node_params, "max_delay", "x"),
+            Err(ParamError::Invalid {
+                name: "max_delay".to_string(),
+                value: "x".to_string(),
+            })
+        );
+        assert_eq!(
+            set_param(&mut params, &mut node_params, "no_such_param", "1"),
+            Err(ParamError::Unknown("no_such_param".to_string()))
+        );
+    }
+
+    #[test]
+    fn overrides_from_env() {
+        let mut params = SimulationParams::default();
+        let mut node_params = NodeParams::default();
+        let vars = vec![
+            ("EWOK_PARAM_MAX_DELAY".to_string(), "50".to_string()),
+            ("EWOK_PARAM_MIN_SECTION_SIZE".to_string(), "4".to_string()),
+            ("EWOK_SEED".to_string(), "[1, 2, 3, 4]".to_string()),
+        ];
+        apply_overrides(&mut params, &mut node_params, vars).unwrap();
+        assert_eq!(params.max_delay, 50);
+        assert_eq!(node_params.min_section_size, 4);
+
+        let vars = vec![("EWOK_PARAM_MAX_DELAYS".to_string(), "50".to_string())];
+        assert_eq!(
+            apply_overrides(&mut params, &mut node_params, vars),
+            Err(ParamError::Unknown("max_delays".to_string()))
+        );
+    }
+
+    #[test]
+    fn size_growth() {
+        let mut params = SimulationParams::default();
+        let mut node_params = NodeParams::default();
+        set_param(&mut params, &mut node_params, "split_buffer_growth", "sections:4").unwrap();
+        assert_eq!(node_params.split_buffer_for(3, 100), 1);
+        assert_eq!(node_params.split_buffer_for(9, 100), 3);
+        assert_eq!(node_params.min_section_size_for(9, 100), 8);
+
+        assert_eq!("fixed".parse(), Ok(SizeGrowth::Fixed));
+        assert_eq!("nodes:50".parse(), Ok(SizeGrowth::PerNodes(50)));
+        assert!("nodes:0".parse::<SizeGrowth>().is_err());
+        assert!("sections:0".parse::<SizeGrowth>().is_err());
+        assert!("nodes".parse::<SizeGrowth>().is_err());
+
+        let mut json = serde_json::to_value(&node_params).unwrap();
+        json["split_buffer_growth"] = serde_json::to_value(SizeGrowth::PerSections(0)).unwrap();
+        assert!(serde_json::from_value::<NodeParams>(json).is_err());
+    }
+
+    #[test]
+    fn speed_classes() {
+        let mut params = SimulationParams::default();
+        let mut node_params = NodeParams::default();
+        set_param(&mut params, &mut node_params, "slow_node_ratio", "0.25").unwrap();
+        set_param(&mut params, &mut node_params, "message_capacity", "5").unwrap();
+        assert_eq!(params.slow_node_ratio, 0.25);
+        assert_eq!(params.message_capacity, Some(5));
+
+        assert_eq!(SpeedClass::Fast.capacity(5), 10);
+        assert_eq!(SpeedClass::Normal.capacity(5), 5);
+        assert_eq!(SpeedClass::Slow.capacity(5), 3);
+        assert_eq!(SpeedClass::Slow.capacity(1), 1);
+    }
+
     #[test]
     fn test_quorum() {
-        assert_eq!(501, quorum(1000));
-        assert_eq!(6, quorum(10));
-        assert_eq!(5, quorum(9));
-        assert_eq!(3, quorum(4));
-        assert_eq!(2, quorum(3));
-        assert_eq!(2, quorum(2));
+        let params = NodeParams::default();
+        assert_eq!(501, params.quorum(1000));
+        assert_eq!(6, params.quorum(10));
+        assert_eq!(5, params.quorum(9));
+        assert_eq!(3, params.quorum(4));
+        assert_eq!(2, params.quorum(3));
+        assert_eq!(2, params.quorum(2));
+
+        let params = NodeParams {
+            quorum_numerator: 2,
+            quorum_denominator: 3,
+            ..NodeParams::default()
+        };
+        assert_eq!(667, params.quorum(1000));
+        assert_eq!(7, params.quorum(9));
+        assert_eq!(5, params.quorum(7));
+        assert_eq!(3, params.quorum(3));
+    }
+
+    #[test]
+    fn zero_denominators() {
+        let mut params = SimulationParams::default();
+        let mut node_params = NodeParams::default();
+        for name in &["quorum_denominator", "force_merge_denominator"] {
+            assert_eq!(
+                set_param(&mut params, &mut node_params, name, "0"),
+                Err(ParamError::Invalid {
+                    name: name.to_string(),
+                    value: "0".to_string(),
+                })
+            );
+        }
+        assert_eq!(node_params, NodeParams::default());
+
+        for name in &["quorum_denominator", "force_merge_denominator"] {
+            let mut json = serde_json::to_value(&node_params).unwrap();
+            json[name] = serde_json::Value::from(0);
+            assert!(serde_json::from_value::<NodeParams>(json).is_err());
+        }
+    }
+
+    #[test]
+    fn delivery_percentile_range() {
+        let mut params = SimulationParams::default();
+        let mut node_params = NodeParams::default();
+        set_param(&mut params, &mut node_params, "delivery_percentile", "0.5").unwrap();
+        assert_eq!(params.delivery_percentile, 0.5);
+        for value in &["0", "1", "1.5", "-0.1", "NaN"] {
+            let result = set_param(&mut params, &mut node_params, "delivery_percentile", value);
+            assert!(result.is_err());
+        }
+        assert_eq!(params.delivery_percentile, 0.5);
+
+        for &value in &[0.0, 1.0] {
+            let mut json = serde_json::to_value(&params).unwrap();
+            json["delivery_percentile"] = serde_json::Value::from(value);
+            assert!(serde_json::from_value::<SimulationParams>(json).is_err());
+        }
+    }
+
+    #[test]
+    fn malicious_join_prob_range() {
+        let mut params = SimulationParams::default();
+        let mut node_params = NodeParams::default();
+        for value in &["0", "1", "0.25"] {
+            set_param(&mut params, &mut node_params, "malicious_join_prob", value).unwrap();
+        }
+        assert_eq!(params.malicious_join_prob, 0.25);
+        for value in &["1.5", "-0.1", "NaN"] {
+            let result = set_param(&mut params, &mut node_params, "malicious_join_prob", value);
+            assert!(result.is_err());
+        }
+        assert_eq!(params.malicious_join_prob, 0.25);
+
+        let mut json = serde_json::to_value(&params).unwrap();
+        json["malicious_join_prob"] = serde_json::Value::from(2.0);
+        assert!(serde_json::from_value::<SimulationParams>(json).is_err());
+    }
+
+    #[test]
+    fn force_merge_loss() {
+        let params = NodeParams::default();
+        // Losing quorum, as before the threshold was configurable.
+        for num_nodes in 1..20 {
+            for num_connected in 0..num_nodes + 1 {
+                assert_eq!(
+                    params.is_force_merge_loss(num_connected, num_nodes),
+                    num_connected < params.quorum(num_nodes)
+                );
+            }
+        }
+
+        let params = NodeParams {
+            force_merge_numerator: 1,
+            force_merge_denominator: 4,
+            ..NodeParams::default()
+        };
+        assert!(params.is_force_merge_loss(2, 8));
+        assert!(!params.is_force_merge_loss(3, 8));
     }
 }
@@ -0,0 +1,290 @@
+//! Scripted partitions (the `Partition` and `HealPartition` events), and how the sections they
+//! split converge once they heal.
+//!
+//! A partition cuts the nodes whose names match a prefix off from every other node, and holds the
+//! lost connections until it's healed, rather than letting them reconnect at random. No messages
+//! are delivered across it in the meantime, `Connect` messages included, whether the nodes were
+//! connected when it started or joined later. For each section with members on both sides, we
+//! measure how many steps it takes after healing for its members to agree on a single current block
+//! again, how many witness votes they send in that time, and how many conflicting valid blocks
+//! (different blocks for the same prefix and version) they created from the start of the partition.
+
+use block::{Block, BlockId, Vote};
+use blocks::Blocks;
+use name::{Name, Prefix};
+use node::Node;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How a section split by a partition converged once the partition healed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Healing {
+    /// Prefix of the section when it was split.
+    pub prefix: Prefix,
+    pub partitioned_step: u64,
+    pub healed_step: Option<u64>,
+    /// Step by which the section's members all had the same single current block again.
+    pub converged_step: Option<u64>,
+    /// Number of witness votes the section's members sent between healing and converging.
+    pub witness_votes: u64,
+    /// Number of valid blocks for the section that conflicted with another for the same prefix
+    /// and version.
+    pub conflicting_blocks: usize,
+}
+
+impl Healing {
+    /// Number of steps from healing to converging, if the section has converged.
+    pub fn steps_to_converge(&self) -> Option<u64> {
+        match (self.healed_step, self.converged_step) {
+            (Some(healed), Some(converged)) => Some(converged - healed),
+            _ => None,
+        }
+    }
+}
+
+/// A section split by a partition, which hasn't converged yet.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Split {
+    healing: Healing,
+    /// Members of the section when it was split.
+    members: BTreeSet<Name>,
+    /// Version of the section's block when it was split.
+    version: u64,
+    /// Blocks for the section newer than `version` that any member has found valid.
+    valid_blocks: BTreeSet<BlockId>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Partitions {
+    /// Prefixes whose nodes are cut off from the rest until the partition heals.
+    cut: Vec<Prefix>,
+    /// Pairs of nodes, lower name first, that were connected when they were cut off from each
+    /// other.
+    held: BTreeSet<(Name, Name)>,
+    /// Pairs that were cut off by a partition that has healed, and should reconnect as soon as
+    /// they can.
+    healed: BTreeSet<(Name, Name)>,
+    splits: Vec<Split>,
+    /// Number of messages that weren't delivered because a partition held their sender and
+    /// recipient apart.
+    refused: u64,
+}
+
+fn ordered(x: Name, y: Name) -> (Name, Name) {
+    if x < y { (x, y) } else { (y, x) }
+}
+
+impl Partitions {
+    pub fn new() -> Self {
+        Partitions::default()
+    }
+
+    /// Cut the nodes matching `prefix` off from the rest of `nodes`, returning the pairs to
+    /// disconnect. Every agreed section in `sections` with members on both sides is split.
+    pub fn start(
+        &mut self,
+        step: u64,
+        prefix: Prefix,
+        nodes: &BTreeSet<Name>,
+        sections: &BTreeMap<Prefix, Block>,
+    ) -> Vec<(Name, Name)> {
+        let (inside, outside): (Vec<Name>, Vec<Name>) =
+            nodes.iter().partition(|&&name| prefix.matches(name));
+        let pairs: Vec<(Name, Name)> = inside
+            .iter()
+            .flat_map(|&x| outside.iter().map(move |&y| ordered(x, y)))
+            .collect();
+        self.held.extend(pairs.iter().cloned());
+        self.cut.push(prefix);
+
+        for section in sections.values() {
+            let split = section.members.iter().any(|&name| prefix.matches(name)) &&
+                section.members.iter().any(|&name| !prefix.matches(name));
+            if split && !self.splits.iter().any(|split| split.healing.prefix == section.prefix) {
+                self.splits.push(Split {
+                    healing: Healing {
+                        prefix: section.prefix,
+                        partitioned_step: step,
+                        healed_step: None,
+                        converged_step: None,
+                        witness_votes: 0,
+                        conflicting_blocks: 0,
+                    },
+                    members: (*section.members).clone(),
+                    version: section.version,
+                    valid_blocks: BTreeSet::new(),
+                });
+            }
+        }
+        pairs
+    }
+
+    /// Heal every partition, letting the nodes they cut off reconnect.
+    pub fn heal(&mut self, step: u64) {
+        self.cut.clear();
+        self.healed.extend(self.held.iter().cloned());
+        self.held.clear();
+        for split in &mut self.splits {
+            if split.healing.healed_step.is_none() {
+                split.healing.healed_step = Some(step);
+            }
+        }
+    }
+
+    /// Whether a partition is holding the pair of nodes apart.
+    pub fn holds(&self, x: Name, y: Name) -> bool {
+        self.cut.iter().any(|prefix| prefix.matches(x) != prefix.matches(y))
+    }
+
+    /// Whether a message from `sender` to `recipient` can't be delivered, because a partition
+    /// holds them apart. Refused deliveries are counted.
+    pub fn refuse_delivery(&mut self, sender: Name, recipient: Name) -> bool {
+        let refused = self.holds(sender, recipient);
+        if refused {
+            self.refused += 1;
+        }
+        refused
+    }
+
+    /// Number of messages that partitions have refused to deliver.
+    pub fn refused_deliveries(&self) -> u64 {
+        self.refused
+    }
+
+    /// Whether a healed partition cut the pair of nodes off, in which case they should reconnect
+    /// straight away. The pair is forgotten, so they only do so once.
+    pub fn take_healed(&mut self, x: Name, y: Name) -> bool {
+        self.healed.remove(&ordered(x, y))
+    }
+
+    /// Forget a node that has left the network.
+    pub fn remove_node(&mut self, name: Name) {
+        self.held.retain(|&(x, y)| x != name && y != name);
+        self.healed.retain(|&(x, y)| x != name && y != name);
+    }
+
+    /// Count the witness votes that `voter` sent, for the healed sections it was a member of
+    /// that haven't converged yet.
+    pub fn votes_sent<'a, I>(&mut self, voter: Name, votes: I, blocks: &Blocks)
+    where
+        I: IntoIterator<Item = &'a Vote>,
+    {
+        let witness_votes = votes
+            .into_iter()
+            .filter(|vote| vote.is_witnessing(blocks))
+            .count() as u64;
+        for split in &mut self.splits {
+            if split.healing.healed_step.is_some() && split.healing.converged_step.is_none() &&
+                split.members.contains(&voter)
+            {
+                split.healing.witness_votes += witness_votes;
+            }
+        }
+    }
+
+    /// Update the sections that haven't converged yet with the state of the nodes at the end of
+    /// `step`.
+    pub fn update(&mut self, step: u64, nodes: &BTreeMap<Name, Node>, blocks: &Blocks) {
+        for split in &mut self.splits {
+            if split.healing.converged_step.is_some() {
+                continue;
+            }
+            let members: Vec<&Node> = split
+                .members
+                .iter()
+                .filter_map(|name| nodes.get(name))
+                .collect();
+            let (prefix, version) = (split.healing.prefix, split.version);
+            for node in &members {
+                split.valid_blocks.extend(node.valid_blocks.iter().cloned().filter(|id| {
+                    let block = id.into_block(blocks);
+                    block.prefix.is_compatible(&prefix) && block.version > version
+                }));
+            }
+            split.healing.conflicting_blocks = conflicting(&split.valid_blocks, blocks);
+
+            if split.healing.healed_step.is_none() {
+                continue;
+            }
+            let mut current = members.iter().map(|node| node.our_current_blocks(blocks));
+            let converged = match current.next() {
+                Some(first) => first.len() == 1 && current.all(|blocks| blocks == first),
+                None => true,
+            };
+            if converged {
+                split.healing.converged_step = Some(step);
+            }
+        }
+    }
+
+    /// Whether any split section hasn't converged yet, or any partition is still held.
+    pub fn is_active(&self) -> bool {
+        !self.cut.is_empty() || !self.held.is_empty() || !self.healed.is_empty() ||
+            self.splits.iter().any(|split| split.healing.converged_step.is_none())
+    }
+
+    /// Prefixes whose nodes are currently cut off from the rest.
+    pub fn cut(&self) -> &[Prefix] {
+        &self.cut
+    }
+
+    /// How each section split by a partition has converged so far.
+    pub fn healings(&self) -> Vec<Healing> {
+        self.splits.iter().map(|split| split.healing).collect()
+    }
+}
+
+/// Number of the blocks that share their prefix and version with another of them.
+fn conflicting(ids: &BTreeSet<BlockId>, blocks: &Blocks) -> usize {
+    let mut counts: BTreeMap<(Prefix, u64), usize> = BTreeMap::new();
+    for id in ids {
+        let block = id.into_block(blocks);
+        *counts.entry((block.prefix, block.version)).or_insert(0) += 1;
+    }
+    counts.values().filter(|&&count| count > 1).sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::p;
+    use std::sync::Arc;
+
+    #[test]
+    fn holds_until_healed() {
+        let (a, b, c) = (p("00").lower_bound(), p("10").lower_bound(), p("11").lower_bound());
+        let nodes = btreeset!{ a, b, c };
+        let section = Block {
+            prefix: Prefix::empty(),
+            version: 0,
+            members: Arc::new(nodes.clone()),
+        };
+        let sections = btreemap!{ Prefix::empty() => section };
+
+        let mut partitions = Partitions::new();
+        let pairs = partitions.start(5, p("0"), &nodes, &sections);
+        assert_eq!(pairs, vec![(a, b), (a, c)]);
+        assert!(partitions.holds(c, a));
+        assert!(!partitions.holds(b, c));
+        assert!(!partitions.take_healed(a, b));
+
+        // Nodes that join later are cut off too.
+        let late = p("01").lower_bound();
+        assert!(partitions.refuse_delivery(b, late));
+        assert!(!partitions.refuse_delivery(a, late));
+        assert_eq!(partitions.refused_deliveries(), 1);
+
+        partitions.heal(20);
+        assert!(!partitions.holds(c, a));
+        assert!(!partitions.refuse_delivery(b, late));
+        assert!(partitions.take_healed(a, b));
+        assert!(!partitions.take_healed(a, b));
+
+        let healings = partitions.healings();
+        assert_eq!(healings.len(), 1);
+        assert_eq!(healings[0].prefix, Prefix::empty());
+        assert_eq!(healings[0].partitioned_step, 5);
+        assert_eq!(healings[0].healed_step, Some(20));
+        assert!(partitions.is_active());
+    }
+}
@@ -0,0 +1,195 @@
+//! Connection states of the other members of a node's section.
+//!
+//! A member that we've lost our connection to may still be connected to the rest of the section,
+//! so rather than voting to remove it straight away, we wait `rmconv_timeout` steps for it to
+//! reconnect (rule:RmConv).
+//!
+//! A node's own membership is tracked too: it's only fully confirmed once it's a member of every
+//! one of its section's current blocks. Until then, other members may still disagree about it.
+
+use block::Block;
+use name::Name;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PeerState {
+    Connected,
+    /// We lost our connection to the peer at step `since`.
+    PartiallyLost { since: u64 },
+}
+
+/// Whether a node is a member of its section's current blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Confirmation {
+    /// A member of none of them, e.g. while joining or after being removed.
+    Unconfirmed,
+    /// A member of some of them but not all, since step `since`.
+    PartiallyConfirmed { since: u64 },
+    /// A member of all of them.
+    Confirmed,
+}
+
+impl Confirmation {
+    /// The confirmation of `name` at `step`, given the current blocks for its section and its
+    /// confirmation at the previous step.
+    pub fn update(self, name: Name, section_blocks: &[&Block], step: u64) -> Confirmation {
+        if section_blocks.is_empty() {
+            Confirmation::Unconfirmed
+        } else if nodes_in_all(section_blocks).contains(&name) {
+            Confirmation::Confirmed
+        } else if section_blocks.iter().any(|block| block.members.contains(&name)) {
+            match self {
+                Confirmation::PartiallyConfirmed { .. } => self,
+                _ => Confirmation::PartiallyConfirmed { since: step },
+            }
+        } else {
+            Confirmation::Unconfirmed
+        }
+    }
+
+    /// Number of steps up to `step` that the node has been partially confirmed for.
+    pub fn partial_steps(&self, step: u64) -> Option<u64> {
+        match *self {
+            Confirmation::PartiallyConfirmed { since } => Some(step - since),
+            _ => None,
+        }
+    }
+}
+
+/// Names of the nodes that are members of every one of `blocks`.
+pub fn nodes_in_all(blocks: &[&Block]) -> BTreeSet<Name> {
+    let mut blocks = blocks.iter();
+    let mut names: BTreeSet<Name> = match blocks.next() {
+        Some(block) => (*block.members).clone(),
+        None => return BTreeSet::new(),
+    };
+    for block in blocks {
+        names.retain(|name| block.members.contains(name));
+    }
+    names
+}
+
+/// States of the members of our current section, other than us.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerStates {
+    states: BTreeMap<Name, PeerState>,
+    rmconv_timeout: u64,
+}
+
+impl PeerStates {
+    pub fn new(rmconv_timeout: u64) -> Self {
+        PeerStates {
+            states: BTreeMap::new(),
+            rmconv_timeout,
+        }
+    }
+
+    pub fn get(&self, name: &Name) -> Option<PeerState> {
+        self.states.get(name).cloned()
+    }
+
+    /// Update the states from the members of our section and the peers we're connected to.
+    ///
+    /// Peers that have left the section are forgotten.
+    pub fn update<F>(&mut self, our_name: Name, section: &Block, is_connected: F, step: u64)
+    where
+        F: Fn(&Name) -> bool,
+    {
+        self.states.retain(|name, _| section.members.contains(name));
+        for &name in section.members.iter().filter(|&&name| name != our_name) {
+            let state = self.states.entry(name).or_insert(PeerState::Connected);
+            match (*state, is_connected(&name)) {
+                (PeerState::Connected, false) => *state = PeerState::PartiallyLost { since: step },
+                (PeerState::PartiallyLost { .. }, true) => *state = PeerState::Connected,
+                _ => (),
+            }
+        }
+    }
+
+    /// Peers that we've lost our connections to, and the step we lost each of them.
+    pub fn lost(&self) -> BTreeMap<Name, u64> {
+        self.states
+            .iter()
+            .filter_map(|(&name, &state)| match state {
+                PeerState::PartiallyLost { since } => Some((name, since)),
+                PeerState::Connected => None,
+            })
+            .collect()
+    }
+
+    /// Peers that have been lost for longer than `rmconv_timeout`, and should be removed.
+    pub fn nodes_to_drop(&self, step: u64) -> Vec<Name> {
+        self.states
+            .iter()
+            .filter(|&(_, &state)| match state {
+                PeerState::PartiallyLost { since } => since + self.rmconv_timeout <= step,
+                PeerState::Connected => false,
+            })
+            .map(|(&name, _)| name)
+            .collect()
+    }
+
+    /// The first step after `step` at which a lost peer becomes due for removal, if any.
+    pub fn next_timeout(&self, step: u64) -> Option<u64> {
+        self.states
+            .values()
+            .filter_map(|&state| match state {
+                PeerState::PartiallyLost { since } => Some(since + self.rmconv_timeout),
+                PeerState::Connected => None,
+            })
+            .filter(|&timeout| timeout > step)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use name::{NameBits, Prefix};
+    use std::sync::Arc;
+
+    #[test]
+    fn drops_after_timeout() {
+        let section = Block {
+            prefix: Prefix::empty(),
+            version: 0,
+            members: Arc::new(btreeset!{ Name(0), Name(1), Name(2) }),
+        };
+        let mut peer_states = PeerStates::new(5);
+        peer_states.update(Name(0), &section, |_| true, 0);
+        assert_eq!(peer_states.get(&Name(0)), None);
+        assert_eq!(peer_states.get(&Name(1)), Some(PeerState::Connected));
+
+        peer_states.update(Name(0), &section, |&name| name != Name(1), 3);
+        peer_states.update(Name(0), &section, |&name| name != Name(1), 4);
+        assert_eq!(peer_states.get(&Name(1)), Some(PeerState::PartiallyLost { since: 3 }));
+        assert_eq!(peer_states.next_timeout(4), Some(8));
+        assert!(peer_states.nodes_to_drop(7).is_empty());
+        assert_eq!(peer_states.nodes_to_drop(8), vec![Name(1)]);
+
+        // Reconnecting resets the timeout.
+        peer_states.update(Name(0), &section, |_| true, 5);
+        assert!(peer_states.nodes_to_drop(8).is_empty());
+    }
+
+    #[test]
+    fn confirmation() {
+        let block = |members: &[NameBits]| Block {
+            prefix: Prefix::empty(),
+            version: 1,
+            members: Arc::new(members.iter().cloned().map(Name).collect()),
+        };
+        let (old, new) = (block(&[0, 1, 2]), block(&[0, 1, 2, 3]));
+        assert_eq!(nodes_in_all(&[&old, &new]), btreeset!{ Name(0), Name(1), Name(2) });
+        assert!(nodes_in_all(&[]).is_empty());
+
+        let joining = Confirmation::Unconfirmed.update(Name(3), &[&old], 4);
+        assert_eq!(joining, Confirmation::Unconfirmed);
+        let partial = joining.update(Name(3), &[&old, &new], 5);
+        assert_eq!(partial, Confirmation::PartiallyConfirmed { since: 5 });
+        assert_eq!(partial.update(Name(3), &[&new, &old], 9), partial);
+        assert_eq!(partial.partial_steps(9), Some(4));
+        assert_eq!(partial.update(Name(3), &[&new], 10), Confirmation::Confirmed);
+    }
+}
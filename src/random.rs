@@ -1,36 +1,75 @@
-use rand::{self, thread_rng, XorShiftRng, Rand, Rng, SeedableRng};
-use std::cell::RefCell;
+#[cfg(not(target_arch = "wasm32"))]
+use rand::thread_rng;
+use rand::{self, XorShiftRng, Rand, Rng, SeedableRng};
+use std::cell::{Cell, RefCell};
 use std::env;
 
 thread_local! {
-    static SEED: [u32; 4] = match env::var("EWOK_SEED") {
+    static SEED: Cell<[u32; 4]> = Cell::new(match env::var("EWOK_SEED") {
         Ok(value) => {
-            let nums: Vec<u32> = value.split(|c| c == '[' || c == ']' || c == ' ' || c == ',')
-                                      .filter_map(|s| s.parse().ok())
-                                      .collect();
-            assert_eq!(nums.len(), 4, "EWOK_SEED {} isn't in the form '[1, 2, 3, 4]'.", value);
-            [nums[0], nums[1], nums[2], nums[3]]
+            parse_seed(&value).unwrap_or_else(|| {
+                panic!("EWOK_SEED {} isn't in the form '[1, 2, 3, 4]'.", value)
+            })
         }
-        Err(_) => {
-            let mut rng = thread_rng();
-            [rng.next_u32().wrapping_add(rng.next_u32()),
-             rng.next_u32().wrapping_add(rng.next_u32()),
-             rng.next_u32().wrapping_add(rng.next_u32()),
-             rng.next_u32().wrapping_add(rng.next_u32())]
-        }
-    };
+        Err(_) => fresh_seed(),
+    });
 
     static WEAK_RNG: RefCell<XorShiftRng> = RefCell::new(
         SEED.with(|seed| {
-            println!("Seed: {:?}", seed);
-            XorShiftRng::from_seed(*seed)
+            println!("Seed: {:?}", seed.get());
+            XorShiftRng::from_seed(seed.get())
         })
     );
 }
 
+/// Pick a seed from the OS's random number generator.
+#[cfg(not(target_arch = "wasm32"))]
+fn fresh_seed() -> [u32; 4] {
+    let mut rng = thread_rng();
+    [rng.next_u32().wrapping_add(rng.next_u32()),
+     rng.next_u32().wrapping_add(rng.next_u32()),
+     rng.next_u32().wrapping_add(rng.next_u32()),
+     rng.next_u32().wrapping_add(rng.next_u32())]
+}
+
+/// There's no OS random number generator in WebAssembly, so start from a fixed seed, which the
+/// host can replace with `reseed`.
+#[cfg(target_arch = "wasm32")]
+fn fresh_seed() -> [u32; 4] {
+    [1, 2, 3, 4]
+}
+
 /// Get the seed used for the random number generator.
 pub fn seed() -> [u32; 4] {
-    SEED.with(|seed| *seed)
+    SEED.with(|seed| seed.get())
+}
+
+/// Parse a seed in the form it's printed, e.g. `[1, 2, 3, 4]`.
+pub fn parse_seed(value: &str) -> Option<[u32; 4]> {
+    let nums: Vec<u32> = value.split(&['[', ']', ' ', ','][..])
+                              .filter_map(|s| s.parse().ok())
+                              .collect();
+    if nums.len() == 4 {
+        Some([nums[0], nums[1], nums[2], nums[3]])
+    } else {
+        None
+    }
+}
+
+/// Restart the random number generator from the given seed.
+///
+/// Running the same simulation after reseeding with the same value reproduces it exactly.
+pub fn reseed(seed: [u32; 4]) {
+    SEED.with(|s| s.set(seed));
+    WEAK_RNG.with(|rng| *rng.borrow_mut() = XorShiftRng::from_seed(seed));
+}
+
+/// Switch the random number generator to a stream started from `seed`, part-way through a run.
+///
+/// Unlike `reseed`, this doesn't change the value returned by `seed`, which together with the
+/// points the stream was switched at still reproduces the whole run.
+pub fn switch_stream(seed: [u32; 4]) {
+    WEAK_RNG.with(|rng| *rng.borrow_mut() = XorShiftRng::from_seed(seed));
 }
 
 /// Random value from the thread-local weak RNG.
@@ -39,6 +78,8 @@ pub fn random<T: Rand>() -> T {
 }
 
 /// Sample values from an iterator.
+// Deprecated since rand 0.3.18, which is needed to build for WebAssembly.
+#[allow(deprecated)]
 pub fn sample<T, I>(iterable: I, amount: usize) -> Vec<T>
 where
     I: IntoIterator<Item = T>,
@@ -1,9 +1,9 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::cmp;
 use itertools::Itertools;
-use params::{SimulationParams, NodeParams, quorum};
+use params::{NameDistribution, SimulationParams, NodeParams};
 use blocks::Blocks;
-use name::Name;
+use name::{Name, Prefix, SpreadNames};
 use node::Node;
 use event::Event;
 use random::{random, do_with_probability, shuffle};
@@ -12,41 +12,116 @@ use simulation::Phase;
 pub struct RandomEvents {
     params: SimulationParams,
     node_params: NodeParams,
+    /// Steps of joins generated within the last `churn_memory` steps.
+    recent_joins: VecDeque<u64>,
+    /// Steps of drops generated within the last `churn_memory` steps.
+    recent_drops: VecDeque<u64>,
+    /// Network size that the stable phase began with, for keeping it in a steady state.
+    steady_size: Option<usize>,
+    /// Names for joining nodes, if they're spread evenly rather than random.
+    spread_names: SpreadNames,
+    /// Names of the initial nodes, which mustn't be given to joining nodes.
+    initial_names: BTreeSet<Name>,
 }
 
 impl RandomEvents {
-    pub fn new(params: SimulationParams, node_params: NodeParams) -> Self {
+    pub fn new(
+        params: SimulationParams,
+        node_params: NodeParams,
+        initial_names: BTreeSet<Name>,
+    ) -> Self {
         RandomEvents {
             params,
             node_params,
+            recent_joins: VecDeque::new(),
+            recent_drops: VecDeque::new(),
+            steady_size: None,
+            spread_names: Prefix::empty().spread_names(),
+            initial_names,
         }
     }
 
     pub fn get_events(
-        &self,
+        &mut self,
+        step: u64,
         phase: Phase,
         blocks: &Blocks,
         nodes: &BTreeMap<Name, Node>,
     ) -> Vec<Event> {
         let mut events = vec![];
+        let (prob_join, prob_drop) = self.churn_probabilities(step, phase, nodes.len());
 
-        // Random join.
-        if do_with_probability(self.params.prob_join(phase)) {
-            events.push(self.random_add());
+        // Random join, unless the network has reached its maximum size.
+        let at_max_size = self.params.max_network_size.is_some_and(|max| nodes.len() >= max);
+        if !at_max_size && do_with_probability(prob_join) {
+            events.push(self.random_add(nodes));
+            self.recent_joins.push_back(step);
         }
 
         // Random remove.
-        if do_with_probability(self.params.prob_drop(phase)) {
+        if do_with_probability(prob_drop) {
             if let Some(event) = self.random_remove(blocks, nodes) {
                 events.push(event);
+                self.recent_drops.push_back(step);
             }
         }
 
         events
     }
 
-    fn random_add(&self) -> Event {
-        Event::AddNode(random())
+    /// Probabilities of a random join and a random drop on this step, for a network of
+    /// `network_size` nodes.
+    fn churn_probabilities(&mut self, step: u64, phase: Phase, network_size: usize) -> (f64, f64) {
+        // Forget joins and drops that are too old to affect the probabilities.
+        let oldest = step.saturating_sub(self.params.churn_memory);
+        forget_before(&mut self.recent_joins, oldest);
+        forget_before(&mut self.recent_drops, oldest);
+
+        let correlation = self.params.churn_correlation(phase);
+        let mut prob_join = self.params.prob_join(phase) +
+            correlation * self.recent_drops.len() as f64;
+        let mut prob_drop = self.params.prob_drop(phase) +
+            correlation * self.recent_joins.len() as f64;
+
+        // Pull the network back towards its size at the start of the stable phase.
+        if let Phase::Stable { .. } = phase {
+            if self.params.steady_state {
+                // Squaring the ratio makes up for joins failing more often than drops.
+                let target = *self.steady_size.get_or_insert(network_size) as f64;
+                let ratio = (target / cmp::max(network_size, 1) as f64).powi(2);
+                prob_join *= ratio;
+                prob_drop /= ratio;
+            }
+        }
+
+        (prob_join, prob_drop)
+    }
+
+    /// Generate a client operation on a random piece of data, with probability `data_op_prob`.
+    pub fn get_data_ops(&mut self) -> Vec<Event> {
+        if self.params.data_op_prob > 0.0 && do_with_probability(self.params.data_op_prob) {
+            let data = random();
+            if random() {
+                vec![Event::Put(data)]
+            } else {
+                vec![Event::Get(data)]
+            }
+        } else {
+            vec![]
+        }
+    }
+
+    fn random_add(&mut self, nodes: &BTreeMap<Name, Node>) -> Event {
+        match self.params.name_distribution {
+            NameDistribution::Random => Event::AddNode(random()),
+            NameDistribution::Spread => {
+                let initial_names = &self.initial_names;
+                let name = self.spread_names
+                    .find(|name| !nodes.contains_key(name) && !initial_names.contains(name))
+                    .expect("ran out of names");
+                Event::AddNode(name)
+            }
+        }
     }
 
     fn random_remove(&self, blocks: &Blocks, nodes: &BTreeMap<Name, Node>) -> Option<Event> {
@@ -69,9 +144,10 @@ impl RandomEvents {
                     .intersection(&names_sorted)
                     .count();
                 // Don't sink below a quorum of our current block, OR the min section size.
-                let min_nodes = quorum(cmp::max(
+                let (num_sections, num_nodes) = blocks.network_size(&nodes[&name].current_blocks);
+                let min_nodes = self.node_params.quorum(cmp::max(
                     our_current_block.members.len(),
-                    self.node_params.min_section_size,
+                    self.node_params.min_section_size_for(num_sections, num_nodes),
                 ));
                 if num_live >= min_nodes + 2 {
                     trace!(
@@ -87,3 +163,79 @@ impl RandomEvents {
         None
     }
 }
+
+/// Remove the steps before `oldest` from the front of `recent`.
+fn forget_before(recent: &mut VecDeque<u64>, oldest: u64) {
+    while recent.front().is_some_and(|&step| step < oldest) {
+        recent.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn random_events(params: SimulationParams) -> RandomEvents {
+        RandomEvents::new(params, NodeParams::default(), BTreeSet::new())
+    }
+
+    #[test]
+    fn correlated_churn() {
+        let params = SimulationParams {
+            prob_churn: 0.01,
+            churn_correlation: 0.1,
+            churn_memory: 5,
+            ..SimulationParams::default()
+        };
+        let stable = Phase::Stable { since_step: 0 };
+        let mut events = random_events(params);
+        assert_eq!(events.churn_probabilities(10, stable, 100), (0.01, 0.01));
+
+        // A join makes a drop more likely for the next `churn_memory` steps, and two joins more so.
+        events.recent_joins.push_back(10);
+        events.recent_joins.push_back(11);
+        let (prob_join, prob_drop) = events.churn_probabilities(12, stable, 100);
+        assert_eq!(prob_join, 0.01);
+        assert!((prob_drop - 0.21).abs() < 1e-9);
+        let (_, prob_drop) = events.churn_probabilities(16, stable, 100);
+        assert!((prob_drop - 0.11).abs() < 1e-9);
+        assert_eq!(events.churn_probabilities(17, stable, 100), (0.01, 0.01));
+
+        // And a drop makes a join more likely.
+        events.recent_drops.push_back(20);
+        let (prob_join, prob_drop) = events.churn_probabilities(21, stable, 100);
+        assert!((prob_join - 0.11).abs() < 1e-9);
+        assert_eq!(prob_drop, 0.01);
+
+        // Nothing happens at random while the network is finishing.
+        let finishing = Phase::Finishing { since_step: 0 };
+        assert_eq!(events.churn_probabilities(22, finishing, 100), (0.0, 0.0));
+    }
+
+    #[test]
+    fn steady_state() {
+        let params = SimulationParams {
+            prob_churn: 0.04,
+            steady_state: true,
+            ..SimulationParams::default()
+        };
+        let stable = Phase::Stable { since_step: 0 };
+        let mut events = random_events(params);
+
+        // Joins and drops balance at the size the stable phase started with.
+        assert_eq!(events.churn_probabilities(0, stable, 100), (0.04, 0.04));
+        assert_eq!(events.churn_probabilities(1, stable, 100), (0.04, 0.04));
+
+        // Shrinking makes joins more likely than drops, and growing the reverse.
+        let (prob_join, prob_drop) = events.churn_probabilities(2, stable, 50);
+        assert!((prob_join - 0.16).abs() < 1e-9);
+        assert!((prob_drop - 0.01).abs() < 1e-9);
+        let (prob_join, prob_drop) = events.churn_probabilities(3, stable, 200);
+        assert!((prob_join - 0.01).abs() < 1e-9);
+        assert!((prob_drop - 0.16).abs() < 1e-9);
+
+        // Growth isn't affected.
+        let (prob_join, _) = events.churn_probabilities(4, Phase::Growth, 200);
+        assert_eq!(prob_join, events.params.grow_prob_join);
+    }
+}
@@ -0,0 +1,298 @@
+//! Queries over a recorded run, for debugging it step by step after the fact.
+//!
+//! A `Replayer` loads the trace of a run (see `trace`) and indexes it by node and step, so that
+//! tools can ask what a node received at a given step, or when a block first became valid at a
+//! node, without scanning the whole trace for each question. Only what the trace records can be
+//! answered: blocks become valid at a node when it sees a vote for them reach quorum, so genesis
+//! blocks, which no vote leads to, are never reported as valid.
+
+use block::{Block, BlockId, Vote};
+use blocks::Blocks;
+use event::Event;
+use message::{MessageKind, TraceId};
+use name::Name;
+use params::NodeParams;
+use trace::{Record, TraceReader};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+
+/// A message delivered to a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Received {
+    pub sender: Name,
+    pub message_kind: MessageKind,
+    pub trace_id: TraceId,
+}
+
+/// A node saw a vote reach quorum.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Agreement {
+    pub step: u64,
+    pub node: Name,
+    pub vote: Vote,
+    /// The voters the node counted towards the quorum.
+    pub voters: BTreeSet<Name>,
+}
+
+/// Index of a recorded run.
+#[derive(Clone, Debug, Default)]
+pub struct Replayer {
+    seed: Option<[u32; 4]>,
+    node_params: Option<NodeParams>,
+    last_step: u64,
+    blocks: BTreeMap<BlockId, Block>,
+    events: BTreeMap<u64, Vec<Event>>,
+    /// Every agreement, in the order they were recorded.
+    agreements: Vec<Agreement>,
+    /// Messages delivered to each node at each step, in the order they were delivered.
+    received: BTreeMap<(Name, u64), Vec<Received>>,
+    /// Step at which each block first became valid at each node.
+    first_valid: BTreeMap<Name, BTreeMap<BlockId, u64>>,
+}
+
+impl Replayer {
+    /// Load the trace file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Replayer::from_records(TraceReader::open(path)?))
+    }
+
+    /// Index the records of a trace, in the order they were written.
+    pub fn from_records<I: IntoIterator<Item = Record>>(records: I) -> Self {
+        let mut replayer = Replayer::default();
+        for record in records {
+            match record {
+                Record::Params { seed, node_params, .. } => {
+                    replayer.seed = Some(seed);
+                    replayer.node_params = Some(*node_params);
+                }
+                Record::Step { step, .. } |
+                Record::Finished { step, .. } => replayer.last_step = step,
+                Record::Event { step, event } => {
+                    replayer.events.entry(step).or_default().push(event);
+                }
+                Record::Block { id, block } => {
+                    replayer.blocks.insert(id, block);
+                }
+                Record::Agreement {
+                    step,
+                    node,
+                    from,
+                    to,
+                    voters,
+                } => {
+                    replayer
+                        .first_valid
+                        .entry(node)
+                        .or_default()
+                        .entry(to)
+                        .or_insert(step);
+                    replayer.agreements.push(Agreement {
+                        step,
+                        node,
+                        vote: Vote { from, to },
+                        voters,
+                    });
+                }
+                Record::Delivered {
+                    step,
+                    sender,
+                    recipient,
+                    message_kind,
+                    trace_id,
+                } => {
+                    replayer.received.entry((recipient, step)).or_default().push(Received {
+                        sender,
+                        message_kind,
+                        trace_id,
+                    });
+                }
+                _ => (),
+            }
+        }
+        replayer
+    }
+
+    /// Seed of the run, if the trace recorded its parameters.
+    pub fn seed(&self) -> Option<[u32; 4]> {
+        self.seed
+    }
+
+    /// Node parameters of the run, if the trace recorded them.
+    pub fn node_params(&self) -> Option<&NodeParams> {
+        self.node_params.as_ref()
+    }
+
+    /// Number of the last step the run reached.
+    pub fn last_step(&self) -> u64 {
+        self.last_step
+    }
+
+    /// Contents of a block that the trace described.
+    pub fn block(&self, id: BlockId) -> Option<&Block> {
+        self.blocks.get(&id)
+    }
+
+    /// Every block the trace described, for looking up the blocks of votes.
+    pub fn blocks(&self) -> Blocks {
+        let mut blocks = Blocks::new();
+        for block in self.blocks.values() {
+            blocks.insert(block.clone());
+        }
+        blocks
+    }
+
+    /// Every agreement in the trace, in the order they were recorded.
+    pub fn agreements(&self) -> &[Agreement] {
+        &self.agreements
+    }
+
+    /// Events applied at `step`.
+    pub fn events(&self, step: u64) -> &[Event] {
+        self.events.get(&step).map_or(&[], Vec::as_slice)
+    }
+
+    /// Messages delivered to `node` at `step`.
+    pub fn received(&self, node: Name, step: u64) -> &[Received] {
+        self.received.get(&(node, step)).map_or(&[], Vec::as_slice)
+    }
+
+    /// Step at which `block` first became valid at `node`, if it ever did.
+    pub fn first_valid(&self, node: Name, block: BlockId) -> Option<u64> {
+        self.first_valid.get(&node)?.get(&block).cloned()
+    }
+
+    /// Blocks that had become valid at `node` by the end of `step`, in the order they did.
+    pub fn valid_blocks(&self, node: Name, step: u64) -> Vec<BlockId> {
+        let mut valid: Vec<(u64, BlockId)> = self.first_valid
+            .get(&node)
+            .into_iter()
+            .flat_map(|first_valid| first_valid.iter())
+            .filter(|&(_, &valid_step)| valid_step <= step)
+            .map(|(&block, &valid_step)| (valid_step, block))
+            .collect();
+        valid.sort();
+        valid.into_iter().map(|(_, block)| block).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use event::Event::AddNode;
+    use event_schedule::EventSchedule;
+    use name::Prefix;
+    use random::{random, seed};
+    use simulation::{Phase, Simulation};
+    use std::{env, fs, process};
+    use std::sync::Arc;
+    use testing::{p, quiet_params};
+    use trace;
+
+    #[test]
+    fn queries_by_node_and_step() {
+        let (a, b) = (Name(1), Name(2));
+        let block = |version| {
+            Block {
+                prefix: Prefix::empty(),
+                version,
+                members: Arc::new(btreeset!{ a, b }),
+            }
+        };
+        let (b1, b2) = (block(1), block(2));
+        let delivered = |step, sender, recipient, trace_id| {
+            Record::Delivered {
+                step,
+                sender,
+                recipient,
+                message_kind: MessageKind::Vote,
+                trace_id,
+            }
+        };
+        let agreement = |step, node, from: &Block, to: &Block| {
+            Record::Agreement {
+                step,
+                node,
+                from: from.get_id(),
+                to: to.get_id(),
+                voters: btreeset!{ a, b },
+            }
+        };
+        let records = vec![
+            Record::Block {
+                id: b1.get_id(),
+                block: b1.clone(),
+            },
+            Record::Block {
+                id: b2.get_id(),
+                block: b2.clone(),
+            },
+            delivered(4, a, b, 10),
+            delivered(4, b, a, 11),
+            delivered(4, b, a, 12),
+            agreement(5, a, &b1, &b2),
+            agreement(6, b, &b1, &b2),
+            agreement(7, a, &b1, &b2),
+            Record::Step {
+                step: 7,
+                phase: Phase::Starting,
+                nodes: 2,
+            },
+        ];
+        let replayer = Replayer::from_records(records);
+
+        let received: Vec<_> = replayer.received(a, 4).iter().map(|r| r.trace_id).collect();
+        assert_eq!(received, vec![11, 12]);
+        assert!(replayer.received(a, 5).is_empty());
+        assert_eq!(replayer.first_valid(a, b2.get_id()), Some(5));
+        assert_eq!(replayer.first_valid(b, b2.get_id()), Some(6));
+        assert_eq!(replayer.first_valid(b, b1.get_id()), None);
+        assert!(replayer.valid_blocks(b, 5).is_empty());
+        assert_eq!(replayer.valid_blocks(b, 6), vec![b2.get_id()]);
+        assert_eq!(replayer.block(b2.get_id()), Some(&b2));
+        assert_eq!(replayer.last_step(), 7);
+    }
+
+    #[test]
+    fn reads_recorded_run() {
+        let path = env::temp_dir().join(format!("ewok-replay-{}.jsonl", process::id()));
+        trace::trace_to(&path).unwrap();
+
+        let node_params = NodeParams::default();
+        let sections = btreemap!{
+            p("0") => node_params.min_section_size,
+            p("1") => node_params.min_section_size,
+        };
+        let joining: Name = random();
+        let schedule = EventSchedule::new(btreemap!{ 5 => vec![AddNode(joining)] });
+        let mut simulation =
+            Simulation::new_from(sections, schedule, quiet_params(), node_params.clone());
+        let final_blocks = simulation.run().unwrap();
+        trace::flush();
+
+        let replayer = Replayer::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replayer.seed(), Some(seed()));
+        assert_eq!(replayer.node_params(), Some(&node_params));
+        assert_eq!(replayer.last_step(), simulation.step());
+        assert_eq!(replayer.events(5), &[AddNode(joining)]);
+        let mut steps = 6..=replayer.last_step();
+        assert!(steps.any(|step| !replayer.received(joining, step).is_empty()));
+
+        // The joining node saw its own section's final block become valid, and every agreement
+        // refers to blocks the trace described.
+        let blocks = replayer.blocks();
+        let joined = final_blocks
+            .values()
+            .find(|block| block.members.contains(&joining))
+            .unwrap();
+        assert!(replayer.first_valid(joining, joined.get_id()).is_some());
+        assert!(!replayer.agreements().is_empty());
+        for agreement in replayer.agreements() {
+            assert!(blocks.get(&agreement.vote.from).is_some());
+            assert!(blocks.get(&agreement.vote.to).is_some());
+            assert!(!agreement.voters.is_empty());
+        }
+    }
+}
@@ -0,0 +1,425 @@
+//! SQLite sink for the results of a batch of simulations.
+//!
+//! If the `EWOK_RESULTS_DB` environment variable is set (and ewok is built with the `sqlite`
+//! feature), every trace record is also written to the SQLite database it names. Each run gets a
+//! row in `runs`, and every other table refers to it by `run_id`, so a single database can hold
+//! a whole batch and be queried across runs, e.g.
+//!
+//! ```sql
+//! SELECT json_extract(params, '$.max_delay'), AVG(consistent)
+//! FROM runs JOIN reports USING (run_id) GROUP BY 1;
+//! ```
+//!
+//! Names are stored as zero-padded hex strings and block IDs as hex strings, so that they sort
+//! correctly and match the rest of ewok's output.
+
+use event::Event;
+use name::{Name, NAME_BITS};
+use params::{NodeParams, SimulationParams};
+use trace::Record;
+
+use rusqlite::{self, Connection};
+use serde_json;
+use std::cell::RefCell;
+use std::env;
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    run_id INTEGER PRIMARY KEY,
+    seed TEXT NOT NULL,
+    params TEXT NOT NULL,
+    node_params TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS steps (
+    run_id INTEGER NOT NULL REFERENCES runs,
+    step INTEGER NOT NULL,
+    phase TEXT NOT NULL,
+    nodes INTEGER NOT NULL,
+    messages INTEGER NOT NULL,
+    bytes INTEGER NOT NULL,
+    queue_size INTEGER NOT NULL,
+    PRIMARY KEY (run_id, step)
+);
+CREATE TABLE IF NOT EXISTS events (
+    run_id INTEGER NOT NULL REFERENCES runs,
+    step INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    event TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS agreements (
+    run_id INTEGER NOT NULL REFERENCES runs,
+    step INTEGER NOT NULL,
+    node TEXT NOT NULL,
+    from_block TEXT NOT NULL,
+    to_block TEXT NOT NULL,
+    voters INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS chaos_actions (
+    run_id INTEGER NOT NULL REFERENCES runs,
+    step INTEGER NOT NULL,
+    action TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS data_ops (
+    run_id INTEGER NOT NULL REFERENCES runs,
+    step INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    data TEXT NOT NULL,
+    success INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS storms (
+    run_id INTEGER NOT NULL REFERENCES runs,
+    step INTEGER NOT NULL,
+    node TEXT NOT NULL,
+    messages INTEGER NOT NULL,
+    deferred_votes INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS attackers (
+    run_id INTEGER NOT NULL REFERENCES runs,
+    step INTEGER NOT NULL,
+    prefix TEXT NOT NULL,
+    attackers INTEGER NOT NULL,
+    members INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS overload_crashes (
+    run_id INTEGER NOT NULL REFERENCES runs,
+    step INTEGER NOT NULL,
+    node TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    load INTEGER NOT NULL,
+    after_merge INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS reports (
+    run_id INTEGER PRIMARY KEY REFERENCES runs,
+    step INTEGER NOT NULL,
+    consistent INTEGER NOT NULL,
+    sections INTEGER NOT NULL,
+    nodes INTEGER NOT NULL,
+    force_merges INTEGER NOT NULL DEFAULT 0
+);
+";
+
+/// Metrics for the step in progress, written once the step ends.
+struct StepRow {
+    step: u64,
+    phase: String,
+    nodes: usize,
+    messages: usize,
+    bytes: usize,
+}
+
+/// Database of results, to which the records of one run at a time are written.
+pub struct ResultsDb {
+    conn: Connection,
+    run_id: Option<i64>,
+    current_step: Option<StepRow>,
+}
+
+impl ResultsDb {
+    /// Open the database at `path`, creating it and its tables if they don't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(ResultsDb {
+            conn,
+            run_id: None,
+            current_step: None,
+        })
+    }
+
+    /// Start a new run, to which subsequent records belong.
+    pub fn begin_run(
+        &mut self,
+        seed: [u32; 4],
+        params: &SimulationParams,
+        node_params: &NodeParams,
+    ) -> rusqlite::Result<i64> {
+        // A run that panicked may have left its last step uncommitted.
+        self.commit()?;
+        self.conn.execute(
+            "INSERT INTO runs (seed, params, node_params) VALUES (?1, ?2, ?3)",
+            rusqlite::params![format!("{:?}", seed), to_json(params), to_json(node_params)],
+        )?;
+        let run_id = self.conn.last_insert_rowid();
+        self.run_id = Some(run_id);
+        self.current_step = None;
+        Ok(run_id)
+    }
+
+    /// Write a trace record to the database, as part of the current run.
+    ///
+    /// Records that arrive before the run's `Params` record are ignored.
+    pub fn write(&mut self, record: &Record) -> rusqlite::Result<()> {
+        if let Record::Params {
+            seed,
+            ref params,
+            ref node_params,
+        } = *record
+        {
+            self.begin_run(seed, params, node_params)?;
+            return Ok(());
+        }
+        let run_id = match self.run_id {
+            Some(run_id) => run_id,
+            None => return Ok(()),
+        };
+        match *record {
+            Record::Params { .. } | Record::Block { .. } => (),
+            Record::Step { step, phase, nodes } => {
+                // Each step is written in a single transaction, as committing every row separately
+                // is very slow.
+                self.commit()?;
+                self.conn.execute_batch("BEGIN")?;
+                self.current_step = Some(StepRow {
+                    step,
+                    phase: format!("{:?}", phase),
+                    nodes,
+                    messages: 0,
+                    bytes: 0,
+                });
+            }
+            Record::Event { step, ref event } => {
+                self.conn.execute(
+                    "INSERT INTO events (run_id, step, kind, event) VALUES (?1, ?2, ?3, ?4)",
+                    rusqlite::params![run_id, step, event_kind(event), to_json(event)],
+                )?;
+            }
+            Record::Agreement {
+                step,
+                node,
+                from,
+                to,
+                ref voters,
+            } => {
+                self.conn.execute(
+                    "INSERT INTO agreements (run_id, step, node, from_block, to_block, voters) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    rusqlite::params![
+                        run_id,
+                        step,
+                        name_hex(node),
+                        from.to_string(),
+                        to.to_string(),
+                        voters.len(),
+                    ],
+                )?;
+            }
+            Record::Sent { count, bytes, .. } => {
+                if let Some(ref mut row) = self.current_step {
+                    row.messages += count;
+                    row.bytes += bytes;
+                }
+            }
+            Record::SentByKind { .. } | Record::Delivered { .. } => (),
+            Record::Chaos { step, ref action } => {
+                self.conn.execute(
+                    "INSERT INTO chaos_actions (run_id, step, action) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![run_id, step, to_json(action)],
+                )?;
+            }
+            Record::DataOp {
+                step,
+                ref event,
+                success,
+            } => {
+                let data = match *event {
+                    Event::Put(data) | Event::Get(data) => name_hex(data),
+                    _ => String::new(),
+                };
+                self.conn.execute(
+                    "INSERT INTO data_ops (run_id, step, kind, data, success) \
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    rusqlite::params![run_id, step, event_kind(event), data, success],
+                )?;
+            }
+            Record::Storm {
+                step,
+                node,
+                messages,
+                deferred_votes,
+            } => {
+                self.conn.execute(
+                    "INSERT INTO storms (run_id, step, node, messages, deferred_votes) \
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    rusqlite::params![run_id, step, name_hex(node), messages, deferred_votes],
+                )?;
+            }
+            Record::Attackers {
+                step,
+                prefix,
+                attackers,
+                members,
+            } => {
+                self.conn.execute(
+                    "INSERT INTO attackers (run_id, step, prefix, attackers, members) \
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    rusqlite::params![run_id, step, prefix.to_string(), attackers, members],
+                )?;
+            }
+            Record::OverloadCrash {
+                step,
+                node,
+                prefix,
+                load,
+                after_merge,
+            } => {
+                self.conn.execute(
+                    "INSERT INTO overload_crashes (run_id, step, node, prefix, load, after_merge) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    rusqlite::params![
+                        run_id,
+                        step,
+                        name_hex(node),
+                        prefix.to_string(),
+                        load,
+                        after_merge
+                    ],
+                )?;
+            }
+            Record::Queue { step, size } => {
+                if let Some(row) = self.current_step.take() {
+                    debug_assert_eq!(row.step, step);
+                    self.conn.execute(
+                        "INSERT INTO steps (run_id, step, phase, nodes, messages, bytes, \
+                         queue_size) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                        rusqlite::params![
+                            run_id,
+                            row.step,
+                            row.phase,
+                            row.nodes,
+                            row.messages,
+                            row.bytes,
+                            size,
+                        ],
+                    )?;
+                }
+                self.commit()?;
+            }
+            Record::Finished {
+                step,
+                consistent,
+                sections,
+                nodes,
+                force_merges,
+            } => {
+                self.conn.execute(
+                    "INSERT INTO reports (run_id, step, consistent, sections, nodes, \
+                     force_merges) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    rusqlite::params![run_id, step, consistent, sections, nodes, force_merges],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Commit the transaction in progress, if there is one.
+    fn commit(&mut self) -> rusqlite::Result<()> {
+        if self.conn.is_autocommit() {
+            Ok(())
+        } else {
+            self.conn.execute_batch("COMMIT")
+        }
+    }
+
+    /// Number of rows in a table, mostly for testing.
+    pub fn count(&self, table: &str) -> rusqlite::Result<i64> {
+        self.conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0))
+    }
+}
+
+fn to_json<T: ::serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("failed to serialise value")
+}
+
+/// Full-width hex representation of a name.
+fn name_hex(name: Name) -> String {
+    format!("{:01$x}", name.0, NAME_BITS / 4)
+}
+
+fn event_kind(event: &Event) -> &'static str {
+    match *event {
+        Event::AddNode(_) => "AddNode",
+        Event::AddNodeUntil(..) => "AddNodeUntil",
+        Event::RemoveNode(_) => "RemoveNode",
+        Event::RemoveNodeFrom(_) => "RemoveNodeFrom",
+        Event::CrashNode(_) => "CrashNode",
+        Event::AddStaleNode(_) => "AddStaleNode",
+        Event::RestartNode(_) => "RestartNode",
+        Event::Put(_) => "Put",
+        Event::Get(_) => "Get",
+        Event::Partition(_) => "Partition",
+        Event::HealPartition => "HealPartition",
+        Event::AddClient(_) => "AddClient",
+        Event::RemoveClient(_) => "RemoveClient",
+        Event::IntroduceRoots => "IntroduceRoots",
+    }
+}
+
+thread_local! {
+    static RESULTS: RefCell<Option<ResultsDb>> = RefCell::new(
+        env::var("EWOK_RESULTS_DB").ok().map(|path| {
+            ResultsDb::open(&path)
+                .unwrap_or_else(|e| panic!("couldn't open results database {}: {}", path, e))
+        })
+    );
+}
+
+/// Direct results for this thread to the database at `path`, replacing any existing database.
+pub fn results_to<P: AsRef<Path>>(path: P) -> rusqlite::Result<()> {
+    let db = ResultsDb::open(path)?;
+    RESULTS.with(|results| *results.borrow_mut() = Some(db));
+    Ok(())
+}
+
+/// Whether results are being written to a database on this thread.
+pub fn is_enabled() -> bool {
+    RESULTS.with(|results| results.borrow().is_some())
+}
+
+/// Write a trace record to this thread's database, if there is one.
+pub fn record(record: &Record) {
+    RESULTS.with(|results| if let Some(ref mut db) = *results.borrow_mut() {
+        db.write(record).expect("failed to write to results database");
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use event_schedule::EventSchedule;
+    use name::Prefix;
+    use shrink::replay_params;
+    use simulation::Simulation;
+    use std::fs;
+
+    #[test]
+    fn records_runs() {
+        let path = env::temp_dir().join(format!("ewok-results-{}.db", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        results_to(&path).unwrap();
+
+        for _ in 0..2 {
+            let sections = btreemap!{ Prefix::empty() => 8 };
+            let events = vec![(1, Event::AddNode(Name(1)))];
+            let mut simulation = Simulation::new_from(
+                sections,
+                EventSchedule::from_events(events.clone()),
+                replay_params(&SimulationParams::default(), &events),
+                NodeParams::default(),
+            );
+            simulation.run().unwrap();
+        }
+
+        RESULTS.with(|results| {
+            let results = results.borrow();
+            let db = results.as_ref().unwrap();
+            assert_eq!(db.count("runs").unwrap(), 2);
+            assert_eq!(db.count("events").unwrap(), 2);
+            assert_eq!(db.count("reports").unwrap(), 2);
+            assert!(db.count("steps").unwrap() > 2);
+            assert!(db.count("agreements").unwrap() > 0);
+        });
+        RESULTS.with(|results| *results.borrow_mut() = None);
+        fs::remove_file(&path).unwrap();
+    }
+}
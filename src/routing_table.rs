@@ -0,0 +1,112 @@
+//! Kademlia-style routing table, for modelling nodes that only know some of the network.
+
+use blocks::{Blocks, CurrentBlocks};
+use name::Name;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The nodes that a node knows how to reach directly.
+///
+/// Every member of our own section is known, while the rest of the network is grouped into
+/// buckets by the length of the prefix they share with our name, keeping only the `bucket_size`
+/// nodes closest to us from each bucket.
+#[derive(Clone, Debug)]
+pub struct RoutingTable {
+    our_name: Name,
+    bucket_size: usize,
+    /// Members of the section(s) that we belong to.
+    section: BTreeSet<Name>,
+    /// Map from common prefix length with our name to the nodes in that bucket.
+    buckets: BTreeMap<usize, BTreeSet<Name>>,
+}
+
+impl RoutingTable {
+    pub fn new(our_name: Name, bucket_size: usize) -> Self {
+        RoutingTable {
+            our_name,
+            bucket_size,
+            section: BTreeSet::new(),
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// Rebuild the table from the members of the given current blocks.
+    pub fn update(&mut self, blocks: &Blocks, current_blocks: &CurrentBlocks) {
+        let our_name = self.our_name;
+        let mut section = BTreeSet::new();
+        let mut candidates: BTreeMap<usize, Vec<Name>> = BTreeMap::new();
+        for block in blocks.block_contents(current_blocks) {
+            if block.prefix.matches(our_name) {
+                section.extend(block.members.iter().cloned());
+                continue;
+            }
+            for &name in block.members.iter() {
+                candidates
+                    .entry(our_name.common_prefix(name))
+                    .or_default()
+                    .push(name);
+            }
+        }
+
+        self.buckets = candidates
+            .into_iter()
+            .map(|(bucket, mut names)| {
+                names.sort_by(|&lhs, &rhs| our_name.cmp_distance(lhs, rhs));
+                names.dedup();
+                names.truncate(self.bucket_size);
+                (bucket, names.into_iter().collect())
+            })
+            .collect();
+        section.remove(&our_name);
+        self.section = section;
+    }
+
+    /// Returns `true` if we can reach `name` directly.
+    pub fn contains(&self, name: &Name) -> bool {
+        self.section.contains(name) ||
+            self.buckets
+                .get(&self.our_name.common_prefix(*name))
+                .is_some_and(|bucket| bucket.contains(name))
+    }
+
+    /// Returns the known node closest to `target` by XOR distance, if we know any.
+    pub fn closest(&self, target: Name) -> Option<Name> {
+        self.section
+            .iter()
+            .chain(self.buckets.values().flat_map(|bucket| bucket.iter()))
+            .min_by(|&&lhs, &&rhs| target.cmp_distance(lhs, rhs))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use block::Block;
+    use name::Prefix;
+    use std::sync::Arc;
+
+    #[test]
+    fn buckets_are_limited() {
+        let name = |bits: u8| Prefix::short(8, bits).lower_bound();
+        let mut blocks = Blocks::new();
+        let current_blocks = btreeset!{
+            blocks.insert(Block {
+                prefix: Prefix::short(1, 0),
+                version: 0,
+                members: Arc::new(btreeset!{ name(0b00000000), name(0b00010000), name(0b01000000) }),
+            }),
+            blocks.insert(Block {
+                prefix: Prefix::short(1, 0b10000000),
+                version: 0,
+                members: Arc::new(btreeset!{ name(0b10000000), name(0b11000000), name(0b11100000) }),
+            }),
+        };
+
+        let mut table = RoutingTable::new(name(0b00000000), 2);
+        table.update(&blocks, &current_blocks);
+        assert!(table.contains(&name(0b00010000)) && table.contains(&name(0b01000000)));
+        assert!(table.contains(&name(0b10000000)) && table.contains(&name(0b11000000)));
+        assert!(!table.contains(&name(0b11100000)));
+        assert_eq!(table.closest(name(0b11100000)), Some(name(0b11000000)));
+    }
+}
@@ -0,0 +1,291 @@
+//! Named setups that can be run by name, e.g. `ewok --scenario parallel_merge`.
+//!
+//! Each scenario starts from a fixed set of sections and a schedule of events, with random churn
+//! and disconnections turned off, so that a run is reproduced exactly by its seed. Parameters set
+//! by environment variables still apply on top of the scenario's own.
+
+use event::Event::*;
+use event_schedule::EventSchedule;
+use name::{Name, Prefix};
+use params::{CandidateBehaviour, NameDistribution, NodeParams, SimulationParams};
+use random::random;
+use simulation::Simulation;
+use testing::{add_events, p, quiet_params, sections};
+
+use std::cmp;
+
+pub struct Scenario {
+    /// Name to run the scenario by.
+    pub name: &'static str,
+    /// What happens in the scenario.
+    pub description: &'static str,
+    params: fn() -> (SimulationParams, NodeParams),
+    build: fn(SimulationParams, NodeParams) -> Simulation,
+}
+
+impl Scenario {
+    /// The parameters that the scenario runs with unless they're overridden.
+    pub fn params(&self) -> (SimulationParams, NodeParams) {
+        (self.params)()
+    }
+
+    /// Set up the scenario's simulation with the given parameters.
+    ///
+    /// Names are chosen randomly, so reseed first to reproduce a run.
+    pub fn simulation(&self, params: SimulationParams, node_params: NodeParams) -> Simulation {
+        (self.build)(params, node_params)
+    }
+}
+
+/// Every registered scenario.
+pub const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "four_sections",
+        description: "Three nodes leave two of four sections, which merge.",
+        params: || (quiet_params(), NodeParams::default()),
+        build: four_sections,
+    },
+    Scenario {
+        name: "parallel_merge",
+        description: "00 and 01 merge into 0 at the same time that 10 and 11 merge into 1.",
+        params: || {
+            let params = SimulationParams {
+                max_delay: 20,
+                ..quiet_params()
+            };
+            (params, NodeParams::default())
+        },
+        build: parallel_merge,
+    },
+    Scenario {
+        name: "growth_then_cascade",
+        description: "Two sections grow and split into 10, 110 and 111, then 10 shrinks, causing \
+                      a cascading merge.",
+        params: || (quiet_params(), NodeParams::default()),
+        build: growth_then_cascade,
+    },
+    Scenario {
+        name: "malicious_joins",
+        description: "Malicious nodes repeatedly try to join a single section with resource \
+                      proof enabled.",
+        params: || {
+            let params = SimulationParams {
+                malicious_join_prob: 1.0,
+                malicious_behaviour: CandidateBehaviour::PartialConnections,
+                ..quiet_params()
+            };
+            let node_params = NodeParams {
+                resource_proof_steps: 5,
+                ..NodeParams::default()
+            };
+            (params, node_params)
+        },
+        build: malicious_joins,
+    },
+    Scenario {
+        name: "relocation",
+        description: "Nodes join two sections and are each relocated to the section of their \
+                      relocated name.",
+        params: || {
+            let node_params = NodeParams {
+                relocate_new_nodes: true,
+                ..NodeParams::default()
+            };
+            (quiet_params(), node_params)
+        },
+        build: relocation,
+    },
+    Scenario {
+        name: "eclipse_attack",
+        description: "A coordinated group of attackers join 0 in quick succession, each cutting \
+                      itself off from the honest members of the section once it's added.",
+        params: || (quiet_params(), NodeParams::default()),
+        build: |params, node_params| {
+            let num_attackers = node_params.min_section_size;
+            eclipse_attack(params, node_params, p("0"), num_attackers, 5)
+        },
+    },
+    Scenario {
+        name: "sybil_flood",
+        description: "Sybil nodes numbering half the network all join four sections within 10 \
+                      steps, set by the `sybil_*` parameters.",
+        params: || {
+            let params = SimulationParams {
+                sybil_fraction: 0.5,
+                ..quiet_params()
+            };
+            (params, NodeParams::default())
+        },
+        build: sybil_flood,
+    },
+    Scenario {
+        name: "partition_heal",
+        description: "Section 0 is split by a partition between 00 and 01 while a node leaves \
+                      each side, and then the partition heals.",
+        params: || {
+            // Spread names put half of section 0 on each side of the partition.
+            let params = SimulationParams {
+                name_distribution: NameDistribution::Spread,
+                ..quiet_params()
+            };
+            (params, NodeParams::default())
+        },
+        build: partition_heal,
+    },
+];
+
+/// Find the scenario with the given name.
+pub fn find(name: &str) -> Option<&'static Scenario> {
+    SCENARIOS.iter().find(|scenario| scenario.name == name)
+}
+
+fn four_sections(params: SimulationParams, node_params: NodeParams) -> Simulation {
+    let sections = sections(&["00", "01", "10", "11"], node_params.min_section_size);
+    let event_schedule = EventSchedule::new(btreemap! {
+        0 => vec![
+            RemoveNodeFrom(p("00")),
+            RemoveNodeFrom(p("01")),
+        ],
+        3 => vec![RemoveNodeFrom(p("01"))]
+    });
+    Simulation::new_from(sections, event_schedule, params, node_params)
+}
+
+fn parallel_merge(params: SimulationParams, node_params: NodeParams) -> Simulation {
+    let sections = sections(&["00", "01", "10", "11"], node_params.min_section_size);
+    let event_schedule = EventSchedule::new(btreemap! {
+        0 => vec![
+            RemoveNodeFrom(p("00")),
+            RemoveNodeFrom(p("11")),
+        ],
+    });
+    Simulation::new_from(sections, event_schedule, params, node_params)
+}
+
+fn growth_then_cascade(params: SimulationParams, node_params: NodeParams) -> Simulation {
+    let sections = sections(&["0", "1"], node_params.min_section_size);
+
+    let step_size = 20;
+    let mut schedule = EventSchedule::empty();
+    let add_to = |prefix: Prefix| AddNode(prefix.substituted_in(random()));
+
+    // 8 nodes in 10.
+    add_events(&mut schedule, 0, step_size, (0..9).map(|_| add_to(p("10"))).collect());
+    // 8 nodes in 111 (should cause a split into 10 and 11).
+    add_events(&mut schedule, 50, step_size, (0..9).map(|_| add_to(p("111"))).collect());
+    // 8 nodes in 110 (should cause a split into 110 and 111).
+    add_events(&mut schedule, 50, step_size, (0..9).map(|_| add_to(p("110"))).collect());
+    // Remove 8 nodes from 10, should cause a cascading merge!
+    add_events(
+        &mut schedule,
+        50,
+        2 * step_size,
+        (0..8).map(|_| RemoveNodeFrom(p("10"))).collect(),
+    );
+
+    Simulation::new_from(sections, schedule, params, node_params)
+}
+
+fn malicious_joins(params: SimulationParams, node_params: NodeParams) -> Simulation {
+    let sections = btreemap! {
+        Prefix::empty() => node_params.min_section_size
+    };
+    // Give each candidate time to be turned away before the next tries.
+    let mut schedule = EventSchedule::empty();
+    let spacing = node_params.join_timeout + 10;
+    add_events(&mut schedule, 0, spacing, (0..5).map(|_| AddNode(random())).collect());
+    Simulation::new_from(sections, schedule, params, node_params)
+}
+
+fn relocation(params: SimulationParams, node_params: NodeParams) -> Simulation {
+    let sections = sections(&["0", "1"], node_params.min_section_size);
+    let mut schedule = EventSchedule::empty();
+    add_events(&mut schedule, 0, 10, (0..4).map(|_| AddNode(random())).collect());
+    Simulation::new_from(sections, schedule, params, node_params)
+}
+
+/// Set up an eclipse attack on one of two sections, `0` and `1`.
+///
+/// `num_attackers` attacker-controlled nodes with names in `target` join one every `spacing`
+/// steps. They behave honestly until they're added, and then refuse connections to every member
+/// of their section that isn't an attacker. Whether they ever make up a quorum of an agreed block
+/// is reported when the simulation finishes, and by `Simulation::attacker_quorum`.
+pub fn eclipse_attack(
+    params: SimulationParams,
+    node_params: NodeParams,
+    target: Prefix,
+    num_attackers: usize,
+    spacing: u64,
+) -> Simulation {
+    let sections = sections(&["0", "1"], node_params.min_section_size);
+    let attackers: Vec<Name> = (0..num_attackers)
+        .map(|_| target.substituted_in(random()))
+        .collect();
+    let mut schedule = EventSchedule::empty();
+    add_events(
+        &mut schedule,
+        0,
+        spacing,
+        attackers.iter().map(|&name| AddNode(name)).collect(),
+    );
+    let mut simulation = Simulation::new_from(sections, schedule, params, node_params);
+    simulation.add_attackers(attackers, CandidateBehaviour::Eclipse);
+    simulation
+}
+
+/// Flood four sections with sybil nodes, which follow the protocol but are all controlled by the
+/// attacker.
+///
+/// The number of sybils is `sybil_fraction` of the network's size, and each joins at a random step
+/// within the first `sybil_window` steps, with a name in `sybil_target` if it's set. How many of
+/// each section's members are sybils as the joins are handled is traced, and the peak reported
+/// when the simulation finishes.
+fn sybil_flood(params: SimulationParams, node_params: NodeParams) -> Simulation {
+    let sections = sections(&["00", "01", "10", "11"], node_params.min_section_size);
+    let network_size: usize = sections.values().sum();
+    let num_sybils = (params.sybil_fraction * network_size as f64).ceil() as usize;
+    let window = cmp::max(params.sybil_window, 1);
+
+    let mut schedule = EventSchedule::empty();
+    let mut sybils = vec![];
+    for _ in 0..num_sybils {
+        let name = match params.sybil_target {
+            Some(prefix) => prefix.substituted_in(random()),
+            None => random(),
+        };
+        let step = 1 + random::<u64>() % window;
+        schedule.schedule.entry(step).or_default().push(AddNode(name));
+        sybils.push(name);
+    }
+    let mut simulation = Simulation::new_from(sections, schedule, params, node_params);
+    simulation.add_attackers(sybils, CandidateBehaviour::Honest);
+    simulation
+}
+
+/// Split section 0 with a partition, churn on both sides of it, and then heal it. How long the
+/// section takes to converge is reported when the simulation finishes, and by
+/// `Simulation::partition_healings`.
+fn partition_heal(params: SimulationParams, node_params: NodeParams) -> Simulation {
+    let sections = sections(&["0", "1"], node_params.min_section_size);
+    let event_schedule = EventSchedule::new(btreemap! {
+        10 => vec![Partition(p("00"))],
+        20 => vec![RemoveNodeFrom(p("00"))],
+        30 => vec![RemoveNodeFrom(p("01"))],
+        80 => vec![HealPartition],
+    });
+    Simulation::new_from(sections, event_schedule, params, node_params)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn names_are_unique() {
+        let names: BTreeSet<_> = SCENARIOS.iter().map(|scenario| scenario.name).collect();
+        assert_eq!(names.len(), SCENARIOS.len());
+        assert_eq!(find("parallel_merge").map(|scenario| scenario.name), Some("parallel_merge"));
+        assert!(find("no_such_scenario").is_none());
+    }
+}
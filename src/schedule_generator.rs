@@ -0,0 +1,181 @@
+//! Random event schedules that obey constraints on when their events can happen, for use as fuzz
+//! corpora and libraries of reproducible scenarios (see the `schedule` tool).
+//!
+//! Every random choice is drawn from the seeded random number generator, so reseeding first
+//! reproduces a schedule exactly.
+
+use event::Event::{self, AddNode, RemoveNodeFrom};
+use event_schedule::EventSchedule;
+use name::Prefix;
+use random::{random, sample_single, shuffle};
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Constraints on a randomly generated schedule.
+#[derive(Clone, Debug)]
+pub struct Constraints {
+    /// Prefixes of the sections that nodes join and are removed from.
+    pub prefixes: Vec<Prefix>,
+    /// First step on which events can happen.
+    pub start_step: u64,
+    /// Number of steps over which the events are spread.
+    pub steps: u64,
+    /// Total number of nodes that join.
+    pub joins: usize,
+    /// Total number of nodes that are removed.
+    pub drops: usize,
+    /// Maximum number of events on a single step.
+    pub max_events_per_step: usize,
+    /// Minimum number of steps between two removals from the same prefix.
+    pub min_removal_gap: u64,
+}
+
+impl Default for Constraints {
+    fn default() -> Self {
+        Constraints {
+            prefixes: vec![Prefix::empty()],
+            start_step: 1,
+            steps: 100,
+            joins: 10,
+            drops: 10,
+            max_events_per_step: 1,
+            min_removal_gap: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GenerateError {
+    /// There are no prefixes to choose events in.
+    NoPrefixes,
+    /// No step could be found for an event within the constraints.
+    Unsatisfiable(Event),
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GenerateError::NoPrefixes => write!(f, "no prefixes to generate events in"),
+            GenerateError::Unsatisfiable(ref event) => {
+                write!(f, "couldn't find a step for {:?} within the constraints", event)
+            }
+        }
+    }
+}
+
+/// Generate a random schedule that obeys the constraints.
+///
+/// Joins are to random names in random prefixes, and removals are `RemoveNodeFrom` a random
+/// prefix. Each event in turn is placed on a random one of the steps that it can happen on within
+/// the constraints, and an error is returned if there are none left.
+pub fn generate(constraints: &Constraints) -> Result<EventSchedule, GenerateError> {
+    if constraints.prefixes.is_empty() {
+        return Err(GenerateError::NoPrefixes);
+    }
+    let random_prefix = || {
+        sample_single(constraints.prefixes.iter().cloned()).expect("there are prefixes")
+    };
+    let mut events: Vec<Event> = (0..constraints.joins)
+        .map(|_| AddNode(random_prefix().substituted_in(random())))
+        .chain((0..constraints.drops).map(|_| RemoveNodeFrom(random_prefix())))
+        .collect();
+    shuffle(&mut events);
+
+    let mut schedule: BTreeMap<u64, Vec<Event>> = BTreeMap::new();
+    let steps = constraints.start_step..constraints.start_step + constraints.steps;
+    for event in events {
+        let step = sample_single(steps.clone().filter(|&step| {
+            fits(constraints, &schedule, step, &event)
+        })).ok_or_else(|| GenerateError::Unsatisfiable(event.clone()))?;
+        schedule.entry(step).or_default().push(event);
+    }
+    Ok(EventSchedule::new(schedule))
+}
+
+/// Whether `event` can happen on `step` without breaking the constraints.
+fn fits(
+    constraints: &Constraints,
+    schedule: &BTreeMap<u64, Vec<Event>>,
+    step: u64,
+    event: &Event,
+) -> bool {
+    let on_step = schedule.get(&step).map_or(0, Vec::len);
+    if on_step >= constraints.max_events_per_step {
+        return false;
+    }
+    let prefix = match *event {
+        RemoveNodeFrom(prefix) => prefix,
+        _ => return true,
+    };
+    let gap = constraints.min_removal_gap;
+    let nearby = step.saturating_sub(gap.saturating_sub(1))..step.saturating_add(gap);
+    gap == 0 ||
+        !schedule
+            .range(nearby)
+            .any(|(_, events)| events.contains(&RemoveNodeFrom(prefix)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testing::p;
+
+    #[test]
+    fn obeys_constraints() {
+        let constraints = Constraints {
+            prefixes: vec![p("0"), p("1")],
+            start_step: 10,
+            steps: 100,
+            joins: 20,
+            drops: 6,
+            max_events_per_step: 2,
+            min_removal_gap: 8,
+        };
+        for _ in 0..20 {
+            let schedule = generate(&constraints).unwrap();
+            let events = schedule.events();
+            let joins = events.iter().filter(|&&(_, ref event)| match *event {
+                AddNode(name) => p("0").matches(name) || p("1").matches(name),
+                _ => false,
+            });
+            assert_eq!(joins.count(), 20);
+            let removals: Vec<(u64, Prefix)> = events
+                .iter()
+                .filter_map(|&(step, ref event)| match *event {
+                    RemoveNodeFrom(prefix) => Some((step, prefix)),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(removals.len(), 6);
+            for (i, &(step1, prefix1)) in removals.iter().enumerate() {
+                for &(step2, prefix2) in &removals[i + 1..] {
+                    assert!(prefix1 != prefix2 || step2 - step1 >= 8);
+                }
+            }
+            for (&step, events) in &schedule.schedule {
+                assert!(step >= 10 && step < 110);
+                assert!(events.len() <= 2);
+            }
+        }
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        let constraints = Constraints {
+            steps: 5,
+            joins: 6,
+            drops: 0,
+            ..Constraints::default()
+        };
+        match generate(&constraints) {
+            Err(GenerateError::Unsatisfiable(AddNode(_))) => (),
+            result => panic!("unexpected result: {:?}", result.map(|s| s.events())),
+        }
+        let no_prefixes = Constraints {
+            prefixes: vec![],
+            ..Constraints::default()
+        };
+        assert_eq!(generate(&no_prefixes).err(), Some(GenerateError::NoPrefixes));
+    }
+}
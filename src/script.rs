@@ -0,0 +1,256 @@
+//! Per-step custom logic for scenarios, written in [Rhai](https://rhai.rs) and run without
+//! recompiling (behind the `scripting` feature).
+//!
+//! A script defines an `on_step` function, which is called at the end of every step that the
+//! simulation runs with the step number, a map from the prefix of each section the nodes agree on
+//! to its number of members, and arrays of the prefixes of the sections that have just split and
+//! just been formed by merges. It can schedule events for the next step by calling:
+//!
+//! * `add_node(prefix)`: add a node with a random name matching the prefix;
+//! * `remove_node(prefix)`: remove a random node whose name matches the prefix;
+//! * `partition(prefix, steps)`: cut the nodes matching the prefix off from the rest, healing
+//!   every partition after the given number of steps;
+//! * `heal()`: heal every partition.
+//!
+//! Prefixes are strings of bits, with `""` for the empty prefix. For example, to disconnect the
+//! two halves of section 01 for 30 steps when it splits:
+//!
+//! ```text
+//! fn on_step(step, sections, split, merged) {
+//!     if split.contains("01") {
+//!         partition("010", 30);
+//!     }
+//! }
+//! ```
+//!
+//! Name a script in an event schedule file (see `event_schedule`) to run it with the schedule, or
+//! add it to a simulation with `Simulation::add_hook`.
+
+use block::Block;
+use event::Event;
+use name::Prefix;
+use random::random;
+use simulation::StepHook;
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Name of the function that scripts define to run at the end of every step.
+const STEP_FUNCTION: &str = "on_step";
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Error from loading a script.
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(io::Error),
+    /// The script couldn't be compiled, or failed while running its top-level statements.
+    Invalid(String),
+    /// The script doesn't define an `on_step` function.
+    NoStepFunction,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScriptError::Io(ref e) => write!(f, "couldn't read script: {}", e),
+            ScriptError::Invalid(ref e) => write!(f, "invalid script: {}", e),
+            ScriptError::NoStepFunction => {
+                write!(f, "script doesn't define an {} function", STEP_FUNCTION)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for ScriptError {
+    fn from(e: io::Error) -> Self {
+        ScriptError::Io(e)
+    }
+}
+
+/// A compiled script, run as a hook at the end of every step.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    /// The step the script is being run for.
+    step: Rc<Cell<u64>>,
+    /// Events that the script has scheduled on this step, along with their steps.
+    events: Rc<RefCell<Vec<(u64, Event)>>>,
+    /// Prefixes of the agreed sections at the end of the last step.
+    prefixes: Vec<Prefix>,
+}
+
+fn parse_prefix(prefix: &str) -> ScriptResult<Prefix> {
+    prefix.parse().map_err(|()| format!("invalid prefix: {:?}", prefix).into())
+}
+
+impl Script {
+    /// Load a script from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Script, ScriptError> {
+        Self::compile(&fs::read_to_string(path)?)
+    }
+
+    /// Compile a script from its source.
+    pub fn compile(source: &str) -> Result<Script, ScriptError> {
+        let step = Rc::new(Cell::new(0));
+        let events = Rc::new(RefCell::new(vec![]));
+        let mut engine = Engine::new();
+
+        let schedule = |events: &Rc<RefCell<Vec<(u64, Event)>>>, step: &Rc<Cell<u64>>| {
+            let (events, step) = (Rc::clone(events), Rc::clone(step));
+            move |delay: u64, event: Event| events.borrow_mut().push((step.get() + delay, event))
+        };
+        let push = schedule(&events, &step);
+        engine.register_fn("add_node", move |prefix: &str| -> ScriptResult<()> {
+            let prefix = parse_prefix(prefix)?;
+            push(1, Event::AddNode(prefix.substituted_in(random())));
+            Ok(())
+        });
+        let push = schedule(&events, &step);
+        engine.register_fn("remove_node", move |prefix: &str| -> ScriptResult<()> {
+            push(1, Event::RemoveNodeFrom(parse_prefix(prefix)?));
+            Ok(())
+        });
+        let push = schedule(&events, &step);
+        let partition = move |prefix: &str, steps: i64| -> ScriptResult<()> {
+            if steps <= 0 {
+                return Err(format!("partition must last at least a step, not {}", steps).into());
+            }
+            push(1, Event::Partition(parse_prefix(prefix)?));
+            push(1 + steps as u64, Event::HealPartition);
+            Ok(())
+        };
+        engine.register_fn("partition", partition);
+        let push = schedule(&events, &step);
+        engine.register_fn("heal", move || push(1, Event::HealPartition));
+
+        let ast = engine.compile(source).map_err(|e| ScriptError::Invalid(e.to_string()))?;
+        if !ast.iter_functions().any(|function| function.name == STEP_FUNCTION) {
+            return Err(ScriptError::NoStepFunction);
+        }
+        engine.run_ast(&ast).map_err(|e| ScriptError::Invalid(e.to_string()))?;
+        Ok(Script {
+            engine,
+            ast,
+            step,
+            events,
+            prefixes: vec![],
+        })
+    }
+}
+
+impl StepHook for Script {
+    fn step_end(&mut self, step: u64, sections: &BTreeMap<Prefix, Block>) -> Vec<(u64, Event)> {
+        let split: Array = self.prefixes
+            .iter()
+            .filter(|old| {
+                !sections.contains_key(old) &&
+                    sections.keys().any(|prefix| old.is_prefix_of(prefix) && prefix != *old)
+            })
+            .map(|prefix| Dynamic::from(prefix.to_string()))
+            .collect();
+        let merged: Array = sections
+            .keys()
+            .filter(|prefix| {
+                !self.prefixes.contains(prefix) &&
+                    self.prefixes.iter().any(|old| prefix.is_prefix_of(old) && old != *prefix)
+            })
+            .map(|prefix| Dynamic::from(prefix.to_string()))
+            .collect();
+        self.prefixes = sections.keys().cloned().collect();
+        let members: Map = sections
+            .values()
+            .map(|block| {
+                (block.prefix.to_string().into(), Dynamic::from(block.members.len() as i64))
+            })
+            .collect();
+
+        self.step.set(step);
+        let args = (step as i64, members, split, merged);
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            &self.ast,
+            STEP_FUNCTION,
+            args,
+        );
+        if let Err(e) = result {
+            error!("script failed at step {}: {}", step, e);
+        }
+        self.events.borrow_mut().drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use name::Name;
+    use std::sync::Arc;
+    use testing::p;
+
+    fn sections(prefixes: &[&str]) -> BTreeMap<Prefix, Block> {
+        prefixes
+            .iter()
+            .map(|prefix| {
+                let block = Block {
+                    prefix: p(prefix),
+                    version: 0,
+                    members: Arc::new(btreeset!{ Name(1) }),
+                };
+                (p(prefix), block)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn partition_on_split() {
+        let mut script = Script::compile(
+            r#"
+            fn on_step(step, sections, split, merged) {
+                if split.contains("01") {
+                    partition("010", 30);
+                }
+                if merged.contains("1") && sections["1"] == 1 {
+                    add_node("1");
+                }
+            }
+            "#,
+        ).unwrap();
+        assert!(script.step_end(10, &sections(&["00", "01", "10", "11"])).is_empty());
+        assert!(script.step_end(11, &sections(&["00", "01", "10", "11"])).is_empty());
+        assert_eq!(
+            script.step_end(12, &sections(&["00", "010", "011", "10", "11"])),
+            vec![(13, Event::Partition(p("010"))), (43, Event::HealPartition)]
+        );
+        let events = script.step_end(13, &sections(&["00", "010", "011", "1"]));
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            (14, Event::AddNode(name)) => assert!(p("1").matches(name)),
+            ref event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn invalid_scripts() {
+        match Script::compile("fn on_step(step) {") {
+            Err(ScriptError::Invalid(_)) => (),
+            result => panic!("unexpected result: {:?}", result.err()),
+        }
+        match Script::compile("fn on_tick(step) {}") {
+            Err(ScriptError::NoStepFunction) => (),
+            result => panic!("unexpected result: {:?}", result.err()),
+        }
+        // Errors while running `on_step` are logged, and schedule nothing.
+        let mut script =
+            Script::compile("fn on_step(step, sections, split, merged) { remove_node(\"2\"); }")
+                .unwrap();
+        assert!(script.step_end(1, &sections(&[""])).is_empty());
+    }
+}
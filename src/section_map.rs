@@ -0,0 +1,120 @@
+//! Per-step record of the sections that the network agrees on, for plotting section evolution.
+//!
+//! If the `EWOK_SECTION_MAP` environment variable is set, the simulation writes a CSV file to the
+//! path it names, with the columns `step,prefix,version,members`. A row is written at the end of
+//! a step for each prefix whose agreed version or member count changed during it, so the first
+//! step has a row for every section. A prefix that's no longer agreed on, e.g. because it split
+//! or merged, gets a final row with an empty version and no members.
+
+use block::Block;
+use name::Prefix;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Column names, written as the first line of the file.
+pub const HEADER: &str = "step,prefix,version,members";
+
+/// A section map file that grows as the simulation progresses.
+pub struct SectionMapFile {
+    writer: BufWriter<File>,
+    /// Agreed version and member count of each prefix, as of the last row written for it.
+    sections: BTreeMap<Prefix, (u64, usize)>,
+}
+
+impl SectionMapFile {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", HEADER)?;
+        Ok(SectionMapFile {
+            writer,
+            sections: BTreeMap::new(),
+        })
+    }
+
+    /// Create a section map file at the path in the `EWOK_SECTION_MAP` variable, if it's set.
+    pub fn from_env() -> Option<Self> {
+        let path = env::var("EWOK_SECTION_MAP").ok()?;
+        Some(SectionMapFile::create(&path).unwrap_or_else(|e| {
+            panic!("couldn't create section map {}: {}", path, e)
+        }))
+    }
+
+    /// Write a row for every section that changed since the last update.
+    ///
+    /// `sections` are the sections agreed on at the end of `step`, as found by
+    /// `consistency::agreed_sections`.
+    pub fn update(&mut self, step: u64, sections: &BTreeMap<Prefix, Block>) -> io::Result<()> {
+        let mut changed = false;
+        for (prefix, block) in sections {
+            let section = (block.version, block.members.len());
+            if self.sections.get(prefix) != Some(&section) {
+                writeln!(self.writer, "{},{},{},{}", step, prefix, section.0, section.1)?;
+                self.sections.insert(*prefix, section);
+                changed = true;
+            }
+        }
+        let gone: Vec<Prefix> = self.sections
+            .keys()
+            .filter(|prefix| !sections.contains_key(prefix))
+            .cloned()
+            .collect();
+        for prefix in gone {
+            writeln!(self.writer, "{},{},,0", step, prefix)?;
+            self.sections.remove(&prefix);
+            changed = true;
+        }
+        // Flush whenever there's something new, so that the file of a run that panics is still
+        // usable.
+        if changed {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use name::Name;
+    use std::fs;
+    use std::iter;
+    use std::sync::Arc;
+
+    fn block(prefix: Prefix, version: u64, members: u64) -> Block {
+        Block {
+            prefix,
+            version,
+            members: Arc::new((0..members).map(|i| prefix.substituted_in(Name(i.into()))).collect()),
+        }
+    }
+
+    fn sections(blocks: Vec<Block>) -> BTreeMap<Prefix, Block> {
+        blocks.into_iter().map(|block| (block.prefix, block)).collect()
+    }
+
+    #[test]
+    fn rows_for_changes_only() {
+        let empty = Prefix::empty();
+        let (p0, p1) = (empty.pushed(false), empty.pushed(true));
+        let path = env::temp_dir().join(format!("ewok-section-map-{}.csv", ::std::process::id()));
+        let mut map = SectionMapFile::create(&path).unwrap();
+
+        map.update(0, &sections(vec![block(empty, 0, 8)])).unwrap();
+        map.update(1, &sections(vec![block(empty, 0, 8)])).unwrap();
+        map.update(2, &sections(vec![block(empty, 1, 9)])).unwrap();
+        map.update(3, &sections(vec![block(p0, 2, 5), block(p1, 2, 4)])).unwrap();
+        map.update(4, &sections(vec![block(p0, 3, 5), block(p1, 2, 4)])).unwrap();
+
+        let csv = fs::read_to_string(&path).unwrap();
+        let expected: Vec<&str> = iter::once(HEADER)
+            .chain(vec!["0,,0,8", "2,,1,9", "3,0,2,5", "3,1,2,4", "3,,,0", "4,0,3,5"])
+            .collect();
+        assert_eq!(csv.lines().collect::<Vec<_>>(), expected);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+//! Reduce a failing schedule of events to a smaller one that still fails.
+//!
+//! The shrinker repeatedly removes chunks of events from the schedule, keeping each removal that
+//! leaves the simulation failing, and halving the chunk size once no chunk can be removed. The
+//! random number generator is reseeded before every run so that disconnections and message delays
+//! are drawn from the same sequence each time.
+
+use event::Event;
+use event_schedule::EventSchedule;
+use name::Prefix;
+use params::{NodeParams, SimulationParams};
+use random::reseed;
+use simulation::Simulation;
+
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Everything needed to re-run a simulation except its schedule of events.
+#[derive(Clone, Debug)]
+pub struct Reproduction {
+    pub sections: BTreeMap<Prefix, usize>,
+    pub params: SimulationParams,
+    pub node_params: NodeParams,
+    pub seed: [u32; 4],
+}
+
+impl Reproduction {
+    /// Run the simulation with the given events, returning true if it panicked or finished
+    /// inconsistent.
+    pub fn fails(&self, events: &[(u64, Event)]) -> bool {
+        reseed(self.seed);
+        let mut simulation = Simulation::new_from(
+            self.sections.clone(),
+            EventSchedule::from_events(events.iter().cloned()),
+            self.params.clone(),
+            self.node_params.clone(),
+        );
+        !matches!(panic::catch_unwind(AssertUnwindSafe(|| simulation.run())), Ok(Ok(_)))
+    }
+
+    /// Find a subset of `events` that still makes the simulation fail, and from which no single
+    /// event can be removed without it passing.
+    ///
+    /// Returns `None` if the simulation doesn't fail with all of `events`. `progress` is called
+    /// with the number of events remaining each time the schedule gets smaller.
+    pub fn shrink<F>(&self, mut events: Vec<(u64, Event)>, mut progress: F) -> Option<Vec<(u64, Event)>>
+    where
+        F: FnMut(usize),
+    {
+        if !self.fails(&events) {
+            return None;
+        }
+
+        let mut chunk_size = events.len() / 2;
+        while chunk_size > 0 {
+            let mut removed_any = false;
+            let mut start = 0;
+            while start < events.len() {
+                let end = (start + chunk_size).min(events.len());
+                let candidate: Vec<_> = events[..start]
+                    .iter()
+                    .chain(&events[end..])
+                    .cloned()
+                    .collect();
+                if self.fails(&candidate) {
+                    events = candidate;
+                    removed_any = true;
+                    progress(events.len());
+                } else {
+                    start = end;
+                }
+            }
+            if !removed_any {
+                chunk_size /= 2;
+            }
+        }
+        Some(events)
+    }
+}
+
+/// Parameters for replaying a recorded list of events rather than generating random ones.
+///
+/// All churn is turned off and the simulation stays in the stable phase until the last event has
+/// happened, so that it terminates whichever events are removed. The network parameters, like
+/// delays and disconnection rates, are kept.
+pub fn replay_params(params: &SimulationParams, events: &[(u64, Event)]) -> SimulationParams {
+    SimulationParams {
+        grow_prob_join: 0.0,
+        grow_prob_drop: 0.0,
+        prob_churn: 0.0,
+        shrink_prob_join: 0.0,
+        shrink_prob_drop: 0.0,
+        churn_correlation: 0.0,
+        starting_complete: 0,
+        grow_complete: 0,
+        stable_steps: events.iter().map(|&(step, _)| step).max().unwrap_or(0),
+        ..params.clone()
+    }
+}
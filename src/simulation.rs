@@ -1,21 +1,43 @@
 use std::collections::{BTreeMap, BTreeSet};
+use std::cmp;
+use std::error::Error;
+use std::fmt;
 use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
 use itertools::Itertools;
+use rayon::prelude::*;
 
-use network::Network;
+use network::{Network, QueueAge, SentCount};
 use event::Event;
 use event_schedule::EventSchedule;
 use node::Node;
+use orphans::{self, OrphanVote};
+use overload::{Overload, OverloadCrash};
+use partition::{Healing, Partitions};
 use name::{Name, Prefix};
-use block::{Block, BlockId};
-use blocks::Blocks;
-use generate::generate_network;
-use consistency::check_consistency;
-use message::Message;
+use block::{Block, BlockId, Vote};
+use blocks::{Blocks, SectionHealth};
+use chaos::ChaosAction;
+use client::ClientNode;
+use generate::{generate_network, generate_roots, network_from_blocks};
+use invariant::InvariantViolation;
+use latency::{self, AgreementLatencies};
+use logging::{log_context, set_log_context, set_log_seed, set_log_step, RunMarker, StepMarker};
+use consistency::{agreed_sections, check_consistency, is_available, Inconsistent};
+use coverage::{Rule, RuleCoverage};
+use message::{Message, MessageKind};
 use message::MessageContent::*;
-use params::{NodeParams, SimulationParams, quorum};
-use random::{sample_single, do_with_probability, seed};
+use heat_map::HeatMapFile;
+use metrics::MetricsFile;
+use mock::MockNode;
+use params::{CandidateBehaviour, NodeParams, RestartMode, SimulationParams, SpeedClass};
+use random::{random, sample_single, do_with_probability, seed, switch_stream};
 use random_events::RandomEvents;
+use section_map::SectionMapFile;
+use snapshot::{NodeSnapshot, QueuedMessage, Snapshot, SNAPSHOT_MAGIC, SNAPSHOT_VERSION};
+use topology::Topology;
+use trace::{self, Record};
 use self::detail::DisconnectedPair;
 
 mod detail {
@@ -55,7 +77,7 @@ mod detail {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Phase {
     Starting,
     Growth,
@@ -64,12 +86,50 @@ pub enum Phase {
     Finishing { since_step: u64 },
 }
 
+/// Why a run didn't end with the network consistent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunError {
+    /// The run finished with the network inconsistent, or an invariant violated. The seed
+    /// reproduces it.
+    Inconsistent([u32; 4]),
+    /// A pause condition held at the end of the given step. The run hasn't finished, and carries
+    /// on from there once it's resumed.
+    Paused(u64),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RunError::Inconsistent(seed) => {
+                write!(f, "network not consistent with seed {:?}", seed)
+            }
+            RunError::Paused(step) => write!(f, "run paused at step {}", step),
+        }
+    }
+}
+
+impl Error for RunError {}
+
+/// Maximum number of steps the finishing phase can run for.
+const MAX_EXTRA_STEPS: u64 = 1000;
+
+/// Custom logic run at the end of every step that the simulation runs, which can react to the
+/// state of the network by scheduling events (see `Simulation::add_hook`).
+pub trait StepHook {
+    /// Called with the sections the nodes agree on at the end of `step`, returning events to add
+    /// to the schedule along with the steps they should occur at, which must be after `step`.
+    fn step_end(&mut self, step: u64, sections: &BTreeMap<Prefix, Block>) -> Vec<(u64, Event)>;
+}
+
 pub struct Simulation {
     nodes: BTreeMap<Name, Node>,
     blocks: Blocks,
     network: Network,
     /// Set of blocks that all nodes start from (often just a single genesis block).
     genesis_set: BTreeSet<BlockId>,
+    /// Genesis blocks of each of the networks that started unaware of each other, by the prefix
+    /// that it covers. Empty if there's a single network.
+    roots: BTreeMap<Prefix, BTreeSet<BlockId>>,
     /// Parameters for the network and the simulation.
     params: SimulationParams,
     /// Parameters for nodes.
@@ -82,8 +142,94 @@ pub struct Simulation {
     random_events: RandomEvents,
     /// Event schedule - specifying events to happen at various steps.
     event_schedule: EventSchedule,
+    /// The step that the simulation is currently on (or finished on).
+    step: u64,
+    /// The next step to run.
+    next_step: u64,
+    /// Number of consecutive steps in the finishing phase on which nothing was sent.
+    no_op_step_count: u64,
+    /// Whether the simulation has finished.
+    finished: bool,
+    /// Live metrics file, if enabled.
+    metrics: Option<MetricsFile>,
+    /// Per-step section map file, if enabled.
+    section_map: Option<SectionMapFile>,
+    /// Heat map of messages between sections, if enabled.
+    heat_map: Option<HeatMapFile>,
+    /// Number of times nodes that have since been removed started voting to force-merge.
+    removed_force_merges: u64,
+    /// Number of proof requests that nodes which have since been removed skipped.
+    removed_version_gap_skips: u64,
+    /// Number of proofs not requested for unagreed votes by nodes that have been removed.
+    removed_unagreed_proof_skips: u64,
+    /// Number of truncated chain segments found by nodes that have been removed.
+    removed_truncated_segments: u64,
+    /// Number of joining nodes queued by nodes that have been removed.
+    removed_queued_joins: u64,
+    /// Number of times each protocol rule fired for nodes that have since been removed.
+    removed_rules: RuleCoverage,
+    /// Number of blocks that nodes which have since been removed pruned from their history.
+    removed_pruned_blocks: u64,
+    /// Number of scheduled assertions that have failed.
+    failed_assertions: usize,
+    /// Number of client data operations that have succeeded and failed.
+    data_ops: (u64, u64),
+    /// Number of chaos actions taken.
+    chaos_actions: u64,
+    /// Nodes that have crashed and not yet restarted, as they were when they crashed.
+    crashed: BTreeMap<Name, Node>,
+    /// Clients following the blocks of their sections, which are never members of them.
+    clients: BTreeMap<Name, ClientNode>,
+    /// Names of the coordinated group of attackers, and how they behave once they've joined.
+    attackers: BTreeMap<Name, CandidateBehaviour>,
+    /// The first block agreed with a quorum of attackers among its members, and the step it was
+    /// agreed on.
+    attacker_quorum: Option<(u64, Block)>,
+    /// Number of attackers among the members of each agreed section, as of the last step.
+    attacker_counts: BTreeMap<Prefix, (usize, usize)>,
+    /// The largest fraction of an agreed section's members that were attackers, along with the
+    /// step and the section's prefix.
+    peak_attackers: Option<(u64, Prefix, f64)>,
+    /// Steps between each vote being cast and the last member learning of its agreement, if
+    /// votes are being tracked (see `latency::is_enabled`).
+    latencies: Option<AgreementLatencies>,
+    /// Nodes' recent load, and the crashes it caused.
+    overload: Overload,
+    /// Number and size of the messages of each kind sent so far.
+    sent_by_kind: BTreeMap<MessageKind, SentCount>,
+    /// Number of votes to remove members that were still alive, by the speed classes of the
+    /// voter and the member.
+    live_removal_votes: BTreeMap<(SpeedClass, SpeedClass), u64>,
+    /// Largest backlog of messages that a node of each speed class has had.
+    peak_backlogs: BTreeMap<SpeedClass, usize>,
+    /// Scripted partitions, and how the sections they split converge.
+    partitions: Partitions,
+    /// Number of nodes that gave up joining with their clocks ahead of the network's, and with
+    /// their clocks in sync or behind.
+    gave_up: (u64, u64),
+    /// Number of message storms that nodes have been in.
+    storms: u64,
+    /// Number of votes that nodes have deferred because of message storms.
+    deferred_votes: u64,
+    /// Last step on which a node learned of an agreement.
+    last_agreement_step: Option<u64>,
+    /// Custom logic to run at the end of every step.
+    hooks: Vec<Box<dyn StepHook>>,
+    /// Scripted handling of the messages that some nodes receive and send.
+    mocks: BTreeMap<Name, MockNode>,
+    /// Invariants that have been violated, and the steps they were violated on.
+    violations: Vec<(u64, InvariantViolation)>,
+    /// Conditions that pause the run the first time they hold at the end of a step.
+    pause_conditions: Vec<PauseCondition>,
+    /// Path to write a snapshot to when the run pauses, if any.
+    pause_snapshot: Option<PathBuf>,
+    /// Step that the run is paused at, if it is.
+    paused: Option<u64>,
 }
 
+/// Condition on the state of a simulation at the end of a step.
+type PauseCondition = Box<dyn Fn(&Simulation) -> bool>;
+
 impl Simulation {
     /// Create a new simulation with a single seed node.
     pub fn new(params: SimulationParams, node_params: NodeParams) -> Self {
@@ -109,14 +255,90 @@ impl Simulation {
         node_params: NodeParams,
     ) -> Self {
         let mut blocks = Blocks::new();
-        let (nodes, genesis_set) = generate_network(&mut blocks, &sections, &node_params);
-        let network = Network::new(params.max_delay);
-        let random_events = RandomEvents::new(params.clone(), node_params.clone());
+        let (nodes, genesis_set) =
+            generate_network(&mut blocks, &sections, params.name_distribution, &node_params);
+        Self::from_network(blocks, nodes, genesis_set, event_schedule, params, node_params)
+    }
+
+    /// Create a new simulation with several networks that each cover part of the namespace, and
+    /// start unaware of each other (see `generate::generate_roots`), until an `IntroduceRoots`
+    /// event. Nodes that join a network know only its genesis blocks.
+    pub fn new_from_roots(
+        roots: BTreeMap<Prefix, BTreeMap<Prefix, usize>>,
+        event_schedule: EventSchedule,
+        params: SimulationParams,
+        node_params: NodeParams,
+    ) -> Self {
+        let mut blocks = Blocks::new();
+        let (nodes, roots) =
+            generate_roots(&mut blocks, &roots, params.name_distribution, &node_params);
+        let genesis_set = roots.values().flat_map(|genesis| genesis.iter().cloned()).collect();
+        let mut simulation =
+            Self::from_network(blocks, nodes, genesis_set, event_schedule, params, node_params);
+        simulation.roots = roots;
+        simulation
+    }
+
+    /// Create a new simulation with exactly the sections and disconnections in `topology`.
+    pub fn from_topology(
+        topology: &Topology,
+        event_schedule: EventSchedule,
+        params: SimulationParams,
+        node_params: NodeParams,
+    ) -> Self {
+        let mut blocks = Blocks::new();
+        let (nodes, genesis_set) =
+            network_from_blocks(&mut blocks, &topology.genesis_blocks(), &node_params);
+        let mut simulation =
+            Self::from_network(blocks, nodes, genesis_set, event_schedule, params, node_params);
+
+        // Start from the state a disconnection leaves the pair in, so that they can reconnect.
+        for &(n1, n2) in &topology.disconnected {
+            debug!("Node({}) and Node({}) start disconnected", n1, n2);
+            for &(node, peer) in &[(n1, n2), (n2, n1)] {
+                let node = simulation.nodes.get_mut(&node).expect("disconnected node is missing");
+                node.connections.remove(&peer);
+            }
+            simulation.disconnected.insert(DisconnectedPair::new(n1, n2));
+        }
+        simulation
+    }
+
+    fn from_network(
+        blocks: Blocks,
+        nodes: BTreeMap<Name, Node>,
+        genesis_set: BTreeSet<BlockId>,
+        event_schedule: EventSchedule,
+        params: SimulationParams,
+        node_params: NodeParams,
+    ) -> Self {
+        let mut network = Network::new(&params);
+        let heat_map = HeatMapFile::from_env();
+        if heat_map.is_some() {
+            network.count_pairs();
+        }
+        let overload = Overload::new(params.overload_drop_prob, params.overload_window);
+        let latencies = if latency::is_enabled(&params) {
+            Some(AgreementLatencies::new())
+        } else {
+            None
+        };
+        let random_events = RandomEvents::new(
+            params.clone(),
+            node_params.clone(),
+            nodes.keys().cloned().collect(),
+        );
+        let mut nodes = nodes;
+        for node in nodes.values_mut() {
+            node.speed = random_speed(&params);
+            node.clock_skew = random_clock_skew(&params);
+        }
 
         Simulation {
             blocks,
             nodes,
             genesis_set,
+            roots: BTreeMap::new(),
             network,
             params,
             node_params,
@@ -124,22 +346,115 @@ impl Simulation {
             disconnected: BTreeSet::new(),
             random_events,
             event_schedule,
+            step: 0,
+            next_step: 0,
+            no_op_step_count: 0,
+            finished: false,
+            metrics: MetricsFile::from_env(),
+            section_map: SectionMapFile::from_env(),
+            heat_map,
+            removed_force_merges: 0,
+            removed_version_gap_skips: 0,
+            removed_unagreed_proof_skips: 0,
+            removed_truncated_segments: 0,
+            removed_queued_joins: 0,
+            removed_rules: RuleCoverage::new(),
+            removed_pruned_blocks: 0,
+            failed_assertions: 0,
+            data_ops: (0, 0),
+            chaos_actions: 0,
+            crashed: BTreeMap::new(),
+            clients: BTreeMap::new(),
+            attackers: BTreeMap::new(),
+            attacker_quorum: None,
+            attacker_counts: BTreeMap::new(),
+            peak_attackers: None,
+            latencies,
+            overload,
+            sent_by_kind: BTreeMap::new(),
+            live_removal_votes: BTreeMap::new(),
+            peak_backlogs: BTreeMap::new(),
+            partitions: Partitions::new(),
+            gave_up: (0, 0),
+            storms: 0,
+            deferred_votes: 0,
+            last_agreement_step: None,
+            hooks: vec![],
+            mocks: BTreeMap::new(),
+            violations: vec![],
+            pause_conditions: vec![],
+            pause_snapshot: None,
+            paused: None,
         }
     }
 
     fn apply_add_node(&mut self, joining: Name, step: u64) {
         // Make the node active, and let it build its way up from the genesis block(s).
-        let genesis_set = self.genesis_set.clone();
+        let genesis_set = self.genesis_for(joining);
         let params = self.node_params.clone();
-        let node = Node::new(joining, &self.blocks, genesis_set, params, step);
+        let mut node = Node::new(joining, &self.blocks, genesis_set, params, step);
+        node.speed = random_speed(&self.params);
+        if node.speed != SpeedClass::Normal {
+            debug!("Node({}): joining as a {:?} node", joining, node.speed);
+        }
+        node.clock_skew = random_clock_skew(&self.params);
+        if node.clock_skew != 0 {
+            debug!("Node({}): joining with a clock skew of {}", joining, node.clock_skew);
+        }
         self.nodes.insert(joining, node);
     }
 
-    fn apply_remove_node(&mut self, leaving_node: Name) {
+    /// Replace a node with a new node under its relocated name, which joins the section that the
+    /// name belongs to.
+    fn apply_relocation(&mut self, old_name: Name, new_name: Name, step: u64) {
+        debug!("Node({}): rejoining as {}", old_name, new_name);
+        let old_node = self.apply_remove_node(old_name);
+        let removal_msgs = Event::RemoveNode(old_name).broadcast(&self.nodes);
+        self.network.send(step, removal_msgs);
+
+        if self.nodes.contains_key(&new_name) {
+            debug!("Node({}): name already taken, not rejoining", new_name);
+            return;
+        }
+        self.apply_add_node(new_name, step);
+        // The same machine rejoins, so it's just as fast, and its clock is just as far off.
+        if let (Some(node), Some(old_node)) = (self.nodes.get_mut(&new_name), old_node) {
+            node.speed = old_node.speed;
+            node.clock_skew = old_node.clock_skew;
+        }
+        let join_msgs = self.nodes
+            .keys()
+            .filter(|&&recipient| recipient != new_name)
+            .map(|&recipient| {
+                Message {
+                    sender: new_name,
+                    recipient,
+                    content: Arc::new(NodeRelocated(old_name)),
+                    trace_id: 0,
+                }
+            })
+            .collect();
+        self.network.send(step, join_msgs);
+    }
+
+    /// Remove a node from the network, returning it if it was alive.
+    fn apply_remove_node(&mut self, leaving_node: Name) -> Option<Node> {
         debug!("Node({}): dying...", leaving_node);
 
-        // Remove the node.
-        self.nodes.remove(&leaving_node);
+        // Remove the node, keeping count of its force-merges and rules for the report.
+        let node = self.nodes.remove(&leaving_node);
+        if let Some(ref node) = node {
+            self.removed_force_merges += node.force_merges;
+            self.removed_version_gap_skips += node.version_gap_skips;
+            self.removed_unagreed_proof_skips += node.unagreed_proof_skips;
+            self.removed_truncated_segments += node.truncated_segments;
+            self.removed_queued_joins += node.queued_joins;
+            self.removed_rules.add(&node.rules);
+            self.removed_pruned_blocks += node.pruned_blocks;
+        }
+
+        self.partitions.remove_node(leaving_node);
+        self.mocks.remove(&leaving_node);
 
         // Remove any "disconnections" associated with this node.
         let disconnected = mem::replace(&mut self.disconnected, BTreeSet::new());
@@ -149,13 +464,238 @@ impl Simulation {
                 pair.lower() != leaving_node && pair.higher() != leaving_node
             })
             .collect();
+        node
     }
 
     fn apply_event(&mut self, event: &Event, step: u64) {
         match *event {
-            Event::AddNode(name) => self.apply_add_node(name, step),
-            Event::RemoveNode(name) => self.apply_remove_node(name),
+            Event::AddNode(name) | Event::AddNodeUntil(name, _) => {
+                self.apply_add_node(name, step);
+                if let Event::AddNodeUntil(_, give_up_step) = *event {
+                    if let Some(node) = self.nodes.get_mut(&name) {
+                        node.give_up_step = Some(give_up_step);
+                    }
+                }
+                let malicious_join_prob = self.params.malicious_join_prob;
+                if malicious_join_prob > 0.0 && do_with_probability(malicious_join_prob) {
+                    let behaviour = self.params.malicious_behaviour;
+                    debug!("Node({}): joining as a malicious node ({:?})", name, behaviour);
+                    if let Some(node) = self.nodes.get_mut(&name) {
+                        node.behaviour = behaviour;
+                    }
+                }
+                if let Some(&behaviour) = self.attackers.get(&name) {
+                    self.join_as_attacker(name, behaviour);
+                } else if self.nodes.get(&name).is_some_and(|node| {
+                    node.behaviour == CandidateBehaviour::Eclipse
+                })
+                {
+                    self.join_as_attacker(name, CandidateBehaviour::Eclipse);
+                }
+            }
+            Event::RemoveNode(name) => {
+                self.apply_remove_node(name);
+            }
             Event::RemoveNodeFrom(_) => panic!("normalise RemoveNodeFrom before applying"),
+            Event::CrashNode(name) => {
+                if let Some(node) = self.apply_remove_node(name) {
+                    self.crashed.insert(name, node);
+                }
+            }
+            Event::AddStaleNode(name) => self.apply_add_stale_node(name, step),
+            Event::RestartNode(name) => self.apply_restart(name, step),
+            Event::Partition(prefix) => self.apply_partition(prefix, step),
+            Event::HealPartition => {
+                debug!("healing partitions at step {}", step);
+                self.partitions.heal(step);
+            }
+            Event::IntroduceRoots => self.apply_introduce_roots(step),
+            Event::AddClient(name) => self.apply_add_client(name, step),
+            Event::RemoveClient(name) => {
+                match self.clients.remove(&name) {
+                    Some(client) => self.network.send(step, client.disconnect()),
+                    None => warn!("Client({}): can't remove a client that isn't connected", name),
+                }
+            }
+            Event::Put(data) | Event::Get(data) => {
+                let sections = agreed_sections(&self.blocks, &self.nodes);
+                let success = sections
+                    .values()
+                    .find(|section| section.prefix.matches(data))
+                    .is_some_and(|section| {
+                        is_available(&self.blocks, section, &self.nodes, &self.node_params)
+                    });
+                if success {
+                    self.data_ops.0 += 1;
+                } else {
+                    debug!("{:?} failed at step {}", event, step);
+                    self.data_ops.1 += 1;
+                }
+                trace::record(Record::DataOp {
+                    step,
+                    event: event.clone(),
+                    success,
+                });
+            }
+        }
+    }
+
+    /// The genesis blocks that a node with the given name starts from: those of the network that
+    /// covers its name.
+    fn genesis_for(&self, name: Name) -> BTreeSet<BlockId> {
+        self.roots
+            .iter()
+            .find(|&(root, _)| root.matches(name))
+            .map_or_else(|| self.genesis_set.clone(), |(_, genesis)| genesis.clone())
+    }
+
+    /// The prefix of the network that covers the given name, if there are several networks.
+    fn root_of(&self, name: Name) -> Option<Prefix> {
+        self.roots.keys().find(|root| root.matches(name)).cloned()
+    }
+
+    /// Introduce the networks that started unaware of each other, by having every node send its
+    /// votes to every node of the other networks.
+    fn apply_introduce_roots(&mut self, step: u64) {
+        if self.roots.len() < 2 {
+            warn!("there's only one network, so there are none to introduce");
+            return;
+        }
+        let names: Vec<(Name, Option<Prefix>)> = self.nodes
+            .keys()
+            .map(|&name| (name, self.root_of(name)))
+            .collect();
+        let mut messages = vec![];
+        for &(name, root) in &names {
+            let node = self.nodes.get_mut(&name).unwrap();
+            for &(peer, peer_root) in &names {
+                if peer_root != root {
+                    messages.push(node.construct_bootstrap_msg(peer));
+                }
+            }
+        }
+        debug!("introducing {} networks with {} messages", self.roots.len(), messages.len());
+        self.network.send(step, messages);
+    }
+
+    /// Make a newly joined node an attacker with the given behaviour, allied with every other
+    /// attacker.
+    fn join_as_attacker(&mut self, name: Name, behaviour: CandidateBehaviour) {
+        debug!("Node({}): joining as an attacker ({:?})", name, behaviour);
+        self.attackers.insert(name, behaviour);
+        if let Some(node) = self.nodes.get_mut(&name) {
+            node.behaviour = behaviour;
+        }
+        let allies: BTreeSet<Name> = self.attackers.keys().cloned().collect();
+        for attacker in &allies {
+            if let Some(node) = self.nodes.get_mut(attacker) {
+                node.allies = allies.clone();
+            }
+        }
+    }
+
+    /// Record the first agreed block in which the attackers make up a quorum.
+    fn check_attacker_quorum(&mut self, step: u64, block_id: BlockId) {
+        if self.attacker_quorum.is_some() {
+            return;
+        }
+        let block = block_id.into_block(&self.blocks);
+        let attackers = block
+            .members
+            .iter()
+            .filter(|name| self.attackers.contains_key(name))
+            .cloned()
+            .collect();
+        if block_id.is_quorum(&self.blocks, &attackers, &self.node_params) {
+            warn!("attackers reached quorum in {:?} at step {}", block, step);
+            self.attacker_quorum = Some((step, block.clone()));
+        }
+    }
+
+    /// Connect a new client to the section that the network agrees its name belongs to.
+    fn apply_add_client(&mut self, name: Name, step: u64) {
+        if self.clients.contains_key(&name) || self.nodes.contains_key(&name) {
+            warn!("Client({}): name already taken, not connecting", name);
+            return;
+        }
+        let sections = agreed_sections(&self.blocks, &self.nodes);
+        match sections.values().find(|section| section.prefix.matches(name)) {
+            Some(section) => {
+                let (client, messages) = ClientNode::new(name, section);
+                debug!("{}: connecting to {:?}", client, section);
+                self.clients.insert(name, client);
+                self.network.send(step, messages);
+            }
+            None => warn!("Client({}): no agreed section to connect to", name),
+        }
+    }
+
+    /// Add a node that only knows the genesis blocks, and announce it to their surviving members.
+    fn apply_add_stale_node(&mut self, name: Name, step: u64) {
+        self.apply_add_node(name, step);
+        let messages: Vec<Message> = self.blocks
+            .block_contents(self.genesis_for(name))
+            .into_iter()
+            .flat_map(|block| block.members.iter().cloned())
+            .filter(|member| *member != name && self.nodes.contains_key(member))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|recipient| {
+                Message {
+                    sender: name,
+                    recipient,
+                    content: Arc::new(NodeJoined),
+                    trace_id: 0,
+                }
+            })
+            .collect();
+        if messages.is_empty() {
+            warn!("Node({}): no genesis members left to announce ourselves to", name);
+        } else {
+            debug!(
+                "Node({}): starting late, announced to {} genesis members",
+                name,
+                messages.len()
+            );
+        }
+        self.network.send(step, messages);
+    }
+
+    /// Cut the nodes matching `prefix` off from the rest, until the partition heals.
+    fn apply_partition(&mut self, prefix: Prefix, step: u64) {
+        let sections = agreed_sections(&self.blocks, &self.nodes);
+        let nodes = self.nodes.keys().cloned().collect();
+        let pairs = self.partitions.start(step, prefix, &nodes, &sections);
+        debug!("partitioning {:?} at step {}: {} pairs cut off", prefix, step, pairs.len());
+        let mut messages = vec![];
+        for (n1, n2) in pairs {
+            if !self.nodes[&n1].is_disconnected_from(&n2) ||
+                !self.nodes[&n2].is_disconnected_from(&n1)
+            {
+                messages.extend(self.disconnect(DisconnectedPair::new(n1, n2)));
+            }
+        }
+        self.network.send(step, messages);
+    }
+
+    /// Bring a crashed node back under the same name, keeping its blocks and votes if the
+    /// restart mode is persistent. A node that never crashed just joins.
+    fn apply_restart(&mut self, name: Name, step: u64) {
+        let crashed = self.crashed.remove(&name);
+        self.apply_add_node(name, step);
+        if let (Some(node), Some(crashed)) = (self.nodes.get_mut(&name), crashed.as_ref()) {
+            node.speed = crashed.speed;
+            node.clock_skew = crashed.clock_skew;
+        }
+        match (self.params.restart_mode, crashed) {
+            (RestartMode::Persistent, Some(crashed)) => {
+                debug!("Node({}): restarting with its state from before the crash", name);
+                if let Some(node) = self.nodes.get_mut(&name) {
+                    node.restore_chain_state(crashed);
+                }
+            }
+            (RestartMode::Fresh, Some(_)) => debug!("Node({}): restarting from scratch", name),
+            (_, None) => warn!("Node({}): restarted without crashing, joining instead", name),
         }
     }
 
@@ -177,7 +717,11 @@ impl Simulation {
                 None => return vec![],
             }
         };
+        self.disconnect(pair)
+    }
 
+    /// Kill the connection between a pair of nodes, leaving them to reconnect later.
+    fn disconnect(&mut self, pair: DisconnectedPair) -> Vec<Message> {
         debug!(
             "Node({}) and Node({}) disconnecting from each other...",
             pair.lower(),
@@ -187,12 +731,14 @@ impl Simulation {
             Message {
                 sender: pair.lower(),
                 recipient: pair.higher(),
-                content: Disconnect,
+                content: Arc::new(Disconnect),
+                trace_id: 0,
             },
             Message {
                 sender: pair.higher(),
                 recipient: pair.lower(),
-                content: Disconnect,
+                content: Arc::new(Disconnect),
+                trace_id: 0,
             },
         ];
 
@@ -201,15 +747,19 @@ impl Simulation {
     }
 
     /// Try to reconnect all pairs of nodes which have previously become disconnected. Each pair
-    /// will only succeed with `SimulationParams::prob_reconnect` probability.
+    /// will only succeed with `SimulationParams::prob_reconnect` probability for the current
+    /// phase.
     fn reconnect_pairs(&mut self) -> Vec<Message> {
         let disconnected = mem::replace(&mut self.disconnected, BTreeSet::new());
         let mut messages = vec![];
         for pair in disconnected {
-            // Ensure both have realised they're disconnected.
+            // Ensure both have realised they're disconnected. Pairs held apart by a partition
+            // stay disconnected until it heals, and then reconnect straight away.
             if self.nodes[&pair.lower()].is_disconnected_from(&pair.higher()) &&
                 self.nodes[&pair.higher()].is_disconnected_from(&pair.lower()) &&
-                do_with_probability(self.params.prob_reconnect(self.phase))
+                !self.partitions.holds(pair.lower(), pair.higher()) &&
+                (self.partitions.take_healed(pair.lower(), pair.higher()) ||
+                     do_with_probability(self.params.prob_reconnect(self.phase)))
             {
                 debug!(
                     "Node({}) and Node({}) reconnecting to each other...",
@@ -219,12 +769,14 @@ impl Simulation {
                 messages.push(Message {
                     sender: pair.lower(),
                     recipient: pair.higher(),
-                    content: Connect,
+                    content: Arc::new(Connect),
+                    trace_id: 0,
                 });
                 messages.push(Message {
                     sender: pair.higher(),
                     recipient: pair.lower(),
-                    content: Connect,
+                    content: Arc::new(Connect),
+                    trace_id: 0,
                 });
             } else {
                 self.disconnected.insert(pair);
@@ -239,11 +791,36 @@ impl Simulation {
         events.extend(self.event_schedule.get_events(step));
         if self.event_schedule.is_empty() {
             events.extend(self.random_events.get_events(
+                step,
                 self.phase,
                 &self.blocks,
                 &self.nodes,
             ));
         }
+        match self.phase {
+            Phase::Finishing { .. } => (),
+            _ => events.extend(self.random_events.get_data_ops()),
+        }
+        if self.overload.is_enabled() {
+            for crash in self.overload.crash_nodes(step, &self.nodes, &self.blocks) {
+                debug!(
+                    "Node({}): crashing from overload ({} messages) in {:?}",
+                    crash.node,
+                    crash.load,
+                    crash.prefix
+                );
+                trace::record(Record::OverloadCrash {
+                    step,
+                    node: crash.node,
+                    prefix: crash.prefix,
+                    load: crash.load,
+                    after_merge: crash.after_merge,
+                });
+                if !events.contains(&Event::RemoveNode(crash.node)) {
+                    events.push(Event::RemoveNode(crash.node));
+                }
+            }
+        }
         trace!("events: {:?}", events);
 
         let mut ev_messages = vec![];
@@ -252,6 +829,7 @@ impl Simulation {
             if let Some(ev) = ev.normalise(&self.nodes) {
                 ev_messages.extend(ev.broadcast(&self.nodes));
                 self.apply_event(&ev, step);
+                trace::record(Record::Event { step, event: ev });
             }
         }
 
@@ -266,122 +844,1239 @@ impl Simulation {
         // Try to reconnect any previously-disconnected pairs.
         let reconnect_messages = self.reconnect_pairs();
         self.network.send(step, reconnect_messages);
+
+        let chaos_messages = self.take_chaos_action(step);
+        self.network.send(step, chaos_messages);
+    }
+
+    /// Take a random chaos action, if chaos mode is on and the network is in the stable phase.
+    fn take_chaos_action(&mut self, step: u64) -> Vec<Message> {
+        match self.phase {
+            Phase::Stable { .. } if self.params.chaos => (),
+            _ => return vec![],
+        }
+        if !do_with_probability(self.params.chaos_prob) {
+            return vec![];
+        }
+        let nodes: Vec<Name> = self.nodes.keys().cloned().collect();
+        let action = match ChaosAction::random(&self.params, step, &nodes) {
+            Some(action) => action,
+            None => return vec![],
+        };
+        info!("chaos action at step {}: {:?}", step, action);
+        self.chaos_actions += 1;
+        trace::record(Record::Chaos {
+            step,
+            action: action.clone(),
+        });
+
+        let pairs: Vec<(Name, Name)> = match action {
+            ChaosAction::Disconnect(n1, n2) => vec![(n1, n2)],
+            ChaosAction::Partition(ref partition) => {
+                partition
+                    .iter()
+                    .cartesian_product(nodes.iter().filter(|name| !partition.contains(name)))
+                    .map(|(&n1, &n2)| (n1, n2))
+                    .collect()
+            }
+            ChaosAction::MessageLoss { prob, until } => {
+                self.network.lose_messages(prob, until);
+                vec![]
+            }
+            ChaosAction::Duplicate { prob, until } => {
+                self.network.duplicate_messages(prob, until);
+                vec![]
+            }
+            ChaosAction::SlowNode { node, until } => {
+                self.network.slow_down(node, until);
+                vec![]
+            }
+        };
+        let mut messages = vec![];
+        for (n1, n2) in pairs {
+            if !self.nodes[&n1].is_disconnected_from(&n2) ||
+                !self.nodes[&n2].is_disconnected_from(&n1)
+            {
+                messages.extend(self.disconnect(DisconnectedPair::new(n1, n2)));
+            }
+        }
+        messages
     }
 
     /// Run the simulation, returning Ok iff the network was consistent upon termination.
-    pub fn run(&mut self) -> Result<BTreeMap<Prefix, Block>, [u32; 4]> {
-        let max_extra_steps = 1000;
-        let mut no_op_step_count = 0;
-
-        for step in 0.. {
-            // Generate events unless we're in the finishing phase, in which case we let the event
-            // queue empty out.
-            if let Phase::Finishing { since_step } = self.phase {
-                if step > since_step + max_extra_steps {
-                    break;
-                }
-                if self.network.queue_is_empty() {
-                    if no_op_step_count > self.node_params.max_timeout() {
-                        break;
-                    } else {
-                        no_op_step_count += 1;
-                    }
+    ///
+    /// If a pause condition holds, this returns `RunError::Paused` without finishing the run, so
+    /// that it can be resumed and run again.
+    pub fn run(&mut self) -> Result<BTreeMap<Prefix, Block>, RunError> {
+        while self.run_step() {}
+        if let Some(step) = self.paused {
+            return Err(RunError::Paused(step));
+        }
+        self.finish().map_err(RunError::Inconsistent)
+    }
+
+    /// Run the next step of the simulation, returning `false` if it has finished instead.
+    pub fn run_step(&mut self) -> bool {
+        if self.finished || self.paused.is_some() {
+            return false;
+        }
+        let step = self.next_step;
+        if step == 0 {
+            trace::record(Record::Params {
+                seed: seed(),
+                params: Box::new(self.params.clone()),
+                node_params: Box::new(self.node_params.clone()),
+            });
+        }
+        self.step = step;
+        if let Some(seed) = self.event_schedule.reseed_at(step) {
+            info!("switching the random number generator to seed {:?}", seed);
+            switch_stream(seed);
+        }
+
+        // Generate events unless we're in the finishing phase, in which case we let the event
+        // queue empty out.
+        if let Phase::Finishing { since_step } = self.phase {
+            if step > since_step + MAX_EXTRA_STEPS {
+                if self.no_op_step_count <= self.node_params.join_timeout {
+                    let violation = InvariantViolation::NotQuiescent {
+                        extra_steps: MAX_EXTRA_STEPS,
+                    };
+                    error!("invariant violated at step {}: {}", step, violation);
+                    self.violations.push((step, violation));
+                }
+                self.finished = true;
+                return false;
+            }
+            let backlogged = self.nodes.values().any(|node| !node.backlog.is_empty()) ||
+                self.mocks.values().any(MockNode::is_holding);
+            if self.network.queue_is_empty() && !backlogged {
+                if self.no_op_step_count > self.node_params.max_timeout() {
+                    self.finished = true;
+                    return false;
                 } else {
-                    no_op_step_count = 0;
+                    self.no_op_step_count += 1;
                 }
-                info!(
-                    "-- step {} ({:?}) {} nodes --",
-                    step,
-                    self.phase,
-                    self.nodes.len()
+            } else {
+                self.no_op_step_count = 0;
+            }
+            self.log_step_start(step);
+        } else {
+            self.log_step_start(step);
+            self.generate_events(step);
+        }
+
+        // Nodes only read the shared blocks while handling messages, so each node's
+        // messages can be handled on a separate thread.
+        let mut inboxes: BTreeMap<Name, Vec<Message>> = BTreeMap::new();
+        let tracing = trace::is_enabled();
+        let mut client_inbox = vec![];
+        for message in self.network.receive(step) {
+            let to_client = self.clients.contains_key(&message.recipient);
+            // Nothing crosses a partition until it heals, so nodes on either side can't reconnect.
+            // A `Disconnect` stands for a node noticing that the connection was lost, so it still
+            // gets through.
+            if !to_client && !self.clients.contains_key(&message.sender) &&
+                *message.content != Disconnect &&
+                self.partitions.refuse_delivery(message.sender, message.recipient)
+            {
+                trace!(
+                    "partition refused {:?} from {} to {}",
+                    message.content.kind(),
+                    message.sender,
+                    message.recipient
                 );
+                continue;
+            }
+            if to_client || self.nodes.contains_key(&message.recipient) {
+                if tracing {
+                    trace::record(Record::Delivered {
+                        step,
+                        sender: message.sender,
+                        recipient: message.recipient,
+                        message_kind: message.content.kind(),
+                        trace_id: message.trace_id,
+                    });
+                }
+                if to_client {
+                    client_inbox.push(message);
+                } else {
+                    inboxes.entry(message.recipient).or_default().push(message);
+                }
             } else {
-                info!(
-                    "-- step {} ({:?}) {} nodes --",
-                    step,
-                    self.phase,
-                    self.nodes.len()
+                debug!(
+                    "dropping message for dead node {} in trace {}",
+                    message.recipient,
+                    message.trace_id
                 );
-                self.generate_events(step);
             }
-
-            let delivered = self.network.receive(step);
-            for message in delivered {
-                match self.nodes.get_mut(&message.recipient) {
-                    Some(node) => {
-                        let new_messages = node.handle_message(message, &self.blocks, step);
-                        self.network.send(step, new_messages);
-                    }
-                    None => {
-                        debug!("dropping message for dead node {}", message.recipient);
-                    }
+        }
+        for (&name, mock) in &mut self.mocks {
+            let delivered = inboxes.remove(&name).unwrap_or_default();
+            let (handled, replies) = mock.receive(step, delivered);
+            inboxes.insert(name, handled);
+            self.network.send(step, replies);
+            self.network.send(step, mock.release_sent(step));
+        }
+        // Nodes with a limited capacity handle what they can, and queue the rest for later steps.
+        let capacity = self.params.message_capacity;
+        let mut work: Vec<_> = self.nodes
+            .iter_mut()
+            .filter_map(|(&name, node)| {
+                let delivered = inboxes.remove(&name).unwrap_or_default();
+                let inbox = node.take_messages(delivered, capacity);
+                if inbox.is_empty() {
+                    None
+                } else {
+                    Some((name, node, inbox))
                 }
+            })
+            .collect();
+        for &mut (name, ref node, ref inbox) in &mut work {
+            if self.overload.is_enabled() {
+                self.overload.handled(name, step, inbox.len());
             }
+            let peak = self.peak_backlogs.entry(node.speed).or_insert(0);
+            *peak = cmp::max(*peak, node.backlog.len());
+        }
+        // Lines logged from the worker threads have this run's step and go to its log file.
+        let log_context = log_context();
+        let blocks = &self.blocks;
+        let responses: Vec<Vec<Message>> = work.into_par_iter()
+            .map(|(_, node, inbox)| {
+                set_log_context(log_context);
+                inbox
+                    .into_iter()
+                    .flat_map(|message| node.handle_message(message, blocks, step))
+                    .collect()
+            })
+            .collect();
+        for new_messages in responses {
+            self.send_from_node(step, new_messages);
+        }
+        for message in client_inbox {
+            let client = self.clients.get_mut(&message.recipient).expect("client is connected");
+            let new_messages = client.handle_message(message, &self.blocks);
+            self.network.send(step, new_messages);
+        }
 
-            // Shutdown nodes that have failed to join.
-            let mut to_shutdown = BTreeSet::new();
-            for (name, node) in &self.nodes {
-                if node.should_shutdown(&self.blocks, step) {
-                    to_shutdown.insert(*name);
+        // Move nodes that their sections have relocated.
+        let relocations: Vec<(Name, Name)> = self.nodes
+            .iter()
+            .filter_map(|(&name, node)| node.relocate_to.map(|new_name| (name, new_name)))
+            .collect();
+        for (name, new_name) in relocations {
+            self.apply_relocation(name, new_name, step);
+        }
+
+        // Shutdown nodes that have failed to join.
+        let mut to_shutdown = BTreeSet::new();
+        for (name, node) in &self.nodes {
+            if node.should_shutdown(&self.blocks, step) {
+                to_shutdown.insert(*name);
+            }
+        }
+
+        for name in to_shutdown {
+            trace!("Node({}): voluntarily shutting down", name);
+            if let Some(node) = self.apply_remove_node(name) {
+                if node.clock_skew > 0 {
+                    self.gave_up.0 += 1;
+                } else {
+                    self.gave_up.1 += 1;
                 }
             }
+            let removal_msgs = Event::RemoveNode(name).broadcast(&self.nodes);
+            self.network.send(step, removal_msgs);
+        }
 
-            for name in to_shutdown {
-                trace!("Node({}): voluntarily shutting down", name);
-                self.apply_remove_node(name);
-                let removal_msgs = Event::RemoveNode(name).broadcast(&self.nodes);
-                self.network.send(step, removal_msgs);
-            }
-
-            // Update node state (current blocks), and send new votes.
-            for node in self.nodes.values_mut() {
-                match node.our_current_blocks(&self.blocks).into_iter().count() {
-                    0 => (),
-                    1 => node.check_conflicting_block_count(&self.blocks),
-                    count => {
-                        panic!(
-                            "{:?}\nhas {} current blocks for own section.",
-                            node.as_debug(&self.blocks),
-                            count
-                        )
-                    }
+        // Update node state (current blocks), and send new votes. Each node inserts the
+        // blocks it votes for into its own overlay, and these are collected afterwards.
+        let blocks = &mut self.blocks;
+        let work: Vec<_> = self.nodes
+            .values_mut()
+            .map(|node| (node, blocks.overlay()))
+            .collect();
+        let updates: Vec<_> = work.into_par_iter()
+            .map(|(node, mut overlay)| {
+                set_log_context(log_context);
+                let violation = match node.our_current_blocks(&overlay).len() {
+                    0 => Ok(()),
+                    1 => node.check_conflicting_block_count(&overlay),
+                    count => Err(InvariantViolation::CurrentBlocks {
+                        node: node.our_name,
+                        count,
+                    }),
+                };
+                if let Err(violation) = violation {
+                    return (node.our_name, vec![], BTreeSet::new(), None, overlay, Some(violation));
                 }
-                self.network.send(
+                let (mut messages, agreements) = node.update_state(&overlay, step);
+                let sending = messages.len();
+                messages.extend(node.broadcast_new_votes(&mut overlay, step, sending));
+                (node.our_name, messages, agreements, node.storm, overlay, None)
+            })
+            .collect();
+        for (name, messages, agreements, storm, overlay, violation) in updates {
+            self.blocks.absorb(overlay);
+            if let Some(violation) = violation {
+                error!("invariant violated at step {}: {}", step, violation);
+                debug!("{:?}", self.nodes[&name].as_debug(&self.blocks));
+                self.violations.push((step, violation));
+                continue;
+            }
+            if let Some(storm) = storm {
+                self.storms += 1;
+                self.deferred_votes += storm.deferred_votes as u64;
+                trace::record(Record::Storm {
                     step,
-                    node.update_state(&mut self.blocks, step),
-                );
-                self.network.send(
+                    node: name,
+                    messages: storm.messages,
+                    deferred_votes: storm.deferred_votes,
+                });
+            }
+            let mut votes = BTreeSet::new();
+            for message in &messages {
+                if let VoteMsg(ref vote) = *message.content {
+                    if let Some(ref mut latencies) = self.latencies {
+                        latencies.cast(vote, step);
+                    }
+                    votes.insert(vote);
+                }
+            }
+            // Votes to remove members that are still alive are spurious: the voter missed the
+            // member's messages, most likely because one of them is too slow.
+            self.partitions.votes_sent(name, votes.iter().cloned(), &self.blocks);
+            let voter_speed = self.nodes[&name].speed;
+            for vote in votes {
+                let removed = removed_member(vote, &self.blocks);
+                if let Some(member) = removed.and_then(|name| self.nodes.get(&name)) {
+                    *self.live_removal_votes
+                        .entry((voter_speed, member.speed))
+                        .or_insert(0) += 1;
+                }
+            }
+            if !agreements.is_empty() {
+                self.last_agreement_step = Some(step);
+            }
+            // The trace belongs to this thread, so agreements are recorded here. Members' ages
+            // are recorded in the same order, so that nodes weigh votes by them from the next
+            // step on, and `verify` can rebuild them from the trace.
+            for (vote, voters) in agreements {
+                self.blocks.record_agreement(&vote);
+                if let Some(ref mut latencies) = self.latencies {
+                    latencies.learned(&vote, name, &self.blocks, step);
+                }
+                if !self.attackers.is_empty() {
+                    self.check_attacker_quorum(step, vote.to);
+                }
+                trace::record_agreement(
                     step,
-                    node.broadcast_new_votes(&mut self.blocks, step),
+                    name,
+                    vote.from.into_block(&self.blocks),
+                    vote.to.into_block(&self.blocks),
+                    &voters,
                 );
             }
+            self.send_from_node(step, messages);
+        }
 
-            self.phase = self.phase_for_next_step(step);
+        // The run is meaningless once an invariant is violated, so it ends with this step.
+        if !self.violations.is_empty() {
+            self.finished = true;
+        }
+        self.check_assertions(step);
+        if self.partitions.is_active() {
+            self.partitions.update(step, &self.nodes, &self.blocks);
+        }
+        self.run_hooks(step);
 
-            debug!(
-                "- {} messages still in queue. -",
-                self.network.messages_in_queue()
-            );
+        self.phase = self.phase_for_next_step(step);
+
+        self.log_step_end(step);
+        self.check_pause_conditions(step);
+
+        // Skip over steps on which nothing can happen. They would have counted as no-op steps
+        // while finishing, as the queue stays empty throughout.
+        let next_step = self.next_active_step(step, self.no_op_step_count);
+        if let Phase::Finishing { .. } = self.phase {
+            self.no_op_step_count += next_step - step - 1;
         }
+        self.next_step = next_step;
+        true
+    }
 
+    /// Send messages that a node has sent while handling messages or updating its state, holding
+    /// back or dropping those that its mock says to.
+    fn send_from_node(&mut self, step: u64, messages: Vec<Message>) {
+        if self.mocks.is_empty() {
+            self.network.send(step, messages);
+            return;
+        }
+        let mocks = &mut self.mocks;
+        let messages = messages
+            .into_iter()
+            .filter_map(|message| match mocks.get_mut(&message.sender) {
+                Some(mock) => mock.send(step, message),
+                None => Some(message),
+            })
+            .collect();
+        self.network.send(step, messages);
+    }
+
+    /// Run the hooks for the end of the given step, and schedule the events they return.
+    fn run_hooks(&mut self, step: u64) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let sections = agreed_sections(&self.blocks, &self.nodes);
+        for hook in &mut self.hooks {
+            for (event_step, event) in hook.step_end(step, &sections) {
+                if event_step > step {
+                    debug!("hook scheduled {:?} for step {}", event, event_step);
+                    self.event_schedule.add_event(event_step, event);
+                } else {
+                    warn!("hook scheduled {:?} for step {}, which has passed", event, event_step);
+                }
+            }
+        }
+    }
+
+    /// Pause the run if any of the pause conditions hold at the end of the given step, taking a
+    /// snapshot if one was asked for.
+    fn check_pause_conditions(&mut self, step: u64) {
+        if self.pause_conditions.is_empty() {
+            return;
+        }
+        let mut conditions = mem::take(&mut self.pause_conditions);
+        let held = conditions.iter().position(|condition| condition(self));
+        if let Some(index) = held {
+            drop(conditions.remove(index));
+            warn!("pausing at step {}: pause condition {} holds", step, index);
+            self.paused = Some(step);
+            if let Some(ref path) = self.pause_snapshot {
+                self.snapshot().save(path).unwrap_or_else(|e| {
+                    panic!("couldn't write snapshot {}: {}", path.display(), e)
+                });
+                info!("wrote a snapshot of step {} to {}", step, path.display());
+            }
+        }
+        self.pause_conditions = conditions;
+    }
+
+    /// Check the assertions scheduled for the given step against the sections that the nodes
+    /// agree on.
+    fn check_assertions(&mut self, step: u64) {
+        let assertions = self.event_schedule.get_assertions(step);
+        if assertions.is_empty() {
+            return;
+        }
+        let sections = agreed_sections(&self.blocks, &self.nodes);
+        for assertion in assertions {
+            if assertion.holds(&sections) {
+                debug!("assertion held: {:?}", assertion);
+            } else {
+                error!(
+                    "assertion failed at step {}: {:?}, with sections {:?}",
+                    step,
+                    assertion,
+                    sections
+                        .values()
+                        .map(|block| (block.prefix, block.members.len()))
+                        .collect::<Vec<_>>()
+                );
+                self.failed_assertions += 1;
+            }
+        }
+    }
+
+    /// Whether the simulation has finished, so that `run_step` won't do anything more.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Check the final state of a finished simulation, returning Ok iff the network is
+    /// consistent.
+    pub fn finish(&mut self) -> Result<BTreeMap<Prefix, Block>, [u32; 4]> {
         debug!("-- final node states --");
         for node in self.nodes.values() {
             debug!("{:?}", node.as_debug(&self.blocks));
             trace!("{:#?}", node.connections);
         }
 
-        assert!(
-            no_op_step_count > self.node_params.join_timeout,
-            "Votes were still being sent and received after {} extra steps during which no \
-                 churn was triggered.",
-            max_extra_steps
+        let mut result = check_consistency(&self.blocks, &self.nodes, &self.node_params);
+        let unchecked = self.event_schedule.assertions_after(self.step);
+        if unchecked > 0 {
+            error!("{} assertions were scheduled after the last step", unchecked);
+        }
+        if self.failed_assertions > 0 || unchecked > 0 {
+            error!("{} assertions failed", self.failed_assertions + unchecked);
+            result = Err(Inconsistent);
+        }
+        if !self.violations.is_empty() {
+            error!("{} invariants violated", self.violations.len());
+            result = Err(Inconsistent);
+        }
+        info!("force-merge votes started: {}", self.force_merges());
+        info!(
+            "proofs requested under the {:?} policy: {}",
+            self.node_params.proof_request_policy,
+            self.proof_requests()
         );
+        if self.version_gap_skips() > 0 {
+            info!("proofs not requested for being too far ahead: {}", self.version_gap_skips());
+        }
+        if self.unagreed_proof_skips() > 0 {
+            info!("proofs not requested for unagreed votes: {}", self.unagreed_proof_skips());
+        }
+        if self.truncated_segments() > 0 {
+            warn!("chain segments missing a predecessor: {}", self.truncated_segments());
+        }
+        info!("rules fired: {}", self.rule_coverage());
+        if self.network.overdue() > 0 {
+            warn!(
+                "connections held messages past max_delay {} times, the oldest now queued: {:?}",
+                self.network.overdue(),
+                self.oldest_queued(3)
+            );
+        }
+        if let Some(depth) = self.node_params.history_depth {
+            info!(
+                "history depth {}: {} blocks pruned, at most {} valid blocks per node",
+                depth,
+                self.pruned_blocks(),
+                self.max_valid_blocks()
+            );
+        }
+        let (succeeded, failed) = self.data_ops;
+        if succeeded + failed > 0 {
+            info!(
+                "client data operations: {} succeeded, {} failed ({:.1}% available)",
+                succeeded,
+                failed,
+                100.0 * succeeded as f64 / (succeeded + failed) as f64
+            );
+        }
+        for (prefix, health) in self.section_health() {
+            if health.is_at_risk() {
+                warn!("section {:?} is at risk: {:?}", prefix, health);
+            } else {
+                debug!("section {:?} health: {:?}", prefix, health);
+            }
+        }
+        if !self.attackers.is_empty() {
+            match self.attacker_quorum {
+                Some((step, ref block)) => {
+                    warn!(
+                        "{} attackers reached quorum in {:?} at step {}",
+                        self.attackers.len(),
+                        block,
+                        step
+                    )
+                }
+                None => {
+                    info!(
+                        "{} attackers never reached quorum in an agreed block",
+                        self.attackers.len()
+                    )
+                }
+            }
+            if let Some((step, prefix, fraction)) = self.peak_attackers {
+                info!(
+                    "attackers peaked at {:.1}% of section {:?} at step {}",
+                    100.0 * fraction,
+                    prefix,
+                    step
+                );
+            }
+        }
+        let latencies = self.agreement_latencies();
+        let votes: usize = latencies.values().sum();
+        if votes > 0 {
+            let total: u64 = latencies.iter().map(|(&steps, &count)| steps * count as u64).sum();
+            info!(
+                "agreement latency: mean {:.1} steps, max {} steps, over {} votes",
+                total as f64 / votes as f64,
+                latencies.keys().next_back().cloned().unwrap_or(0),
+                votes
+            );
+        }
+        latency::write_to_env(&self.params, &latencies);
+        self.report_orphans();
+        if self.overload.is_enabled() {
+            self.report_overload();
+        }
+        if self.params.max_clock_skew > 0 {
+            info!(
+                "nodes that gave up joining: {} with clocks ahead, {} with clocks in sync or \
+                 behind",
+                self.gave_up.0,
+                self.gave_up.1
+            );
+        }
+        if self.partitions.refused_deliveries() > 0 {
+            info!(
+                "partitions refused {} deliveries",
+                self.partitions.refused_deliveries()
+            );
+        }
+        for healing in self.partitions.healings() {
+            report_healing(&healing);
+        }
+        if self.params.slow_node_ratio + self.params.fast_node_ratio > 0.0 ||
+            self.params.message_capacity.is_some()
+        {
+            self.report_speeds();
+        }
+        // Agreements carry the list of their voters unless their signatures are aggregated, so
+        // their sizes show what aggregation saves.
+        for kind in &[MessageKind::VoteAgreed, MessageKind::VoteAggregated] {
+            if let Some(count) = self.sent_by_kind.get(kind) {
+                info!(
+                    "{} messages: {} sent, {} bytes ({:.1} bytes each)",
+                    kind,
+                    count.messages,
+                    count.bytes,
+                    count.bytes as f64 / count.messages as f64
+                );
+            }
+        }
+        if self.node_params.storm_threshold.is_some() {
+            info!(
+                "message storms: {}, votes deferred: {}, throttling {}, converged at step {}",
+                self.storms,
+                self.deferred_votes,
+                if self.node_params.throttle_storms { "on" } else { "off" },
+                self.last_agreement_step.map_or("-".to_string(), |step| step.to_string())
+            );
+        }
+        trace::record(Record::Finished {
+            step: self.step,
+            consistent: result.is_ok(),
+            sections: result.as_ref().map_or(0, |sections| sections.len()),
+            nodes: self.nodes.len(),
+            force_merges: self.force_merges(),
+        });
+        trace::flush();
+        if let Some(ref metrics) = self.metrics {
+            metrics.write().expect("failed to write metrics file");
+        }
+        if let Some(ref mut heat_map) = self.heat_map {
+            heat_map.finish().expect("failed to write heat map");
+        }
+        result.map_err(|_| seed())
+    }
 
-        check_consistency(
-            &self.blocks,
+    /// The next step on which anything can happen, after `step`.
+    ///
+    /// Once no messages are queued, nodes only act again when a timeout expires. If there are no
+    /// random events or disconnections either, the steps until the next timeout or scheduled
+    /// event do nothing, and aren't worth simulating (or recording in the trace).
+    fn next_active_step(&self, step: u64, no_op_step_count: u64) -> u64 {
+        let random_events = match self.phase {
+            Phase::Finishing { .. } => false,
+            phase => {
+                self.event_schedule.is_empty() || self.params.prob_disconnect(phase) > 0.0 ||
+                    !self.disconnected.is_empty() || self.params.chaos ||
+                    self.params.data_op_prob > 0.0 || self.overload.is_loaded(step + 1)
+            }
+        };
+        if random_events || !self.network.queue_is_empty() {
+            return step + 1;
+        }
+
+        // Step at which the phase could change, or the simulation end, because of the step
+        // count alone.
+        let phase_deadline = match self.phase {
+            Phase::Stable { since_step } => Some(since_step + self.params.stable_steps),
+            Phase::Finishing { since_step } => {
+                let max_timeout = self.node_params.max_timeout();
+                let no_op_deadline =
+                    step + cmp::max(1, (max_timeout + 2).saturating_sub(no_op_step_count));
+                Some(cmp::min(since_step + MAX_EXTRA_STEPS + 1, no_op_deadline))
+            }
+            _ => None,
+        };
+        self.nodes
+            .values()
+            .filter_map(|node| node.next_timeout(step))
+            .chain(self.event_schedule.next_step(step))
+            .chain(self.mocks.values().filter_map(|mock| mock.next_release(step)))
+            .chain(phase_deadline)
+            .filter(|&next_step| next_step > step)
+            .min()
+            .unwrap_or(step + 1)
+    }
+
+    /// Log how many nodes of each speed class there are, how far behind they fell, and how many
+    /// spurious votes to remove live members they cast or were the target of.
+    fn report_speeds(&self) {
+        for &speed in &[SpeedClass::Fast, SpeedClass::Normal, SpeedClass::Slow] {
+            let count = |voter: bool| -> u64 {
+                self.live_removal_votes
+                    .iter()
+                    .filter(|&(&(by, of), _)| if voter { by == speed } else { of == speed })
+                    .map(|(_, &votes)| votes)
+                    .sum()
+            };
+            info!(
+                "{:?} nodes: {} alive, peak backlog {} messages, {} votes to remove live members \
+                 cast, {} against them",
+                speed,
+                self.nodes.values().filter(|node| node.speed == speed).count(),
+                self.peak_backlogs.get(&speed).cloned().unwrap_or(0),
+                count(true),
+                count(false)
+            );
+        }
+    }
+
+    /// Log how many votes never reached quorum, for each prefix and likely reason.
+    fn report_orphans(&self) {
+        let orphans = self.orphan_votes();
+        if orphans.is_empty() {
+            return;
+        }
+        info!("orphan votes: {} never reached quorum", orphans.len());
+        for ((prefix, reason), count) in orphans::group(&orphans) {
+            info!("  {} in {:?}: {}", count, prefix, reason);
+        }
+        for orphan in &orphans {
+            debug!(
+                "orphan vote cast at step {} with {} voters ({}): {:?}",
+                orphan.cast_step,
+                orphan.support,
+                orphan.reason,
+                orphan.vote.as_debug(&self.blocks)
+            );
+        }
+    }
+
+    /// Log how many nodes crashed from overload, and how many of those crashes were part of
+    /// correlated failures, separating sections formed by merges from the rest.
+    fn report_overload(&self) {
+        let groups = self.overload.correlated_failures();
+        for group in &groups {
+            let (first, last) = (group[0], group[group.len() - 1]);
+            info!(
+                "correlated failure: {} crashes in {:?} from step {} to {}{}",
+                group.len(),
+                first.prefix,
+                first.step,
+                last.step,
+                if group.iter().any(|crash| crash.after_merge) {
+                    " after a merge"
+                } else {
+                    ""
+                }
+            );
+        }
+        let count = |after_merge: bool| {
+            let crashes = self.overload
+                .crashes()
+                .iter()
+                .filter(|crash| crash.after_merge == after_merge)
+                .count();
+            let correlated = groups
+                .iter()
+                .flat_map(|group| group.iter())
+                .filter(|crash| crash.after_merge == after_merge)
+                .count();
+            (crashes, correlated)
+        };
+        let (merged, merged_correlated) = count(true);
+        let (other, other_correlated) = count(false);
+        info!(
+            "overload crashes: {} in sections formed by merges ({} correlated), {} elsewhere \
+             ({} correlated)",
+            merged,
+            merged_correlated,
+            other,
+            other_correlated
+        );
+    }
+
+    /// Number of chaos actions taken so far (see `SimulationParams::chaos`).
+    pub fn chaos_actions(&self) -> u64 {
+        self.chaos_actions
+    }
+
+    /// Number of times any node, live or removed, started voting to force-merge with a sibling
+    /// that it had lost its connections to.
+    pub fn force_merges(&self) -> u64 {
+        self.removed_force_merges +
+            self.nodes.values().map(|node| node.force_merges).sum::<u64>()
+    }
+
+    /// Number of times any node, live or removed, didn't request proof of a block because it was
+    /// too far ahead of the node's current blocks.
+    pub fn version_gap_skips(&self) -> u64 {
+        self.removed_version_gap_skips +
+            self.nodes.values().map(|node| node.version_gap_skips).sum::<u64>()
+    }
+
+    /// Number of times any node, live or removed, didn't request proof of a block because the
+    /// vote referencing it hadn't reached quorum.
+    pub fn unagreed_proof_skips(&self) -> u64 {
+        self.removed_unagreed_proof_skips +
+            self.nodes.values().map(|node| node.unagreed_proof_skips).sum::<u64>()
+    }
+
+    /// Number of chain segments that any node, live or removed, found a predecessor missing
+    /// from.
+    pub fn truncated_segments(&self) -> u64 {
+        self.removed_truncated_segments +
+            self.nodes.values().map(|node| node.truncated_segments).sum::<u64>()
+    }
+
+    /// Number of joining nodes that any node, live or removed, queued while another candidate
+    /// for its section was pending.
+    pub fn queued_joins(&self) -> u64 {
+        self.removed_queued_joins +
+            self.nodes.values().map(|node| node.queued_joins).sum::<u64>()
+    }
+
+    /// Number of proofs that any node, live or removed, has requested.
+    pub fn proof_requests(&self) -> u64 {
+        self.rule_coverage().count(Rule::ProofRequest)
+    }
+
+    /// Largest number of peers that any live node is connected to.
+    pub fn most_connections(&self) -> usize {
+        self.nodes
+            .values()
+            .map(|node| node.connections.iter().filter(|&&peer| peer != node.our_name).count())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Number of superseded blocks that any node, live or removed, pruned from its history.
+    pub fn pruned_blocks(&self) -> u64 {
+        self.removed_pruned_blocks +
+            self.nodes.values().map(|node| node.pruned_blocks).sum::<u64>()
+    }
+
+    /// Largest number of valid blocks that a live node holds.
+    pub fn max_valid_blocks(&self) -> usize {
+        self.nodes.values().map(|node| node.valid_blocks.len()).max().unwrap_or(0)
+    }
+
+    /// Votes that were cast but haven't reached quorum, with the likely reason for each. Empty
+    /// unless votes are being tracked.
+    pub fn orphan_votes(&self) -> Vec<OrphanVote> {
+        let latencies = match self.latencies {
+            Some(ref latencies) => latencies,
+            None => return vec![],
+        };
+        let sections = agreed_sections(&self.blocks, &self.nodes);
+        orphans::orphan_votes(
+            latencies.unagreed(),
             &self.nodes,
-            self.node_params.min_section_size as usize,
-        ).map_err(|_| seed())
+            &sections,
+            &self.blocks,
+            &self.node_params,
+        )
+    }
+
+    /// Number of times each protocol rule fired for any node, live or removed.
+    pub fn rule_coverage(&self) -> RuleCoverage {
+        let mut coverage = self.removed_rules.clone();
+        for node in self.nodes.values() {
+            coverage.add(&node.rules);
+        }
+        coverage
+    }
+
+    /// Number of times a node was in a message storm, and the total number of votes deferred by
+    /// throttling them.
+    pub fn storms(&self) -> (u64, u64) {
+        (self.storms, self.deferred_votes)
+    }
+
+    /// The last step on which any node learned of an agreement, which is the step the network
+    /// converged on once the run is over.
+    pub fn convergence_step(&self) -> Option<u64> {
+        self.last_agreement_step
+    }
+
+    /// Number of client data operations that have succeeded and failed.
+    pub fn data_ops(&self) -> (u64, u64) {
+        self.data_ops
+    }
+
+    /// Mark the nodes with the given names as a coordinated group of attackers, which join with
+    /// the given behaviour and ally with each other.
+    pub fn add_attackers<I>(&mut self, names: I, behaviour: CandidateBehaviour)
+    where
+        I: IntoIterator<Item = Name>,
+    {
+        self.attackers.extend(names.into_iter().map(|name| (name, behaviour)));
+    }
+
+    /// Histogram of the number of steps between each agreed vote being cast and the last member
+    /// of its sections learning of its agreement. Empty unless votes are being tracked.
+    pub fn agreement_latencies(&self) -> latency::Histogram {
+        self.latencies
+            .as_ref()
+            .map_or_else(latency::Histogram::new, AgreementLatencies::histogram)
+    }
+
+    /// The largest fraction of an agreed section's members that were attackers at the end of any
+    /// step, along with the step and the section's prefix.
+    pub fn peak_attackers(&self) -> Option<(u64, Prefix, f64)> {
+        self.peak_attackers
+    }
+
+    /// The first block agreed with a quorum of attackers among its members, and the step
+    /// it was agreed on, if the attackers ever got that far.
+    pub fn attacker_quorum(&self) -> Option<(u64, &Block)> {
+        self.attacker_quorum.as_ref().map(|&(step, ref block)| (step, block))
+    }
+
+    /// Number of live nodes that have found a block of a network other than their own to be
+    /// valid, when there are several networks that started unaware of each other.
+    pub fn nodes_knowing_other_roots(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|&(&name, node)| {
+                let root = self.root_of(name);
+                self.blocks.block_contents(&node.valid_blocks).into_iter().any(|block| {
+                    self.roots.keys().any(|other| {
+                        Some(*other) != root && other.is_compatible(&block.prefix)
+                    })
+                })
+            })
+            .count()
+    }
+
+    /// The connections with the oldest undelivered messages, up to `count` of them, oldest first.
+    pub fn oldest_queued(&self, count: usize) -> Vec<QueueAge> {
+        self.network.oldest_queued(self.step, count)
+    }
+
+    /// Number of times that a connection held messages past `max_delay`.
+    pub fn overdue_connections(&self) -> u64 {
+        self.network.overdue()
+    }
+
+    /// Number of times that a connection delivered its second burst of messages ahead of its
+    /// first (see `SimulationParams::burst_reorder_prob`).
+    pub fn reordered_bursts(&self) -> u64 {
+        self.network.reordered_bursts()
+    }
+
+    /// Number and size of the messages of each kind sent so far, up to the end of the last step.
+    pub fn sent_by_kind(&self) -> &BTreeMap<MessageKind, SentCount> {
+        &self.sent_by_kind
+    }
+
+    /// Nodes that have crashed from overload so far.
+    pub fn overload_crashes(&self) -> &[OverloadCrash] {
+        self.overload.crashes()
+    }
+
+    /// Number of votes cast so far to remove members that were still alive, by the speed classes
+    /// of the voter and the member.
+    pub fn live_removal_votes(&self) -> &BTreeMap<(SpeedClass, SpeedClass), u64> {
+        &self.live_removal_votes
+    }
+
+    /// Number of nodes that gave up joining with their clocks ahead of the network's, and with
+    /// their clocks in sync or behind.
+    pub fn gave_up(&self) -> (u64, u64) {
+        self.gave_up
+    }
+
+    /// How each section split by a scripted partition has converged since it healed.
+    pub fn partition_healings(&self) -> Vec<Healing> {
+        self.partitions.healings()
+    }
+
+    /// Number of messages between nodes that partitions refused to deliver.
+    pub fn partition_refused_deliveries(&self) -> u64 {
+        self.partitions.refused_deliveries()
+    }
+
+    /// Pause the run at the end of the first step after which `condition` holds, so that the
+    /// state can be examined there: `run_step` does nothing until `resume` is called, and `run`
+    /// returns `RunError::Paused`. Each condition pauses the run at most once. For example:
+    ///
+    /// ```text
+    /// simulation.pause_when(|sim| sim.queue_size() > 10_000);
+    /// simulation.pause_when(|sim| !sim.forked_prefixes().is_empty());
+    /// ```
+    pub fn pause_when<F: Fn(&Simulation) -> bool + 'static>(&mut self, condition: F) {
+        self.pause_conditions.push(Box::new(condition));
+    }
+
+    /// Write a snapshot of the simulation to `path` whenever the run pauses, which the `inspect`
+    /// tool can then examine.
+    pub fn snapshot_on_pause<P: Into<PathBuf>>(&mut self, path: P) {
+        self.pause_snapshot = Some(path.into());
+    }
+
+    /// The step that the run is paused at, if it is.
+    pub fn paused(&self) -> Option<u64> {
+        self.paused
+    }
+
+    /// Carry on with a paused run.
+    pub fn resume(&mut self) {
+        self.paused = None;
+    }
+
+    /// Number of messages queued in the network.
+    pub fn queue_size(&self) -> usize {
+        self.network.messages_in_queue()
+    }
+
+    /// Prefixes for which the nodes' current blocks include more than one block of the same
+    /// version, i.e. for which different nodes agreed on different blocks.
+    pub fn forked_prefixes(&self) -> BTreeSet<Prefix> {
+        let mut versions: BTreeMap<(Prefix, u64), BTreeSet<BlockId>> = BTreeMap::new();
+        for node in self.nodes.values() {
+            for block in self.blocks.block_contents(&node.current_blocks) {
+                versions
+                    .entry((block.prefix, block.version))
+                    .or_default()
+                    .insert(block.get_id());
+            }
+        }
+        versions
+            .into_iter()
+            .filter(|(_, ids)| ids.len() > 1)
+            .map(|((prefix, _), _)| prefix)
+            .collect()
+    }
+
+    /// Run `hook` at the end of every step from now on.
+    pub fn add_hook<H: StepHook + 'static>(&mut self, hook: H) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Invariants that have been violated, and the steps they were violated on. A violation ends
+    /// the run, so there's at most one step's worth.
+    pub fn violations(&self) -> &[(u64, InvariantViolation)] {
+        &self.violations
+    }
+
+    /// Script the handling of the messages that the node with the given name receives and
+    /// sends, for as long as it's in the network. The node needn't have joined yet.
+    pub fn add_mock(&mut self, name: Name, mock: MockNode) {
+        self.mocks.insert(name, mock);
+    }
+
+    /// The live node with the given name, if any.
+    pub fn node(&self, name: &Name) -> Option<&Node> {
+        self.nodes.get(name)
+    }
+
+    /// The mock attached to the named node, if any.
+    pub fn mock(&self, name: &Name) -> Option<&MockNode> {
+        self.mocks.get(name)
+    }
+
+    /// Clients that are connected to the network.
+    pub fn clients(&self) -> &BTreeMap<Name, ClientNode> {
+        &self.clients
+    }
+
+    /// Health of every section that any node has a current block for.
+    pub fn section_health(&self) -> BTreeMap<Prefix, SectionHealth> {
+        let current_blocks: BTreeSet<BlockId> = self.nodes
+            .values()
+            .flat_map(|node| node.current_blocks.iter().cloned())
+            .collect();
+        let connections: BTreeMap<Name, BTreeSet<Name>> = self.nodes
+            .iter()
+            .map(|(&name, node)| (name, node.connections.clone()))
+            .collect();
+        let mut health = BTreeMap::new();
+        for block in self.blocks.block_contents(&current_blocks) {
+            if health.contains_key(&block.prefix) {
+                continue;
+            }
+            if let Some(section_health) = self.blocks.section_health(
+                &current_blocks,
+                block.prefix,
+                &connections,
+                &self.node_params,
+            )
+            {
+                health.insert(block.prefix, section_health);
+            }
+        }
+        health
+    }
+
+    /// Whether the node with the given name is alive.
+    pub fn has_node(&self, name: &Name) -> bool {
+        self.nodes.contains_key(name)
+    }
+
+    /// The step that the simulation is currently on, or the last step if it has finished.
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    /// Which phase the simulation is currently in.
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Take a snapshot of the simulation's state.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut blocks: Vec<_> = self.blocks.iter().cloned().collect();
+        blocks.sort_by_key(Block::get_id);
+        Snapshot {
+            magic: SNAPSHOT_MAGIC.to_string(),
+            version: SNAPSHOT_VERSION,
+            step: self.step,
+            phase: self.phase,
+            params: self.params.clone(),
+            node_params: self.node_params.clone(),
+            genesis_set: self.genesis_set.clone(),
+            blocks,
+            nodes: self.nodes.values().map(NodeSnapshot::new).collect(),
+            messages: self.network
+                .queued_messages()
+                .into_iter()
+                .map(|(step_sent, message)| {
+                    QueuedMessage {
+                        step_sent,
+                        message: message.clone(),
+                    }
+                })
+                .collect(),
+            clients: self.clients.values().cloned().collect(),
+            mocks: self.mocks.clone(),
+            partitions: self.partitions.clone(),
+        }
+    }
+
+    fn log_step_start(&self, step: u64) {
+        set_log_step(step);
+        if step == 0 {
+            set_log_seed(seed());
+            let marker = RunMarker {
+                seed: seed(),
+                params: self.params.clone(),
+                node_params: self.node_params.clone(),
+            };
+            info!("{}", marker.to_line());
+        }
+        let marker = StepMarker {
+            step,
+            phase: self.phase,
+            nodes: self.nodes.len(),
+            queue_size: self.network.messages_in_queue(),
+            seed: seed(),
+        };
+        info!("{}", marker.to_line());
+        info!(
+            "-- step {} ({:?}) {} nodes --",
+            step,
+            self.phase,
+            self.nodes.len()
+        );
+        trace::record(Record::Step {
+            step,
+            phase: self.phase,
+            nodes: self.nodes.len(),
+        });
+    }
+
+    fn log_step_end(&mut self, step: u64) {
+        let queue_size = self.network.messages_in_queue();
+        debug!("- {} messages still in queue. -", queue_size);
+
+        for (node, count) in self.network.take_sent_counts() {
+            trace::record(Record::Sent {
+                step,
+                node,
+                count: count.messages,
+                bytes: count.bytes,
+            });
+        }
+        for (message_kind, count) in self.network.take_kind_counts() {
+            self.sent_by_kind.entry(message_kind).or_default().add(count);
+            trace::record(Record::SentByKind {
+                step,
+                message_kind,
+                count: count.messages,
+                bytes: count.bytes,
+            });
+        }
+        trace::record(Record::Queue {
+            step,
+            size: queue_size,
+        });
+        if self.metrics.is_some() {
+            let prefixes = self.nodes
+                .values()
+                .flat_map(|node| self.blocks.block_contents(&node.current_blocks))
+                .map(|block| block.prefix)
+                .collect();
+            let nodes = self.nodes.len();
+            let confirmations: Vec<_> = self.nodes.values().map(|node| node.confirmation).collect();
+            let requests = self.proof_requests();
+            let skips = self.version_gap_skips() + self.unagreed_proof_skips();
+            let truncated_segments = self.truncated_segments();
+            if let Some(ref mut metrics) = self.metrics {
+                metrics.count_proof_requests(requests, skips);
+                metrics.count_truncated_segments(truncated_segments);
+                metrics.update(step, nodes, queue_size, prefixes, &confirmations).expect(
+                    "failed to write metrics file",
+                );
+            }
+        }
+        if self.section_map.is_some() || self.heat_map.is_some() || !self.attackers.is_empty() ||
+            self.overload.is_enabled()
+        {
+            let sections = agreed_sections(&self.blocks, &self.nodes);
+            self.overload.update_sections(&sections);
+            if let Some(ref mut section_map) = self.section_map {
+                section_map.update(step, &sections).expect("failed to write section map");
+            }
+            if let Some(ref mut heat_map) = self.heat_map {
+                let pair_counts = self.network.take_pair_counts();
+                heat_map.update(step, &pair_counts, &sections).expect(
+                    "failed to write heat map",
+                );
+            }
+            if !self.attackers.is_empty() {
+                self.count_attackers(step, &sections);
+            }
+        }
+        // Flush every step so that the trace of a run that panics is still usable.
+        trace::flush();
+    }
+
+    /// Record how many members of each agreed section are attackers, tracing any changes.
+    fn count_attackers(&mut self, step: u64, sections: &BTreeMap<Prefix, Block>) {
+        let mut counts = BTreeMap::new();
+        for (&prefix, section) in sections {
+            let attackers = section
+                .members
+                .iter()
+                .filter(|name| self.attackers.contains_key(name))
+                .count();
+            let members = section.members.len();
+            if self.attacker_counts.get(&prefix) != Some(&(attackers, members)) {
+                trace::record(Record::Attackers {
+                    step,
+                    prefix,
+                    attackers,
+                    members,
+                });
+            }
+            let fraction = attackers as f64 / members as f64;
+            if attackers > 0 && self.peak_attackers.is_none_or(|(_, _, peak)| fraction > peak) {
+                self.peak_attackers = Some((step, prefix, fraction));
+            }
+            counts.insert(prefix, (attackers, members));
+        }
+        self.attacker_counts = counts;
     }
 
     fn phase_for_next_step(&self, step: u64) -> Phase {
@@ -400,7 +2095,12 @@ impl Simulation {
                 }
             }
             Growth => {
-                if self.nodes.len() >= self.params.grow_complete {
+                // Growth can't go past the maximum network size, so stop there at the latest.
+                let grow_complete = self.params.max_network_size.map_or(
+                    self.params.grow_complete,
+                    |max| cmp::min(max, self.params.grow_complete),
+                );
+                if self.nodes.len() >= grow_complete {
                     Stable { since_step: step + 1 }
                 } else {
                     Growth
@@ -418,7 +2118,10 @@ impl Simulation {
                 }
             }
             Shrinking => {
-                if self.nodes.len() <= quorum(self.node_params.min_section_size) + 1 {
+                let num_sections = agreed_sections(&self.blocks, &self.nodes).len();
+                let min_section_size =
+                    self.node_params.min_section_size_for(num_sections, self.nodes.len());
+                if self.nodes.len() <= self.node_params.quorum(min_section_size) + 1 {
                     Finishing { since_step: step + 1 }
                 } else {
                     Shrinking
@@ -428,3 +2131,80 @@ impl Simulation {
         }
     }
 }
+
+/// Log how a section split by a partition converged once it healed.
+fn report_healing(healing: &Healing) {
+    match (healing.healed_step, healing.steps_to_converge()) {
+        (Some(healed_step), Some(steps)) => {
+            info!(
+                "partition of {:?} from step {} to {}: converged after {} steps, with {} witness \
+                 votes and {} conflicting valid blocks",
+                healing.prefix,
+                healing.partitioned_step,
+                healed_step,
+                steps,
+                healing.witness_votes,
+                healing.conflicting_blocks
+            )
+        }
+        (Some(healed_step), None) => {
+            warn!(
+                "partition of {:?} from step {} to {}: never converged, with {} witness votes and \
+                 {} conflicting valid blocks",
+                healing.prefix,
+                healing.partitioned_step,
+                healed_step,
+                healing.witness_votes,
+                healing.conflicting_blocks
+            )
+        }
+        (None, _) => {
+            warn!(
+                "partition of {:?} from step {} never healed, with {} conflicting valid blocks",
+                healing.prefix,
+                healing.partitioned_step,
+                healing.conflicting_blocks
+            )
+        }
+    }
+}
+
+/// Choose a speed class for a new node, with the probabilities given by the parameters.
+fn random_speed(params: &SimulationParams) -> SpeedClass {
+    // Don't draw a random number unless we have to, so that seeds reproduce the same runs.
+    if params.slow_node_ratio + params.fast_node_ratio <= 0.0 {
+        return SpeedClass::Normal;
+    }
+    let x = random::<f64>();
+    if x < params.slow_node_ratio {
+        SpeedClass::Slow
+    } else if x < params.slow_node_ratio + params.fast_node_ratio {
+        SpeedClass::Fast
+    } else {
+        SpeedClass::Normal
+    }
+}
+
+/// Choose how far a new node's clock is from the network's, within `max_clock_skew` steps.
+fn random_clock_skew(params: &SimulationParams) -> i64 {
+    // As for speeds, only draw a random number if skew is enabled.
+    if params.max_clock_skew == 0 {
+        return 0;
+    }
+    let range = 2 * params.max_clock_skew + 1;
+    (random::<u64>() % range) as i64 - params.max_clock_skew as i64
+}
+
+/// The member that `vote` removes from its section, if it removes one and changes nothing else.
+fn removed_member(vote: &Vote, blocks: &Blocks) -> Option<Name> {
+    let from = vote.from.into_block(blocks);
+    let to = vote.to.into_block(blocks);
+    if from.prefix != to.prefix || to.members.len() + 1 != from.members.len() {
+        return None;
+    }
+    let mut removed = from.members.difference(&to.members);
+    match (removed.next(), removed.next()) {
+        (Some(&name), None) => Some(name),
+        _ => None,
+    }
+}
@@ -0,0 +1,345 @@
+//! Versioned on-disk snapshots of a simulation's state.
+//!
+//! A snapshot is a single JSON document holding the parameters of a run, every block, the state
+//! of every node and the messages queued in the network at the end of a step. It starts with a
+//! magic string and a format version, which are checked before anything else is decoded, so that
+//! a snapshot written by a newer version of ewok is rejected with a clear error rather than
+//! misread. Fields added by later versions must have defaults, so that older snapshots still load.
+//!
+//! Version 2 added the state of clients, mocks and partitions, and more of each node's state:
+//! its peers' connection states, queued candidates and approvals of candidates, resource proof
+//! challengers, speed, backlog, clock skew and deferred votes. A version 1 snapshot loads with
+//! all of these empty.
+
+use block::{Block, BlockId, Vote};
+use blocks::{Blocks, CurrentBlocks, ValidBlocks};
+use client::ClientNode;
+use message::Message;
+use mock::MockNode;
+use name::Name;
+use node::Node;
+use params::{NodeParams, SimulationParams, SpeedClass};
+use partition::Partitions;
+use peer_state::PeerStates;
+use simulation::Phase;
+
+use serde_json::{self, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Magic string identifying a file as an ewok snapshot.
+pub const SNAPSHOT_MAGIC: &str = "ewok-snapshot";
+
+/// Version of the snapshot format written by this version of ewok.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// The state of a simulation at the end of a step.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Always `SNAPSHOT_MAGIC`.
+    pub magic: String,
+    /// Version of the format the snapshot was written in.
+    pub version: u32,
+    /// The step that the snapshot was taken at.
+    pub step: u64,
+    pub phase: Phase,
+    pub params: SimulationParams,
+    pub node_params: NodeParams,
+    /// Blocks that all nodes started from.
+    pub genesis_set: BTreeSet<BlockId>,
+    /// Every block created so far, sorted by ID. IDs aren't stored, as they're computed from the
+    /// blocks' contents.
+    pub blocks: Vec<Block>,
+    /// State of every live node, sorted by name.
+    pub nodes: Vec<NodeSnapshot>,
+    /// Messages that have been sent but not yet delivered.
+    pub messages: Vec<QueuedMessage>,
+    /// Connected clients, sorted by name.
+    #[serde(default)]
+    pub clients: Vec<ClientNode>,
+    /// Mocks attached to nodes, including those that haven't joined yet.
+    #[serde(default)]
+    pub mocks: BTreeMap<Name, MockNode>,
+    #[serde(default)]
+    pub partitions: Partitions,
+}
+
+/// The persistent state of a single node.
+///
+/// Caches that can be rebuilt from this state, like the reverse vote counts and the routing
+/// table, aren't stored. Neither is the message filter, so a node restored from a snapshot may
+/// resend messages it sent shortly before the snapshot was taken.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeSnapshot {
+    pub name: Name,
+    pub step_created: u64,
+    pub valid_blocks: ValidBlocks,
+    pub current_candidate_blocks: ValidBlocks,
+    pub current_blocks: CurrentBlocks,
+    pub prev_current_blocks: CurrentBlocks,
+    /// Every vote the node has seen, with its voters, sorted by vote.
+    pub votes: Vec<(Vote, BTreeSet<Name>)>,
+    pub recent_votes: BTreeSet<Vote>,
+    pub connections: BTreeSet<Name>,
+    pub connect_requests: BTreeSet<Name>,
+    /// Map from candidates to the step they were added.
+    pub candidates: BTreeMap<Name, u64>,
+    /// Members of our section that have approved each candidate.
+    #[serde(default)]
+    pub candidate_approvals: BTreeMap<Name, BTreeSet<Name>>,
+    /// Nodes that joined while another candidate was pending, with the step they were queued.
+    #[serde(default)]
+    pub queued_candidates: Vec<(Name, u64)>,
+    #[serde(default)]
+    pub peer_states: PeerStates,
+    /// Members who have challenged the node to prove its resources, and the number of parts of
+    /// its response it's sent each of them.
+    #[serde(default)]
+    pub proof_challengers: BTreeMap<Name, u64>,
+    #[serde(default)]
+    pub speed: SpeedClass,
+    /// Messages delivered to the node that it hasn't had the capacity to handle yet.
+    #[serde(default)]
+    pub backlog: Vec<Message>,
+    #[serde(default)]
+    pub clock_skew: i64,
+    /// Witnessing votes held back during a message storm.
+    #[serde(default)]
+    pub deferred_votes: Vec<Vote>,
+}
+
+/// A message in the network queue, with the step it was sent at.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueuedMessage {
+    pub step_sent: u64,
+    pub message: Message,
+}
+
+/// Error from reading a snapshot.
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// The snapshot isn't valid JSON, or doesn't match the format its version describes.
+    Json(serde_json::Error),
+    /// The file doesn't start with `SNAPSHOT_MAGIC`, so isn't a snapshot.
+    BadMagic,
+    /// The snapshot was written in a newer format than this version of ewok understands.
+    UnsupportedVersion(u32),
+    /// A node refers to a block that isn't in the snapshot.
+    MissingBlock { node: Name, block: BlockId },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::Io(ref e) => write!(f, "couldn't read snapshot: {}", e),
+            SnapshotError::Json(ref e) => write!(f, "invalid snapshot: {}", e),
+            SnapshotError::BadMagic => write!(f, "not an ewok snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "snapshot version {} is newer than the supported version {}",
+                    version,
+                    SNAPSHOT_VERSION
+                )
+            }
+            SnapshotError::MissingBlock { node, block } => {
+                write!(f, "Node({}) refers to missing block {}", node, block)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(e: serde_json::Error) -> Self {
+        SnapshotError::Json(e)
+    }
+}
+
+impl Snapshot {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        serde_json::to_writer(writer, self).map_err(io::Error::from)
+    }
+
+    /// Read a snapshot, checking its magic and version before decoding the rest of it.
+    pub fn read<R: Read>(reader: R) -> Result<Self, SnapshotError> {
+        let value: Value = serde_json::from_reader(reader)?;
+        if value.get("magic").and_then(Value::as_str) != Some(SNAPSHOT_MAGIC) {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = value
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or(SnapshotError::BadMagic)?;
+        if version > u64::from(SNAPSHOT_VERSION) {
+            return Err(SnapshotError::UnsupportedVersion(version as u32));
+        }
+        let snapshot: Snapshot = serde_json::from_value(value)?;
+        snapshot.check_blocks()?;
+        Ok(snapshot)
+    }
+
+    /// The blocks in the snapshot, keyed by ID.
+    pub fn blocks(&self) -> Blocks {
+        let mut blocks = Blocks::new();
+        for block in &self.blocks {
+            blocks.insert(block.clone());
+        }
+        blocks
+    }
+
+    /// Check that every block a node refers to is in the snapshot.
+    fn check_blocks(&self) -> Result<(), SnapshotError> {
+        let ids: BTreeSet<_> = self.blocks.iter().map(Block::get_id).collect();
+        for node in &self.nodes {
+            let referenced = node.valid_blocks
+                .iter()
+                .chain(&node.current_candidate_blocks)
+                .chain(&node.current_blocks)
+                .chain(&node.prev_current_blocks)
+                .chain(node.votes.iter().flat_map(|entry| vec![&entry.0.from, &entry.0.to]))
+                .chain(node.deferred_votes.iter().flat_map(|vote| vec![&vote.from, &vote.to]));
+            for &block in referenced {
+                if !ids.contains(&block) {
+                    return Err(SnapshotError::MissingBlock {
+                        node: node.name,
+                        block,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl NodeSnapshot {
+    pub fn new(node: &Node) -> Self {
+        let mut votes: Vec<_> = node.vote_counts
+            .iter()
+            .flat_map(|(&from, map)| {
+                map.iter().map(
+                    move |(&to, voters)| (Vote { from, to }, voters.clone()),
+                )
+            })
+            .collect();
+        votes.sort();
+        NodeSnapshot {
+            name: node.our_name,
+            step_created: node.step_created,
+            valid_blocks: node.valid_blocks.clone(),
+            current_candidate_blocks: node.current_candidate_blocks.clone(),
+            current_blocks: node.current_blocks.clone(),
+            prev_current_blocks: node.prev_current_blocks.clone(),
+            votes,
+            recent_votes: node.recent_votes.clone(),
+            connections: node.connections.clone(),
+            connect_requests: node.connect_requests.clone(),
+            candidates: node.candidates
+                .iter()
+                .map(|(&name, candidate)| (name, candidate.step_added()))
+                .collect(),
+            candidate_approvals: node.candidates
+                .iter()
+                .map(|(&name, candidate)| (name, candidate.approvals().clone()))
+                .collect(),
+            queued_candidates: node.queued_candidates.iter().cloned().collect(),
+            peer_states: node.peer_states.clone(),
+            proof_challengers: node.proof_challengers.clone(),
+            speed: node.speed,
+            backlog: node.backlog.iter().cloned().collect(),
+            clock_skew: node.clock_skew,
+            deferred_votes: node.deferred_votes.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use event_schedule::EventSchedule;
+    use name::Prefix;
+    use simulation::Simulation;
+
+    #[test]
+    fn round_trip() {
+        let sections = btreemap!{ Prefix::short(1, 0) => 8, Prefix::short(1, 0b10000000) => 8 };
+        let simulation = Simulation::new_from(
+            sections,
+            EventSchedule::empty(),
+            SimulationParams::default(),
+            NodeParams::default(),
+        );
+        let snapshot = simulation.snapshot();
+        assert_eq!(snapshot.nodes.len(), 16);
+        assert_eq!(snapshot.blocks.len(), 2);
+
+        let mut bytes = vec![];
+        snapshot.write(&mut bytes).unwrap();
+        let decoded = Snapshot::read(&bytes[..]).unwrap();
+        assert_eq!(snapshot, decoded);
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let sections = btreemap!{ Prefix::empty() => 8 };
+        let mut simulation = Simulation::new_from(
+            sections,
+            EventSchedule::empty(),
+            SimulationParams::default(),
+            NodeParams::default(),
+        );
+        let name = Prefix::empty().substituted_in(Name(0));
+        simulation.add_mock(name, MockNode::new());
+        let snapshot = simulation.snapshot();
+        assert_eq!(snapshot.mocks.len(), 1);
+
+        // Version 1 snapshots didn't have the state added since.
+        let mut value = serde_json::to_value(&snapshot).unwrap();
+        value["version"] = Value::from(1);
+        for field in &["clients", "mocks", "partitions"] {
+            value.as_object_mut().unwrap().remove(*field);
+        }
+        for node in value["nodes"].as_array_mut().unwrap() {
+            node.as_object_mut().unwrap().remove("peer_states");
+            node.as_object_mut().unwrap().remove("clock_skew");
+        }
+        let decoded = Snapshot::read(value.to_string().as_bytes()).unwrap();
+        assert!(decoded.mocks.is_empty());
+        assert_eq!(decoded.nodes, snapshot.nodes);
+    }
+
+    #[test]
+    fn checks_header() {
+        let mut snapshot: Value = serde_json::from_str(r#"{"magic": "ewok-snapshot"}"#).unwrap();
+        snapshot["version"] = Value::from(SNAPSHOT_VERSION + 1);
+        match Snapshot::read(snapshot.to_string().as_bytes()) {
+            Err(SnapshotError::UnsupportedVersion(v)) => assert_eq!(v, SNAPSHOT_VERSION + 1),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        snapshot["magic"] = Value::from("something-else");
+        match Snapshot::read(snapshot.to_string().as_bytes()) {
+            Err(SnapshotError::BadMagic) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -1,7 +1,6 @@
 use name::Name;
 use block::{Block, Vote};
-use blocks::{Blocks, CurrentBlocks};
-use std::collections::BTreeSet;
+use blocks::{Blocks, CurrentBlocks, Derivation};
 
 pub fn split_blocks(
     blocks: &mut Blocks,
@@ -34,30 +33,18 @@ fn split_block(
     if block.should_split(min_split_size) &&
         neighbours_ok(blocks, block, current_blocks, min_split_size)
     {
-        let p0 = block.prefix.pushed(false);
-        let p1 = block.prefix.pushed(true);
-        let (s0, s1): (BTreeSet<_>, _) = block.members.iter().partition(|name| p0.matches(**name));
-        let b0 = blocks.insert(Block {
-            prefix: p0,
-            version: block.version + 1,
-            members: s0,
-        });
-        let b1 = blocks.insert(Block {
-            prefix: p1,
-            version: block.version + 1,
-            members: s1,
-        });
-
-        let v0 = Vote {
-            from: block.get_id(),
-            to: b0,
-        };
-        let v1 = Vote {
-            from: block.get_id(),
-            to: b1,
-        };
-
-        vec![v0, v1]
+        let from = block.get_id();
+        block
+            .prefix
+            .split()
+            .iter()
+            .map(|&prefix| {
+                Vote {
+                    from,
+                    to: blocks.derive(from, Derivation::Split(prefix)),
+                }
+            })
+            .collect()
     } else {
         vec![]
     }
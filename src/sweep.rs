@@ -0,0 +1,113 @@
+//! Run many simulations over a grid of values for two parameters.
+
+use params::{NodeParams, SimulationParams, set_param, ParamError};
+use simulation::{Phase, Simulation};
+
+use std::panic::{self, AssertUnwindSafe};
+
+/// A parameter to vary, and the values to give it.
+#[derive(Clone, Debug)]
+pub struct Axis {
+    pub param: String,
+    pub values: Vec<String>,
+}
+
+/// A sweep over every combination of values for two parameters.
+#[derive(Clone, Debug)]
+pub struct Sweep {
+    /// Parameters used for every run, except for those being varied.
+    pub params: SimulationParams,
+    pub node_params: NodeParams,
+    pub x: Axis,
+    pub y: Axis,
+    /// Number of simulations to run for each combination of values.
+    pub runs: usize,
+}
+
+/// Outcome of all the runs for a single combination of values.
+#[derive(Clone, Debug)]
+pub struct SweepResult {
+    pub x: String,
+    pub y: String,
+    pub runs: usize,
+    /// Number of runs that finished inconsistent, or panicked.
+    pub failures: usize,
+    /// Mean number of steps taken to converge once churn stopped, over the successful runs.
+    pub mean_convergence: Option<f64>,
+}
+
+impl SweepResult {
+    pub fn failure_rate(&self) -> f64 {
+        self.failures as f64 / self.runs as f64
+    }
+}
+
+impl Sweep {
+    /// Check that both axes name real parameters and that all of their values parse.
+    pub fn validate(&self) -> Result<(), ParamError> {
+        for axis in &[&self.x, &self.y] {
+            for value in &axis.values {
+                let mut params = self.params.clone();
+                let mut node_params = self.node_params.clone();
+                set_param(&mut params, &mut node_params, &axis.param, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the sweep, calling `progress` after each combination of values is complete.
+    pub fn run<F>(&self, mut progress: F) -> Result<Vec<SweepResult>, ParamError>
+    where
+        F: FnMut(&SweepResult),
+    {
+        self.validate()?;
+
+        let mut results = vec![];
+        for y in &self.y.values {
+            for x in &self.x.values {
+                let mut params = self.params.clone();
+                let mut node_params = self.node_params.clone();
+                set_param(&mut params, &mut node_params, &self.x.param, x)?;
+                set_param(&mut params, &mut node_params, &self.y.param, y)?;
+
+                let mut failures = 0;
+                let mut convergence = vec![];
+                for _ in 0..self.runs {
+                    match run_once(params.clone(), node_params.clone()) {
+                        Some(steps) => convergence.push(steps),
+                        None => failures += 1,
+                    }
+                }
+
+                let result = SweepResult {
+                    x: x.clone(),
+                    y: y.clone(),
+                    runs: self.runs,
+                    failures,
+                    mean_convergence: mean(&convergence),
+                };
+                progress(&result);
+                results.push(result);
+            }
+        }
+        Ok(results)
+    }
+}
+
+/// Run a single simulation, returning the number of steps it took to converge if it succeeded.
+fn run_once(params: SimulationParams, node_params: NodeParams) -> Option<u64> {
+    let mut simulation = Simulation::new(params, node_params);
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| simulation.run()));
+    match (outcome, simulation.phase()) {
+        (Ok(Ok(_)), Phase::Finishing { since_step }) => Some(simulation.step() - since_step),
+        _ => None,
+    }
+}
+
+fn mean(values: &[u64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<u64>() as f64 / values.len() as f64)
+    }
+}
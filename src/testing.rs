@@ -0,0 +1,100 @@
+//! Helpers for writing scripted simulations, shared by the tests, the scenarios and any
+//! experiments built on ewok.
+
+use event::Event;
+use event_schedule::EventSchedule;
+use name::Prefix;
+use params::SimulationParams;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Prefix from a string of bits, e.g. `p("0110")`.
+///
+/// The empty string is the empty prefix, and underscores and spaces are ignored, so long
+/// prefixes can be grouped like `p("0110_1001")`. Panics if the string isn't a valid prefix.
+pub fn p(bits: &str) -> Prefix {
+    let digits: String = bits.chars().filter(|&c| c != '_' && c != ' ').collect();
+    digits.parse().unwrap_or_else(|()| panic!("invalid prefix literal: {:?}", bits))
+}
+
+/// Set of prefixes from their strings of bits, as parsed by `p`.
+pub fn prefixes(bits: &[&str]) -> BTreeSet<Prefix> {
+    bits.iter().map(|bits| p(bits)).collect()
+}
+
+/// Sections with the given prefixes, all of the same size, to pass to `Simulation::new_from`.
+pub fn sections(bits: &[&str], size: usize) -> BTreeMap<Prefix, usize> {
+    bits.iter().map(|bits| (p(bits), size)).collect()
+}
+
+/// The step `offset` steps after the last event in the schedule.
+pub fn step_num(schedule: &EventSchedule, offset: u64) -> u64 {
+    schedule.schedule.keys().next_back().cloned().unwrap_or(0) + offset
+}
+
+/// Schedule `events` one at a time every `spacing` steps, with the first `spacing` steps after
+/// `step_num(schedule, offset)`.
+pub fn add_events(schedule: &mut EventSchedule, offset: u64, spacing: u64, events: Vec<Event>) {
+    let start_step = step_num(schedule, offset);
+
+    let timed_events = events.into_iter().enumerate().map(|(i, ev)| {
+        (start_step + (i as u64 + 1) * spacing, vec![ev])
+    });
+
+    schedule.schedule.extend(timed_events);
+}
+
+/// Parameters without any random churn or disconnections, which stay in the stable phase until
+/// every scheduled event has happened.
+pub fn quiet_params() -> SimulationParams {
+    SimulationParams {
+        grow_prob_join: 0.0,
+        grow_prob_drop: 0.0,
+        prob_churn: 0.0,
+        shrink_prob_join: 0.0,
+        shrink_prob_drop: 0.0,
+        grow_prob_disconnect: 0.0,
+        grow_prob_reconnect: 0.0,
+        prob_disconnect: 0.0,
+        prob_reconnect: 0.0,
+        shrink_prob_disconnect: 0.0,
+        shrink_prob_reconnect: 0.0,
+        starting_complete: 0,
+        grow_complete: 0,
+        stable_steps: 1000,
+        ..SimulationParams::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use event::Event::AddNode;
+    use name::Name;
+
+    #[test]
+    fn prefix_literals() {
+        assert_eq!(p(""), Prefix::empty());
+        assert_eq!(p("0110_1001"), p("01101001"));
+        assert_eq!(p("01 1"), p("011"));
+        assert_eq!(prefixes(&["00", "01", "1"]), btreeset!{ p("00"), p("01"), p("1") });
+        assert_eq!(sections(&["00", "01"], 8), btreemap!{ p("00") => 8, p("01") => 8 });
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid prefix literal")]
+    fn invalid_prefix_literal() {
+        p("012");
+    }
+
+    #[test]
+    fn spaced_events() {
+        let mut schedule = EventSchedule::new(btreemap!{ 5 => vec![] });
+        add_events(&mut schedule, 10, 3, vec![AddNode(Name(1)), AddNode(Name(2))]);
+        assert_eq!(
+            schedule.events(),
+            vec![(18, AddNode(Name(1))), (21, AddNode(Name(2)))]
+        );
+        assert_eq!(step_num(&schedule, 1), 22);
+    }
+}
@@ -0,0 +1,199 @@
+//! Initial network topologies loaded from files.
+//!
+//! A topology lists every section with the exact names of its members, and the pairs of nodes
+//! that start out disconnected, so that a configuration can be reproduced exactly rather than
+//! regenerated randomly. Topologies are JSON, e.g.
+//!
+//! ```json
+//! {
+//!   "sections": { "0": [1, 2, 3], "1": [9223372036854775808, 9223372036854775809] },
+//!   "disconnected": [[1, 9223372036854775808]]
+//! }
+//! ```
+//!
+//! Names are written as they are in traces: as numbers, or as decimal strings with the
+//! `wide-names` feature.
+
+use block::Block;
+use name::{Name, Prefix, PrefixSet};
+
+use serde_json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Topology {
+    /// Members of each section, by prefix.
+    pub sections: BTreeMap<Prefix, BTreeSet<Name>>,
+    /// Pairs of nodes that aren't connected to each other at the start.
+    #[serde(default)]
+    pub disconnected: Vec<(Name, Name)>,
+}
+
+/// Error from loading a topology.
+#[derive(Debug)]
+pub enum TopologyError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The prefixes don't cover the whole namespace exactly once.
+    BadPrefixes(Vec<Prefix>),
+    /// A section has no members.
+    EmptySection(Prefix),
+    /// A node's name doesn't match the prefix of the section it's listed in.
+    WrongSection { name: Name, prefix: Prefix },
+    /// A disconnected pair refers to a node that isn't in any section.
+    UnknownNode(Name),
+    /// A node is listed as disconnected from itself.
+    SelfDisconnection(Name),
+}
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TopologyError::Io(ref e) => write!(f, "couldn't read topology: {}", e),
+            TopologyError::Json(ref e) => write!(f, "invalid topology: {}", e),
+            TopologyError::BadPrefixes(ref prefixes) => {
+                write!(
+                    f,
+                    "prefixes {:?} don't cover the namespace without overlapping",
+                    prefixes
+                )
+            }
+            TopologyError::EmptySection(prefix) => {
+                write!(f, "section {:?} has no members", prefix)
+            }
+            TopologyError::WrongSection { name, prefix } => {
+                write!(f, "Node({}) doesn't belong in section {:?}", name, prefix)
+            }
+            TopologyError::UnknownNode(name) => {
+                write!(f, "Node({}) is disconnected but isn't in any section", name)
+            }
+            TopologyError::SelfDisconnection(name) => {
+                write!(f, "Node({}) can't be disconnected from itself", name)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for TopologyError {
+    fn from(e: io::Error) -> Self {
+        TopologyError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for TopologyError {
+    fn from(e: serde_json::Error) -> Self {
+        TopologyError::Json(e)
+    }
+}
+
+impl Topology {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TopologyError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Read a topology and check that it describes a valid network.
+    pub fn read<R: Read>(reader: R) -> Result<Self, TopologyError> {
+        let topology: Topology = serde_json::from_reader(reader)?;
+        topology.validate()?;
+        Ok(topology)
+    }
+
+    /// Check that the sections cover the namespace, that every section has members and every node
+    /// is in the right section, and that disconnected pairs are made of distinct nodes in the
+    /// network.
+    pub fn validate(&self) -> Result<(), TopologyError> {
+        let prefixes: PrefixSet = self.sections.keys().cloned().collect();
+        if !prefixes.is_complete_cover() || !prefixes.is_disjoint() {
+            return Err(TopologyError::BadPrefixes(
+                self.sections.keys().cloned().collect(),
+            ));
+        }
+        for (&prefix, members) in &self.sections {
+            if members.is_empty() {
+                return Err(TopologyError::EmptySection(prefix));
+            }
+            if let Some(&name) = members.iter().find(|&&name| !prefix.matches(name)) {
+                return Err(TopologyError::WrongSection { name, prefix });
+            }
+        }
+        for &(n1, n2) in &self.disconnected {
+            if n1 == n2 {
+                return Err(TopologyError::SelfDisconnection(n1));
+            }
+            for &name in &[n1, n2] {
+                if !self.sections.values().any(|members| members.contains(&name)) {
+                    return Err(TopologyError::UnknownNode(name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The genesis blocks for the sections.
+    pub fn genesis_blocks(&self) -> BTreeSet<Block> {
+        self.sections
+            .iter()
+            .map(|(&prefix, members)| {
+                Block {
+                    prefix,
+                    members: Arc::new(members.clone()),
+                    version: 0,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use name::NAME_BITS;
+
+    fn read(json: &str) -> Result<Topology, TopologyError> {
+        Topology::read(json.as_bytes())
+    }
+
+    #[test]
+    fn validates() {
+        // Names are written differently with `wide-names`.
+        let n1 = serde_json::to_string(&Name(1)).unwrap();
+        let hi = serde_json::to_string(&Name(1 << (NAME_BITS - 1))).unwrap();
+        let topology = read(&format!(
+            r#"{{"sections": {{"0": [{}], "1": [{}]}}, "disconnected": [[{}, {}]]}}"#,
+            n1,
+            hi,
+            n1,
+            hi
+        )).unwrap();
+        assert_eq!(topology.genesis_blocks().len(), 2);
+        assert_eq!(topology.disconnected.len(), 1);
+
+        match read(&format!(r#"{{"sections": {{"0": [{}]}}}}"#, n1)) {
+            Err(TopologyError::BadPrefixes(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match read(&format!(r#"{{"sections": {{"0": [{}], "1": []}}}}"#, hi)) {
+            Err(TopologyError::WrongSection { .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match read(&format!(r#"{{"sections": {{"0": [{}], "1": []}}}}"#, n1)) {
+            Err(TopologyError::EmptySection(prefix)) => assert_eq!(prefix.to_string(), "1"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let n2 = serde_json::to_string(&Name(2)).unwrap();
+        match read(&format!(
+            r#"{{"sections": {{"": [{}]}}, "disconnected": [[{}, {}]]}}"#,
+            n1,
+            n1,
+            n2
+        )) {
+            Err(TopologyError::UnknownNode(Name(2))) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! Structured, machine-readable record of a simulation run.
+//!
+//! If the `EWOK_TRACE` environment variable is set, the simulation writes one JSON record per
+//! line to the file it names. The tools in `src/bin` read this file rather than scraping the
+//! free-text logs, whose format changes whenever a `Debug` impl does. Records are also passed to
+//! the results database, if one is enabled (see `results_db`).
+
+use block::{Block, BlockId};
+use chaos::ChaosAction;
+use event::Event;
+use message::{MessageKind, TraceId};
+use name::{Name, Prefix};
+use params::{NodeParams, SimulationParams};
+#[cfg(feature = "sqlite")]
+use results_db;
+use simulation::Phase;
+
+use serde_json;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashSet};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::Path;
+
+/// A single entry in the trace file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Record {
+    /// The parameters and seed of the run, written once before anything else.
+    ///
+    /// The parameters are boxed so that the other, far more common, records stay small.
+    Params {
+        seed: [u32; 4],
+        params: Box<SimulationParams>,
+        node_params: Box<NodeParams>,
+    },
+    /// Start of a simulation step.
+    Step { step: u64, phase: Phase, nodes: usize },
+    /// An event applied by the simulation, either scheduled or random.
+    Event { step: u64, event: Event },
+    /// Contents of a block, emitted once before the first record that refers to it.
+    Block { id: BlockId, block: Block },
+    /// A node saw a vote reach quorum (the equivalent of the "new valid vote" log line).
+    Agreement {
+        step: u64,
+        node: Name,
+        from: BlockId,
+        to: BlockId,
+        /// The voters the node counted towards the quorum.
+        #[serde(default)]
+        voters: BTreeSet<Name>,
+    },
+    /// Number of messages a node sent during a step, and their total size in bytes.
+    Sent {
+        step: u64,
+        node: Name,
+        count: usize,
+        #[serde(default)]
+        bytes: usize,
+    },
+    /// Number of messages of a single kind sent by all nodes during a step, and their total size
+    /// in bytes.
+    SentByKind {
+        step: u64,
+        message_kind: MessageKind,
+        count: usize,
+        #[serde(default)]
+        bytes: usize,
+    },
+    /// A message was delivered, to be handled by its recipient during the step.
+    ///
+    /// Messages sent while handling it share its `trace_id`, so the messages caused by a single
+    /// message can be followed across nodes by filtering on it.
+    Delivered {
+        step: u64,
+        sender: Name,
+        recipient: Name,
+        message_kind: MessageKind,
+        trace_id: TraceId,
+    },
+    /// Number of messages still queued at the end of a step.
+    Queue { step: u64, size: usize },
+    /// A chaos action taken by the simulation.
+    Chaos { step: u64, action: ChaosAction },
+    /// Outcome of a client data operation (`Event::Put` or `Event::Get`).
+    DataOp {
+        step: u64,
+        event: Event,
+        success: bool,
+    },
+    /// A node was about to send more than `storm_threshold` messages while updating its state,
+    /// and deferred some of its votes to the next step if throttling.
+    Storm {
+        step: u64,
+        node: Name,
+        messages: usize,
+        deferred_votes: usize,
+    },
+    /// Number of attackers among the members of an agreed section, written at the end of a step
+    /// whenever either number changes.
+    Attackers {
+        step: u64,
+        prefix: Prefix,
+        attackers: usize,
+        members: usize,
+    },
+    /// A node crashed from overload, having handled `load` messages within the last
+    /// `overload_window` steps. `after_merge` is true if its section was formed by a merge.
+    OverloadCrash {
+        step: u64,
+        node: Name,
+        prefix: Prefix,
+        load: usize,
+        after_merge: bool,
+    },
+    /// Outcome of the final consistency check, written once at the end of the run.
+    Finished {
+        step: u64,
+        consistent: bool,
+        sections: usize,
+        nodes: usize,
+        /// Number of times a node started voting to force-merge with a sibling.
+        #[serde(default)]
+        force_merges: u64,
+    },
+}
+
+/// Writes records to a file, remembering which blocks have already been described.
+pub struct TraceWriter {
+    writer: BufWriter<File>,
+    blocks_written: HashSet<BlockId>,
+}
+
+impl TraceWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(TraceWriter {
+            writer: BufWriter::new(File::create(path)?),
+            blocks_written: HashSet::new(),
+        })
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")
+    }
+
+    /// Write a `Block` record unless one has already been written for this block.
+    pub fn write_block(&mut self, block: &Block) -> io::Result<()> {
+        let id = block.get_id();
+        if self.blocks_written.insert(id) {
+            self.write(&Record::Block {
+                id,
+                block: block.clone(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+thread_local! {
+    static TRACE: RefCell<Option<TraceWriter>> = RefCell::new(
+        env::var("EWOK_TRACE").ok().map(|path| {
+            TraceWriter::create(&path)
+                .unwrap_or_else(|e| panic!("couldn't create trace file {}: {}", path, e))
+        })
+    );
+}
+
+/// Direct trace output for this thread to the given file, replacing any existing trace.
+pub fn trace_to<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let writer = TraceWriter::create(path)?;
+    TRACE.with(|trace| *trace.borrow_mut() = Some(writer));
+    Ok(())
+}
+
+/// Whether trace output is enabled for this thread.
+pub fn is_enabled() -> bool {
+    TRACE.with(|trace| trace.borrow().is_some())
+}
+
+/// Run `f` against the trace writer, if tracing is enabled.
+fn with_writer<F>(f: F)
+where
+    F: FnOnce(&mut TraceWriter) -> io::Result<()>,
+{
+    TRACE.with(|trace| if let Some(ref mut writer) = *trace.borrow_mut() {
+        f(writer).expect("failed to write trace record");
+    });
+}
+
+/// Record a single entry in the trace.
+pub fn record(record: Record) {
+    #[cfg(feature = "sqlite")]
+    results_db::record(&record);
+    with_writer(|writer| writer.write(&record));
+}
+
+/// Record that `node` saw the vote from `from` to `to` become valid with the votes of `voters`.
+pub fn record_agreement(step: u64, node: Name, from: &Block, to: &Block, voters: &BTreeSet<Name>) {
+    #[cfg(feature = "sqlite")]
+    {
+        if results_db::is_enabled() {
+            results_db::record(&Record::Agreement {
+                step,
+                node,
+                from: from.get_id(),
+                to: to.get_id(),
+                voters: voters.clone(),
+            });
+        }
+    }
+    with_writer(|writer| {
+        writer.write_block(from)?;
+        writer.write_block(to)?;
+        writer.write(&Record::Agreement {
+            step,
+            node,
+            from: from.get_id(),
+            to: to.get_id(),
+            voters: voters.clone(),
+        })
+    });
+}
+
+/// Flush any buffered records to disk.
+pub fn flush() {
+    with_writer(TraceWriter::flush);
+}
+
+/// Iterator over the records of a trace file.
+pub struct TraceReader {
+    lines: Lines<BufReader<File>>,
+}
+
+impl TraceReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(TraceReader { lines: BufReader::new(File::open(path)?).lines() })
+    }
+}
+
+impl Iterator for TraceReader {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Record> {
+        for line in &mut self.lines {
+            let line = line.expect("failed to read trace file");
+            if line.is_empty() {
+                continue;
+            }
+            return Some(serde_json::from_str(&line).unwrap_or_else(|e| {
+                panic!("invalid trace record {:?}: {}", line, e)
+            }));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use name::Prefix;
+    use std::sync::Arc;
+
+    #[test]
+    fn record_round_trip() {
+        let block = Block {
+            prefix: Prefix::short(2, 0b01000000),
+            version: 3,
+            members: Arc::new(btreeset!{ Name(1 << 62), Name((1 << 62) + 5) }),
+        };
+        let records = vec![
+            Record::Params {
+                seed: [1, 2, 3, 4],
+                params: Box::new(SimulationParams::default()),
+                node_params: Box::new(NodeParams::default()),
+            },
+            Record::Step {
+                step: 7,
+                phase: Phase::Stable { since_step: 2 },
+                nodes: 2,
+            },
+            Record::Block {
+                id: block.get_id(),
+                block: block.clone(),
+            },
+            Record::Agreement {
+                step: 7,
+                node: Name(1 << 62),
+                from: block.get_id(),
+                to: block.get_id(),
+                voters: (*block.members).clone(),
+            },
+            Record::Delivered {
+                step: 7,
+                sender: Name(1 << 62),
+                recipient: Name((1 << 62) + 5),
+                message_kind: MessageKind::RequestProof,
+                trace_id: 12,
+            },
+            Record::Chaos {
+                step: 8,
+                action: ChaosAction::Partition((*block.members).clone()),
+            },
+            Record::Chaos {
+                step: 8,
+                action: ChaosAction::MessageLoss {
+                    prob: 0.25,
+                    until: 20,
+                },
+            },
+            Record::DataOp {
+                step: 8,
+                event: Event::Put(Name(1 << 61)),
+                success: false,
+            },
+            Record::Storm {
+                step: 8,
+                node: Name(1 << 62),
+                messages: 120,
+                deferred_votes: 14,
+            },
+            Record::Attackers {
+                step: 8,
+                prefix: Prefix::empty().pushed(true),
+                attackers: 3,
+                members: 9,
+            },
+            Record::OverloadCrash {
+                step: 8,
+                node: Name(1 << 62),
+                prefix: Prefix::empty().pushed(false),
+                load: 85,
+                after_merge: true,
+            },
+            Record::Finished {
+                step: 9,
+                consistent: true,
+                sections: 1,
+                nodes: 2,
+                force_merges: 3,
+            },
+        ];
+        for record in records {
+            let json = serde_json::to_string(&record).unwrap();
+            let decoded: Record = serde_json::from_str(&json).unwrap();
+            assert_eq!(record, decoded);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Bindings for driving a simulation from JavaScript, e.g. an in-browser visualiser.
+//!
+//! Build with `cargo build --lib --target wasm32-unknown-unknown --features web` and generate
+//! the JavaScript glue with `wasm-bindgen`. A visualiser creates a `WebSimulation`, calls `step`
+//! as often as it likes, and renders the JSON returned by `state`, which is a snapshot in the
+//! format of the `snapshot` module.
+
+use params::{NodeParams, SimulationParams, set_param};
+use random::{parse_seed, reseed};
+use simulation::Simulation;
+
+use serde_json::{self, Value};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct WebSimulation {
+    simulation: Simulation,
+}
+
+#[wasm_bindgen]
+impl WebSimulation {
+    /// Create a simulation with a single seed node.
+    ///
+    /// `config` is a JSON object mapping parameter names to values as strings, e.g.
+    /// `{"max_delay": "5", "seed": "[1, 2, 3, 4]"}`. Parameters that aren't given keep their
+    /// defaults.
+    #[wasm_bindgen(constructor)]
+    pub fn new(config: &str) -> Result<WebSimulation, JsValue> {
+        let config: serde_json::Map<String, Value> = serde_json::from_str(config)
+            .map_err(|e| JsValue::from_str(&format!("invalid config: {}", e)))?;
+        let mut params = SimulationParams::default();
+        let mut node_params = NodeParams::default();
+        for (name, value) in config {
+            let value = match value {
+                Value::String(value) => value,
+                value => value.to_string(),
+            };
+            if name == "seed" {
+                let seed = parse_seed(&value).ok_or_else(|| {
+                    JsValue::from_str(&format!("invalid seed: {:?}", value))
+                })?;
+                reseed(seed);
+            } else {
+                set_param(&mut params, &mut node_params, &name, &value).map_err(|e| {
+                    JsValue::from_str(&e.to_string())
+                })?;
+            }
+        }
+        Ok(WebSimulation { simulation: Simulation::new(params, node_params) })
+    }
+
+    /// Run the next step, returning `false` if the simulation has finished or is paused
+    /// instead.
+    pub fn step(&mut self) -> bool {
+        self.simulation.run_step()
+    }
+
+    /// Pause the simulation once more than `size` messages are queued, until `resume` is
+    /// called.
+    pub fn pause_when_queue_exceeds(&mut self, size: usize) {
+        self.simulation.pause_when(move |sim| sim.queue_size() > size);
+    }
+
+    /// Pause the simulation once nodes agree on different blocks of the same version for a
+    /// prefix, until `resume` is called.
+    pub fn pause_on_fork(&mut self) {
+        self.simulation.pause_when(|sim| !sim.forked_prefixes().is_empty());
+    }
+
+    /// Whether the simulation is paused, in which case `step` returns `false`.
+    pub fn is_paused(&self) -> bool {
+        self.simulation.paused().is_some()
+    }
+
+    /// Carry on with a paused simulation.
+    pub fn resume(&mut self) {
+        self.simulation.resume();
+    }
+
+    /// The step that the simulation is on.
+    pub fn current_step(&self) -> u64 {
+        self.simulation.step()
+    }
+
+    /// JSON dump of the simulation's current state.
+    pub fn state(&self) -> String {
+        serde_json::to_string(&self.simulation.snapshot()).expect("failed to serialise state")
+    }
+}
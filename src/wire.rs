@@ -0,0 +1,473 @@
+//! Compact, stable binary encoding of messages.
+//!
+//! Message sizes are measured by this encoding rather than by the sizes of Rust structs, so that
+//! they reflect what a real implementation would send. The format is:
+//!
+//! * names: `NAME_BITS / 8` bytes, big-endian;
+//! * block IDs and other integers: 8 bytes, big-endian;
+//! * lengths of collections: unsigned LEB128;
+//! * a message: sender, recipient, then a one-byte tag for the kind of content followed by the
+//!   content's fields in declaration order.
+//!
+//! Trace IDs are bookkeeping for the simulation rather than part of the protocol, so they aren't
+//! encoded, and decoded messages have none. Neither are the signers of aggregate signatures, which
+//! are encoded as their length followed by that many zero bytes.
+//!
+//! Tags are fixed, so new kinds of content must be given new tags rather than reusing old ones.
+
+use block::{BlockId, Vote};
+use message::{AggregateSignature, Message, MessageContent};
+use message::MessageContent::*;
+use name::{Name, NameBits, NAME_BITS};
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
+
+const NAME_BYTES: usize = NAME_BITS / 8;
+
+const TAG_VOTE: u8 = 0;
+const TAG_VOTE_AGREED: u8 = 1;
+const TAG_VOTE_BUNDLE: u8 = 2;
+const TAG_REQUEST_PROOF: u8 = 3;
+const TAG_NO_PROOF: u8 = 4;
+const TAG_NODE_JOINED: u8 = 5;
+const TAG_BOOTSTRAP: u8 = 6;
+const TAG_CONNECT: u8 = 7;
+const TAG_DISCONNECT: u8 = 8;
+const TAG_RESOURCE_PROOF_CHALLENGE: u8 = 9;
+const TAG_RESOURCE_PROOF_RESPONSE: u8 = 10;
+const TAG_APPROVE_CANDIDATE: u8 = 11;
+const TAG_RELOCATE: u8 = 12;
+const TAG_NODE_RELOCATED: u8 = 13;
+const TAG_CLIENT_CONNECT: u8 = 14;
+const TAG_BLOCK_UPDATE: u8 = 15;
+const TAG_VOTE_AGGREGATED: u8 = 16;
+
+/// Error from decoding a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ended in the middle of the message.
+    UnexpectedEnd,
+    /// The content has a tag that doesn't belong to any kind of content.
+    UnknownTag(u8),
+    /// A length doesn't fit in a `usize`.
+    LengthOverflow,
+    /// There were bytes left over after the message.
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of message"),
+            DecodeError::UnknownTag(tag) => write!(f, "unknown content tag: {}", tag),
+            DecodeError::LengthOverflow => write!(f, "length too large"),
+            DecodeError::TrailingBytes(n) => write!(f, "{} bytes after end of message", n),
+        }
+    }
+}
+
+/// Encode a message.
+pub fn encode(message: &Message) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(encoded_len(message));
+    write_message(&mut bytes, message);
+    bytes
+}
+
+/// Number of bytes in the encoding of a message, computed without encoding it.
+pub fn encoded_len(message: &Message) -> usize {
+    encoded_len_with_content(content_len(&message.content))
+}
+
+/// Number of bytes in the encoding of a message whose content takes `content_len` bytes.
+pub fn encoded_len_with_content(content_len: usize) -> usize {
+    2 * NAME_BYTES + content_len
+}
+
+/// Number of bytes in the encoding of a message's content, computed without encoding it.
+pub fn content_len(content: &MessageContent) -> usize {
+    let mut counter = Counter(0);
+    write_content(&mut counter, content);
+    counter.0
+}
+
+/// Decode a message, which must take up the whole of `bytes`.
+pub fn decode(bytes: &[u8]) -> Result<Message, DecodeError> {
+    let mut reader = Reader { bytes };
+    let message = Message {
+        sender: reader.name()?,
+        recipient: reader.name()?,
+        content: Arc::new(reader.content()?),
+        trace_id: 0,
+    };
+    match reader.bytes.len() {
+        0 => Ok(message),
+        n => Err(DecodeError::TrailingBytes(n)),
+    }
+}
+
+/// Destination for encoded bytes.
+trait Sink {
+    fn put(&mut self, bytes: &[u8]);
+
+    fn put_zeros(&mut self, n: usize);
+}
+
+impl Sink for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+
+    fn put_zeros(&mut self, n: usize) {
+        self.resize(self.len() + n, 0);
+    }
+}
+
+/// Sink that only counts the bytes written to it.
+struct Counter(usize);
+
+impl Sink for Counter {
+    fn put(&mut self, bytes: &[u8]) {
+        self.0 += bytes.len();
+    }
+
+    fn put_zeros(&mut self, n: usize) {
+        self.0 += n;
+    }
+}
+
+fn write_message<S: Sink>(sink: &mut S, message: &Message) {
+    write_name(sink, message.sender);
+    write_name(sink, message.recipient);
+    write_content(sink, &message.content);
+}
+
+fn write_content<S: Sink>(sink: &mut S, content: &MessageContent) {
+    match *content {
+        VoteMsg(ref vote) => {
+            sink.put(&[TAG_VOTE]);
+            write_vote(sink, vote);
+        }
+        VoteAgreedMsg((ref vote, ref voters)) => {
+            sink.put(&[TAG_VOTE_AGREED]);
+            write_vote(sink, vote);
+            write_names(sink, voters);
+        }
+        VoteBundle(ref votes) => {
+            sink.put(&[TAG_VOTE_BUNDLE]);
+            write_votes_with_voters(sink, votes);
+        }
+        RequestProof(block, ref current_blocks) => {
+            sink.put(&[TAG_REQUEST_PROOF]);
+            write_block_id(sink, block);
+            write_len(sink, current_blocks.len());
+            for &id in current_blocks {
+                write_block_id(sink, id);
+            }
+        }
+        NoProof(block) => {
+            sink.put(&[TAG_NO_PROOF]);
+            write_block_id(sink, block);
+        }
+        NodeJoined => sink.put(&[TAG_NODE_JOINED]),
+        BootstrapMsg(ref votes) => {
+            sink.put(&[TAG_BOOTSTRAP]);
+            write_votes_with_voters(sink, votes);
+        }
+        Connect => sink.put(&[TAG_CONNECT]),
+        Disconnect => sink.put(&[TAG_DISCONNECT]),
+        ResourceProofChallenge => sink.put(&[TAG_RESOURCE_PROOF_CHALLENGE]),
+        ResourceProofResponse(part) => {
+            sink.put(&[TAG_RESOURCE_PROOF_RESPONSE]);
+            sink.put(&part.to_be_bytes());
+        }
+        ApproveCandidate(candidate, ref approvers) => {
+            sink.put(&[TAG_APPROVE_CANDIDATE]);
+            write_name(sink, candidate);
+            write_names(sink, approvers);
+        }
+        Relocate(node, new_name) => {
+            sink.put(&[TAG_RELOCATE]);
+            write_name(sink, node);
+            write_name(sink, new_name);
+        }
+        NodeRelocated(old_name) => {
+            sink.put(&[TAG_NODE_RELOCATED]);
+            write_name(sink, old_name);
+        }
+        ClientConnect => sink.put(&[TAG_CLIENT_CONNECT]),
+        BlockUpdate(block) => {
+            sink.put(&[TAG_BLOCK_UPDATE]);
+            write_block_id(sink, block);
+        }
+        VoteAggregatedMsg((ref vote, ref signature)) => {
+            sink.put(&[TAG_VOTE_AGGREGATED]);
+            write_vote(sink, vote);
+            write_len(sink, signature.len);
+            sink.put_zeros(signature.len);
+        }
+    }
+}
+
+fn write_name<S: Sink>(sink: &mut S, name: Name) {
+    sink.put(&name.0.to_be_bytes());
+}
+
+fn write_names<S: Sink>(sink: &mut S, names: &BTreeSet<Name>) {
+    write_len(sink, names.len());
+    for &name in names {
+        write_name(sink, name);
+    }
+}
+
+fn write_block_id<S: Sink>(sink: &mut S, id: BlockId) {
+    sink.put(&id.to_bits().to_be_bytes());
+}
+
+fn write_vote<S: Sink>(sink: &mut S, vote: &Vote) {
+    write_block_id(sink, vote.from);
+    write_block_id(sink, vote.to);
+}
+
+fn write_votes_with_voters<S: Sink>(sink: &mut S, votes: &[(Vote, BTreeSet<Name>)]) {
+    write_len(sink, votes.len());
+    for (vote, voters) in votes {
+        write_vote(sink, vote);
+        write_names(sink, voters);
+    }
+}
+
+fn write_len<S: Sink>(sink: &mut S, mut len: usize) {
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            sink.put(&[byte]);
+            return;
+        }
+        sink.put(&[byte | 0x80]);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn name(&mut self) -> Result<Name, DecodeError> {
+        let mut buf = [0; NAME_BYTES];
+        buf.copy_from_slice(self.take(NAME_BYTES)?);
+        Ok(Name(NameBits::from_be_bytes(buf)))
+    }
+
+    fn names(&mut self) -> Result<BTreeSet<Name>, DecodeError> {
+        let len = self.len()?;
+        (0..len).map(|_| self.name()).collect()
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(buf))
+    }
+
+    fn block_id(&mut self) -> Result<BlockId, DecodeError> {
+        Ok(BlockId::from_bits(self.u64()?))
+    }
+
+    fn vote(&mut self) -> Result<Vote, DecodeError> {
+        Ok(Vote {
+            from: self.block_id()?,
+            to: self.block_id()?,
+        })
+    }
+
+    fn votes_with_voters(&mut self) -> Result<Vec<(Vote, BTreeSet<Name>)>, DecodeError> {
+        let len = self.len()?;
+        (0..len).map(|_| Ok((self.vote()?, self.names()?))).collect()
+    }
+
+    fn len(&mut self) -> Result<usize, DecodeError> {
+        let mut len = 0usize;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            let bits = (byte & 0x7f) as usize;
+            if shift >= usize::BITS as usize || (bits << shift) >> shift != bits {
+                return Err(DecodeError::LengthOverflow);
+            }
+            len |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(len);
+            }
+            shift += 7;
+        }
+    }
+
+    fn content(&mut self) -> Result<MessageContent, DecodeError> {
+        Ok(match self.byte()? {
+            TAG_VOTE => VoteMsg(self.vote()?),
+            TAG_VOTE_AGREED => VoteAgreedMsg((self.vote()?, self.names()?)),
+            TAG_VOTE_BUNDLE => VoteBundle(self.votes_with_voters()?),
+            TAG_REQUEST_PROOF => {
+                let block = self.block_id()?;
+                let len = self.len()?;
+                let current_blocks = (0..len).map(|_| self.block_id()).collect::<Result<_, _>>()?;
+                RequestProof(block, current_blocks)
+            }
+            TAG_NO_PROOF => NoProof(self.block_id()?),
+            TAG_NODE_JOINED => NodeJoined,
+            TAG_BOOTSTRAP => BootstrapMsg(Arc::new(self.votes_with_voters()?)),
+            TAG_CONNECT => Connect,
+            TAG_DISCONNECT => Disconnect,
+            TAG_RESOURCE_PROOF_CHALLENGE => ResourceProofChallenge,
+            TAG_RESOURCE_PROOF_RESPONSE => ResourceProofResponse(self.u64()?),
+            TAG_APPROVE_CANDIDATE => ApproveCandidate(self.name()?, self.names()?),
+            TAG_RELOCATE => Relocate(self.name()?, self.name()?),
+            TAG_NODE_RELOCATED => NodeRelocated(self.name()?),
+            TAG_CLIENT_CONNECT => ClientConnect,
+            TAG_BLOCK_UPDATE => BlockUpdate(self.block_id()?),
+            TAG_VOTE_AGGREGATED => {
+                let vote = self.vote()?;
+                let len = self.len()?;
+                self.take(len)?;
+                let signature = AggregateSignature {
+                    signers: BTreeSet::new(),
+                    len,
+                };
+                VoteAggregatedMsg((vote, signature))
+            }
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use block::Block;
+
+    #[test]
+    fn round_trip() {
+        let b0 = Block::genesis(Name(1));
+        let b1 = b0.add_node(Name(2));
+        let vote = Vote {
+            from: b0.get_id(),
+            to: b1.get_id(),
+        };
+        let voters: BTreeSet<_> = (0..200).map(Name).collect();
+        let contents = vec![
+            VoteMsg(vote.clone()),
+            VoteAgreedMsg((vote.clone(), voters.clone())),
+            VoteBundle(vec![(vote.clone(), voters.clone()), (vote.clone(), BTreeSet::new())]),
+            RequestProof(b1.get_id(), btreeset!{ b0.get_id(), b1.get_id() }),
+            NoProof(b0.get_id()),
+            NodeJoined,
+            BootstrapMsg(Arc::new(vec![(vote.clone(), voters)])),
+            Connect,
+            Disconnect,
+            ResourceProofChallenge,
+            ResourceProofResponse(3),
+            ApproveCandidate(Name(3), btreeset!{ Name(1), Name(2) }),
+            Relocate(Name(3), Name(4)),
+            NodeRelocated(Name(3)),
+            ClientConnect,
+            BlockUpdate(b1.get_id()),
+            VoteAggregatedMsg((
+                vote.clone(),
+                AggregateSignature {
+                    signers: BTreeSet::new(),
+                    len: 96,
+                },
+            )),
+        ];
+        for content in contents {
+            let message = Message {
+                sender: Name(1),
+                recipient: Name(2),
+                content: Arc::new(content),
+                trace_id: 0,
+            };
+            let bytes = encode(&message);
+            assert_eq!(bytes.len(), encoded_len(&message));
+            assert_eq!(decode(&bytes), Ok(message));
+        }
+    }
+
+    #[test]
+    fn aggregate_signature_size() {
+        let vote = Vote {
+            from: BlockId::from_bits(3),
+            to: BlockId::from_bits(4),
+        };
+        let signers: BTreeSet<_> = (0..200).map(Name).collect();
+        let agreed = VoteAgreedMsg((vote.clone(), signers.clone()));
+        let aggregated = VoteAggregatedMsg((
+            vote,
+            AggregateSignature {
+                signers,
+                len: 96,
+            },
+        ));
+        // Tag, vote, then the signers or the signature, with their lengths.
+        assert_eq!(content_len(&agreed), 1 + 16 + 2 + 200 * NAME_BYTES);
+        assert_eq!(content_len(&aggregated), 1 + 16 + 1 + 96);
+
+        // Only the signature is sent, not its signers.
+        let message = Message {
+            sender: Name(1),
+            recipient: Name(2),
+            content: Arc::new(aggregated),
+            trace_id: 0,
+        };
+        match *decode(&encode(&message)).unwrap().content {
+            VoteAggregatedMsg((_, ref signature)) => {
+                assert!(signature.signers.is_empty());
+                assert_eq!(signature.len, 96);
+            }
+            ref content => panic!("decoded the wrong content: {:?}", content),
+        }
+    }
+
+    #[test]
+    fn stable_encoding() {
+        let message = Message {
+            sender: Name(1),
+            recipient: Name(2),
+            content: Arc::new(VoteAgreedMsg((
+                Vote {
+                    from: BlockId::from_bits(3),
+                    to: BlockId::from_bits(4),
+                },
+                btreeset!{ Name(5) },
+            ))),
+            trace_id: 0,
+        };
+        let mut expected = vec![];
+        for &name in &[1, 2] {
+            expected.extend(vec![0; NAME_BYTES - 1]);
+            expected.push(name);
+        }
+        expected.push(TAG_VOTE_AGREED);
+        expected.extend(&[0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 4, 1]);
+        expected.extend(vec![0; NAME_BYTES - 1]);
+        expected.push(5);
+        assert_eq!(encode(&message), expected);
+
+        assert_eq!(decode(&expected[..expected.len() - 1]), Err(DecodeError::UnexpectedEnd));
+    }
+}
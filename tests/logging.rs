@@ -0,0 +1,68 @@
+//! The logger is set up once per process, so tests of what a run logs have a binary of their own.
+
+extern crate ewok;
+#[macro_use]
+extern crate maplit;
+extern crate serde_json;
+
+use ewok::event::Event::*;
+use ewok::event_schedule::EventSchedule;
+use ewok::logging::{init_logging_with, RunMarker, StepMarker};
+use ewok::params::{NodeParams, SimulationParams};
+use ewok::random::reseed;
+use ewok::simulation::Simulation;
+use ewok::testing::{p, quiet_params};
+use serde_json::Value;
+use std::{env, fs, process};
+
+// Lines about nodes are logged from the worker threads that handle each step's messages, and
+// still go to the run's file, stamped with the step they were logged at.
+#[test]
+fn json_log_file() {
+    env::set_var("EWOK_LOG_FORMAT", "json");
+    let dir = env::temp_dir().join(format!("ewok-json-log-{}", process::id()));
+    let params = SimulationParams {
+        log_file: Some(dir.join("{seed}.log").to_str().unwrap().to_string()),
+        log_levels: Some("ewok=debug".to_string()),
+        ..quiet_params()
+    };
+    init_logging_with(&params);
+    reseed([1, 2, 3, 4]);
+
+    let node_params = NodeParams::default();
+    let sections = btreemap! {
+        p("00") => node_params.min_section_size,
+        p("01") => node_params.min_section_size,
+        p("10") => node_params.min_section_size,
+        p("11") => node_params.min_section_size
+    };
+    let event_schedule = EventSchedule::new(btreemap! {
+        0 => vec![RemoveNodeFrom(p("00")), RemoveNodeFrom(p("01"))],
+        3 => vec![RemoveNodeFrom(p("01"))]
+    });
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+    simulation.run().unwrap();
+
+    let files: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    assert_eq!(files.len(), 1, "{:?}", files);
+    let contents = fs::read_to_string(&files[0]).unwrap();
+    let mut lines = contents.lines();
+    assert!(RunMarker::parse(lines.next().unwrap()).is_some());
+
+    let mut step = 0;
+    let mut node_lines = 0;
+    for line in lines {
+        if let Some(marker) = StepMarker::parse(line) {
+            step = marker.step;
+        }
+        let json: Value = serde_json::from_str(line).unwrap();
+        assert_eq!(json["step"], step, "{}", line);
+        if !json["node"].is_null() {
+            node_lines += 1;
+        }
+    }
+    assert!(step > 3);
+    assert!(node_lines > 0);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
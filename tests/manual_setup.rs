@@ -4,103 +4,1179 @@ extern crate maplit;
 #[macro_use]
 extern crate unwrap;
 
-use ewok::name::Prefix;
-use ewok::event::Event;
+use ewok::block::{BlockId, Vote};
+use ewok::message::{AggregateSignature, Message, MessageKind};
+use ewok::mock::{MockBehaviour, MockNode};
+use ewok::message::MessageContent::VoteAggregatedMsg;
+use ewok::name::{Name, Prefix};
 use ewok::event::Event::*;
 use ewok::event_schedule::EventSchedule;
+use ewok::invariant::InvariantViolation;
+use ewok::event_schedule::Assertion::*;
 use ewok::logging::init_logging;
-use ewok::simulation::Simulation;
-use ewok::params::{SimulationParams, NodeParams};
-use ewok::random::random;
+use ewok::block::Block;
+use ewok::coverage::Rule;
+use ewok::event::Event;
+use ewok::simulation::{RunError, Simulation, StepHook};
+use ewok::params::{SimulationParams, NodeParams, CandidateBehaviour, DelayDistribution,
+                   NameDistribution, ProofRequestPolicy, QuorumWeight, RestartMode};
+use ewok::random::{random, reseed, seed};
+use ewok::testing::{add_events, p, prefixes, step_num};
+use ewok::scenarios;
+use ewok::snapshot::Snapshot;
+use ewok::topology::Topology;
+use ewok::wire;
+use std::collections::{BTreeMap, BTreeSet};
 use std::iter;
+use std::sync::Arc;
 
 // TODO: parameterise tests by their basic parameters like max_delay and num_steps
 // so we can easily run all the tests with different values.
 fn default_params() -> SimulationParams {
     SimulationParams {
         max_delay: 5,
+        delay_distribution: DelayDistribution::Geometric,
+        delivery_percentile: 0.95,
+        burst_reorder_prob: 0.0,
         grow_prob_join: 0.0,
         grow_prob_drop: 0.0,
         prob_churn: 0.0,
+        steady_state: false,
         shrink_prob_join: 0.0,
         shrink_prob_drop: 0.0,
+        churn_correlation: 0.0,
+        churn_memory: 5,
+        max_network_size: None,
+        name_distribution: NameDistribution::Random,
+        grow_prob_disconnect: 0.0,
+        grow_prob_reconnect: 0.0,
         prob_disconnect: 0.0,
         prob_reconnect: 0.0,
+        shrink_prob_disconnect: 0.0,
+        shrink_prob_reconnect: 0.0,
         starting_complete: 0,
         grow_complete: 0,
         stable_steps: 1000,
+        log_file: None,
+        log_file_max_bytes: None,
+        log_levels: None,
+        malicious_join_prob: 0.0,
+        malicious_behaviour: CandidateBehaviour::Honest,
+        restart_mode: RestartMode::Fresh,
+        chaos: false,
+        chaos_prob: 0.0,
+        chaos_duration: 0,
+        chaos_max_partition: 0,
+        chaos_max_loss: 0.0,
+        chaos_max_duplicate: 0.0,
+        data_op_prob: 0.0,
+        sybil_fraction: 0.0,
+        sybil_window: 10,
+        sybil_target: None,
+        overload_drop_prob: 0.0,
+        overload_window: 10,
+        slow_node_ratio: 0.0,
+        fast_node_ratio: 0.0,
+        message_capacity: None,
+        max_clock_skew: 0,
+        track_votes: false,
     }
 }
 
-fn p0() -> Prefix {
-    Prefix::short(1, 0)
+/// Run a registered scenario with its own parameters.
+fn run_scenario(name: &str) {
+    init_logging();
+
+    let scenario = unwrap!(scenarios::find(name));
+    let (params, node_params) = scenario.params();
+    let mut simulation = scenario.simulation(params, node_params);
+
+    simulation.run().unwrap();
 }
-fn p1() -> Prefix {
-    Prefix::short(1, 0b10000000)
+
+#[test]
+fn four_sections() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams::default();
+
+    let sections =
+        btreemap! {
+        p("00") => node_params.min_section_size,
+        p("01") => node_params.min_section_size,
+        p("10") => node_params.min_section_size,
+        p("11") => node_params.min_section_size
+    };
+
+    let event_schedule = EventSchedule::new(btreemap! {
+        0 => vec![
+            RemoveNodeFrom(p("00")),
+            RemoveNodeFrom(p("01")),
+        ],
+        3 => vec![RemoveNodeFrom(p("01"))]
+    });
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    simulation.run().unwrap();
 }
-fn p00() -> Prefix {
-    Prefix::short(2, 0b00000000)
+
+#[test]
+fn four_sections_scenario() {
+    run_scenario("four_sections");
 }
-fn p01() -> Prefix {
-    Prefix::short(2, 0b01000000)
+
+// Once the partition heals, section 0 converges on a single chain.
+#[test]
+fn partition_heal() {
+    init_logging();
+
+    let scenario = unwrap!(scenarios::find("partition_heal"));
+    let (params, node_params) = scenario.params();
+    let mut simulation = scenario.simulation(params, node_params);
+
+    simulation.run().unwrap();
+    assert!(simulation.partition_refused_deliveries() > 0);
+    let healings = simulation.partition_healings();
+    assert_eq!(healings.len(), 1);
+    assert_eq!(healings[0].prefix, p("0"));
+    assert_eq!(healings[0].healed_step, Some(80));
+    assert!(healings[0].converged_step.is_some());
+    // Each side of the partition went its own way in the meantime.
+    assert!(healings[0].conflicting_blocks > 0);
 }
-fn p10() -> Prefix {
-    Prefix::short(2, 0b10000000)
+
+// Once the merge has settled, every section is fully connected and up to date.
+#[test]
+fn healthy_after_merge() {
+    init_logging();
+
+    let scenario = unwrap!(scenarios::find("four_sections"));
+    let (params, node_params) = scenario.params();
+    let mut simulation = scenario.simulation(params, node_params);
+
+    let sections = simulation.run().unwrap();
+    let health = simulation.section_health();
+    assert_eq!(health.len(), sections.len());
+    for (prefix, health) in health {
+        assert!(!health.is_at_risk(), "{:?} is at risk: {:?}", prefix, health);
+        assert_eq!(health.staleness, 0);
+    }
 }
-fn p11() -> Prefix {
-    Prefix::short(2, 0b11000000)
+
+// Intermediate states are checked against the schedule's assertions: a section that loses a
+// member merges with its sibling, and the rest of the network is unchanged.
+#[test]
+fn merge_with_assertions() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams::default();
+    let min_section_size = node_params.min_section_size;
+
+    let sections =
+        btreemap! {
+        p("00") => min_section_size,
+        p("01") => min_section_size,
+        p("1") => min_section_size
+    };
+
+    let mut event_schedule = EventSchedule::new(btreemap! {
+        1 => vec![RemoveNodeFrom(p("00"))]
+    });
+    event_schedule.add_assertion(0, Prefixes(prefixes(&["00", "01", "1"])));
+    event_schedule.add_assertion(0, MinMembers(p("00"), min_section_size));
+    event_schedule.add_assertion(200, Prefixes(prefixes(&["0", "1"])));
+    event_schedule.add_assertion(200, MinMembers(p("0"), 2 * min_section_size - 1));
+    event_schedule.add_assertion(200, MaxMembers(p("1"), min_section_size));
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    simulation.run().unwrap();
+    assert!(simulation.rule_coverage().fired(Rule::Merge));
 }
-fn p010() -> Prefix {
-    Prefix::short(3, 0b01000000)
+
+/// Adds a node to section 1 once, as soon as section 0 is formed by a merge.
+struct AddAfterMerge {
+    added: bool,
 }
-fn p100() -> Prefix {
-    Prefix::short(3, 0b10000000)
+
+impl StepHook for AddAfterMerge {
+    fn step_end(&mut self, step: u64, sections: &BTreeMap<Prefix, Block>) -> Vec<(u64, Event)> {
+        if self.added || !sections.contains_key(&p("0")) {
+            return vec![];
+        }
+        self.added = true;
+        vec![(step + 1, AddNode(p("1").substituted_in(random())))]
+    }
 }
-fn p110() -> Prefix {
-    Prefix::short(3, 0b11000000)
+
+// Hooks react to the state of the network at the end of each step by scheduling events.
+#[test]
+fn hook_reacts_to_merge() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams::default();
+    let min_section_size = node_params.min_section_size;
+
+    let sections =
+        btreemap! {
+        p("00") => min_section_size,
+        p("01") => min_section_size,
+        p("1") => min_section_size
+    };
+    let event_schedule = EventSchedule::new(btreemap! {
+        1 => vec![RemoveNodeFrom(p("00"))]
+    });
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+    simulation.add_hook(AddAfterMerge { added: false });
+
+    let sections = simulation.run().unwrap();
+    assert_eq!(sections.len(), 2);
+    assert!(sections.contains_key(&p("0")));
+    assert_eq!(sections[&p("1")].members.len(), min_section_size + 1);
 }
-fn p111() -> Prefix {
-    Prefix::short(3, 0b11100000)
+
+// Exact sections from a topology, with a pair of nodes that start out disconnected.
+#[test]
+fn topology_with_disconnection() {
+    init_logging();
+
+    let params = SimulationParams {
+        prob_reconnect: 1.0,
+        ..default_params()
+    };
+    let node_params = NodeParams::default();
+
+    let sections: BTreeMap<_, BTreeSet<_>> = btreemap! {
+        p("0") => p("0").spread_names().take(node_params.min_section_size + 1).collect(),
+        p("1") => p("1").spread_names().take(node_params.min_section_size + 1).collect()
+    };
+    let disconnected = vec![
+        (
+            *sections[&p("0")].iter().next().unwrap(),
+            *sections[&p("1")].iter().next().unwrap(),
+        ),
+    ];
+    let topology = Topology {
+        sections,
+        disconnected,
+    };
+    unwrap!(topology.validate());
+
+    let event_schedule = EventSchedule::new(btreemap! {
+        0 => vec![RemoveNodeFrom(p("1"))]
+    });
+
+    let mut simulation =
+        Simulation::from_topology(&topology, event_schedule, params, node_params);
+
+    simulation.run().unwrap();
 }
-fn p0110() -> Prefix {
-    Prefix::short(4, 0b01100000)
+
+// A node only joins once it has sent every part of its resource proof.
+#[test]
+fn join_with_resource_proof() {
+    init_logging();
+
+    let params = SimulationParams {
+        max_delay: 2,
+        ..default_params()
+    };
+    let node_params = NodeParams {
+        resource_proof_steps: 5,
+        ..NodeParams::default()
+    };
+
+    let sections = btreemap! {
+        Prefix::empty() => node_params.min_section_size
+    };
+    let joining = random();
+    let event_schedule = EventSchedule::new(btreemap! {
+        0 => vec![AddNode(joining)]
+    });
+
+    let mut simulation =
+        Simulation::new_from(sections, event_schedule, params, node_params.clone());
+
+    let blocks = simulation.run().unwrap();
+    assert!(blocks[&Prefix::empty()].members.contains(&joining));
+
+    // Every member challenged the candidate, which answered each of them once per step.
+    let sent = simulation.sent_by_kind();
+    let challenges = unwrap!(sent.get(&MessageKind::ResourceProofChallenge)).messages;
+    let responses = unwrap!(sent.get(&MessageKind::ResourceProofResponse)).messages;
+    assert!(challenges >= node_params.min_section_size);
+    assert!(responses >= challenges * node_params.resource_proof_steps as usize);
 }
-fn p0111() -> Prefix {
-    Prefix::short(4, 0b01110000)
+
+// Nodes that join while another candidate is proving its resources are queued, and added once
+// it has been approved.
+#[test]
+fn join_one_candidate_at_a_time() {
+    init_logging();
+
+    let params = SimulationParams {
+        max_delay: 2,
+        ..default_params()
+    };
+    let node_params = NodeParams {
+        resource_proof_steps: 5,
+        candidate_queue_timeout: Some(100),
+        ..NodeParams::default()
+    };
+
+    let sections = btreemap! {
+        Prefix::empty() => node_params.min_section_size
+    };
+    let joining: Vec<Name> = (0..3).map(|_| random()).collect();
+    let event_schedule = EventSchedule::new(btreemap! {
+        0 => vec![AddNode(joining[0])],
+        3 => vec![AddNode(joining[1])],
+        6 => vec![AddNode(joining[2])]
+    });
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    let blocks = simulation.run().unwrap();
+    // The later joiners arrive while the first is still proving itself, so they're queued, but
+    // they're all added in the end.
+    assert!(simulation.queued_joins() > 0);
+    for name in &joining {
+        assert!(blocks[&Prefix::empty()].members.contains(name));
+    }
 }
-fn p1010() -> Prefix {
-    Prefix::short(4, 0b10100000)
+
+/// Run a section in which `silent` members neither send nor receive approvals of candidates,
+/// returning whether a node that joins it is added.
+fn join_with_silent_approvers(silent: usize) -> bool {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams::default();
+
+    let members: BTreeSet<Name> = Prefix::empty()
+        .spread_names()
+        .take(node_params.min_section_size)
+        .collect();
+    let topology = Topology {
+        sections: btreemap! { Prefix::empty() => members.clone() },
+        disconnected: vec![],
+    };
+    let joining: Name = random();
+    let event_schedule = EventSchedule::new(btreemap! {
+        0 => vec![AddNode(joining)]
+    });
+
+    let mut simulation =
+        Simulation::from_topology(&topology, event_schedule, params, node_params);
+    for &member in members.iter().take(silent) {
+        let mock = MockNode::new()
+            .on_receive(MessageKind::ApproveCandidate, MockBehaviour::Drop)
+            .on_send(MessageKind::ApproveCandidate, MockBehaviour::Drop);
+        simulation.add_mock(member, mock);
+    }
+
+    let blocks = simulation.run().unwrap();
+    blocks[&Prefix::empty()].members.contains(&joining)
 }
-fn p1011() -> Prefix {
-    Prefix::short(4, 0b10110000)
+
+// A candidate is only added once a quorum of its section has approved it.
+#[test]
+fn join_needs_quorum_approval() {
+    let node_params = NodeParams::default();
+    let size = node_params.min_section_size;
+    let quorum = node_params.quorum(size);
+    assert!(join_with_silent_approvers(size - quorum));
+    assert!(!join_with_silent_approvers(size - quorum + 1));
 }
 
+// A new node is relocated to the section its relocated name belongs to, and isn't relocated
+// again once it's there.
 #[test]
-fn four_sections() {
+fn relocate_new_node() {
     init_logging();
 
     let params = default_params();
-    let node_params = NodeParams::default();
+    let node_params = NodeParams {
+        relocate_new_nodes: true,
+        ..NodeParams::default()
+    };
 
     let sections =
         btreemap! {
-        p00() => node_params.min_section_size,
-        p01() => node_params.min_section_size,
-        p10() => node_params.min_section_size,
-        p11() => node_params.min_section_size
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size
     };
+    let joining = random();
+    let event_schedule = EventSchedule::new(btreemap! {
+        0 => vec![AddNode(joining)]
+    });
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    let blocks = simulation.run().unwrap();
+    let relocated = joining.relocated();
+    assert!(blocks.values().all(|block| !block.members.contains(&joining)));
+    assert!(blocks.values().any(|block| block.members.contains(&relocated)));
+}
+
+// Candidates that don't prove their resources, don't connect to enough of the section, or go
+// silent after joining are never added, and give up once their timeouts expire.
+#[test]
+fn malicious_candidates_expelled() {
+    init_logging();
 
+    for &behaviour in &[
+        CandidateBehaviour::NoResourceProof,
+        CandidateBehaviour::PartialConnections,
+        CandidateBehaviour::Silent,
+    ]
+    {
+        let params = SimulationParams {
+            malicious_join_prob: 1.0,
+            malicious_behaviour: behaviour,
+            ..default_params()
+        };
+        let node_params = NodeParams {
+            resource_proof_steps: 5,
+            ..NodeParams::default()
+        };
+
+        let sections = btreemap! {
+            Prefix::empty() => node_params.min_section_size
+        };
+        let joining = random();
+        let event_schedule = EventSchedule::new(btreemap! {
+            0 => vec![AddNode(joining)]
+        });
+
+        let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+        let blocks = simulation.run().unwrap();
+        assert!(
+            !blocks[&Prefix::empty()].members.contains(&joining),
+            "{:?} candidate was added",
+            behaviour
+        );
+        assert!(!simulation.has_node(&joining), "{:?} candidate is still alive", behaviour);
+    }
+}
+
+// `AddNodeUntil` adds a node with an exact name that gives up and shuts down at the given step if
+// it hasn't joined by then. A node that gives up before it can prove its resources is never
+// added and leaves the network, while one with a later give-up step joins as usual.
+#[test]
+fn join_with_give_up_step() {
+    init_logging();
+
+    let node_params = NodeParams {
+        resource_proof_steps: 10,
+        ..NodeParams::default()
+    };
+    let sections = btreemap! {
+        Prefix::empty() => node_params.min_section_size
+    };
+    // The first node gives up long before it could finish its resource proof, while the second
+    // has plenty of time to join.
+    let impatient = Name(0x1234);
+    let patient = Name(0x5678);
     let event_schedule = EventSchedule::new(btreemap! {
-        0 => vec![
-            RemoveNodeFrom(p00()),
-            RemoveNodeFrom(p01()),
-        ],
-        3 => vec![RemoveNodeFrom(p01())]
+        0 => vec![AddNodeUntil(impatient, 3), AddNodeUntil(patient, 500)]
     });
 
+    let mut simulation =
+        Simulation::new_from(sections, event_schedule, default_params(), node_params);
+
+    let blocks = simulation.run().unwrap();
+    let members = &blocks[&Prefix::empty()].members;
+    assert!(!members.contains(&impatient));
+    assert!(!simulation.has_node(&impatient));
+    assert!(members.contains(&patient));
+}
+
+// Bursts of messages are often delivered out of order while nodes disconnect and reconnect, so
+// Connect and Disconnect messages sent on different steps can overtake each other. Reordering
+// never holds a message back past its deadline.
+#[test]
+fn burst_reordering() {
+    init_logging();
+
+    let params = SimulationParams {
+        burst_reorder_prob: 0.2,
+        prob_disconnect: 0.05,
+        prob_reconnect: 0.2,
+        stable_steps: 200,
+        ..default_params()
+    };
+    let node_params = NodeParams::default();
+    let sections = btreemap! {
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
+    };
+    let mut schedule = EventSchedule::empty();
+    add_events(&mut schedule, 0, 20, (0..4).map(|_| AddNode(random())).collect());
+
+    let mut simulation = Simulation::new_from(sections, schedule, params, node_params);
+    simulation.run().unwrap();
+    assert!(simulation.reordered_bursts() > 0);
+    assert_eq!(simulation.overdue_connections(), 0);
+}
+
+// A member crashes, and the section changes while it's down. Whether it restarts from scratch
+// or with its stale blocks and votes, it's added back to the section.
+#[test]
+fn restart_after_crash() {
+    init_logging();
+
+    for &restart_mode in &[RestartMode::Fresh, RestartMode::Persistent] {
+        let params = SimulationParams {
+            restart_mode,
+            ..default_params()
+        };
+        let node_params = NodeParams::default();
+        let sections = btreemap! {
+            Prefix::empty() => node_params.min_section_size
+        };
+        let crashing = random();
+        let event_schedule = EventSchedule::new(btreemap! {
+            0 => vec![AddNode(crashing)],
+            50 => vec![CrashNode(crashing)],
+            60 => vec![AddNode(random())],
+            80 => vec![AddNode(random())],
+            100 => vec![RemoveNodeFrom(Prefix::empty())],
+            150 => vec![RestartNode(crashing)],
+        });
+
+        let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+        let blocks = simulation.run().unwrap();
+        assert!(
+            blocks[&Prefix::empty()].members.contains(&crashing),
+            "{:?} restart wasn't added back",
+            restart_mode
+        );
+    }
+}
+
+// Client requests fail while most of a section has crashed, and succeed again once the crashed
+// members have restarted.
+#[test]
+fn data_availability() {
+    init_logging();
+
+    let params = SimulationParams {
+        restart_mode: RestartMode::Persistent,
+        ..default_params()
+    };
+    let sections = btreemap! {
+        Prefix::empty() => 1
+    };
+    let joining: Vec<Name> = (0..2).map(|_| random()).collect();
+    let data = random();
+    let mut event_schedule = EventSchedule::empty();
+    add_events(
+        &mut event_schedule,
+        0,
+        20,
+        joining.iter().map(|&name| AddNode(name)).collect(),
+    );
+    let mut crashes: Vec<_> = joining.iter().map(|&name| CrashNode(name)).collect();
+    crashes.push(Get(data));
+    let crash_step = step_num(&event_schedule, 30);
+    event_schedule.schedule.insert(crash_step, crashes);
+    let restarts = joining.iter().map(|&name| RestartNode(name)).collect();
+    event_schedule.schedule.insert(crash_step + 10, restarts);
+    event_schedule.schedule.insert(crash_step + 60, vec![Put(data)]);
+
+    let node_params = NodeParams::default();
     let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
 
     simulation.run().unwrap();
+    assert_eq!(simulation.data_ops(), (1, 1));
+}
+
+// With aggregate signatures, every agreement is sent as a signature of a fixed size rather than
+// the list of its voters.
+#[test]
+fn aggregate_signatures() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams {
+        aggregate_signature_bytes: Some(96),
+        ..NodeParams::default()
+    };
+    let sections = btreemap! {
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
+    };
+    let mut event_schedule = EventSchedule::empty();
+    add_events(
+        &mut event_schedule,
+        0,
+        10,
+        (0..6).map(|_| AddNode(random())).collect(),
+    );
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    unwrap!(simulation.run());
+    let sent = simulation.sent_by_kind();
+    assert!(!sent.contains_key(&MessageKind::VoteAgreed));
+    let aggregated = unwrap!(sent.get(&MessageKind::VoteAggregated));
+    let vote = Vote {
+        from: BlockId::from_bits(1),
+        to: BlockId::from_bits(2),
+    };
+    let signature = AggregateSignature {
+        signers: BTreeSet::new(),
+        len: 96,
+    };
+    let message = Message {
+        sender: Name(1),
+        recipient: Name(2),
+        content: Arc::new(VoteAggregatedMsg((vote, signature))),
+        trace_id: 0,
+    };
+    assert!(aggregated.messages > 0);
+    assert_eq!(aggregated.bytes, aggregated.messages * wire::encoded_len(&message));
+}
+
+// Busy section members crash from overload, and are removed from the network.
+#[test]
+fn overload_crashes() {
+    init_logging();
+
+    let params = SimulationParams {
+        overload_drop_prob: 0.0001,
+        overload_window: 10,
+        ..default_params()
+    };
+    let node_params = NodeParams::default();
+    let sections = btreemap! {
+        p("0") => node_params.min_section_size + 6,
+        p("1") => node_params.min_section_size + 6,
+    };
+    let mut event_schedule = EventSchedule::empty();
+    add_events(
+        &mut event_schedule,
+        0,
+        20,
+        (0..10).map(|_| AddNode(random())).collect(),
+    );
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    unwrap!(simulation.run());
+    let crashes = simulation.overload_crashes();
+    assert!(!crashes.is_empty());
+    for crash in crashes {
+        assert!(crash.load > 0);
+        assert!(crash.prefix.matches(crash.node));
+        assert!(!simulation.has_node(&crash.node));
+    }
+}
+
+// Sections still converge when some of their members handle messages and prove their resources
+// more slowly than the rest, with every node's message capacity limited.
+#[test]
+fn slow_nodes() {
+    init_logging();
+
+    let params = SimulationParams {
+        slow_node_ratio: 0.3,
+        fast_node_ratio: 0.2,
+        message_capacity: Some(5),
+        ..default_params()
+    };
+    let node_params = NodeParams {
+        resource_proof_steps: 4,
+        ..NodeParams::default()
+    };
+    let sections = btreemap! {
+        p("0") => node_params.min_section_size + 2,
+        p("1") => node_params.min_section_size + 2,
+    };
+    let mut event_schedule = EventSchedule::empty();
+    add_events(
+        &mut event_schedule,
+        0,
+        20,
+        (0..6).map(|_| AddNode(random())).collect(),
+    );
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    simulation.run().unwrap();
+}
+
+// Chaos actions fire during the stable phase when chaos mode is on, but not when their
+// probability is zero, and the network stays consistent either way.
+#[test]
+fn chaos_mode() {
+    init_logging();
+
+    let node_params = NodeParams::default();
+    let topology = Topology {
+        sections: btreemap! {
+            p("0") => p("0").spread_names().take(node_params.min_section_size).collect(),
+            p("1") => p("1").spread_names().take(node_params.min_section_size).collect(),
+        },
+        disconnected: vec![],
+    };
+    let joining: Vec<Name> = (0..4).map(|_| random()).collect();
+    let run = |chaos_prob| {
+        let params = SimulationParams {
+            prob_reconnect: 1.0,
+            stable_steps: 200,
+            chaos: true,
+            chaos_prob,
+            chaos_duration: 10,
+            chaos_max_partition: 2,
+            chaos_max_loss: 0.1,
+            chaos_max_duplicate: 0.1,
+            ..default_params()
+        };
+        let mut event_schedule = EventSchedule::empty();
+        add_events(
+            &mut event_schedule,
+            0,
+            20,
+            joining.iter().cloned().map(AddNode).collect(),
+        );
+
+        let mut simulation =
+            Simulation::from_topology(&topology, event_schedule, params, node_params.clone());
+        let blocks = simulation.run().unwrap();
+        let members: BTreeSet<Name> = blocks
+            .values()
+            .flat_map(|block| block.members.iter().cloned())
+            .collect();
+        (members, simulation.step(), simulation.chaos_actions())
+    };
+
+    let (calm_members, calm_steps, calm_actions) = run(0.0);
+    assert_eq!(calm_actions, 0);
+    let all: BTreeSet<Name> = topology
+        .sections
+        .values()
+        .flat_map(|members| members.iter().cloned())
+        .chain(joining.iter().cloned())
+        .collect();
+    assert_eq!(calm_members, all);
+
+    // Lost connections and messages can cost the chaotic run members, but it never gains any and
+    // never finishes any sooner.
+    let (chaos_members, chaos_steps, chaos_actions) = run(0.05);
+    assert!(chaos_actions > 0);
+    assert!(chaos_members.is_subset(&calm_members));
+    assert!(chaos_steps >= calm_steps);
+}
+
+// Nodes still join when their clocks are up to half a join timeout ahead of or behind the
+// network's.
+#[test]
+fn clock_skew() {
+    init_logging();
+
+    let node_params = NodeParams::default();
+    let params = SimulationParams {
+        max_clock_skew: node_params.join_timeout / 2,
+        ..default_params()
+    };
+    let sections = btreemap! {
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
+    };
+    let joining: Vec<Name> = (0..6).map(|_| random()).collect();
+    let mut event_schedule = EventSchedule::empty();
+    add_events(
+        &mut event_schedule,
+        0,
+        20,
+        joining.iter().map(|&name| AddNode(name)).collect(),
+    );
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    let sections = simulation.run().unwrap();
+    for name in &joining {
+        assert!(sections.values().any(|section| section.members.contains(name)));
+    }
+}
+
+// A node that starts long after genesis, knowing only the genesis blocks, skips proof requests for
+// the votes from far ahead that it hears of first, catches up from the bootstrap messages of the
+// genesis members, and joins its section.
+#[test]
+fn stale_node_catches_up() {
+    init_logging();
+
+    let node_params = NodeParams::default();
+    let (mut simulation, stale) = stale_node_simulation(node_params.clone());
+
+    let sections = simulation.run().unwrap();
+    let section = &sections[&p("0")];
+    assert!(section.version > node_params.proof_request_window);
+    assert!(section.members.contains(&stale));
+    assert!(unwrap!(simulation.mock(&stale)).scripted_received(MessageKind::Bootstrap) > 0);
+
+    // The node has caught up to its section's current block itself, with no gaps in the history
+    // it was sent, rather than only having been added by the others.
+    let node = unwrap!(simulation.node(&stale));
+    assert!(node.version_gap_skips > 0);
+    assert!(node.current_blocks.contains(&section.get_id()));
+    assert_eq!(node.truncated_segments, 0);
+}
+
+/// Two sections, with a stale node starting in section 0 once churn has taken the section well
+/// past its genesis blocks. The stale node's bootstrap messages are held back until it's been
+/// added, so it hears of votes far ahead of its genesis blocks first. Returns the simulation and
+/// the stale node's name.
+fn stale_node_simulation(node_params: NodeParams) -> (Simulation, Name) {
+    let params = default_params();
+    let sections = btreemap! {
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
+    };
+    // Churn takes the section's blocks well past the genesis version, leaving the genesis members
+    // in place to announce the stale node to.
+    let churn = (0..16)
+        .flat_map(|_| {
+            let name = p("0").substituted_in(random());
+            vec![AddNode(name), RemoveNode(name)]
+        })
+        .collect();
+    let stale = p("0").substituted_in(random());
+    let mut event_schedule = EventSchedule::empty();
+    add_events(&mut event_schedule, 0, 20, churn);
+    add_events(&mut event_schedule, 0, 20, vec![AddStaleNode(stale)]);
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+    let mock = MockNode::new().on_receive(MessageKind::Bootstrap, MockBehaviour::Delay(100));
+    simulation.add_mock(stale, mock);
+    (simulation, stale)
+}
+
+// Under the `agreed` policy, nodes only request proof of blocks referenced by agreed votes, so
+// the same growth and cascade of merges needs far fewer requests than under `version_gap`.
+#[test]
+fn proof_request_policies() {
+    init_logging();
+
+    let scenario = unwrap!(scenarios::find("growth_then_cascade"));
+    let mut requests = vec![];
+    for &policy in &[ProofRequestPolicy::VersionGap, ProofRequestPolicy::Agreed] {
+        let (params, node_params) = scenario.params();
+        let node_params = NodeParams {
+            proof_request_policy: policy,
+            ..node_params
+        };
+        let mut simulation = scenario.simulation(params, node_params);
+
+        simulation.run().unwrap();
+        assert_eq!(simulation.version_gap_skips(), 0);
+        requests.push((simulation.proof_requests(), simulation.unagreed_proof_skips()));
+    }
+    let (gap_requests, gap_skips) = requests[0];
+    let (agreed_requests, agreed_skips) = requests[1];
+    assert_eq!(gap_skips, 0);
+    assert!(agreed_skips > 0);
+    assert!(agreed_requests < gap_requests);
+
+    // The window only matters to a node that has fallen behind: one wide enough to cover the
+    // stale node's gap has it request proof of every block it sees instead of skipping some.
+    let skips = |proof_request_window| {
+        let node_params = NodeParams {
+            proof_request_window,
+            ..NodeParams::default()
+        };
+        let (mut simulation, _) = stale_node_simulation(node_params);
+        simulation.run().unwrap();
+        simulation.version_gap_skips()
+    };
+    assert!(skips(NodeParams::default().proof_request_window) > 0);
+    assert_eq!(skips(1000), 0);
+}
+
+// Nodes that can only keep some of their connections keep those to their own section and its
+// closest neighbours, and the network still grows and splits. (With room for fewer than a section
+// and half its sibling, nodes vote to drop their own members or force-merge with their sibling.)
+#[test]
+fn max_connections() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams {
+        max_connections: Some(30),
+        ..NodeParams::default()
+    };
+    let sections = btreemap! {
+        p("00") => node_params.min_section_size,
+        p("01") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
+    };
+    let joining = (0..20)
+        .map(|i| {
+            let prefix = if i % 2 == 0 { p("10") } else { p("11") };
+            AddNode(prefix.substituted_in(random()))
+        })
+        .collect();
+    let mut event_schedule = EventSchedule::empty();
+    add_events(&mut event_schedule, 0, 20, joining);
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    let sections = simulation.run().unwrap();
+    assert_eq!(sections.len(), 4);
+    assert_eq!(simulation.most_connections(), 30);
+}
+
+// Nodes keep only the newest superseded blocks for each prefix, while a node joining after the
+// churn still proves its way up to the current blocks.
+#[test]
+fn history_depth() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams {
+        history_depth: Some(2),
+        ..NodeParams::default()
+    };
+    let sections = btreemap! {
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
+    };
+    let churn = (0..8)
+        .flat_map(|_| {
+            let name = p("0").substituted_in(random());
+            vec![AddNode(name), RemoveNode(name)]
+        })
+        .collect();
+    let late = p("0").substituted_in(random());
+    let mut event_schedule = EventSchedule::empty();
+    add_events(&mut event_schedule, 0, 20, churn);
+    add_events(&mut event_schedule, 400, 20, vec![AddNode(late)]);
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    let sections = simulation.run().unwrap();
+    assert!(sections[&p("0")].version > 10);
+    assert!(sections[&p("0")].members.contains(&late));
+    assert!(simulation.pruned_blocks() > 0);
+    assert!(simulation.max_valid_blocks() <= 10);
+}
+
+// Two networks that cover halves of the namespace start unaware of each other. Once they're
+// introduced, each is sent the other's votes, but can't prove them back to a genesis block it
+// knows, so neither accepts the other's blocks: they stay separate rather than knitting together.
+#[test]
+fn introduce_roots() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams::default();
+    let size = node_params.min_section_size;
+    let roots = btreemap! {
+        p("0") => btreemap! { p("0") => size },
+        p("1") => btreemap! { p("10") => size, p("11") => size },
+    };
+    let joining = (0..4).map(|_| AddNode(random())).collect();
+    let mut event_schedule = EventSchedule::empty();
+    add_events(&mut event_schedule, 0, 20, joining);
+    event_schedule.add_event(100, IntroduceRoots);
+    let more = (0..4).map(|_| AddNode(random())).collect();
+    add_events(&mut event_schedule, 120, 20, more);
+
+    let mut simulation = Simulation::new_from_roots(roots, event_schedule, params, node_params);
+    let sections = simulation.run().unwrap();
+    assert_eq!(sections.len(), 3);
+    assert_eq!(simulation.nodes_knowing_other_roots(), 0);
+    assert!(simulation.violations().is_empty());
+}
+
+// A run pauses at the end of the first step after which a condition holds, writing a snapshot
+// of that step and returning from `run` without finishing, and carries on to a consistent finish
+// once resumed.
+#[test]
+fn pause_on_condition() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams::default();
+    let sections = btreemap! {
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
+    };
+    let mut event_schedule = EventSchedule::empty();
+    let joining = (0..4).map(|_| AddNode(random())).collect();
+    add_events(&mut event_schedule, 0, 20, joining);
+
+    let path = std::env::temp_dir().join(format!("ewok-pause-{}.json", std::process::id()));
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+    simulation.pause_when(|sim| sim.queue_size() > 20);
+    simulation.snapshot_on_pause(&path);
+
+    let paused = simulation.run();
+    let step = unwrap!(simulation.paused());
+    assert_eq!(paused, Err(RunError::Paused(step)));
+    assert!(simulation.queue_size() > 20);
+    assert!(!simulation.run_step());
+    assert_eq!(simulation.step(), step);
+    assert_eq!(unwrap!(Snapshot::load(&path)).step, step);
+    unwrap!(std::fs::remove_file(&path));
+
+    simulation.resume();
+    simulation.run().unwrap();
+    assert_eq!(simulation.paused(), None);
+    assert!(simulation.forked_prefixes().is_empty());
+}
+
+// Switching the random number generator to a new seed part-way through a run keeps it
+// reproducible from its original seed: runs that switch to different seeds are identical until
+// the switch, and differ from then on.
+#[test]
+fn reseed_from_schedule() {
+    init_logging();
+
+    let run = |new_seed: [u32; 4]| {
+        reseed([5, 6, 7, 8]);
+        let params = SimulationParams {
+            track_votes: true,
+            ..default_params()
+        };
+        let node_params = NodeParams::default();
+        let sections = btreemap! {
+            p("0") => node_params.min_section_size,
+            p("1") => node_params.min_section_size,
+        };
+        let mut event_schedule = EventSchedule::empty();
+        let joining = (0..6).map(|_| AddNode(random())).collect();
+        add_events(&mut event_schedule, 0, 10, joining);
+        event_schedule.add_reseed(30, new_seed);
+        let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+        while simulation.step() < 29 {
+            assert!(simulation.run_step());
+        }
+        assert!(simulation.step() < 30);
+        let before = simulation.snapshot();
+        unwrap!(simulation.run());
+        assert_eq!(seed(), [5, 6, 7, 8]);
+        (before, (simulation.step(), simulation.agreement_latencies()))
+    };
+    let (before, after) = run([1, 2, 3, 4]);
+    assert_eq!(run([1, 2, 3, 4]), (before.clone(), after.clone()));
+    let (other_before, other_after) = run([9, 10, 11, 12]);
+    assert_eq!(before, other_before);
+    assert_ne!(after, other_after);
+}
+
+// Violating an invariant ends the run as a failure instead of panicking. With a limit of one
+// valid block per prefix and version, every node violates it on the first step.
+#[test]
+fn invariant_violation_ends_run() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams {
+        max_conflicting_blocks: 1,
+        ..NodeParams::default()
+    };
+    let sections = btreemap! {
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
+    };
+    let mut simulation =
+        Simulation::new_from(sections, EventSchedule::empty(), params, node_params);
+
+    assert!(simulation.run().is_err());
+    assert_eq!(simulation.step(), 0);
+    let violations = simulation.violations();
+    assert_eq!(violations.len(), 2 * NodeParams::default().min_section_size as usize);
+    match violations[0] {
+        (0, InvariantViolation::ConflictingBlocks { count: 1, .. }) => (),
+        ref violation => panic!("unexpected violation: {:?}", violation),
+    }
+}
+
+// A member that refuses to prove blocks and whose agreements arrive late doesn't stop the rest of
+// its section from agreeing on the nodes that join after it.
+#[test]
+fn mock_member() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams::default();
+    let sections = btreemap! {
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
+    };
+    let mocked = p("0").substituted_in(random());
+    let joining: Vec<Name> = (0..3).map(|_| p("0").substituted_in(random())).collect();
+    let mut event_schedule = EventSchedule::empty();
+    add_events(&mut event_schedule, 0, 20, vec![AddNode(mocked)]);
+    let first_join = step_num(&event_schedule, 100) + 20;
+    add_events(&mut event_schedule, 100, 20, joining.iter().map(|&name| AddNode(name)).collect());
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+    let mock = MockNode::new()
+        .on_receive(MessageKind::RequestProof, MockBehaviour::RefuseProof)
+        .on_send(MessageKind::VoteAgreed, MockBehaviour::Delay(30));
+    simulation.add_mock(mocked, mock);
+
+    // Run up to the step at which the first of the others joins, which is too soon for any
+    // agreement on it.
+    while simulation.step() < first_join {
+        assert!(simulation.run_step());
+    }
+    let held_before = unwrap!(simulation.mock(&mocked)).scripted_sent(MessageKind::VoteAgreed);
+
+    let sections = simulation.run().unwrap();
+    let members = &sections[&p("0")].members;
+    assert!(members.contains(&mocked));
+    assert!(joining.iter().all(|name| members.contains(name)));
+    // The mock held back its agreements on those joins.
+    let held = unwrap!(simulation.mock(&mocked)).scripted_sent(MessageKind::VoteAgreed);
+    assert!(held - held_before >= joining.len() as u64);
+}
+
+// Clients follow their sections' blocks as nodes join them, without ever becoming members.
+#[test]
+fn clients_follow_sections() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams::default();
+    let sections = btreemap! {
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
+    };
+    let clients: Vec<Name> = (0..4).map(|_| random()).collect();
+    let mut event_schedule = EventSchedule::new(btreemap! {
+        1 => clients.iter().map(|&name| AddClient(name)).collect(),
+    });
+    add_events(
+        &mut event_schedule,
+        0,
+        10,
+        (0..6).map(|_| AddNode(random())).collect(),
+    );
+
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    let agreed = unwrap!(simulation.run());
+    assert_eq!(simulation.clients().len(), clients.len());
+    for client in simulation.clients().values() {
+        let section = unwrap!(agreed.values().find(|block| block.prefix.matches(client.name)));
+        assert_eq!(client.section, Some(section.get_id()));
+        assert_eq!(client.connected_to, *section.members);
+        assert!(!section.members.contains(&client.name));
+        assert!(client.updates_received > 0);
+    }
 }
 
 // 00 and 01 merge into 0 at the same time that 10 and 11 merge into 1.
@@ -116,16 +1192,16 @@ fn parallel_merge() {
 
     let sections =
         btreemap! {
-        p00() => node_params.min_section_size,
-        p01() => node_params.min_section_size,
-        p10() => node_params.min_section_size,
-        p11() => node_params.min_section_size
+        p("00") => node_params.min_section_size,
+        p("01") => node_params.min_section_size,
+        p("10") => node_params.min_section_size,
+        p("11") => node_params.min_section_size
     };
 
     let event_schedule = EventSchedule::new(btreemap! {
         0 => vec![
-            RemoveNodeFrom(p00()),
-            RemoveNodeFrom(p11()),
+            RemoveNodeFrom(p("00")),
+            RemoveNodeFrom(p("11")),
         ],
     });
 
@@ -134,6 +1210,52 @@ fn parallel_merge() {
     simulation.run().unwrap();
 }
 
+#[test]
+fn parallel_merge_scenario() {
+    run_scenario("parallel_merge");
+}
+
+/// Run the parallel merge with storms detected, and throttled if `throttle` is set, returning
+/// the number of storms, the number of votes deferred and the step the network converged on.
+fn parallel_merge_storms(run_seed: [u32; 4], throttle: bool) -> (u64, u64, u64) {
+    init_logging();
+    reseed(run_seed);
+
+    let scenario = unwrap!(scenarios::find("parallel_merge"));
+    let (params, node_params) = scenario.params();
+    let node_params = NodeParams {
+        storm_threshold: Some(10),
+        throttle_storms: throttle,
+        ..node_params
+    };
+    let mut simulation = scenario.simulation(params, node_params);
+
+    simulation.run().unwrap();
+    let (storms, deferred_votes) = simulation.storms();
+    (storms, deferred_votes, unwrap!(simulation.convergence_step()))
+}
+
+// Nodes in a message storm during the merges defer their witnessing votes, but the network
+// still converges, at most a message delay later than it does without throttling.
+#[test]
+fn parallel_merge_throttled() {
+    let run_seed = seed();
+    let (storms, deferred_votes, unthrottled) = parallel_merge_storms(run_seed, false);
+    assert!(storms > 0);
+    assert_eq!(deferred_votes, 0);
+
+    let (storms, deferred_votes, throttled) = parallel_merge_storms(run_seed, true);
+    assert!(storms > 0);
+    assert!(deferred_votes > 0);
+    let (params, _) = unwrap!(scenarios::find("parallel_merge")).params();
+    assert!(
+        throttled <= unthrottled + params.max_delay,
+        "converged at step {} throttled, {} unthrottled",
+        throttled,
+        unthrottled
+    );
+}
+
 #[test]
 fn parallel_merge_with_adds() {
     init_logging();
@@ -146,18 +1268,18 @@ fn parallel_merge_with_adds() {
 
     let sections =
         btreemap! {
-        p00() => node_params.min_section_size,
-        p01() => node_params.min_section_size,
-        p10() => node_params.min_section_size,
-        p11() => node_params.min_section_size
+        p("00") => node_params.min_section_size,
+        p("01") => node_params.min_section_size,
+        p("10") => node_params.min_section_size,
+        p("11") => node_params.min_section_size
     };
 
     let event_schedule = EventSchedule::new(btreemap! {
         0 => vec![
-            RemoveNodeFrom(p00()),
-            RemoveNodeFrom(p11()),
-            AddNode(p00().substituted_in(random())),
-            AddNode(p11().substituted_in(random())),
+            RemoveNodeFrom(p("00")),
+            RemoveNodeFrom(p("11")),
+            AddNode(p("00").substituted_in(random())),
+            AddNode(p("11").substituted_in(random())),
         ],
     });
 
@@ -179,22 +1301,22 @@ fn parallel_cascading_merges() {
 
     let sections =
         btreemap! {
-        p00() => node_params.min_section_size,
-        p010() => node_params.min_section_size,
-        p0110() => node_params.min_section_size,
-        p0111() => node_params.min_section_size,
-        p100() => node_params.min_section_size,
-        p1010() => node_params.min_section_size,
-        p1011() => node_params.min_section_size,
-        p11() => node_params.min_section_size
+        p("00") => node_params.min_section_size,
+        p("010") => node_params.min_section_size,
+        p("0110") => node_params.min_section_size,
+        p("0111") => node_params.min_section_size,
+        p("100") => node_params.min_section_size,
+        p("1010") => node_params.min_section_size,
+        p("1011") => node_params.min_section_size,
+        p("11") => node_params.min_section_size
     };
 
     let event_schedule = EventSchedule::new(btreemap! {
         0 => vec![
-            RemoveNodeFrom(p00()),
-            RemoveNodeFrom(p010()),
-            RemoveNodeFrom(p100()),
-            RemoveNodeFrom(p11()),
+            RemoveNodeFrom(p("00")),
+            RemoveNodeFrom(p("010")),
+            RemoveNodeFrom(p("100")),
+            RemoveNodeFrom(p("11")),
         ],
     });
 
@@ -251,14 +1373,14 @@ fn two_drop_merge() {
 
     let sections =
         btreemap! {
-        p0() => node_params.min_section_size,
-        p1() => node_params.min_section_size,
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
     };
 
     let schedule = EventSchedule::new(btreemap! {
         0 => vec![
-            RemoveNodeFrom(p0()),
-            RemoveNodeFrom(p0()),
+            RemoveNodeFrom(p("0")),
+            RemoveNodeFrom(p("0")),
         ],
     });
 
@@ -275,14 +1397,14 @@ fn cascading_merge() {
 
     let sections =
         btreemap! {
-        p0() => node_params.min_section_size,
-        p10() => node_params.min_section_size,
-        p11() => node_params.min_section_size,
+        p("0") => node_params.min_section_size,
+        p("10") => node_params.min_section_size,
+        p("11") => node_params.min_section_size,
     };
 
     let schedule = EventSchedule::new(btreemap! {
         0 => vec![
-            RemoveNodeFrom(p0()),
+            RemoveNodeFrom(p("0")),
         ],
     });
 
@@ -308,13 +1430,13 @@ fn one_join_one_drop() {
 
     let sections =
         btreemap! {
-        p0() => node_params.min_section_size,
-        p1() => node_params.min_section_size,
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
     };
 
     let schedule = EventSchedule::new(btreemap! {
-        0 => vec![RemoveNodeFrom(p0())],
-        1 => vec![AddNode(p0().substituted_in(random()))],
+        0 => vec![RemoveNodeFrom(p("0"))],
+        1 => vec![AddNode(p("0").substituted_in(random()))],
     });
 
     let mut simulation = Simulation::new_from(sections, schedule, params, node_params);
@@ -330,15 +1452,15 @@ fn triple_drop_merge() {
 
     let sections =
         btreemap! {
-        p0() => node_params.min_section_size,
-        p1() => node_params.min_section_size,
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
     };
 
     let schedule = EventSchedule::new(btreemap! {
         0 => vec![
-            RemoveNodeFrom(p0()),
-            RemoveNodeFrom(p0()),
-            RemoveNodeFrom(p0())
+            RemoveNodeFrom(p("0")),
+            RemoveNodeFrom(p("0")),
+            RemoveNodeFrom(p("0"))
         ],
     });
 
@@ -346,18 +1468,33 @@ fn triple_drop_merge() {
     simulation.run().unwrap();
 }
 
-fn step_num(schedule: &EventSchedule, offset: u64) -> u64 {
-    schedule.schedule.keys().next_back().cloned().unwrap_or(0) + offset
-}
+// Losing most of section 1 at once leaves it unable to agree on its own blocks, so section 0
+// forces a merge, though only once it has been disconnected for `force_merge_delay` steps.
+#[test]
+fn delayed_force_merge() {
+    init_logging();
+
+    let params = default_params();
+    let node_params = NodeParams {
+        force_merge_delay: 10,
+        ..NodeParams::default()
+    };
 
-fn add_events(schedule: &mut EventSchedule, offset: u64, spacing: u64, events: Vec<Event>) {
-    let start_step = step_num(schedule, offset);
+    let sections =
+        btreemap! {
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size
+    };
 
-    let timed_events = events.into_iter().enumerate().map(|(i, ev)| {
-        (start_step + (i as u64 + 1) * spacing, vec![ev])
+    let event_schedule = EventSchedule::new(btreemap! {
+        0 => (0..5).map(|_| RemoveNodeFrom(p("1"))).collect()
     });
 
-    schedule.schedule.extend(timed_events);
+    let mut simulation = Simulation::new_from(sections, event_schedule, params, node_params);
+
+    let blocks = simulation.run().unwrap();
+    assert!(blocks.contains_key(&Prefix::empty()));
+    assert!(simulation.force_merges() > 0);
 }
 
 #[test]
@@ -369,8 +1506,8 @@ fn growth_then_cascade() {
 
     let sections =
         btreemap! {
-        p0() => node_params.min_section_size,
-        p1() => node_params.min_section_size,
+        p("0") => node_params.min_section_size,
+        p("1") => node_params.min_section_size,
     };
 
     let step_size = 20;
@@ -383,7 +1520,7 @@ fn growth_then_cascade() {
         &mut schedule,
         0,
         step_size,
-        (0..9).map(|_| add_to(p10())).collect(),
+        (0..9).map(|_| add_to(p("10"))).collect(),
     );
 
     // 8 nodes in 111 (should cause a split into 10 and 11).
@@ -391,7 +1528,7 @@ fn growth_then_cascade() {
         &mut schedule,
         50,
         step_size,
-        (0..9).map(|_| add_to(p111())).collect(),
+        (0..9).map(|_| add_to(p("111"))).collect(),
     );
 
     // 8 nodes in 110 (should cause a split into 110 and 111).
@@ -399,7 +1536,7 @@ fn growth_then_cascade() {
         &mut schedule,
         50,
         step_size,
-        (0..9).map(|_| add_to(p110())).collect(),
+        (0..9).map(|_| add_to(p("110"))).collect(),
     );
 
     // Remove 8 nodes from 10, should cause a cascading merge!
@@ -407,13 +1544,74 @@ fn growth_then_cascade() {
         &mut schedule,
         50,
         2 * step_size,
-        (0..8).map(|_| RemoveNodeFrom(p10())).collect(),
+        (0..8).map(|_| RemoveNodeFrom(p("10"))).collect(),
     );
 
     let mut simulation = Simulation::new_from(sections, schedule, params, node_params);
     simulation.run().unwrap();
 }
 
+#[test]
+fn growth_then_cascade_scenario() {
+    run_scenario("growth_then_cascade");
+}
+
+#[test]
+fn malicious_joins() {
+    run_scenario("malicious_joins");
+}
+
+#[test]
+fn relocation() {
+    run_scenario("relocation");
+}
+
+// Sections still agree on removals and merges when members' votes are weighed by age.
+#[test]
+fn age_weighted_quorums() {
+    init_logging();
+
+    let scenario = unwrap!(scenarios::find("four_sections"));
+    let (params, node_params) = scenario.params();
+    let node_params = NodeParams {
+        quorum_weight: QuorumWeight::Age,
+        ..node_params
+    };
+    let mut simulation = scenario.simulation(params, node_params);
+
+    let sections = simulation.run().unwrap();
+    assert_eq!(sections.len(), 3);
+}
+
+// Attackers that cut themselves off from the honest members of their section are removed before
+// they can make up a quorum of it.
+#[test]
+fn eclipse_attack() {
+    init_logging();
+
+    let scenario = unwrap!(scenarios::find("eclipse_attack"));
+    let (params, node_params) = scenario.params();
+    let mut simulation = scenario.simulation(params, node_params);
+
+    simulation.run().unwrap();
+    assert_eq!(simulation.attacker_quorum(), None);
+}
+
+// Sybils flooding every section at once are only added as fast as the sections can handle their
+// joins.
+#[test]
+fn sybil_flood() {
+    init_logging();
+
+    let scenario = unwrap!(scenarios::find("sybil_flood"));
+    let (params, node_params) = scenario.params();
+    let mut simulation = scenario.simulation(params, node_params);
+
+    simulation.run().unwrap();
+    let (_, _, peak) = unwrap!(simulation.peak_attackers());
+    assert!(peak > 0.0 && peak < 1.0);
+}
+
 #[test]
 fn growth_then_force_merge() {
     init_logging();
@@ -436,7 +1634,7 @@ fn growth_then_force_merge() {
         &mut schedule,
         0,
         step_size,
-        (0..9).map(|_| add_to(p00())).collect(),
+        (0..9).map(|_| add_to(p("00"))).collect(),
     );
 
     // 9 nodes in 01.
@@ -444,7 +1642,7 @@ fn growth_then_force_merge() {
         &mut schedule,
         0,
         step_size,
-        (0..9).map(|_| add_to(p01())).collect(),
+        (0..9).map(|_| add_to(p("01"))).collect(),
     );
 
     // 9 nodes in 10.
@@ -452,7 +1650,7 @@ fn growth_then_force_merge() {
         &mut schedule,
         0,
         step_size,
-        (0..9).map(|_| add_to(p10())).collect(),
+        (0..9).map(|_| add_to(p("10"))).collect(),
     );
 
     // 9 nodes in 11.
@@ -460,7 +1658,7 @@ fn growth_then_force_merge() {
         &mut schedule,
         0,
         step_size,
-        (0..9).map(|_| add_to(p11())).collect(),
+        (0..9).map(|_| add_to(p("11"))).collect(),
     );
 
     // add some blocks to 10.
@@ -468,15 +1666,30 @@ fn growth_then_force_merge() {
         &mut schedule,
         0,
         step_size,
-        (0..2).map(|_| add_to(p10())).collect(),
+        (0..2).map(|_| add_to(p("10"))).collect(),
     );
 
     // Drop 5 nodes from 11 simultaneously - should trigger a force merge
     let step = step_num(&schedule, 150);
     schedule.schedule.extend(iter::once(
-        (step, (0..5).map(|_| RemoveNodeFrom(p11())).collect()),
+        (step, (0..5).map(|_| RemoveNodeFrom(p("11"))).collect()),
     ));
 
     let mut simulation = Simulation::new_from(sections, schedule, params, node_params);
     simulation.run().unwrap();
+
+    // The section that loses its members is merged by force, rather than for being too small.
+    let rules = simulation.rule_coverage();
+    let exercised = [
+        Rule::Add,
+        Rule::Remove,
+        Rule::Split,
+        Rule::ForceMerge,
+        Rule::Witness,
+        Rule::Bootstrap,
+        Rule::ProofRequest,
+    ];
+    for &rule in &exercised {
+        assert!(rules.fired(rule), "{:?} never fired: {}", rule, rules);
+    }
 }
@@ -0,0 +1,113 @@
+//! A test written by `minimize`, kept so that building the tests checks that what it writes
+//! compiles. The tests of `src/bin/minimize.rs` check that it still writes this.
+
+extern crate ewok;
+#[macro_use]
+extern crate maplit;
+
+use ewok::event::Event::*;
+use ewok::event_schedule::EventSchedule;
+use ewok::logging::init_logging;
+use ewok::name::Name;
+use ewok::random::reseed;
+use ewok::simulation::Simulation;
+
+// Needs `use ewok::event::Event::*;`, `use ewok::name::Name;` and `use ewok::random::reseed;`,
+// along with the other imports of `tests/manual_setup.rs`.
+#[test]
+fn minimized_failure() {
+    init_logging();
+    reseed([1, 2, 3, 4]);
+
+    let params = ewok::params::SimulationParams {
+        max_delay: 5,
+        delay_distribution: ewok::params::DelayDistribution::Geometric,
+        delivery_percentile: 0.95,
+        burst_reorder_prob: 0.0,
+        grow_prob_join: 0.0,
+        grow_prob_drop: 0.0,
+        prob_churn: 0.0,
+        steady_state: false,
+        shrink_prob_join: 0.0,
+        shrink_prob_drop: 0.0,
+        churn_correlation: 0.0,
+        churn_memory: 5,
+        max_network_size: None,
+        name_distribution: ewok::params::NameDistribution::Random,
+        grow_prob_disconnect: 0.0,
+        grow_prob_reconnect: 0.0,
+        prob_disconnect: 0.0,
+        prob_reconnect: 0.0,
+        shrink_prob_disconnect: 0.0,
+        shrink_prob_reconnect: 0.0,
+        starting_complete: 0,
+        grow_complete: 0,
+        stable_steps: 200,
+        log_file: None,
+        log_file_max_bytes: None,
+        log_levels: None,
+        malicious_join_prob: 0.0,
+        malicious_behaviour: ewok::params::CandidateBehaviour::Honest,
+        restart_mode: ewok::params::RestartMode::Persistent,
+        chaos: false,
+        chaos_prob: 0.05,
+        chaos_duration: 20,
+        chaos_max_partition: 2,
+        chaos_max_loss: 0.05,
+        chaos_max_duplicate: 0.05,
+        data_op_prob: 0.0,
+        sybil_fraction: 0.0,
+        sybil_window: 10,
+        sybil_target: Some("01".parse::<ewok::name::Prefix>().unwrap()),
+        overload_drop_prob: 0.0,
+        overload_window: 10,
+        slow_node_ratio: 0.0,
+        fast_node_ratio: 0.0,
+        message_capacity: None,
+        max_clock_skew: 0,
+        track_votes: false,
+    };
+    let node_params = ewok::params::NodeParams {
+        min_section_size: 8,
+        min_section_size_growth: ewok::params::SizeGrowth::Fixed,
+        split_buffer: 1,
+        split_buffer_growth: ewok::params::SizeGrowth::PerSections(2),
+        join_timeout: 20,
+        self_shutdown_timeout: 100,
+        max_conflicting_blocks: 20,
+        quorum_numerator: 1,
+        quorum_denominator: 2,
+        quorum_weight: ewok::params::QuorumWeight::Age,
+        routing_bucket_size: None,
+        resource_proof_steps: 0,
+        rmconv_timeout: 0,
+        candidate_queue_timeout: None,
+        force_merge_numerator: 1,
+        force_merge_denominator: 2,
+        force_merge_delay: 0,
+        relocate_new_nodes: false,
+        storm_threshold: None,
+        throttle_storms: false,
+        aggregate_signature_bytes: None,
+        history_depth: None,
+        proof_request_policy: ewok::params::ProofRequestPolicy::VersionGap,
+        proof_request_window: 10,
+        max_connections: None,
+        recover_chain_gaps: false,
+        recipient_policy: ewok::params::RecipientRule::AllNeighbours,
+    };
+
+    let sections = btreemap! {
+        "0".parse::<ewok::name::Prefix>().unwrap() => 8,
+        "1".parse::<ewok::name::Prefix>().unwrap() => 8,
+    };
+
+    let schedule = EventSchedule::new(btreemap! {
+        10 => vec![AddNode(Name(0x0123456789abcdef)), RemoveNodeFrom("1".parse::<ewok::name::Prefix>().unwrap())],
+        20 => vec![CrashNode(Name(0x0123456789abcdef))],
+        30 => vec![RestartNode(Name(0x0123456789abcdef))],
+    });
+
+    let mut simulation = Simulation::new_from(sections, schedule, params, node_params);
+    simulation.run().unwrap();
+}